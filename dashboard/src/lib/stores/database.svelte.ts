@@ -0,0 +1,11 @@
+// `main` is reserved and can never be the name of an additional database
+export const DB_MAIN = 'main';
+
+export let DATABASE = $state({name: DB_MAIN});
+
+/**
+ * The `?db=` query param for the currently selected database, empty for the main one.
+ */
+export function dbParam() {
+    return DATABASE.name === DB_MAIN ? '' : `?db=${encodeURIComponent(DATABASE.name)}`;
+}
@@ -10,7 +10,56 @@ export interface IRaftMetrics {
     current_leader: number,
     millis_since_quorum_ack?: number,
     membership_config: IStoredMembership,
-    replication: Map<number, ILogId>
+    replication: Map<number, ILogId>,
+    sqlite: ISqliteConfig,
+    write_cost: IWriteCostMetrics,
+    read_pool: IReadPoolMetrics,
+    divergence: IDivergenceMetrics,
+}
+
+export interface IWriteCostMetrics {
+    limit: number,
+    max_steps: number,
+    near_limit: number,
+    aborted: number,
+}
+
+export interface IReadPoolMetrics {
+    readers: number,
+    idle: number,
+    min: number,
+    max: number,
+    queue_len: number,
+    queries: number,
+    wait_avg_micros: number,
+    wait_max_micros: number,
+}
+
+export interface IDivergenceMetrics {
+    checks: number,
+    last_log_index?: number,
+    last_hash?: string,
+    divergent_nodes: number[],
+    divergent_tables: string[],
+    missing_nodes: number[],
+    resyncs: number,
+}
+
+export interface ISqliteConfig {
+    preset: 'low-memory' | 'balanced' | 'throughput',
+    writer: ISqlitePragmas,
+    reader: ISqlitePragmas,
+}
+
+export interface ISqlitePragmas {
+    synchronous: 'off' | 'normal' | 'full',
+    page_size: number,
+    journal_size_limit: number,
+    wal_autocheckpoint: number,
+    auto_vacuum: 'none' | 'full' | 'incremental',
+    cache_size: number,
+    mmap_size: number,
+    temp_store: 'default' | 'file' | 'memory',
 }
 
 export interface IVote {
@@ -0,0 +1,171 @@
+// Copyright 2025 Sebastian Dobe <sebastiandobe@mailbox.org>
+
+#![forbid(unsafe_code)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr, Path};
+
+/// Derives `hiqlite::FromRow` and `From<hiqlite::Row<'_>>` for a struct with named fields.
+///
+/// The generated code works with local (`Row::Borrowed`) and remote (`Row::Owned`) rows.
+///
+/// Field attributes:
+/// - `#[row(rename = "col")]` reads the value from the column `col` instead of the field name
+/// - `#[row(default)]` uses `Default::default()` if the column does not exist or has an
+///   incompatible type
+/// - `#[row(flatten)]` maps the field from the same row with its own `FromRow` impl
+/// - `#[row(with = "path::to::fn")]` reads the column as the input type of `fn` and converts it
+///   with `fn(value) -> T`
+///
+/// `Option<_>` fields are mapped from `NULL` values to `None`.
+///
+/// ```rust, notest
+/// #[derive(hiqlite::FromRow)]
+/// struct Entity {
+///     id: i64,
+///     #[row(rename = "desc")]
+///     description: Option<String>,
+///     #[row(default)]
+///     tags: Option<String>,
+///     #[row(with = "Status::from_i64")]
+///     status: Status,
+///     #[row(flatten)]
+///     audit: Audit,
+/// }
+/// ```
+#[proc_macro_derive(FromRow, attributes(row))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    default: bool,
+    flatten: bool,
+    with: Option<Path>,
+}
+
+impl FieldAttrs {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut slf = Self::default();
+
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("row")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    slf.rename = Some(value.value());
+                } else if meta.path.is_ident("default") {
+                    slf.default = true;
+                } else if meta.path.is_ident("flatten") {
+                    slf.flatten = true;
+                } else if meta.path.is_ident("with") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    slf.with = Some(value.parse()?);
+                } else {
+                    return Err(meta.error(
+                        "unsupported row attribute, expected one of: rename, default, flatten, with",
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+
+        if slf.flatten && (slf.rename.is_some() || slf.with.is_some()) {
+            return Err(syn::Error::new(
+                field.span(),
+                "`flatten` cannot be combined with `rename` or `with`",
+            ));
+        }
+
+        Ok(slf)
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "FromRow can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "FromRow can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        let ident = field
+            .ident
+            .as_ref()
+            .expect("named fields to always have an ident");
+        let attrs = FieldAttrs::parse(field)?;
+
+        let column = attrs
+            .rename
+            .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+
+        let value = if attrs.flatten {
+            let ty = &field.ty;
+            quote! { <#ty as ::hiqlite::FromRow>::from_row(row) }
+        } else if let Some(with) = attrs.with {
+            quote! { row.try_get(#column).map(#with) }
+        } else {
+            quote! { row.try_get(#column) }
+        };
+
+        let value = if attrs.default {
+            quote! { (#value).unwrap_or_default() }
+        } else {
+            quote! { #value? }
+        };
+
+        values.push(quote! { #ident: #value });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    // `From<Row<'r>>` needs an additional lifetime
+    let mut generics_from = input.generics.clone();
+    generics_from.params.insert(0, syn::parse_quote!('__r));
+    let (impl_generics_from, _, _) = generics_from.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::hiqlite::FromRow for #name #ty_generics #where_clause {
+            fn from_row(row: &mut ::hiqlite::Row<'_>) -> ::std::result::Result<Self, ::hiqlite::Error> {
+                ::std::result::Result::Ok(Self {
+                    #(#values,)*
+                })
+            }
+        }
+
+        impl #impl_generics_from ::std::convert::From<::hiqlite::Row<'__r>> for #name #ty_generics #where_clause {
+            fn from(mut row: ::hiqlite::Row<'__r>) -> Self {
+                match <Self as ::hiqlite::FromRow>::from_row(&mut row) {
+                    ::std::result::Result::Ok(slf) => slf,
+                    ::std::result::Result::Err(err) => panic!(
+                        "Cannot map row into {}: {}",
+                        ::std::stringify!(#name),
+                        err
+                    ),
+                }
+            }
+        }
+    })
+}
@@ -1,5 +1,15 @@
-use crate::NodeId;
+#[cfg(feature = "sqlite")]
+use crate::{
+    store::state_machine::sqlite::{
+        coalesce::WriteCoalescer,
+        deterministic::{self, WriteStamp},
+        state_machine::{Indexed, Query, QueryWrite, QueryWriteEntry},
+    },
+    Response,
+};
+use crate::{Error, NodeId};
 use serde::Deserialize;
+use std::borrow::Cow;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use tokio::sync::{Mutex, MutexGuard};
@@ -23,23 +33,47 @@ use crate::store::state_machine::sqlite::{
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(try_from = "String")]
 pub enum RaftType {
     #[cfg(feature = "sqlite")]
     Sqlite,
+    /// An additional SQLite Raft group with an id `>= 1`
+    #[cfg(feature = "sqlite")]
+    Shard(u16),
     #[cfg(feature = "cache")]
     Cache,
     Unknown,
 }
 
 impl RaftType {
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> Cow<'static, str> {
         match self {
             #[cfg(feature = "sqlite")]
-            RaftType::Sqlite => "sqlite",
+            RaftType::Sqlite => "sqlite".into(),
+            #[cfg(feature = "sqlite")]
+            RaftType::Shard(group) => format!("shard_{}", group).into(),
+            #[cfg(feature = "cache")]
+            RaftType::Cache => "cache".into(),
+            RaftType::Unknown => "unknown".into(),
+        }
+    }
+}
+
+impl TryFrom<String> for RaftType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(RaftType::Sqlite),
             #[cfg(feature = "cache")]
-            RaftType::Cache => "cache",
-            RaftType::Unknown => "unknown",
+            "cache" => Ok(RaftType::Cache),
+            #[cfg(feature = "sqlite")]
+            s if s.starts_with("shard_") => match s[6..].parse::<u16>() {
+                Ok(group) if group > 0 => Ok(RaftType::Shard(group)),
+                _ => Err(format!("invalid raft type: {}", value)),
+            },
+            _ => Err(format!("invalid raft type: {}", value)),
         }
     }
 }
@@ -51,12 +85,17 @@ pub(crate) struct AppState {
     pub addr_api: String,
     #[cfg(feature = "sqlite")]
     pub raft_db: StateRaftDB,
+    /// Additional SQLite Raft groups. The group id is the index + 1.
+    #[cfg(feature = "sqlite")]
+    pub raft_db_shards: Vec<StateRaftDB>,
     #[cfg(feature = "cache")]
     pub raft_cache: StateRaftCache,
     pub secret_raft: String,
     pub secret_api: String,
     #[cfg(feature = "sqlite")]
     pub client_buffers_db: Mutex<HashMap<NodeId, VecDeque<Vec<u8>>>>,
+    #[cfg(feature = "sqlite")]
+    pub client_buffers_shards: Vec<Mutex<HashMap<NodeId, VecDeque<Vec<u8>>>>>,
     #[cfg(feature = "cache")]
     pub client_buffers_cache: Mutex<HashMap<NodeId, VecDeque<Vec<u8>>>>,
     #[cfg(feature = "dashboard")]
@@ -71,13 +110,59 @@ impl AppState {
     pub async fn get_buf_lock(
         &self,
         raft_type: &RaftType,
-    ) -> MutexGuard<HashMap<NodeId, VecDeque<Vec<u8>>>> {
+    ) -> Result<MutexGuard<HashMap<NodeId, VecDeque<Vec<u8>>>>, Error> {
         match raft_type {
             #[cfg(feature = "sqlite")]
-            RaftType::Sqlite => self.client_buffers_db.lock().await,
+            RaftType::Sqlite => Ok(self.client_buffers_db.lock().await),
+            #[cfg(feature = "sqlite")]
+            RaftType::Shard(group) => match group
+                .checked_sub(1)
+                .and_then(|idx| self.client_buffers_shards.get(idx as usize))
+            {
+                Some(buf) => Ok(buf.lock().await),
+                None => Err(Error::BadRequest(
+                    format!("shard {} does not exist", group).into(),
+                )),
+            },
             #[cfg(feature = "cache")]
-            RaftType::Cache => self.client_buffers_cache.lock().await,
-            RaftType::Unknown => unreachable!("Invalid RaftType"),
+            RaftType::Cache => Ok(self.client_buffers_cache.lock().await),
+            RaftType::Unknown => Err(Error::BadRequest("Invalid RaftType".into())),
+        }
+    }
+
+    /// Makes sure that a `RaftType` from a request exists on this node.
+    pub fn check_raft_type(&self, raft_type: &RaftType) -> Result<(), Error> {
+        #[cfg(feature = "sqlite")]
+        if let RaftType::Shard(group) = raft_type {
+            if *group == 0 || *group as usize > self.raft_db_shards.len() {
+                return Err(Error::BadRequest(
+                    format!("shard {} does not exist", group).into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl AppState {
+    /// Returns the SQLite Raft group with the given id, where `0` is the main one.
+    pub fn raft_db_group(&self, group: u16) -> Option<&StateRaftDB> {
+        if group == 0 {
+            Some(&self.raft_db)
+        } else {
+            self.raft_db_shards.get(group as usize - 1)
+        }
+    }
+
+    /// Returns the SQLite Raft for the given type, or an error if this shard does not exist.
+    pub fn raft_db_for(&self, raft_type: &RaftType) -> Result<&StateRaftDB, Error> {
+        match raft_type {
+            RaftType::Shard(group) => group
+                .checked_sub(1)
+                .and_then(|idx| self.raft_db_shards.get(idx as usize))
+                .ok_or_else(|| Error::BadRequest(format!("shard {} does not exist", group).into())),
+            _ => Ok(&self.raft_db),
         }
     }
 }
@@ -97,7 +182,91 @@ pub struct StateRaftDB {
     pub logs_writer: flume::Sender<crate::store::logs::rocksdb::ActionWrite>,
     pub sql_writer: flume::Sender<WriterRequest>,
     pub read_pool: SqlitePool,
+    pub named_read_pools: HashMap<String, SqlitePool>,
     pub log_statements: bool,
+    /// The default timeout for local reads, `None` if disabled
+    pub query_timeout: Option<std::time::Duration>,
+    /// The max time to wait for a log index to be applied locally
+    pub wait_applied_timeout: std::time::Duration,
+    pub sqlite_config: crate::SqliteConfig,
+    pub(crate) write_cost:
+        std::sync::Arc<crate::store::state_machine::sqlite::cost::WriteCostGuard>,
+    pub(crate) checksums: std::sync::Arc<crate::store::state_machine::sqlite::checksum::Checksums>,
+    /// Snapshot chunks are looked up inside this folder before they are transferred
+    pub(crate) path_snapshots: String,
+    pub leader_contact: crate::query::staleness::LeaderContact,
+    pub lease: Option<std::sync::Arc<crate::query::lease::LeaderLease>>,
+    pub coalescer: Option<WriteCoalescer>,
+}
+
+#[cfg(feature = "sqlite")]
+impl StateRaftDB {
+    /// Validates the given `QueryWrite` and proposes it to the Raft, stamped with the current
+    /// timestamp and a random seed. This must be used for all writes instead of
+    /// `raft.client_write()` directly to have deterministic SQL functions on all nodes.
+    pub async fn client_write(
+        &self,
+        query: QueryWrite,
+    ) -> Result<openraft::raft::ClientWriteResponse<TypeConfigSqlite>, Error> {
+        self.client_write_db(None, query).await
+    }
+
+    /// The same as `client_write()`, but for the given named database, or the main one for `None`.
+    pub async fn client_write_db(
+        &self,
+        db: Option<String>,
+        query: QueryWrite,
+    ) -> Result<openraft::raft::ClientWriteResponse<TypeConfigSqlite>, Error> {
+        if let Some(name) = &db {
+            self.named_read_pool(name)?;
+            #[cfg(feature = "backup")]
+            if matches!(query, QueryWrite::Backup(_)) {
+                return Err(Error::BadRequest(
+                    "backups are only supported for the main database".into(),
+                ));
+            }
+        }
+        deterministic::validate_query_write(&query)?;
+
+        let entry = QueryWriteEntry {
+            stamp: WriteStamp::now(),
+            db,
+            query,
+        };
+        Ok(self.raft.client_write(entry).await?)
+    }
+
+    /// Returns the read pool for the given named database.
+    pub fn named_read_pool(&self, db: &str) -> Result<&SqlitePool, Error> {
+        self.named_read_pools
+            .get(db)
+            .ok_or_else(|| Error::BadRequest(format!("database '{}' does not exist", db).into()))
+    }
+
+    /// Returns the read pool for the given named database, or the main one for `None`.
+    pub fn read_pool_for(&self, db: Option<&str>) -> Result<&SqlitePool, Error> {
+        match db {
+            None => Ok(&self.read_pool),
+            Some(db) => self.named_read_pool(db),
+        }
+    }
+
+    /// Proposes a single `QueryWrite::Execute`. If write coalescing is enabled, concurrent
+    /// executes will be packed into a single Raft entry.
+    pub async fn execute(&self, query: Query) -> Result<Indexed<usize>, Error> {
+        if let Some(coalescer) = &self.coalescer {
+            // validate upfront, so an invalid query can't fail the whole batch
+            deterministic::validate_sql(&query.sql)?;
+            return coalescer.execute(query).await;
+        }
+
+        let res = self.client_write(QueryWrite::Execute(query)).await?;
+        let log_index = res.log_id.index;
+        match res.data {
+            Response::Execute(res) => res.result.map(|res| Indexed::new(res, log_index)),
+            _ => unreachable!(),
+        }
+    }
 }
 
 #[cfg(feature = "cache")]
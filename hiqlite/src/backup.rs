@@ -1,13 +1,17 @@
 use crate::app_state::AppState;
 use crate::helpers::set_path_access;
+use crate::pitr::{self, PitrTarget};
 use crate::s3::S3Config;
 use crate::store::logs;
+use crate::store::state_machine::sqlite::functions::SqlFunctions;
 use crate::store::state_machine::sqlite::state_machine::{
     PathBackups, PathDb, PathLockFile, PathSnapshots, QueryWrite, StateMachineData,
     StateMachineSqlite,
 };
+use crate::store::state_machine::sqlite::writer::{self, META_KEY_BACKUP_INDEX};
 use crate::{Client, Error, NodeConfig};
 use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
 use std::env;
 use std::ops::Sub;
 use std::path::Path;
@@ -24,6 +28,8 @@ pub const BACKUP_DB_NAME: &str = "restore.sqlite";
 pub struct BackupConfig {
     cron_schedule: cron::Schedule,
     keep_days: u16,
+    /// Interval in seconds for pushing archived Raft logs to S3, `0` to disable
+    archive_interval: u64,
 }
 
 impl Default for BackupConfig {
@@ -31,6 +37,7 @@ impl Default for BackupConfig {
         Self {
             cron_schedule: cron::Schedule::from_str("0 30 2 * * * *").unwrap(),
             keep_days: 30,
+            archive_interval: 0,
         }
     }
 }
@@ -41,9 +48,21 @@ impl BackupConfig {
             cron_schedule: cron::Schedule::from_str(cron_schedule)
                 .map_err(|_| Error::Config("Invalid syntax for cron_schedule".into()))?,
             keep_days,
+            archive_interval: 0,
         })
     }
 
+    /// Enables continuous archiving of the Raft logs to S3 for point-in-time recovery. Logs are
+    /// collected into segments, which are closed and pushed after `interval_secs`.
+    pub fn with_archive_interval(mut self, interval_secs: u64) -> Self {
+        self.archive_interval = interval_secs;
+        self
+    }
+
+    pub(crate) fn archive_interval(&self) -> Option<Duration> {
+        (self.archive_interval > 0).then(|| Duration::from_secs(self.archive_interval))
+    }
+
     pub fn from_env() -> Self {
         let cron_str = env::var("HQL_BACKUP_CRON").unwrap_or_else(|_| "0 30 2 * * * *".to_string());
         let cron_schedule =
@@ -54,9 +73,15 @@ impl BackupConfig {
             .parse::<u16>()
             .expect("Cannot parse HQL_BACKUP_KEEP_DAYS to u16");
 
+        let archive_interval = env::var("HQL_BACKUP_ARCHIVE_INTERVAL")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<u64>()
+            .expect("Cannot parse HQL_BACKUP_ARCHIVE_INTERVAL to u64");
+
         Self {
             cron_schedule,
             keep_days,
+            archive_interval,
         }
     }
 }
@@ -65,6 +90,8 @@ impl BackupConfig {
 pub enum BackupSource {
     S3(String),
     File(String),
+    /// The latest backup on S3 before the target with the archived Raft logs replayed on top
+    Pitr(PitrTarget),
 }
 
 impl BackupSource {
@@ -79,8 +106,18 @@ impl BackupSource {
             return Some(Self::File(file.to_string()));
         }
 
+        if let Some(target) = var.strip_prefix("pitr:") {
+            return match target.parse() {
+                Ok(target) => Some(Self::Pitr(target)),
+                Err(err) => {
+                    error!("Cannot restore from backup - {}", err);
+                    None
+                }
+            };
+        }
+
         error!(
-            "HQL_BACKUP_RESTORE must start with either 's3:' or 'file:'. \
+            "HQL_BACKUP_RESTORE must start with either 's3:', 'file:' or 'pitr:'. \
             Cannot restore from backup - unknown prefix: {}",
             var
         );
@@ -160,7 +197,10 @@ async fn backup_cron_job(
         }
 
         for object in bucket.contents.iter() {
-            if let Some(dt) = dt_from_backup_name(&object.key) {
+            // archived logs are only needed for the replay on top of the remaining backups
+            if let Some(dt) =
+                dt_from_backup_name(&object.key).or_else(|| pitr::dt_from_segment_name(&object.key))
+            {
                 if dt < threshold {
                     info!("Deleting expired backup: {}", object.key);
                     s3_config.bucket.delete(object.key.clone()).await?;
@@ -223,7 +263,7 @@ pub(crate) async fn backup_local_cleanup(backup_path: String) -> Result<(), Erro
     Ok(())
 }
 
-fn dt_from_backup_name(name: &str) -> Option<DateTime<Utc>> {
+pub(crate) fn dt_from_backup_name(name: &str) -> Option<DateTime<Utc>> {
     if let Some(backup) = name.strip_prefix("backup_node_") {
         let (_, rest) = match backup.split_once("_") {
             None => {
@@ -283,7 +323,7 @@ pub(crate) async fn restore_backup_start(node_config: &NodeConfig) -> Result<boo
 pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Result<(), Error> {
     info!("Starting database restore from backup {:?}", src);
 
-    if let BackupSource::S3(_) = &src {
+    if matches!(src, BackupSource::S3(_) | BackupSource::Pitr(_)) {
         if node_config.s3_config.is_none() {
             return Err(Error::S3(
                 "No `S3Config` given, cannot restore backup".to_string(),
@@ -302,7 +342,7 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     fs::create_dir_all(&path_backups).await?;
     set_path_access(&path_backups, 0o700).await?;
 
-    let (path_backup, remove_src) = match src {
+    let (path_backup, remove_src, replay) = match src {
         BackupSource::S3(s3_obj) => {
             let s3_config = match &node_config.s3_config {
                 None => {
@@ -314,7 +354,7 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
             };
             let path_backup = format!("{}/{}", path_backups, BACKUP_DB_NAME);
             s3_config.pull(&s3_obj, &path_backup).await?;
-            (path_backup, true)
+            (path_backup, true, None)
         }
         BackupSource::File(path_src) => {
             let (path, filename) = path_src.rsplit_once('/').unwrap_or(("", &path_src));
@@ -325,11 +365,24 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
             let path_backup = format!("{}/{}", path_backups, filename);
 
             fs::copy(path_src, &path_backup).await?;
-            (path_backup, false)
+            (path_backup, false, None)
+        }
+        BackupSource::Pitr(target) => {
+            let s3_config = match &node_config.s3_config {
+                None => {
+                    return Err(Error::S3(
+                        "No `S3Config` given, cannot run point-in-time recovery".to_string(),
+                    ));
+                }
+                Some(c) => c,
+            };
+            let path_backup = format!("{}/{}", path_backups, BACKUP_DB_NAME);
+            let point = pitr::pull_backup(s3_config, &target, &path_backup).await?;
+            (path_backup, true, Some((target, point, s3_config)))
         }
     };
 
-    is_metadata_ok(path_backup.clone()).await?;
+    is_metadata_ok(path_backup.clone(), node_config.sql_functions.clone()).await?;
     debug!("Database backup metadata is ok");
 
     debug!("Removing old data");
@@ -338,6 +391,7 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     let _ = fs::remove_dir_all(&path_snapshots).await;
     let _ = fs::remove_dir_all(&path_lock_file).await;
     let _ = fs::remove_dir_all(&path_logs).await;
+    let _ = fs::remove_dir_all(pitr::archive_dir(&node_config.data_dir)).await;
 
     fs::create_dir_all(&path_db).await?;
     set_path_access(&path_db, 0o700).await?;
@@ -350,6 +404,11 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     fs::copy(&path_backup, &path_db_full).await?;
     set_path_access(&path_db_full, 0o700).await?;
 
+    if let Some((target, point, s3_config)) = replay {
+        pitr::replay(node_config, s3_config, point, &target).await?;
+    }
+    reset_metadata(path_db_full).await?;
+
     if remove_src {
         info!("Cleaning up S3 backup from {}", path_backup);
         fs::remove_file(path_backup).await?;
@@ -358,13 +417,14 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     Ok(())
 }
 
-async fn is_metadata_ok(path_db: String) -> Result<(), Error> {
+async fn is_metadata_ok(path_db: String, sql_functions: SqlFunctions) -> Result<(), Error> {
     if env::var("HQL_BACKUP_SKIP_VALIDATION") == Ok("true".to_string()) {
         return Ok(());
     }
 
     task::spawn_blocking(move || {
         let conn = rusqlite::Connection::open(path_db)?;
+        sql_functions.apply(&conn)?;
         let mut stmt = conn.prepare_cached("SELECT data FROM _metadata WHERE key = 'meta'")?;
         let bytes = stmt.query_row((), |row| {
             let bytes: Vec<u8> = row.get(0)?;
@@ -379,6 +439,38 @@ async fn is_metadata_ok(path_db: String) -> Result<(), Error> {
     Ok(())
 }
 
+/// Log indexes start over after a restore. The metadata of the backup and the archive must not
+/// be carried over into the new Raft.
+async fn reset_metadata(path_db: String) -> Result<(), Error> {
+    task::spawn_blocking(move || {
+        let conn = rusqlite::Connection::open(path_db)?;
+        let exists = conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_metadata'",
+                (),
+                |_| Ok(()),
+            )
+            .optional()?;
+        // a plain SQLite file with skipped validation gets its metadata on the first start
+        if exists.is_none() {
+            return Ok(());
+        }
+
+        writer::persist_metadata(&conn, &StateMachineData::default())?;
+        conn.execute(
+            "DELETE FROM _metadata WHERE key IN ($1, $2, $3)",
+            (
+                META_KEY_BACKUP_INDEX,
+                pitr::META_KEY_EPOCH,
+                pitr::META_KEY_UPLOADED,
+            ),
+        )?;
+        Ok::<(), Error>(())
+    })
+    .await??;
+    Ok(())
+}
+
 // pub fn restore_backup_finish(state: Arc<AppState>, nodes_count: usize) {
 //     task::spawn(restore_backup_cleanup_task(state, nodes_count));
 // }
@@ -413,7 +505,7 @@ pub async fn restore_backup_finish(state: &Arc<AppState>) {
     let reqs = 10;
     for _ in 0..reqs {
         let start = Instant::now();
-        match state.raft_db.raft.client_write(QueryWrite::RTT).await {
+        match state.raft_db.client_write(QueryWrite::RTT).await {
             Ok(_) => {
                 info!("Raft RTT: {} micros", start.elapsed().as_micros());
             }
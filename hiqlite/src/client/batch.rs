@@ -1,6 +1,6 @@
 use crate::client::stream::{ClientBatchPayload, ClientStreamReq};
 use crate::network::api::ApiStreamResponsePayload;
-use crate::store::state_machine::sqlite::state_machine::QueryWrite;
+use crate::store::state_machine::sqlite::state_machine::{Indexed, QueryWrite};
 use crate::{Client, Error, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
@@ -34,6 +34,21 @@ impl Client {
     /// This means you **must validate and sanitize** the input manually.
     /// Executing unvalidated user input in a batch can open your app to SQL Injections!
     pub async fn batch<S>(&self, sql: S) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.batch_indexed(sql).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `batch()`, but additionally returns the Raft log index this
+    /// batch has been applied at.
+    ///
+    /// You can pass this index into the `query_*_after()` functions to read your own writes
+    /// from any node.
+    pub async fn batch_indexed<S>(
+        &self,
+        sql: S,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error>
     where
         S: Into<Cow<'static, str>>,
     {
@@ -56,16 +71,13 @@ impl Client {
     async fn batch_execute(
         &self,
         sql: Cow<'static, str>,
-    ) -> Result<Vec<Result<usize, Error>>, Error> {
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
-            let res = state
-                .raft_db
-                .raft
-                .client_write(QueryWrite::Batch(sql))
-                .await?;
+            let res = state.raft_db.client_write(QueryWrite::Batch(sql)).await?;
+            let log_index = res.log_id.index;
             let resp: Response = res.data;
             match resp {
-                Response::Batch(res) => res.result,
+                Response::Batch(res) => res.result.map(|res| Indexed::new(res, log_index)),
                 _ => unreachable!(),
             }
         } else {
@@ -9,7 +9,16 @@ use tokio::sync::{watch, RwLock};
 use crate::client::listen_notify::remote::RemoteListener;
 
 #[cfg(feature = "sqlite")]
-use crate::client::stream::ClientStreamReq;
+use crate::network::HEADER_NAME_SECRET;
+#[cfg(feature = "sqlite")]
+use crate::{
+    client::{stream::ClientStreamReq, ShardConn},
+    NodeId,
+};
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
+#[cfg(feature = "sqlite")]
+use tracing::error;
 
 impl Client {
     /// Create a local client that skips network connections if not necessary
@@ -25,6 +34,10 @@ impl Client {
 
         let secret = state.secret_api.as_bytes().to_vec();
 
+        #[cfg(feature = "sqlite")]
+        let (shards, rx_shards) =
+            Self::build_shard_conns(state.raft_db_shards.len() as u16, leader_id, &leader_addr);
+
         #[cfg(feature = "cache")]
         let leader_cache = Arc::new(RwLock::new((leader_id, leader_addr.clone())));
         #[cfg(feature = "sqlite")]
@@ -46,6 +59,8 @@ impl Client {
             tx_client_cache,
             #[cfg(feature = "sqlite")]
             tx_client_db,
+            #[cfg(feature = "sqlite")]
+            shards,
             tls_config,
             api_secret: None,
             request_id: AtomicUsize::new(0),
@@ -61,6 +76,8 @@ impl Client {
         };
 
         slf.find_set_active_leader().await;
+        #[cfg(feature = "sqlite")]
+        slf.open_shard_streams(&secret, rx_shards).await;
 
         #[cfg(feature = "cache")]
         slf.open_stream(
@@ -115,6 +132,23 @@ impl Client {
         let node_id = 0;
         let node_addr = nodes[0].clone();
 
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            // TODO
+            .build()
+            .unwrap();
+
+        // Shards are not supported through the proxy, which only forwards the main Raft group.
+        #[cfg(feature = "sqlite")]
+        let (shards, rx_shards) = {
+            let count = if with_proxy {
+                0
+            } else {
+                Self::fetch_shard_count(&client, &nodes, tls, &api_secret).await
+            };
+            Self::build_shard_conns(count, node_id, &node_addr)
+        };
+
         #[cfg(feature = "cache")]
         let leader_cache = Arc::new(RwLock::new((node_id, node_addr.clone())));
         #[cfg(feature = "sqlite")]
@@ -144,17 +178,13 @@ impl Client {
             #[cfg(feature = "cache")]
             leader_cache,
             nodes,
-            client: Some(
-                reqwest::Client::builder()
-                    .http2_prior_knowledge()
-                    // TODO
-                    .build()
-                    .unwrap(),
-            ),
+            client: Some(client),
             #[cfg(feature = "cache")]
             tx_client_cache,
             #[cfg(feature = "sqlite")]
             tx_client_db,
+            #[cfg(feature = "sqlite")]
+            shards,
             tls_config,
             api_secret: Some(api_secret),
             request_id: AtomicUsize::new(0),
@@ -174,6 +204,8 @@ impl Client {
         if !with_proxy {
             slf.find_set_active_leader().await;
         }
+        #[cfg(feature = "sqlite")]
+        slf.open_shard_streams(&api_secret_bytes, rx_shards).await;
 
         #[cfg(feature = "cache")]
         slf.open_stream(
@@ -193,3 +225,74 @@ impl Client {
         Ok(slf)
     }
 }
+
+#[cfg(feature = "sqlite")]
+impl Client {
+    fn build_shard_conns(
+        count: u16,
+        leader_id: NodeId,
+        leader_addr: &str,
+    ) -> (Vec<ShardConn>, Vec<flume::Receiver<ClientStreamReq>>) {
+        (0..count)
+            .map(|_| {
+                let (tx_client, rx_client) = flume::bounded(2);
+                let conn = ShardConn {
+                    leader: Arc::new(RwLock::new((leader_id, leader_addr.to_string()))),
+                    tx_client,
+                };
+                (conn, rx_client)
+            })
+            .unzip()
+    }
+
+    async fn open_shard_streams(
+        &self,
+        secret: &[u8],
+        rx_shards: Vec<flume::Receiver<ClientStreamReq>>,
+    ) {
+        for (idx, rx_client) in rx_shards.into_iter().enumerate() {
+            let group = idx as u16 + 1;
+            self.find_set_active_leader_shard(group).await;
+            self.open_stream(
+                secret.to_vec(),
+                self.inner.shards[idx].leader.clone(),
+                rx_client,
+                RaftType::Shard(group),
+            );
+        }
+    }
+
+    /// Asks the given nodes for the amount of shards until the first one answers.
+    async fn fetch_shard_count(
+        client: &reqwest::Client,
+        nodes: &[String],
+        tls: bool,
+        api_secret: &str,
+    ) -> u16 {
+        let scheme = if tls { "https" } else { "http" };
+        loop {
+            for addr in nodes {
+                let url = format!("{}://{}/cluster/shards", scheme, addr);
+                match client
+                    .get(url)
+                    .header(HEADER_NAME_SECRET, api_secret)
+                    .send()
+                    .await
+                {
+                    Ok(res) if res.status().is_success() => match res.bytes().await {
+                        Ok(bytes) => match bincode::deserialize::<u16>(bytes.as_ref()) {
+                            Ok(count) => return count,
+                            Err(err) => error!("Error deserializing shard count: {}", err),
+                        },
+                        Err(err) => error!("Error reading shard count: {}", err),
+                    },
+                    // older nodes without sharding support
+                    Ok(res) if res.status() == reqwest::StatusCode::NOT_FOUND => return 0,
+                    Ok(res) => error!("Error looking up shard count: {}", res.status()),
+                    Err(err) => error!("Error looking up shard count: {}", err),
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
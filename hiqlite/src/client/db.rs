@@ -0,0 +1,473 @@
+use crate::app_state::AppState;
+use crate::client::migrate::pending_migrations;
+use crate::client::stream::{
+    CancelOnDrop, ClientDbQueryPayload, ClientDbWritePayload, ClientQueryPayload, ClientStreamReq,
+};
+use crate::migration::Migrations;
+use crate::network::api::ApiStreamResponsePayload;
+use crate::query::rows::RowOwned;
+use crate::store::state_machine::sqlite::state_machine::{Indexed, Query, QueryWrite};
+use crate::{params, query, AppliedMigration, Client, Error, NodeId, Params, Response, Row};
+use rust_embed::RustEmbed;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tokio::sync::{oneshot, RwLock};
+
+impl Client {
+    /// Returns a handle to one of the additional named databases from `NodeConfig.databases`.
+    ///
+    /// All databases share the same Raft group, which means writes to all of them are strictly
+    /// ordered, but each one lives in its own SQLite file with its own read pool.
+    ///
+    /// ```rust, notest
+    /// let billing = client.db("billing");
+    /// billing
+    ///     .execute("INSERT INTO invoice (id) VALUES ($1)", params!("inv1"))
+    ///     .await?;
+    /// ```
+    ///
+    /// The name is not checked until the first request. Unknown databases will return an
+    /// `Error::BadRequest`.
+    pub fn db<S: Into<String>>(&self, name: S) -> ClientDb {
+        ClientDb::new(self.clone(), 0, Some(name.into()))
+    }
+
+    /// Returns a handle to the shard Raft group the given key maps to.
+    ///
+    /// Each shard is an independent Raft group with its own leader, logs and SQLite database,
+    /// which means writes to different shards are not ordered against each other and can be
+    /// applied in parallel. Keys are mapped with a stable hash: `1 + hash(key) % shards`.
+    ///
+    /// ```rust, notest
+    /// let tenant = client.shard("tenant_a");
+    /// tenant
+    ///     .execute("INSERT INTO event (id) VALUES ($1)", params!("ev1"))
+    ///     .await?;
+    /// ```
+    ///
+    /// If `NodeConfig.shards` is `0`, the handle points to the main database. Changing the
+    /// amount of shards later on does not move any existing data.
+    pub fn shard<K: AsRef<[u8]>>(&self, key: K) -> ClientDb {
+        let shards = self.inner.shards.len() as u64;
+        let group = if shards == 0 {
+            0
+        } else {
+            1 + (fnv1a(key.as_ref()) % shards) as u16
+        };
+
+        ClientDb::new(self.clone(), group, None)
+    }
+}
+
+/// 64-bit FNV-1a, which is stable across versions and platforms, unlike the std `Hasher`s.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A handle to a named database or a shard, which you get from `Client::db()` or
+/// `Client::shard()`.
+///
+/// It is as cheap to clone as the `Client` itself.
+#[derive(Clone)]
+pub struct ClientDb {
+    client: Client,
+    group: u16,
+    name: Option<String>,
+}
+
+impl ClientDb {
+    pub(crate) fn new(client: Client, group: u16, name: Option<String>) -> Self {
+        Self {
+            client,
+            group,
+            name,
+        }
+    }
+
+    /// The name of the database this handle points to, or `None` for the main database of
+    /// the Raft group.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The Raft group this handle points to. `0` is the main one, shards start at `1`.
+    pub fn group(&self) -> u16 {
+        self.group
+    }
+
+    /// Works in the same way as `Client::wait_applied()`, but for the Raft group of this
+    /// database.
+    pub async fn wait_applied(&self, log_index: u64) -> Result<(), Error> {
+        let Some(state) = &self.client.inner.state else {
+            return Ok(());
+        };
+        let raft_db = state
+            .raft_db_group(self.group)
+            .expect("local shards to always match the client shards");
+        super::query::wait_applied(raft_db, log_index, None).await
+    }
+
+    /// Works in the same way as `Client::execute()`, but on this database.
+    pub async fn execute<S>(&self, sql: S, params: Params) -> Result<usize, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.execute_indexed(sql, params).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `Client::execute_indexed()`, but on this database. The index
+    /// belongs to the Raft group of this database, which means you need to pass it into
+    /// `ClientDb::wait_applied()` for shards.
+    pub async fn execute_indexed<S>(&self, sql: S, params: Params) -> Result<Indexed<usize>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let query = Query {
+            sql: sql.into(),
+            params,
+        };
+        let res = self.write(QueryWrite::Execute(query)).await?;
+        match res.value {
+            Response::Execute(value) => {
+                value.result.map(|value| Indexed::new(value, res.log_index))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Works in the same way as `Client::txn()`, but on this database.
+    pub async fn txn<C, Q>(&self, sql: Q) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params)>,
+        C: Into<Cow<'static, str>>,
+    {
+        self.txn_indexed(sql).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `Client::txn_indexed()`, but on this database.
+    pub async fn txn_indexed<C, Q>(
+        &self,
+        sql: Q,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params)>,
+        C: Into<Cow<'static, str>>,
+    {
+        let queries = sql
+            .into_iter()
+            .map(|(q, params)| Query {
+                sql: q.into(),
+                params,
+            })
+            .collect();
+        let res = self.write(QueryWrite::Transaction(queries)).await?;
+        match res.value {
+            Response::Transaction(value) => value.map(|value| Indexed::new(value, res.log_index)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Works in the same way as `Client::batch()`, but on this database.
+    ///
+    /// **CAUTION:**
+    /// The queries executed with this `.batch()` are **NOT PREPARED**!
+    /// This means you **must validate and sanitize** the input manually.
+    pub async fn batch<S>(&self, sql: S) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.batch_indexed(sql).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `Client::batch_indexed()`, but on this database.
+    pub async fn batch_indexed<S>(
+        &self,
+        sql: S,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let res = self.write(QueryWrite::Batch(sql.into())).await?;
+        match res.value {
+            Response::Batch(value) => value.result.map(|value| Indexed::new(value, res.log_index)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Works in the same way as `Client::migrate()`, but on this database. Each database keeps
+    /// track of its own applied migrations.
+    #[cold]
+    pub async fn migrate<T: RustEmbed>(&self) -> Result<(), Error> {
+        self.migrate_indexed::<T>().await.map(|_| ())
+    }
+
+    /// Works in the same way as `Client::migrate_indexed()`, but on this database.
+    #[cold]
+    pub async fn migrate_indexed<T: RustEmbed>(&self) -> Result<Option<u64>, Error> {
+        let applied: Vec<AppliedMigration> = self
+            .query_map("SELECT * FROM _migrations ORDER BY id ASC", params!())
+            .await
+            .unwrap_or_default();
+        let migrations = pending_migrations(&applied, Migrations::build::<T>());
+        if migrations.is_empty() {
+            return Ok(None);
+        }
+
+        let res = self.write(QueryWrite::Migration(migrations)).await?;
+        match res.value {
+            Response::Migrate(value) => value.map(|_| Some(res.log_index)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Works in the same way as `Client::query_map()`, but on this database.
+    pub async fn query_map<T, S>(&self, stmt: S, params: Params) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        Ok(self
+            .query_raw(stmt, params)
+            .await?
+            .into_iter()
+            .map(T::from)
+            .collect())
+    }
+
+    /// Works in the same way as `Client::query_map_one()`, but on this database.
+    pub async fn query_map_one<T, S>(&self, stmt: S, params: Params) -> Result<T, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        let mut rows = self.query_raw(stmt, params).await?;
+        if rows.is_empty() {
+            Err(Error::QueryReturnedNoRows("No rows returned".into()))
+        } else if rows.len() > 1 {
+            Err(Error::Sqlite(
+                format!("cannot map {} rows into one", rows.len()).into(),
+            ))
+        } else {
+            Ok(T::from(rows.swap_remove(0)))
+        }
+    }
+
+    /// Works in the same way as `Client::query_map_optional()`, but on this database.
+    pub async fn query_map_optional<T, S>(
+        &self,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        let mut rows = self.query_raw(stmt, params).await?;
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(T::from(rows.swap_remove(0))))
+        }
+    }
+
+    /// Works in the same way as `Client::query_raw()`, but on this database.
+    pub async fn query_raw<S>(&self, stmt: S, params: Params) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        if let Some(state) = &self.client.inner.state {
+            let raft_db = state
+                .raft_db_group(self.group)
+                .expect("local shards to always match the client shards");
+            let rows = query::query_owned_local(
+                raft_db.log_statements,
+                raft_db.read_pool_for(self.name.as_deref())?.clone(),
+                stmt,
+                params,
+                raft_db.query_timeout,
+            )
+            .await?;
+            Ok(rows.into_iter().map(Row::Owned).collect())
+        } else {
+            self.query_remote(stmt, params, false).await
+        }
+    }
+
+    /// Works in the same way as `Client::query_consistent_map()`, but on this database.
+    pub async fn query_consistent_map<T, S>(&self, stmt: S, params: Params) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        Ok(self
+            .query_remote(stmt, params, true)
+            .await?
+            .into_iter()
+            .map(T::from)
+            .collect())
+    }
+
+    async fn query_remote<S>(
+        &self,
+        stmt: S,
+        params: Params,
+        consistent: bool,
+    ) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let query = Query {
+            sql: stmt.into(),
+            params,
+        };
+
+        let res = match self.query_remote_req(query.clone(), consistent).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                let (leader, tx) = self.conn();
+                if self.client.was_leader_update_error(&err, leader, tx).await {
+                    self.query_remote_req(query, consistent).await
+                } else {
+                    return Err(err);
+                }
+            }
+        }?
+        .into_iter()
+        .map(crate::Row::Owned)
+        .collect();
+
+        Ok(res)
+    }
+
+    pub(crate) async fn query_remote_req(
+        &self,
+        query: Query,
+        consistent: bool,
+    ) -> Result<Vec<RowOwned>, Error> {
+        let (ack, rx) = oneshot::channel();
+        let request_id = self.client.new_request_id();
+        let req = if let Some(db) = &self.name {
+            let payload = ClientDbQueryPayload {
+                request_id,
+                db: db.clone(),
+                query,
+                ack,
+            };
+            if consistent {
+                ClientStreamReq::DbQueryConsistent(payload)
+            } else {
+                ClientStreamReq::DbQuery(payload)
+            }
+        } else {
+            let payload = ClientQueryPayload {
+                request_id,
+                query,
+                timeout: None,
+                ack,
+            };
+            if consistent {
+                ClientStreamReq::QueryConsistent(payload)
+            } else {
+                ClientStreamReq::Query(payload)
+            }
+        };
+
+        let tx = self.conn().1;
+        tx.send_async(req)
+            .await
+            .expect("Client Stream Manager to always be running");
+        let cancel = CancelOnDrop::new(tx.clone(), request_id);
+        let res = rx
+            .await
+            .expect("To always receive an answer from Client Stream Manager");
+        cancel.done();
+        match res? {
+            ApiStreamResponsePayload::Query(res) => {
+                assert!(!consistent);
+                res.map(|set| set.into_rows())
+            }
+            ApiStreamResponsePayload::QueryConsistent(res) => {
+                assert!(consistent);
+                res.map(|set| set.into_rows())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) async fn write(&self, query: QueryWrite) -> Result<Indexed<Response>, Error> {
+        match self.write_req(query.clone()).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                let (leader, tx) = self.conn();
+                if self.client.was_leader_update_error(&err, leader, tx).await {
+                    self.write_req(query).await
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    async fn write_req(&self, query: QueryWrite) -> Result<Indexed<Response>, Error> {
+        let (leader, tx) = self.conn();
+
+        if let Some(state) = self.local_leader_state(leader).await {
+            let res = state
+                .raft_db_group(self.group)
+                .expect("local shards to always match the client shards")
+                .client_write_db(self.name.clone(), query)
+                .await?;
+            Ok(Indexed::new(res.data, res.log_id.index))
+        } else {
+            let (ack, rx) = oneshot::channel();
+            tx.send_async(ClientStreamReq::DbWrite(ClientDbWritePayload {
+                request_id: self.client.new_request_id(),
+                db: self.name.clone(),
+                query,
+                ack,
+            }))
+            .await
+            .expect("Client Stream Manager to always be running");
+            let res = rx
+                .await
+                .expect("To always receive an answer from Client Stream Manager")?;
+            match res {
+                ApiStreamResponsePayload::DbWrite(res) => res,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// The leader lock and client stream of this handle's Raft group.
+    fn conn(
+        &self,
+    ) -> (
+        &Arc<RwLock<(NodeId, String)>>,
+        &flume::Sender<ClientStreamReq>,
+    ) {
+        let inner = &self.client.inner;
+        if self.group == 0 {
+            (&inner.leader_db, &inner.tx_client_db)
+        } else {
+            let shard = &inner.shards[self.group as usize - 1];
+            (&shard.leader, &shard.tx_client)
+        }
+    }
+
+    async fn local_leader_state(
+        &self,
+        leader: &Arc<RwLock<(NodeId, String)>>,
+    ) -> Option<&Arc<AppState>> {
+        if let Some(state) = &self.client.inner.state {
+            if state.id == leader.read().await.0 {
+                return Some(state);
+            }
+        }
+        None
+    }
+}
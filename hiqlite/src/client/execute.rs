@@ -1,7 +1,7 @@
 use crate::client::stream::{ClientExecutePayload, ClientStreamReq};
 use crate::network::api::ApiStreamResponsePayload;
 use crate::query::rows::RowOwned;
-use crate::store::state_machine::sqlite::state_machine::{Query, QueryWrite};
+use crate::store::state_machine::sqlite::state_machine::{Indexed, Query, QueryWrite};
 use crate::{Client, Error, Params, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
@@ -19,6 +19,32 @@ impl Client {
     ///     .await?;
     /// ```
     pub async fn execute<S>(&self, sql: S, params: Params) -> Result<usize, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.execute_indexed(sql, params).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `execute()`, but additionally returns the Raft log index this
+    /// query has been applied at.
+    ///
+    /// You can pass this index into the `query_*_after()` functions to read your own writes
+    /// from any node.
+    ///
+    /// ```rust, notest
+    /// let res = client
+    ///     .execute_indexed(
+    ///         "INSERT INTO test (id, num, description) VALUES ($1, $2, $3)",
+    ///         params!("id1", 123, "my description"),
+    ///     )
+    ///     .await?;
+    /// assert_eq!(res.value, 1);
+    ///
+    /// let row: Entity = client
+    ///     .query_as_one_after(res.log_index, "SELECT * FROM test WHERE id = $1", params!("id1"))
+    ///     .await?;
+    /// ```
+    pub async fn execute_indexed<S>(&self, sql: S, params: Params) -> Result<Indexed<usize>, Error>
     where
         S: Into<Cow<'static, str>>,
     {
@@ -43,18 +69,9 @@ impl Client {
     }
 
     #[inline(always)]
-    async fn execute_req(&self, sql: Query) -> Result<usize, Error> {
+    async fn execute_req(&self, sql: Query) -> Result<Indexed<usize>, Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
-            let res = state
-                .raft_db
-                .raft
-                .client_write(QueryWrite::Execute(sql))
-                .await?;
-            let resp: Response = res.data;
-            match resp {
-                Response::Execute(res) => res.result,
-                _ => unreachable!(),
-            }
+            state.raft_db.execute(sql).await
         } else {
             let (ack, rx) = oneshot::channel();
             self.inner
@@ -127,6 +144,21 @@ impl Client {
         sql: S,
         params: Params,
     ) -> Result<Vec<Result<crate::Row, Error>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.execute_returning_indexed(sql, params)
+            .await
+            .map(|res| res.value)
+    }
+
+    /// Works in the same way as `execute_returning()`, but additionally returns the Raft log
+    /// index this query has been applied at. See `execute_indexed()`.
+    pub async fn execute_returning_indexed<S>(
+        &self,
+        sql: S,
+        params: Params,
+    ) -> Result<Indexed<Vec<Result<crate::Row, Error>>>, Error>
     where
         S: Into<Cow<'static, str>>,
     {
@@ -135,7 +167,7 @@ impl Client {
             params,
         };
 
-        let rows = match self.execute_returning_req(sql.clone()).await {
+        let res = match self.execute_returning_req(sql.clone()).await {
             Ok(res) => res,
             Err(err) => {
                 if self
@@ -149,11 +181,11 @@ impl Client {
             }
         };
 
-        let mut res: Vec<Result<crate::Row, Error>> = Vec::with_capacity(rows.len());
-        for row in rows {
-            res.push(row.map(crate::Row::Owned))
-        }
-        Ok(res)
+        Ok(res.map(|rows| {
+            rows.into_iter()
+                .map(|row| row.map(crate::Row::Owned))
+                .collect()
+        }))
     }
 
     /// Execute a query on the database that includes a `RETURNING` statement.
@@ -183,16 +215,18 @@ impl Client {
     pub(crate) async fn execute_returning_req(
         &self,
         sql: Query,
-    ) -> Result<Vec<Result<RowOwned, Error>>, Error> {
+    ) -> Result<Indexed<Vec<Result<RowOwned, Error>>>, Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
                 .client_write(QueryWrite::ExecuteReturning(sql))
                 .await?;
+            let log_index = res.log_id.index;
             let resp: Response = res.data;
             match resp {
-                Response::ExecuteReturning(res) => res.result,
+                Response::ExecuteReturning(res) => {
+                    res.result.map(|rows| Indexed::new(rows, log_index))
+                }
                 _ => unreachable!(),
             }
         } else {
@@ -210,7 +244,9 @@ impl Client {
                 .await
                 .expect("To always receive an answer from Client Stream Manager")?;
             match res {
-                ApiStreamResponsePayload::ExecuteReturning(res) => res,
+                ApiStreamResponsePayload::ExecuteReturning(res) => {
+                    res.map(|res| res.map(|set| set.into_rows()))
+                }
                 _ => unreachable!(),
             }
         }
@@ -0,0 +1,165 @@
+use crate::client::stream::{ClientExecuteManyPayload, ClientStreamReq};
+use crate::network::api::ApiStreamResponsePayload;
+use crate::store::state_machine::sqlite::state_machine::{Indexed, QueryMany, QueryWrite};
+use crate::{Client, Error, Params, Response};
+use std::borrow::Cow;
+use tokio::sync::oneshot;
+
+/// The max estimated size of the params inside a single Raft entry for `execute_many()`.
+const EXECUTE_MANY_MAX_ENTRY_SIZE: usize = 2 * 1024 * 1024;
+
+impl Client {
+    /// Executes the same statement once for each given row of params. Returns the sum of all
+    /// affected rows on success.
+    ///
+    /// In contrast to a `txn()`, the SQL will only be sent once together with a compact matrix
+    /// of all params, and it will be applied with a single prepared statement. This makes it
+    /// the best choice for bulk inserts.
+    ///
+    /// Large inputs will be split into multiple Raft entries of up to ~2MB automatically. Each
+    /// of them will be applied in its own transaction. If any row fails, all rows of the same
+    /// entry will be rolled back and an error is returned, while rows from entries applied
+    /// earlier will stay. All rows must have the same amount of params, and at least one.
+    ///
+    /// ```rust, notest
+    /// let rows_affected = client
+    ///     .execute_many(
+    ///         "INSERT INTO test (id, num, description) VALUES ($1, $2, $3)",
+    ///         (0..100_000).map(|i| params!(i, i * 2, "bulk insert")),
+    ///     )
+    ///     .await?;
+    /// assert_eq!(rows_affected, 100_000);
+    /// ```
+    pub async fn execute_many<S, P>(&self, sql: S, params: P) -> Result<usize, Error>
+    where
+        S: Into<Cow<'static, str>>,
+        P: IntoIterator<Item = Params>,
+    {
+        self.execute_many_indexed(sql, params)
+            .await
+            .map(|res| res.value)
+    }
+
+    /// Works in the same way as `execute_many()`, but additionally returns the Raft log index
+    /// the last entry has been applied at. See `execute_indexed()`.
+    ///
+    /// If no params have been given at all, nothing will be written and the `log_index` is `0`.
+    pub async fn execute_many_indexed<S, P>(
+        &self,
+        sql: S,
+        params: P,
+    ) -> Result<Indexed<usize>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+        P: IntoIterator<Item = Params>,
+    {
+        let sql = sql.into();
+        let mut width = None;
+        let mut chunk = Vec::new();
+        let mut chunk_size = 0;
+        let mut rows_affected = 0;
+        let mut log_index = 0;
+
+        for row in params {
+            if row.is_empty() {
+                return Err(Error::QueryParams(
+                    "each row must have at least one param, use `execute()` for statements \
+                    without params"
+                        .into(),
+                ));
+            }
+
+            match width {
+                None => width = Some(row.len()),
+                Some(w) if w != row.len() => {
+                    return Err(Error::QueryParams(
+                        format!(
+                            "all rows must have the same amount of params: expected {}, got {}",
+                            w,
+                            row.len()
+                        )
+                        .into(),
+                    ));
+                }
+                Some(_) => {}
+            }
+
+            chunk_size += row.iter().map(|p| p.size_hint()).sum::<usize>();
+            chunk.extend(row);
+
+            if chunk_size >= EXECUTE_MANY_MAX_ENTRY_SIZE {
+                let query = QueryMany {
+                    sql: sql.clone(),
+                    width: width.unwrap_or_default(),
+                    params: std::mem::take(&mut chunk),
+                };
+                let res = self.execute_many_with_retry(query).await?;
+                rows_affected += res.value;
+                log_index = res.log_index;
+                chunk_size = 0;
+            }
+        }
+
+        if !chunk.is_empty() {
+            let query = QueryMany {
+                sql,
+                width: width.unwrap_or_default(),
+                params: chunk,
+            };
+            let res = self.execute_many_with_retry(query).await?;
+            rows_affected += res.value;
+            log_index = res.log_index;
+        }
+
+        Ok(Indexed::new(rows_affected, log_index))
+    }
+
+    async fn execute_many_with_retry(&self, query: QueryMany) -> Result<Indexed<usize>, Error> {
+        match self.execute_many_req(query.clone()).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                if self
+                    .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
+                    .await
+                {
+                    self.execute_many_req(query).await
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn execute_many_req(&self, query: QueryMany) -> Result<Indexed<usize>, Error> {
+        if let Some(state) = self.is_leader_db_with_state().await {
+            let res = state
+                .raft_db
+                .client_write(QueryWrite::ExecuteMany(query))
+                .await?;
+            let log_index = res.log_id.index;
+            let resp: Response = res.data;
+            match resp {
+                Response::Execute(res) => res.result.map(|res| Indexed::new(res, log_index)),
+                _ => unreachable!(),
+            }
+        } else {
+            let (ack, rx) = oneshot::channel();
+            self.inner
+                .tx_client_db
+                .send_async(ClientStreamReq::ExecuteMany(ClientExecuteManyPayload {
+                    request_id: self.new_request_id(),
+                    query,
+                    ack,
+                }))
+                .await
+                .expect("Client Stream Manager to always be running");
+            let res = rx
+                .await
+                .expect("To always receive an answer from Client Stream Manager")?;
+            match res {
+                ApiStreamResponsePayload::Execute(res) => res,
+                _ => unreachable!(),
+            }
+        }
+    }
+}
@@ -93,6 +93,56 @@ impl Client {
         }
     }
 
+    /// Finds and sets the current leader for the shard Raft group with the given id.
+    #[cfg(feature = "sqlite")]
+    pub(crate) async fn find_set_active_leader_shard(&self, group: u16) {
+        let leader = &self.inner.shards[group as usize - 1].leader;
+
+        if let Some(state) = &self.inner.state {
+            let raft_db = state
+                .raft_db_group(group)
+                .expect("local shards to always match the client shards");
+            let metrics = raft_db.raft.metrics().borrow().clone();
+            let mut find_leader = Self::find_set_leader(metrics, leader).await;
+
+            while let Err(err) = find_leader {
+                warn!("Find shard {} leader error: {}", group, err);
+                time::sleep(Duration::from_millis(250)).await;
+                let metrics = raft_db.raft.metrics().borrow().clone();
+                find_leader = Self::find_set_leader(metrics, leader).await;
+            }
+        } else {
+            loop {
+                let metrics = self.remote_metrics_loop_shard(group).await;
+                if Self::find_set_leader(metrics, leader).await.is_ok() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    async fn remote_metrics_loop_shard(&self, group: u16) -> RaftMetrics<NodeId, Node> {
+        loop {
+            for addr in &self.inner.nodes {
+                {
+                    let mut lock = self.inner.shards[group as usize - 1].leader.write().await;
+                    *lock = (lock.0, addr.clone());
+                }
+
+                match self.metrics_shard(group).await {
+                    Ok(metrics) => {
+                        return metrics;
+                    }
+                    Err(err) => {
+                        error!("Error looking up shard {} metrics: {}", group, err);
+                    }
+                }
+            }
+            time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
     #[cfg(feature = "cache")]
     async fn remote_metrics_loop_cache(&self) -> RaftMetrics<NodeId, Node> {
         loop {
@@ -9,7 +9,10 @@ use tokio::time;
 use tracing::{debug, info};
 
 #[cfg(feature = "sqlite")]
-use crate::store::{logs::rocksdb::ActionWrite, state_machine::sqlite::writer::WriterRequest};
+use crate::{
+    app_state::StateRaftDB,
+    store::{logs::rocksdb::ActionWrite, state_machine::sqlite::writer::WriterRequest},
+};
 #[cfg(any(feature = "sqlite", feature = "cache"))]
 use crate::{Node, NodeId};
 #[cfg(any(feature = "sqlite", feature = "cache"))]
@@ -33,6 +36,99 @@ impl Client {
         }
     }
 
+    /// Get the write cost guard statistics of this node. See `NodeConfig::write_cost_limit`.
+    ///
+    /// **Note:**
+    /// This works for local clients only.
+    #[cfg(feature = "sqlite")]
+    pub fn metrics_write_cost(&self) -> Result<crate::WriteCostMetrics, Error> {
+        if let Some(state) = &self.inner.state {
+            Ok(state.raft_db.write_cost.metrics())
+        } else {
+            Err(Error::Config(
+                "`metrics_write_cost()` only works for local clients".into(),
+            ))
+        }
+    }
+
+    /// Get the statistics of the read pool for the main database of this node, like the current
+    /// amount of readers and how long queries had to wait for a free one.
+    ///
+    /// **Note:**
+    /// This works for local clients only.
+    #[cfg(feature = "sqlite")]
+    pub fn metrics_read_pool(&self) -> Result<crate::ReadPoolMetrics, Error> {
+        if let Some(state) = &self.inner.state {
+            Ok(state.raft_db.read_pool.metrics())
+        } else {
+            Err(Error::Config(
+                "`metrics_read_pool()` only works for local clients".into(),
+            ))
+        }
+    }
+
+    /// Get the results of the cross-replica divergence checks for the main Raft group.
+    ///
+    /// **Note:**
+    /// This works for local clients only.
+    #[cfg(feature = "sqlite")]
+    pub fn metrics_divergence(&self) -> Result<crate::DivergenceMetrics, Error> {
+        if let Some(state) = &self.inner.state {
+            Ok(state.raft_db.checksums.metrics())
+        } else {
+            Err(Error::Config(
+                "`metrics_divergence()` only works for local clients".into(),
+            ))
+        }
+    }
+
+    /// Returns an `Error::Divergence` if the last divergence check found any node with data
+    /// different from the majority, and the check results otherwise.
+    ///
+    /// **Note:**
+    /// This works for local clients only.
+    #[cfg(feature = "sqlite")]
+    pub fn check_divergence(&self) -> Result<crate::DivergenceMetrics, Error> {
+        let metrics = self.metrics_divergence()?;
+        if metrics.divergent_nodes.is_empty() {
+            Ok(metrics)
+        } else {
+            Err(Error::Divergence(
+                format!(
+                    "nodes {:?} diverged at log index {} in tables {:?}",
+                    metrics.divergent_nodes,
+                    metrics.last_log_index.unwrap_or_default(),
+                    metrics.divergent_tables
+                )
+                .into(),
+            ))
+        }
+    }
+
+    /// Get cluster metrics for the shard Raft group with the given id `1..=NodeConfig.shards`.
+    #[cfg(feature = "sqlite")]
+    pub async fn metrics_shard(&self, group: u16) -> Result<RaftMetrics<NodeId, Node>, Error> {
+        if group == 0 || group as usize > self.inner.shards.len() {
+            return Err(Error::BadRequest(
+                format!("shard {} does not exist", group).into(),
+            ));
+        }
+
+        if let Some(state) = &self.inner.state {
+            let raft_db = state.raft_db_group(group).unwrap();
+            let metrics = raft_db.raft.metrics().borrow().clone();
+            Ok(metrics)
+        } else {
+            let url = self
+                .build_addr(
+                    &format!("/cluster/metrics/shard_{}", group),
+                    &self.inner.shards[group as usize - 1].leader,
+                )
+                .await;
+            self.get_metrics_remote(url).await
+        }
+    }
+
     /// Get cluster metrics for the cache Raft.
     #[cfg(feature = "cache")]
     pub async fn metrics_cache(&self) -> Result<RaftMetrics<NodeId, Node>, Error> {
@@ -177,6 +273,13 @@ impl Client {
                 &self.inner.tx_client_cache,
                 #[cfg(feature = "sqlite")]
                 &self.inner.tx_client_db,
+                #[cfg(feature = "sqlite")]
+                &self
+                    .inner
+                    .shards
+                    .iter()
+                    .map(|s| s.tx_client.clone())
+                    .collect::<Vec<_>>(),
                 &self.inner.tx_shutdown,
             )
             .await
@@ -193,6 +296,7 @@ impl Client {
         state: &Arc<AppState>,
         #[cfg(feature = "cache")] tx_client_cache: &flume::Sender<ClientStreamReq>,
         #[cfg(feature = "sqlite")] tx_client_db: &flume::Sender<ClientStreamReq>,
+        #[cfg(feature = "sqlite")] tx_client_shards: &[flume::Sender<ClientStreamReq>],
         tx_shutdown: &Option<watch::Sender<bool>>,
     ) -> Result<(), Error> {
         #[allow(unused_mut)]
@@ -214,42 +318,23 @@ impl Client {
             let node_count = metrics.membership_config.nodes().count();
             is_single_instance = node_count == 1;
 
-            info!("Shutting down raft sqlite layer");
-            match state.raft_db.raft.shutdown().await {
-                Ok(_) => {
-                    let (tx, rx) = tokio::sync::oneshot::channel();
-
-                    info!("Shutting down sqlite writer");
-                    let _ = state
-                        .raft_db
-                        .sql_writer
-                        .send_async(WriterRequest::Shutdown(tx))
-                        .await;
-
-                    info!("Shutting down sqlite logs writer");
-                    if state
-                        .raft_db
-                        .logs_writer
-                        .send_async(ActionWrite::Shutdown)
-                        .await
-                        .is_ok()
-                    {
-                        // this sometimes fails because of race conditions and internal drop handlers
-                        // it just depends on which task is faster, but in any case the writer
-                        // does a wal flush before exiting
-                        rx.await.expect("To always get an answer from SQL writer");
-                    }
-                }
-                Err(err) => {
-                    return Err(Error::Error(err.to_string().into()));
-                }
+            for (idx, raft_db) in state.raft_db_shards.iter().enumerate() {
+                info!("Shutting down raft shard {}", idx + 1);
+                Self::shutdown_raft_db(raft_db).await?;
             }
+
+            info!("Shutting down raft sqlite layer");
+            Self::shutdown_raft_db(&state.raft_db).await?;
         }
 
         #[cfg(feature = "cache")]
         let _ = tx_client_cache.send_async(ClientStreamReq::Shutdown).await;
         #[cfg(feature = "sqlite")]
         let _ = tx_client_db.send_async(ClientStreamReq::Shutdown).await;
+        #[cfg(feature = "sqlite")]
+        for tx in tx_client_shards {
+            let _ = tx.send_async(ClientStreamReq::Shutdown).await;
+        }
 
         if let Some(tx) = tx_shutdown {
             let _ = tx.send(true);
@@ -274,4 +359,34 @@ impl Client {
         info!("Shutdown complete");
         Ok(())
     }
+
+    #[cfg(feature = "sqlite")]
+    async fn shutdown_raft_db(raft_db: &StateRaftDB) -> Result<(), Error> {
+        match raft_db.raft.shutdown().await {
+            Ok(_) => {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+
+                info!("Shutting down sqlite writer");
+                let _ = raft_db
+                    .sql_writer
+                    .send_async(WriterRequest::Shutdown(tx))
+                    .await;
+
+                info!("Shutting down sqlite logs writer");
+                if raft_db
+                    .logs_writer
+                    .send_async(ActionWrite::Shutdown)
+                    .await
+                    .is_ok()
+                {
+                    // this sometimes fails because of race conditions and internal drop handlers
+                    // it just depends on which task is faster, but in any case the writer
+                    // does a wal flush before exiting
+                    rx.await.expect("To always get an answer from SQL writer");
+                }
+                Ok(())
+            }
+            Err(err) => Err(Error::Error(err.to_string().into())),
+        }
+    }
 }
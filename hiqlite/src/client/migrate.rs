@@ -1,7 +1,7 @@
 use crate::client::stream::{ClientMigratePayload, ClientStreamReq};
 use crate::migration::{Migration, Migrations};
 use crate::network::api::ApiStreamResponsePayload;
-use crate::store::state_machine::sqlite::state_machine::QueryWrite;
+use crate::store::state_machine::sqlite::state_machine::{Indexed, QueryWrite};
 use crate::{params, AppliedMigration, Client, Error, Response};
 use rust_embed::RustEmbed;
 use tokio::sync::oneshot;
@@ -24,52 +24,27 @@ impl Client {
     /// [sqlite-only](https://github.com/sebadob/hiqlite/tree/main/examples/sqlite-only) example.
     #[cold]
     pub async fn migrate<T: RustEmbed>(&self) -> Result<(), Error> {
+        self.migrate_indexed::<T>().await.map(|_| ())
+    }
+
+    /// Works in the same way as `migrate()`, but additionally returns the Raft log index the
+    /// migrations have been applied at. See `execute_indexed()`.
+    ///
+    /// Returns `None` if there was nothing to migrate, because all migrations have been applied
+    /// on this node already.
+    #[cold]
+    pub async fn migrate_indexed<T: RustEmbed>(&self) -> Result<Option<u64>, Error> {
         let applied: Vec<AppliedMigration> = self
             .query_map("SELECT * FROM _migrations ORDER BY id ASC", params!())
             .await
             .unwrap_or_default();
-        let mut migrations = Migrations::build::<T>();
-
-        // At least the beginning of the just built and already applied migrations must match.
-        // We can skip already existing ones early, so they are not sent through the Raft each
-        // time when a client restarts.
-        for (i, migration) in applied.iter().enumerate() {
-            match migrations.get(i) {
-                None => {
-                    warn!(
-                        "Found already applied migration {}_{} / {} which does not exist in given \
-                        migrations. Nothing to do.",
-                        migration.id, migration.name, migration.hash
-                    );
-                    return Ok(());
-                }
-                Some(to_migrate) => {
-                    if to_migrate.id != migration.id {
-                        panic!(
-                            "ID mismatch for '{}' between given and already applied migration: {} != {}",
-                            to_migrate.name, to_migrate.id, migration.id
-                        );
-                    }
-
-                    if to_migrate.hash != migration.hash {
-                        panic!(
-                            "HASH mismatch for '{}' between given and already applied migration: {} != {}",
-                            to_migrate.name, to_migrate.hash, migration.hash
-                        );
-                    }
-                }
-            }
-        }
-        if let Some(last_applied) = applied.last() {
-            migrations.retain(|m| m.id > last_applied.id);
-        }
+        let migrations = pending_migrations(&applied, Migrations::build::<T>());
         if migrations.is_empty() {
-            info!("All migrations have been applied already - nothing to migrate");
-            return Ok(());
+            return Ok(None);
         }
 
-        match self.migrate_execute(migrations).await {
-            Ok(_) => Ok(()),
+        let res = match self.migrate_execute(migrations).await {
+            Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
@@ -80,20 +55,24 @@ impl Client {
                     Err(err)
                 }
             }
-        }
+        }?;
+        Ok(Some(res.log_index))
     }
 
     #[cold]
-    pub(crate) async fn migrate_execute(&self, migrations: Vec<Migration>) -> Result<(), Error> {
+    pub(crate) async fn migrate_execute(
+        &self,
+        migrations: Vec<Migration>,
+    ) -> Result<Indexed<()>, Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
                 .client_write(QueryWrite::Migration(migrations))
                 .await?;
+            let log_index = res.log_id.index;
             let resp: Response = res.data;
             match resp {
-                Response::Migrate(res) => res,
+                Response::Migrate(res) => res.map(|res| Indexed::new(res, log_index)),
                 _ => unreachable!(),
             }
         } else {
@@ -117,3 +96,49 @@ impl Client {
         }
     }
 }
+
+/// Validates the already applied migrations against the given ones and strips them out.
+///
+/// At least the beginning of the just built and already applied migrations must match.
+/// We can skip already existing ones early, so they are not sent through the Raft each
+/// time when a client restarts.
+#[cold]
+pub(crate) fn pending_migrations(
+    applied: &[AppliedMigration],
+    mut migrations: Vec<Migration>,
+) -> Vec<Migration> {
+    for (i, migration) in applied.iter().enumerate() {
+        match migrations.get(i) {
+            None => {
+                warn!(
+                    "Found already applied migration {}_{} / {} which does not exist in given \
+                    migrations. Nothing to do.",
+                    migration.id, migration.name, migration.hash
+                );
+                return Vec::default();
+            }
+            Some(to_migrate) => {
+                if to_migrate.id != migration.id {
+                    panic!(
+                        "ID mismatch for '{}' between given and already applied migration: {} != {}",
+                        to_migrate.name, to_migrate.id, migration.id
+                    );
+                }
+
+                if to_migrate.hash != migration.hash {
+                    panic!(
+                        "HASH mismatch for '{}' between given and already applied migration: {} != {}",
+                        to_migrate.name, to_migrate.hash, migration.hash
+                    );
+                }
+            }
+        }
+    }
+    if let Some(last_applied) = applied.last() {
+        migrations.retain(|m| m.id > last_applied.id);
+    }
+    if migrations.is_empty() {
+        info!("All migrations have been applied already - nothing to migrate");
+    }
+    migrations
+}
@@ -12,10 +12,14 @@ mod batch;
 #[cfg(feature = "cache")]
 mod cache;
 mod create;
+#[cfg(feature = "sqlite")]
+pub(crate) mod db;
 #[cfg(feature = "dlock")]
 pub mod dlock;
 #[cfg(feature = "sqlite")]
 mod execute;
+#[cfg(feature = "sqlite")]
+mod execute_many;
 mod helpers;
 #[cfg(feature = "listen_notify_local")]
 mod listen_notify;
@@ -24,6 +28,8 @@ mod mgmt;
 mod migrate;
 #[cfg(feature = "sqlite")]
 mod query;
+#[cfg(feature = "sqlite")]
+pub(crate) mod query_stream;
 #[cfg(feature = "shutdown-handle")]
 mod shutdown_handle;
 pub mod stream;
@@ -52,6 +58,9 @@ pub(crate) struct DbClient {
     pub(crate) tx_client_cache: flume::Sender<ClientStreamReq>,
     #[cfg(feature = "sqlite")]
     pub(crate) tx_client_db: flume::Sender<ClientStreamReq>,
+    /// Connections to the additional SQLite Raft groups. The group id is the index + 1.
+    #[cfg(feature = "sqlite")]
+    pub(crate) shards: Vec<ShardConn>,
     pub(crate) tls_config: Option<Arc<rustls::ClientConfig>>,
     pub(crate) api_secret: Option<String>,
     pub(crate) request_id: AtomicUsize,
@@ -61,3 +70,9 @@ pub(crate) struct DbClient {
     #[cfg(feature = "listen_notify_local")]
     pub(crate) rx_notify: Option<flume::Receiver<(i64, Vec<u8>)>>,
 }
+
+#[cfg(feature = "sqlite")]
+pub(crate) struct ShardConn {
+    pub(crate) leader: Arc<RwLock<(NodeId, String)>>,
+    pub(crate) tx_client: flume::Sender<ClientStreamReq>,
+}
@@ -1,10 +1,14 @@
-use crate::client::stream::{ClientQueryPayload, ClientStreamReq};
+use crate::app_state::StateRaftDB;
+use crate::client::stream::{CancelOnDrop, ClientQueryPayload, ClientStreamReq};
 use crate::network::api::ApiStreamResponsePayload;
 use crate::query::rows::RowOwned;
+use crate::query::staleness::{self, MaxLag};
 use crate::store::state_machine::sqlite::state_machine::Query;
 use crate::{query, Client, Error, Params, Row};
+use openraft::metrics::WaitError;
 use serde::de::DeserializeOwned;
 use std::borrow::Cow;
+use std::time::Duration;
 use tokio::sync::oneshot;
 
 impl Client {
@@ -26,7 +30,23 @@ impl Client {
     where
         S: Into<Cow<'static, str>>,
     {
-        self.query_remote::<S>(stmt, params, true).await
+        self.query_remote::<S>(stmt, params, true, None).await
+    }
+
+    /// Works in the same way as `query_consistent()`, but the query will be interrupted with an
+    /// `Error::Timeout` on the leader, if it runs longer than `timeout`. This overwrites the
+    /// default `query_timeout` from the `NodeConfig`.
+    pub async fn query_consistent_timeout<S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_remote::<S>(stmt, params, true, Some(timeout))
+            .await
     }
 
     /// Execute a consistent query. This query will run on the leader node only and pause Raft
@@ -45,7 +65,27 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         Ok(self
-            .query_remote(stmt, params, true)
+            .query_remote(stmt, params, true, None)
+            .await?
+            .into_iter()
+            .map(T::from)
+            .collect())
+    }
+
+    /// Works in the same way as `query_consistent_map()`, but with a timeout for this query.
+    /// See `query_consistent_timeout()`.
+    pub async fn query_consistent_map_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        Ok(self
+            .query_remote(stmt, params, true, Some(timeout))
             .await?
             .into_iter()
             .map(T::from)
@@ -85,15 +125,50 @@ impl Client {
     ///     .await?;
     /// ```
     pub async fn query_map<T, S>(&self, stmt: S, params: Params) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_map()`, but the query will be interrupted with an
+    /// `Error::Timeout`, if it runs longer than `timeout`. This overwrites the default
+    /// `query_timeout` from the `NodeConfig`.
+    ///
+    /// ```rust, notest
+    /// let res: Vec<MyStruct> = client
+    ///     .query_map_timeout(Duration::from_secs(1), "SELECT * FROM test", params!())
+    ///     .await?;
+    /// ```
+    pub async fn query_map_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_with(Some(timeout), stmt, params).await
+    }
+
+    async fn query_map_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
     where
         T: for<'r> From<crate::Row<'r>> + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map(state, stmt, params).await
+            query::query_map(state, stmt, params, timeout).await
         } else {
             Ok(self
-                .query_remote(stmt, params, false)
+                .query_remote(stmt, params, false, timeout)
                 .await?
                 .into_iter()
                 .map(T::from)
@@ -111,14 +186,42 @@ impl Client {
     ///     .await?;
     /// ```
     pub async fn query_map_one<T, S>(&self, stmt: S, params: Params) -> Result<T, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_one_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_map_one()`, but with a timeout for this query.
+    /// See `query_map_timeout()`.
+    pub async fn query_map_one_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_one_with(Some(timeout), stmt, params).await
+    }
+
+    async fn query_map_one_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
     where
         T: for<'r> From<crate::Row<'r>> + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map_one(state, stmt, params).await
+            query::query_map_one(state, stmt, params, timeout).await
         } else {
-            let mut rows = self.query_remote(stmt, params, false).await?;
+            let mut rows = self.query_remote(stmt, params, false, timeout).await?;
             if rows.is_empty() {
                 Err(Error::QueryReturnedNoRows("No rows returned".into()))
             } else if rows.len() > 1 {
@@ -138,14 +241,43 @@ impl Client {
         stmt: S,
         params: Params,
     ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_optional_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_map_optional()`, but with a timeout for this query.
+    /// See `query_map_timeout()`.
+    pub async fn query_map_optional_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_map_optional_with(Some(timeout), stmt, params)
+            .await
+    }
+
+    async fn query_map_optional_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
     where
         T: for<'r> From<crate::Row<'r>> + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map_optional(state, stmt, params).await
+            query::query_map_optional(state, stmt, params, timeout).await
         } else {
-            let mut rows = self.query_remote(stmt, params, false).await?;
+            let mut rows = self.query_remote(stmt, params, false, timeout).await?;
             if rows.is_empty() {
                 Ok(None)
             } else {
@@ -168,12 +300,41 @@ impl Client {
     /// **Note:**
     /// This works for local clients only, not for `hiqlite::Client::remote()` or `query_consistent`.
     pub async fn query_as<T, S>(&self, stmt: S, params: Params) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_as()`, but the query will be interrupted with an
+    /// `Error::Timeout`, if it runs longer than `timeout`. This overwrites the default
+    /// `query_timeout` from the `NodeConfig`.
+    pub async fn query_as_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_with(Some(timeout), stmt, params).await
+    }
+
+    async fn query_as_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
     where
         T: DeserializeOwned + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as(state, stmt, params).await
+            query::query_as(state, stmt, params, timeout).await
         } else {
             Err(Error::Config(
                 "`query_as()` only works for local clients, you need to use \
@@ -187,12 +348,40 @@ impl Client {
     ///
     /// Errors if no rows are returned and ignores additional results if more than one row returned.
     pub async fn query_as_one<T, S>(&self, stmt: S, params: Params) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_one_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_as_one()`, but with a timeout for this query.
+    /// See `query_as_timeout()`.
+    pub async fn query_as_one_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_one_with(Some(timeout), stmt, params).await
+    }
+
+    async fn query_as_one_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
     where
         T: DeserializeOwned + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as_one(state, stmt, params).await
+            query::query_as_one(state, stmt, params, timeout).await
         } else {
             Err(Error::Config(
                 "`query_as()` only works for local clients, you need to use \
@@ -206,12 +395,41 @@ impl Client {
     /// Unlike the `query_as_one()`, this does not throw an error if no Rows have been returned,
     /// but just returns `None`.
     pub async fn query_as_optional<T, S>(&self, stmt: S, params: Params) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_optional_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_as_optional()`, but with a timeout for this query.
+    /// See `query_as_timeout()`.
+    pub async fn query_as_optional_timeout<T, S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_as_optional_with(Some(timeout), stmt, params)
+            .await
+    }
+
+    async fn query_as_optional_with<T, S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
     where
         T: DeserializeOwned + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as_optional(state, stmt, params).await
+            query::query_as_optional(state, stmt, params, timeout).await
         } else {
             Err(Error::Config(
                 "`query_as_optional()` only works for local clients, you need to use \
@@ -225,6 +443,32 @@ impl Client {
     /// This can be useful if you just need to know if a query succeeds, or if you need to manually
     /// work with the result without being able to convert it into a type.
     pub async fn query_raw<S>(&self, stmt: S, params: Params) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_raw_with(None, stmt, params).await
+    }
+
+    /// Works in the same way as `query_raw()`, but with a timeout for this query.
+    /// See `query_map_timeout()`.
+    pub async fn query_raw_timeout<S>(
+        &self,
+        timeout: Duration,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_raw_with(Some(timeout), stmt, params).await
+    }
+
+    async fn query_raw_with<S>(
+        &self,
+        timeout: Option<Duration>,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row>, Error>
     where
         S: Into<Cow<'static, str>>,
     {
@@ -234,11 +478,12 @@ impl Client {
                 state.raft_db.read_pool.clone(),
                 stmt,
                 params,
+                timeout.or(state.raft_db.query_timeout),
             )
             .await?;
             Ok(rows.into_iter().map(Row::Owned).collect())
         } else {
-            self.query_remote(stmt, params, false).await
+            self.query_remote(stmt, params, false, timeout).await
         }
     }
 
@@ -262,6 +507,349 @@ impl Client {
         }
     }
 
+    /// Waits until this node has applied at least the given Raft log index to its local
+    /// database. You will get the index from the `*_indexed()` write functions like
+    /// `execute_indexed()`.
+    ///
+    /// Returns an `Error::Timeout` if the index has not been applied after
+    /// `NodeConfig.wait_applied_timeout`. Use `wait_applied_timeout()` to overwrite it.
+    /// For remote clients, this returns immediately, because their queries always run on the
+    /// leader, which has applied a write already before returning it.
+    pub async fn wait_applied(&self, log_index: u64) -> Result<(), Error> {
+        let Some(state) = &self.inner.state else {
+            return Ok(());
+        };
+        wait_applied(&state.raft_db, log_index, None).await
+    }
+
+    /// Works in the same way as `wait_applied()`, but with a custom timeout.
+    pub async fn wait_applied_timeout(
+        &self,
+        log_index: u64,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let Some(state) = &self.inner.state else {
+            return Ok(());
+        };
+        wait_applied(&state.raft_db, log_index, Some(timeout)).await
+    }
+
+    /// Works in the same way as `query_map()`, but makes sure that this node has applied at least
+    /// the given Raft log index before the query runs. This way, you will always read your own
+    /// writes, no matter which node the write was executed on.
+    ///
+    /// ```rust, notest
+    /// let res = client
+    ///     .execute_indexed("INSERT INTO test (id) VALUES ($1)", params!("id1"))
+    ///     .await?;
+    ///
+    /// let rows: Vec<MyStruct> = client
+    ///     .query_map_after(res.log_index, "SELECT * FROM test", params!())
+    ///     .await?;
+    /// ```
+    pub async fn query_map_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_map(stmt, params).await
+    }
+
+    /// Works in the same way as `query_map_one()`, but waits for the given log index to be
+    /// applied first. See `query_map_after()`.
+    pub async fn query_map_one_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_map_one(stmt, params).await
+    }
+
+    /// Works in the same way as `query_map_optional()`, but waits for the given log index to be
+    /// applied first. See `query_map_after()`.
+    pub async fn query_map_optional_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_map_optional(stmt, params).await
+    }
+
+    /// Works in the same way as `query_as()`, but waits for the given log index to be applied
+    /// first. See `query_map_after()`.
+    pub async fn query_as_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_as(stmt, params).await
+    }
+
+    /// Works in the same way as `query_as_one()`, but waits for the given log index to be applied
+    /// first. See `query_map_after()`.
+    pub async fn query_as_one_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_as_one(stmt, params).await
+    }
+
+    /// Works in the same way as `query_as_optional()`, but waits for the given log index to be
+    /// applied first. See `query_map_after()`.
+    pub async fn query_as_optional_after<T, S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_as_optional(stmt, params).await
+    }
+
+    /// Works in the same way as `query_raw()`, but waits for the given log index to be applied
+    /// first. See `query_map_after()`.
+    pub async fn query_raw_after<S>(
+        &self,
+        log_index: u64,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.wait_applied(log_index).await?;
+        self.query_raw(stmt, params).await
+    }
+
+    /// Works in the same way as `query_map()`, but with a bounded staleness of the local data.
+    ///
+    /// If this node is the leader with a quorum ack not older than `max_lag`, or it has applied
+    /// all logs the leader had committed at its last contact and this contact is not older than
+    /// `max_lag`, the query runs locally.
+    /// Otherwise, it will be sent to the leader. You can either pass a `Duration`, or a `u64` for
+    /// the max amount of log entries the local node may be behind.
+    ///
+    /// This is a middle ground between `query_map()` and `query_consistent_map()`, which does not
+    /// need a network round trip in most cases.
+    ///
+    /// ```rust, notest
+    /// let res: Vec<MyStruct> = client
+    ///     .query_map_max_lag(Duration::from_secs(1), "SELECT * FROM test", params!())
+    ///     .await?;
+    ///
+    /// // allow at most 10 log entries lag
+    /// let res: Vec<MyStruct> = client
+    ///     .query_map_max_lag(10, "SELECT * FROM test", params!())
+    ///     .await?;
+    /// ```
+    pub async fn query_map_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        if self.is_local_within(max_lag.into()) {
+            self.query_map(stmt, params).await
+        } else {
+            Ok(self
+                .query_remote(stmt, params, false, None)
+                .await?
+                .into_iter()
+                .map(T::from)
+                .collect())
+        }
+    }
+
+    /// Works in the same way as `query_map_one()`, but with a bounded staleness of the local data.
+    /// See `query_map_max_lag()`.
+    pub async fn query_map_one_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        let mut rows: Vec<T> = self.query_map_max_lag(max_lag, stmt, params).await?;
+        if rows.is_empty() {
+            Err(Error::QueryReturnedNoRows("No rows returned".into()))
+        } else if rows.len() > 1 {
+            Err(Error::Sqlite(
+                format!("cannot map {} rows into one", rows.len()).into(),
+            ))
+        } else {
+            Ok(rows.swap_remove(0))
+        }
+    }
+
+    /// Works in the same way as `query_map_optional()`, but with a bounded staleness of the local
+    /// data. See `query_map_max_lag()`.
+    pub async fn query_map_optional_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        let mut rows: Vec<T> = self.query_map_max_lag(max_lag, stmt, params).await?;
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(rows.swap_remove(0)))
+        }
+    }
+
+    /// Works in the same way as `query_as()`, but with a bounded staleness of the local data.
+    /// See `query_map_max_lag()`.
+    ///
+    /// If the local node is not fresh enough, the rows fetched from the leader will be
+    /// deserialized with `serde` as well.
+    pub async fn query_as_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        if self.inner.state.is_some() && self.is_local_within(max_lag.into()) {
+            self.query_as(stmt, params).await
+        } else {
+            self.query_remote(stmt, params, false, None)
+                .await?
+                .into_iter()
+                .map(|row| match row {
+                    Row::Owned(row) => row.deserialize_into(),
+                    Row::Borrowed(_) => unreachable!("remote rows are always owned"),
+                })
+                .collect()
+        }
+    }
+
+    /// Works in the same way as `query_as_one()`, but with a bounded staleness of the local data.
+    /// See `query_map_max_lag()`.
+    pub async fn query_as_one_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        let mut rows: Vec<T> = self.query_as_max_lag(max_lag, stmt, params).await?;
+        if rows.is_empty() {
+            Err(Error::QueryReturnedNoRows("No rows returned".into()))
+        } else if rows.len() > 1 {
+            Err(Error::Sqlite(
+                format!("cannot map {} rows into one", rows.len()).into(),
+            ))
+        } else {
+            Ok(rows.swap_remove(0))
+        }
+    }
+
+    /// Works in the same way as `query_as_optional()`, but with a bounded staleness of the local
+    /// data. See `query_map_max_lag()`.
+    pub async fn query_as_optional_max_lag<T, S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        let mut rows: Vec<T> = self.query_as_max_lag(max_lag, stmt, params).await?;
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(rows.swap_remove(0)))
+        }
+    }
+
+    /// Works in the same way as `query_raw()`, but with a bounded staleness of the local data.
+    /// See `query_map_max_lag()`.
+    pub async fn query_raw_max_lag<S, L>(
+        &self,
+        max_lag: L,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+        L: Into<MaxLag>,
+    {
+        if self.is_local_within(max_lag.into()) {
+            self.query_raw(stmt, params).await
+        } else {
+            self.query_remote(stmt, params, false, None).await
+        }
+    }
+
+    /// Remote clients always query the leader, which makes them always fresh.
+    #[inline]
+    fn is_local_within(&self, max_lag: MaxLag) -> bool {
+        match &self.inner.state {
+            None => true,
+            Some(state) => staleness::is_within(state, max_lag),
+        }
+    }
+
     /// Executes a query on remote host and returns raw rows.
     /// This is mostly used internally and not directly.
     pub(crate) async fn query_remote<S>(
@@ -269,6 +857,7 @@ impl Client {
         stmt: S,
         params: Params,
         consistent: bool,
+        timeout: Option<Duration>,
     ) -> Result<Vec<crate::Row>, Error>
     where
         S: Into<Cow<'static, str>>,
@@ -278,14 +867,17 @@ impl Client {
             params,
         };
 
-        let res = match self.query_remote_req(query.clone(), consistent).await {
+        let res = match self
+            .query_remote_req(query.clone(), consistent, timeout)
+            .await
+        {
             Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.query_remote_req(query, consistent).await
+                    self.query_remote_req(query, consistent, timeout).await
                 } else {
                     return Err(err);
                 }
@@ -302,20 +894,24 @@ impl Client {
         &self,
         query: Query,
         consistent: bool,
+        timeout: Option<Duration>,
     ) -> Result<Vec<RowOwned>, Error> {
         let (ack, rx) = oneshot::channel();
+        let request_id = self.new_request_id();
 
         let payload = if consistent {
             ClientStreamReq::QueryConsistent(ClientQueryPayload {
-                request_id: self.new_request_id(),
+                request_id,
                 ack,
                 query,
+                timeout,
             })
         } else {
             ClientStreamReq::Query(ClientQueryPayload {
-                request_id: self.new_request_id(),
+                request_id,
                 ack,
                 query,
+                timeout,
             })
         };
 
@@ -324,19 +920,47 @@ impl Client {
             .send_async(payload)
             .await
             .expect("Client Stream Manager to always be running");
+        let cancel = CancelOnDrop::new(self.inner.tx_client_db.clone(), request_id);
         let res = rx
             .await
-            .expect("To always receive an answer from Client Stream Manager")?;
+            .expect("To always receive an answer from Client Stream Manager");
+        cancel.done();
+        let res = res?;
         match res {
             ApiStreamResponsePayload::Query(res) => {
                 assert!(!consistent);
-                res
+                res.map(|set| set.into_rows())
             }
             ApiStreamResponsePayload::QueryConsistent(res) => {
                 assert!(consistent);
-                res
+                res.map(|set| set.into_rows())
             }
             _ => unreachable!(),
         }
     }
 }
+
+/// Waits until the given Raft has applied at least `log_index`. Falls back to the configured
+/// `wait_applied_timeout` if no `timeout` is given.
+pub(crate) async fn wait_applied(
+    raft_db: &StateRaftDB,
+    log_index: u64,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    let timeout = timeout.unwrap_or(raft_db.wait_applied_timeout);
+
+    match raft_db
+        .raft
+        .wait(Some(timeout))
+        .applied_index_at_least(Some(log_index), "read after write")
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(WaitError::Timeout(_, _)) => Err(Error::Timeout(format!(
+            "Log index {} has not been applied after {} ms",
+            log_index,
+            timeout.as_millis()
+        ))),
+        Err(WaitError::ShuttingDown) => Err(Error::Connect("Raft is shutting down".into())),
+    }
+}
@@ -0,0 +1,201 @@
+use crate::client::stream::{ClientQueryStreamPayload, ClientStreamReq};
+use crate::network::api::{ApiStreamResponsePayload, QueryStreamReq};
+use crate::query::rows::RowOwned;
+use crate::store::state_machine::sqlite::state_machine::Query;
+use crate::{query, Client, Error, Params, Row};
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use std::borrow::Cow;
+use tokio::sync::oneshot;
+
+/// A stream of query results, batched in chunks.
+pub type RowStream = BoxStream<'static, Result<Vec<Row<'static>>, Error>>;
+
+impl Client {
+    /// Executes a query and returns the result as a `Stream` of rows, batched in chunks of
+    /// `chunk_size`.
+    ///
+    /// In contrast to `query_raw()`, the result will never be held in memory as a whole, which
+    /// makes this the right choice for very big `SELECT`s like exports. The next chunk will only
+    /// be read from the database when the previous one has been consumed. Dropping the stream
+    /// cancels the query.
+    ///
+    /// For local clients, the query will run on the local read pool. Remote clients will stream
+    /// the result from the leader.
+    ///
+    /// ```rust, notest
+    /// let mut stream = client.query_stream("SELECT * FROM test", params!(), 1000);
+    /// while let Some(chunk) = stream.next().await {
+    ///     for mut row in chunk? {
+    ///         let id: i64 = row.get("id");
+    ///     }
+    /// }
+    /// ```
+    pub fn query_stream<S>(&self, stmt: S, params: Params, chunk_size: usize) -> RowStream
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.query_stream_owned(stmt, params, chunk_size)
+            .map(|res| res.map(|rows| rows.into_iter().map(Row::Owned).collect()))
+            .boxed()
+    }
+
+    pub(crate) fn query_stream_owned<S>(
+        &self,
+        stmt: S,
+        params: Params,
+        chunk_size: usize,
+    ) -> BoxStream<'static, Result<Vec<RowOwned>, Error>>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let query = Query {
+            sql: stmt.into(),
+            params,
+        };
+
+        if let Some(state) = &self.inner.state {
+            let state = state.clone();
+            stream::once(async move {
+                query::query_stream_local(
+                    state.raft_db.log_statements,
+                    state.raft_db.read_pool.clone(),
+                    query.sql,
+                    query.params,
+                    chunk_size,
+                    state.raft_db.query_timeout,
+                )
+                .await
+            })
+            .flat_map(|res| match res {
+                Ok(rx) => rx.into_stream().boxed(),
+                Err(err) => stream::once(async move { Err(err) }).boxed(),
+            })
+            .boxed()
+        } else {
+            let cursor = RemoteCursor {
+                client: self.clone(),
+                stream_id: self.new_request_id(),
+                open: Some((query, chunk_size)),
+                done: false,
+            };
+
+            stream::unfold(cursor, |mut cursor| async move {
+                if cursor.done {
+                    return None;
+                }
+
+                let res = match cursor.open.take() {
+                    Some((query, chunk_size)) => cursor.open_req(query, chunk_size).await,
+                    None => {
+                        cursor
+                            .client
+                            .query_stream_req(QueryStreamReq::Next(cursor.stream_id))
+                            .await
+                    }
+                };
+
+                match res {
+                    Ok(Some(rows)) => Some((Ok(rows), cursor)),
+                    Ok(None) => {
+                        cursor.done = true;
+                        None
+                    }
+                    Err(err) => {
+                        // the server drops the cursor on errors
+                        cursor.done = true;
+                        Some((Err(err), cursor))
+                    }
+                }
+            })
+            .boxed()
+        }
+    }
+
+    async fn query_stream_req(&self, req: QueryStreamReq) -> Result<Option<Vec<RowOwned>>, Error> {
+        let (ack, rx) = oneshot::channel();
+        self.inner
+            .tx_client_db
+            .send_async(ClientStreamReq::QueryStream(ClientQueryStreamPayload {
+                request_id: self.new_request_id(),
+                req,
+                ack,
+            }))
+            .await
+            .expect("Client Stream Manager to always be running");
+        let res = rx
+            .await
+            .expect("To always receive an answer from Client Stream Manager")?;
+        match res {
+            ApiStreamResponsePayload::QueryStream(res) => {
+                res.map(|rows| rows.map(|set| set.into_rows()))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Keeps track of a query cursor on the remote server and closes it when the stream is
+/// dropped before it has been consumed completely.
+struct RemoteCursor {
+    client: Client,
+    stream_id: usize,
+    open: Option<(Query, usize)>,
+    done: bool,
+}
+
+impl RemoteCursor {
+    async fn open_req(
+        &self,
+        query: Query,
+        chunk_size: usize,
+    ) -> Result<Option<Vec<RowOwned>>, Error> {
+        let req = QueryStreamReq::Open {
+            stream_id: self.stream_id,
+            query: query.clone(),
+            chunk_size,
+        };
+        match self.client.query_stream_req(req).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                let client = &self.client;
+                if client
+                    .was_leader_update_error(
+                        &err,
+                        &client.inner.leader_db,
+                        &client.inner.tx_client_db,
+                    )
+                    .await
+                {
+                    let req = QueryStreamReq::Open {
+                        stream_id: self.stream_id,
+                        query,
+                        chunk_size,
+                    };
+                    client.query_stream_req(req).await
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RemoteCursor {
+    fn drop(&mut self) {
+        // nothing to clean up if the stream has never been polled or has ended already
+        if self.done || self.open.is_some() {
+            return;
+        }
+
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let stream_id = self.stream_id;
+            handle.spawn(async move {
+                let _ = client
+                    .query_stream_req(QueryStreamReq::Close(stream_id))
+                    .await;
+            });
+        }
+    }
+}
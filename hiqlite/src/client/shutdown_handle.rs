@@ -11,6 +11,8 @@ pub struct ShutdownHandle {
     tx_client_cache: flume::Sender<ClientStreamReq>,
     #[cfg(feature = "sqlite")]
     tx_client_db: flume::Sender<ClientStreamReq>,
+    #[cfg(feature = "sqlite")]
+    tx_client_shards: Vec<flume::Sender<ClientStreamReq>>,
     tx_shutdown: Option<watch::Sender<bool>>,
     rx_shutdown: watch::Receiver<bool>,
 }
@@ -25,6 +27,8 @@ impl ShutdownHandle {
             &self.tx_client_cache,
             #[cfg(feature = "sqlite")]
             &self.tx_client_db,
+            #[cfg(feature = "sqlite")]
+            &self.tx_client_shards,
             &self.tx_shutdown,
         )
         .await
@@ -48,6 +52,13 @@ impl Client {
                 tx_client_cache: self.inner.tx_client_cache.clone(),
                 #[cfg(feature = "sqlite")]
                 tx_client_db: self.inner.tx_client_db.clone(),
+                #[cfg(feature = "sqlite")]
+                tx_client_shards: self
+                    .inner
+                    .shards
+                    .iter()
+                    .map(|s| s.tx_client.clone())
+                    .collect(),
                 tx_shutdown: self.inner.tx_shutdown.clone(),
                 rx_shutdown,
             })
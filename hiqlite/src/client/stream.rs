@@ -28,7 +28,14 @@ use crate::app_state::RaftType;
 #[cfg(any(feature = "sqlite", feature = "cache"))]
 use crate::network::api::{ApiStreamRequest, ApiStreamRequestPayload};
 #[cfg(feature = "sqlite")]
-use crate::{migration::Migration, store::state_machine::sqlite::state_machine::Query};
+use crate::{
+    migration::Migration,
+    network::api::QueryStreamReq,
+    store::state_machine::sqlite::{
+        guard::GuardedQuery,
+        state_machine::{Query, QueryMany, QueryWrite},
+    },
+};
 
 #[derive(Debug)]
 pub(crate) enum ClientStreamReq {
@@ -36,10 +43,14 @@ pub(crate) enum ClientStreamReq {
     #[cfg(feature = "sqlite")]
     Execute(ClientExecutePayload),
     #[cfg(feature = "sqlite")]
+    ExecuteMany(ClientExecuteManyPayload),
+    #[cfg(feature = "sqlite")]
     ExecuteReturning(ClientExecutePayload),
     #[cfg(feature = "sqlite")]
     Transaction(ClientTransactionPayload),
     #[cfg(feature = "sqlite")]
+    TransactionGuarded(ClientTransactionGuardedPayload),
+    #[cfg(feature = "sqlite")]
     Query(ClientQueryPayload),
     #[cfg(feature = "sqlite")]
     QueryConsistent(ClientQueryPayload),
@@ -47,6 +58,17 @@ pub(crate) enum ClientStreamReq {
     Batch(ClientBatchPayload),
     #[cfg(feature = "sqlite")]
     Migrate(ClientMigratePayload),
+    #[cfg(feature = "sqlite")]
+    QueryStream(ClientQueryStreamPayload),
+    #[cfg(feature = "sqlite")]
+    DbWrite(ClientDbWritePayload),
+    #[cfg(feature = "sqlite")]
+    DbQuery(ClientDbQueryPayload),
+    #[cfg(feature = "sqlite")]
+    DbQueryConsistent(ClientDbQueryPayload),
+    /// Cancels the remote query with this request id
+    #[cfg(feature = "sqlite")]
+    Cancel(usize),
 
     #[cfg(feature = "backup")]
     Backup(ClientBackupPayload),
@@ -80,6 +102,14 @@ pub struct ClientExecutePayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientExecuteManyPayload {
+    pub request_id: usize,
+    pub query: QueryMany,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
 #[cfg(feature = "sqlite")]
 #[derive(Debug)]
 pub struct ClientTransactionPayload {
@@ -88,11 +118,40 @@ pub struct ClientTransactionPayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientTransactionGuardedPayload {
+    pub request_id: usize,
+    pub queries: Vec<GuardedQuery>,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
 #[cfg(feature = "sqlite")]
 #[derive(Debug)]
 pub struct ClientQueryPayload {
     pub request_id: usize,
     pub query: Query,
+    /// Overwrites the default query timeout on the remote node
+    pub timeout: Option<Duration>,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientDbWritePayload {
+    pub request_id: usize,
+    /// `None` targets the main database of the Raft group
+    pub db: Option<String>,
+    pub query: QueryWrite,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientDbQueryPayload {
+    pub request_id: usize,
+    pub db: String,
+    pub query: Query,
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
@@ -112,6 +171,14 @@ pub struct ClientMigratePayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientQueryStreamPayload {
+    pub request_id: usize,
+    pub req: QueryStreamReq,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
 #[cfg(feature = "backup")]
 #[derive(Debug)]
 pub struct ClientBackupPayload {
@@ -128,6 +195,47 @@ pub struct ClientKVPayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+/// Cancels a remote query, if it is dropped before its response has been received, which
+/// interrupts the statement on the serving node.
+#[cfg(feature = "sqlite")]
+pub(crate) struct CancelOnDrop {
+    tx: flume::Sender<ClientStreamReq>,
+    request_id: usize,
+    done: bool,
+}
+
+#[cfg(feature = "sqlite")]
+impl CancelOnDrop {
+    pub(crate) fn new(tx: flume::Sender<ClientStreamReq>, request_id: usize) -> Self {
+        Self {
+            tx,
+            request_id,
+            done: false,
+        }
+    }
+
+    pub(crate) fn done(mut self) {
+        self.done = true;
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let req = ClientStreamReq::Cancel(self.request_id);
+        if let Err(flume::TrySendError::Full(req)) = self.tx.try_send(req) {
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                let _ = tx.send_async(req).await;
+            });
+        }
+    }
+}
+
 #[derive(Debug)]
 enum WritePayload {
     Payload(Vec<u8>),
@@ -191,7 +299,11 @@ async fn client_stream(
             Err(err) => {
                 if let Error::Connect(_) = &err {
                     // TODO keep track if we are connected through a proxy and skip ?
-                    client.find_set_active_leader().await;
+                    match raft_type {
+                        #[cfg(feature = "sqlite")]
+                        RaftType::Shard(group) => client.find_set_active_leader_shard(group).await,
+                        _ => client.find_set_active_leader().await,
+                    }
                 }
 
                 time::sleep(Duration::from_millis(1000)).await;
@@ -253,6 +365,23 @@ async fn client_stream(
                     ))
                 }
 
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::ExecuteMany(ClientExecuteManyPayload {
+                    request_id,
+                    query,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::ExecuteMany(query),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
                 #[cfg(feature = "sqlite")]
                 ClientStreamReq::ExecuteReturning(ClientExecutePayload {
                     request_id,
@@ -287,15 +416,39 @@ async fn client_stream(
                     ))
                 }
 
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::TransactionGuarded(ClientTransactionGuardedPayload {
+                    request_id,
+                    queries,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::TransactionGuarded(queries),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
                 #[cfg(feature = "sqlite")]
                 ClientStreamReq::Query(ClientQueryPayload {
                     request_id,
                     query,
+                    timeout,
                     ack,
                 }) => {
+                    let payload = match timeout {
+                        None => ApiStreamRequestPayload::Query(query),
+                        Some(timeout) => {
+                            ApiStreamRequestPayload::QueryTimeout(query, timeout.as_millis() as u64)
+                        }
+                    };
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::Query(query),
+                        payload,
                     };
                     Some((
                         WritePayload::Payload(bincode::serialize(&req).unwrap()),
@@ -308,11 +461,19 @@ async fn client_stream(
                 ClientStreamReq::QueryConsistent(ClientQueryPayload {
                     request_id,
                     query,
+                    timeout,
                     ack,
                 }) => {
+                    let payload = match timeout {
+                        None => ApiStreamRequestPayload::QueryConsistent(query),
+                        Some(timeout) => ApiStreamRequestPayload::QueryConsistentTimeout(
+                            query,
+                            timeout.as_millis() as u64,
+                        ),
+                    };
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::QueryConsistent(query),
+                        payload,
                     };
                     Some((
                         WritePayload::Payload(bincode::serialize(&req).unwrap()),
@@ -355,6 +516,95 @@ async fn client_stream(
                     ))
                 }
 
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStream(ClientQueryStreamPayload {
+                    request_id,
+                    req,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::QueryStream(req),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbWrite(ClientDbWritePayload {
+                    request_id,
+                    db,
+                    query,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::DbWrite(db, query),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbQuery(ClientDbQueryPayload {
+                    request_id,
+                    db,
+                    query,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::DbQuery(db, query),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbQueryConsistent(ClientDbQueryPayload {
+                    request_id,
+                    db,
+                    query,
+                    ack,
+                }) => {
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::DbQueryConsistent(db, query),
+                    };
+                    Some((
+                        WritePayload::Payload(bincode::serialize(&req).unwrap()),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::Cancel(request_id) => {
+                    // nothing to cancel if the response has been received already
+                    if in_flight.remove(&request_id).is_some() {
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::Cancel,
+                        };
+                        let payload = WritePayload::Payload(bincode::serialize(&req).unwrap());
+                        if let Err(err) = tx_write.send_async(payload).await {
+                            error!("Error sending cancel request to writer: {}", err);
+                            break;
+                        }
+                    }
+                    in_flight_buf.remove(&request_id);
+                    None
+                }
+
                 #[cfg(feature = "backup")]
                 ClientStreamReq::Backup(ClientBackupPayload {
                     request_id,
@@ -512,6 +762,12 @@ async fn client_stream(
                     unreachable!("we should never receive ClientStreamReq::Execute from WS reader")
                 }
                 #[cfg(feature = "sqlite")]
+                ClientStreamReq::ExecuteMany(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::ExecuteMany from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
                 ClientStreamReq::ExecuteReturning(_) => {
                     unreachable!(
                         "we should never receive ClientStreamReq::ExecuteReturning from WS reader"
@@ -524,6 +780,12 @@ async fn client_stream(
                     )
                 }
                 #[cfg(feature = "sqlite")]
+                ClientStreamReq::TransactionGuarded(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::TransactionGuarded from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
                 ClientStreamReq::Query(_) => {
                     unreachable!(
                         "we should never receive ClientStreamReq::QueryConsistent from WS reader"
@@ -543,6 +805,30 @@ async fn client_stream(
                 ClientStreamReq::Migrate(_) => {
                     unreachable!("we should never receive ClientStreamReq::Migrate from WS reader")
                 }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStream(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::QueryStream from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbWrite(_) => {
+                    unreachable!("we should never receive ClientStreamReq::DbWrite from WS reader")
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbQuery(_) => {
+                    unreachable!("we should never receive ClientStreamReq::DbQuery from WS reader")
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::DbQueryConsistent(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::DbQueryConsistent from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::Cancel(_) => {
+                    unreachable!("we should never receive ClientStreamReq::Cancel from WS reader")
+                }
                 #[cfg(feature = "backup")]
                 ClientStreamReq::Backup(_) => {
                     unreachable!("we should never receive ClientStreamReq::Backup from WS reader")
@@ -1,7 +1,10 @@
-use crate::client::stream::{ClientStreamReq, ClientTransactionPayload};
+use crate::client::stream::{
+    ClientStreamReq, ClientTransactionGuardedPayload, ClientTransactionPayload,
+};
 use crate::network::api::ApiStreamResponsePayload;
-use crate::store::state_machine::sqlite::state_machine::{Query, QueryWrite};
-use crate::{Client, Error, Params, Response};
+use crate::store::state_machine::sqlite::guard::GuardedQuery;
+use crate::store::state_machine::sqlite::state_machine::{Indexed, Query, QueryWrite};
+use crate::{Client, Error, Guard, Params, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
 
@@ -32,6 +35,22 @@ impl Client {
     /// }
     /// ```
     pub async fn txn<C, Q>(&self, sql: Q) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params)>,
+        C: Into<Cow<'static, str>>,
+    {
+        self.txn_indexed(sql).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `txn()`, but additionally returns the Raft log index this
+    /// transaction has been applied at.
+    ///
+    /// You can pass this index into the `query_*_after()` functions to read your own writes
+    /// from any node.
+    pub async fn txn_indexed<C, Q>(
+        &self,
+        sql: Q,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error>
     where
         Q: IntoIterator<Item = (C, Params)>,
         C: Into<Cow<'static, str>>,
@@ -63,16 +82,16 @@ impl Client {
     pub(crate) async fn txn_execute(
         &self,
         queries: Vec<Query>,
-    ) -> Result<Vec<Result<usize, Error>>, Error> {
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
                 .client_write(QueryWrite::Transaction(queries))
                 .await?;
+            let log_index = res.log_id.index;
             let resp: Response = res.data;
             match resp {
-                Response::Transaction(res) => res,
+                Response::Transaction(res) => res.map(|res| Indexed::new(res, log_index)),
                 _ => unreachable!(),
             }
         } else {
@@ -95,4 +114,122 @@ impl Client {
             }
         }
     }
+
+    /// Works in the same way as `txn()`, but each query can carry `Guard`s, which makes it
+    /// possible to do a compare-and-set without an additional distributed lock.
+    ///
+    /// All guards are evaluated inside the same database transaction, right before the query
+    /// they belong to, which means they will see the changes of all queries before them.
+    /// If any guard does not match, the whole transaction will be rolled back and an
+    /// `Error::PreconditionFailed` is returned. Its `index` is the position of the failing
+    /// guard, counted over all guards in the given order.
+    ///
+    /// ```rust, notest
+    /// let res = client
+    ///     .txn_guarded([(
+    ///         "UPDATE account SET balance = $1 WHERE id = $2",
+    ///         params!(80, 1),
+    ///         vec![Guard::scalar(
+    ///             "SELECT balance FROM account WHERE id = $1",
+    ///             params!(1),
+    ///             100,
+    ///         )?],
+    ///     )])
+    ///     .await;
+    ///
+    /// match res {
+    ///     Ok(res) => {
+    ///         for inner_res in res {
+    ///             let rows_affected = inner_res?;
+    ///             assert_eq!(rows_affected, 1);
+    ///         }
+    ///     }
+    ///     Err(Error::PreconditionFailed { index, reason }) => {
+    ///         // someone else modified the balance in between
+    ///     }
+    ///     Err(err) => return Err(err),
+    /// }
+    /// ```
+    pub async fn txn_guarded<C, Q>(&self, sql: Q) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params, Vec<Guard>)>,
+        C: Into<Cow<'static, str>>,
+    {
+        self.txn_guarded_indexed(sql).await.map(|res| res.value)
+    }
+
+    /// Works in the same way as `txn_guarded()`, but additionally returns the Raft log index
+    /// this transaction has been applied at. See `txn_indexed()`.
+    pub async fn txn_guarded_indexed<C, Q>(
+        &self,
+        sql: Q,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params, Vec<Guard>)>,
+        C: Into<Cow<'static, str>>,
+    {
+        let queries: Vec<GuardedQuery> = sql
+            .into_iter()
+            .map(|(q, params, guards)| GuardedQuery {
+                guards,
+                query: Query {
+                    sql: q.into(),
+                    params,
+                },
+            })
+            .collect();
+
+        match self.txn_guarded_execute(queries.clone()).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                if self
+                    .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
+                    .await
+                {
+                    self.txn_guarded_execute(queries).await
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) async fn txn_guarded_execute(
+        &self,
+        queries: Vec<GuardedQuery>,
+    ) -> Result<Indexed<Vec<Result<usize, Error>>>, Error> {
+        if let Some(state) = self.is_leader_db_with_state().await {
+            let res = state
+                .raft_db
+                .client_write(QueryWrite::TransactionGuarded(queries))
+                .await?;
+            let log_index = res.log_id.index;
+            let resp: Response = res.data;
+            match resp {
+                Response::Transaction(res) => res.map(|res| Indexed::new(res, log_index)),
+                _ => unreachable!(),
+            }
+        } else {
+            let (ack, rx) = oneshot::channel();
+            self.inner
+                .tx_client_db
+                .send_async(ClientStreamReq::TransactionGuarded(
+                    ClientTransactionGuardedPayload {
+                        request_id: self.new_request_id(),
+                        queries,
+                        ack,
+                    },
+                ))
+                .await
+                .expect("Client Stream Manager to always be running");
+            let res = rx
+                .await
+                .expect("To always receive an answer from Client Stream Manager")?;
+            match res {
+                ApiStreamResponsePayload::Transaction(res) => res,
+                _ => unreachable!(),
+            }
+        }
+    }
 }
@@ -7,6 +7,9 @@ use tracing::{debug, warn};
 
 pub use openraft::Config as RaftConfig;
 
+/// The max amount of additional SQLite Raft groups.
+const MAX_SHARDS: u16 = 64;
+
 #[cfg(feature = "backup")]
 use crate::backup;
 
@@ -34,17 +37,37 @@ pub struct NodeConfig {
     pub nodes: Vec<Node>,
     /// The directory where the replication log, database and snapshots should be stored
     pub data_dir: Cow<'static, str>,
-    /// If the SQLite should be written to disk, provide a filename here.
-    /// It is recommended to leave it set to None if your DB size fits fully into memory, and
-    /// you can afford this. No data will be lost with an in-memory DB because Raft logs and
-    /// snapshots are always persisted and the in-memory DB can be rebuilt quickly after a restart.
+    /// The filename of the SQLite database inside the state machine folder. With `in_memory`,
+    /// it is only used to name the in-memory database.
     pub filename_db: Cow<'static, str>,
+    /// If set to `true`, the SQLite database (and all named databases) will only live in memory
+    /// and never be written to disk. It is recommended if your DB size fits fully into memory,
+    /// and you can afford this. No data will be lost with an in-memory DB because Raft logs and
+    /// snapshots are always persisted and the in-memory DB will be rebuilt from the latest
+    /// snapshot and logs after a restart.
+    pub in_memory: bool,
+    /// Additional named databases. Each one gets its own SQLite file `<name>.db` next to the
+    /// main database, with its own read pool, migrations and snapshots, while all of them are
+    /// replicated through the same Raft. Access them with `client.db("name")`.
+    ///
+    /// Names may only contain `a-z`, `0-9` and `_`, and they must be the same on each node.
+    pub databases: Vec<String>,
+    /// The amount of additional, independent SQLite Raft groups (shards). Each one has its own
+    /// leader, logs and database inside `<data_dir>/shards/<group>`, which means writes to
+    /// different shards do not block each other. Access them with `client.shard(key)`.
+    ///
+    /// Keys are mapped to shards by their hash. Changing this value later on will map existing
+    /// keys to different shards without moving any data. It must be the same on each node.
+    /// The default is `0`, which means all data lives inside the main Raft.
+    pub shards: u16,
     /// Enables statement logging or the SQL writer
     pub log_statements: bool,
     /// The internal cache size for prepared statements. The default is `1024` which could be
     /// reduced in very heavily memory-constrained environments.
     pub prepared_statement_cache_capacity: usize,
-    /// The size of the pooled connections for local database reads.
+    /// The max amount of connections for local database reads. Each connection is owned by a
+    /// dedicated reader thread. Readers are started on demand when queries queue up and stopped
+    /// again after being idle for a while, down to `read_pool_min`.
     ///
     /// Do not confuse this with a pool size for network databases, as it
     /// is much more efficient. You can't really translate between them,
@@ -58,6 +81,23 @@ pub struct NodeConfig {
     ///
     /// default: 4
     pub read_pool_size: usize,
+    /// The amount of read connections which will always be kept open. Must be `>= 1` and
+    /// `<= read_pool_size`.
+    ///
+    /// default: 1
+    pub read_pool_min: usize,
+    /// The default timeout in ms for local reads on the read pool. A query running longer will
+    /// be interrupted and return an `Error::Timeout`, which makes sure a runaway `SELECT` cannot
+    /// block one of the few read connections forever. It can be overwritten per query with the
+    /// `query_*_timeout()` functions. `0` disables the default timeout.
+    ///
+    /// default: 0
+    pub query_timeout: u64,
+    /// The max time in ms `Client::wait_applied()` and the `query_*_after()` functions wait for
+    /// a Raft log index to be applied locally, before they return an `Error::Timeout`.
+    ///
+    /// default: 10000
+    pub wait_applied_timeout: u64,
     /// Enables immediate flush + sync to disk after each Log Store Batch.
     /// The situations where you would need this are very rare, and you
     /// should use it with care.
@@ -78,6 +118,92 @@ pub struct NodeConfig {
     /// a lot more pressure on the disk. If you have lots of writes, it
     /// can pretty quickly kill your SSD for instance.
     pub sync_immediate: bool,
+    /// Enables leader lease reads for consistent queries.
+    ///
+    /// By default, each consistent query makes the leader confirm its leadership with a heartbeat
+    /// round to a quorum of nodes. With a valid lease, derived from heartbeat acknowledgements,
+    /// the leader can skip this round. This relies on bounded clock drift between the nodes, see
+    /// `lease_clock_drift`. As soon as the lease expires, reads fall back to the default.
+    ///
+    /// default: false
+    pub lease_reads: bool,
+    /// The max clock drift in ms between the nodes that a leader lease will tolerate. The lease
+    /// is valid for `election_timeout_min - lease_clock_drift` after a quorum acknowledged a
+    /// heartbeat. Must be lower than `election_timeout_min` from the `raft_config`.
+    ///
+    /// default: 100
+    pub lease_clock_drift: u64,
+    /// The window in µs for which the leader collects concurrent `execute()`s before it proposes
+    /// them as a single Raft entry. Each query is still applied on its own and gets its own
+    /// result. With many concurrent, small writes, this saves most of the Raft round trips in
+    /// exchange for up to this much additional latency. `0` disables write coalescing.
+    ///
+    /// default: 0
+    pub write_coalesce_window: u64,
+    /// The max amount of SQLite VM steps a single write may take inside the writer, for instance
+    /// an `execute()`, a whole transaction or a single statement of a batch. A write exceeding
+    /// it will be aborted and rolled back with an `Error::CostLimit`, instead of stalling the
+    /// apply of all following logs. Writes using more than 80% of it will be logged. Migrations
+    /// are not limited. This must be the same on each node. `0` disables the limit.
+    ///
+    /// default: 0
+    pub write_cost_limit: u64,
+    /// Compress chunks of SQLite snapshots with zstd before sending them to other nodes. This
+    /// saves bandwidth on slow networks at the cost of some CPU. Chunks which a follower already
+    /// has locally are never sent, no matter if this is enabled.
+    ///
+    /// default: false
+    pub snapshot_compression: bool,
+    /// The max time in seconds between two SQLite snapshots. If any new logs have been applied
+    /// since the last one, a snapshot is built after this time, even if the `snapshot_policy`
+    /// from the `raft_config` would not trigger yet. This makes sure that a quiet cluster does not
+    /// keep old logs forever. `0` disables it.
+    ///
+    /// default: 86400
+    pub snapshot_max_interval: u64,
+    /// The max size in bytes of the Raft logs written since the last snapshot. A snapshot, and
+    /// therefore a logs purge, is triggered as soon as they grow above this size. After a
+    /// restart, only the logs written since then are counted. `0` disables it.
+    ///
+    /// default: 0
+    pub snapshot_max_log_size: u64,
+    /// The min time in seconds between two SQLite snapshots. If this is set, snapshots are only
+    /// built after this time, even if `snapshot_policy` or `snapshot_max_log_size` would trigger
+    /// earlier. This prevents constant snapshots in bursty clusters. Must be
+    /// `<= snapshot_max_interval`. `0` disables it.
+    ///
+    /// default: 60
+    pub snapshot_min_interval: u64,
+    /// After an unclean shutdown, the existing database is verified with `PRAGMA quick_check`
+    /// and kept if it is intact. Only the logs after the last applied one will be replayed in
+    /// that case. Set this to `true` to run the much slower, full `PRAGMA integrity_check`
+    /// instead. With the writer running with `synchronous` `OFF`, this only applies if the OS
+    /// has not been restarted (Linux only). Otherwise, the database is always rebuilt from the
+    /// latest snapshot and the logs.
+    ///
+    /// default: false
+    pub recovery_integrity_check: bool,
+    /// The interval in seconds for the cross-replica divergence check. The leader proposes a
+    /// checksum entry, at which each node computes a content hash of all its tables. All nodes
+    /// compare their hashes afterward. `0` disables the check.
+    ///
+    /// default: 3600
+    pub divergence_check_interval: u64,
+    /// If a node diverges from the majority, re-sync it automatically with a copy of the
+    /// databases from the leader.
+    ///
+    /// default: false
+    pub divergence_resync: bool,
+    /// Custom SQL functions and collations, which will be registered on each SQLite connection.
+    /// They cannot be set via env vars and must be the same on each node. feature `sqlite`
+    #[cfg(feature = "sqlite")]
+    pub sql_functions: crate::SqlFunctions,
+    /// SQLite pragmas for the writer and the read pool. Build it from one of the presets with
+    /// `SqliteConfig::preset()` and adjust single values if needed. feature `sqlite`
+    ///
+    /// default: `SqlitePreset::Balanced`
+    #[cfg(feature = "sqlite")]
+    pub sqlite_config: crate::SqliteConfig,
     /// The internal Raft config. This must be the same on each node.
     /// You will get good defaults with `NodeConfig::default_raft_config(_)`.
     pub raft_config: RaftConfig,
@@ -109,10 +235,31 @@ impl Default for NodeConfig {
             nodes: vec![],
             data_dir: "hiqlite".into(),
             filename_db: "hiqlite.db".into(),
+            in_memory: false,
+            databases: Vec::default(),
+            shards: 0,
             log_statements: false,
             prepared_statement_cache_capacity: 1024,
             read_pool_size: 4,
+            read_pool_min: 1,
+            query_timeout: 0,
+            wait_applied_timeout: 10_000,
             sync_immediate: false,
+            lease_reads: false,
+            lease_clock_drift: 100,
+            write_coalesce_window: 0,
+            write_cost_limit: 0,
+            snapshot_compression: false,
+            snapshot_max_interval: 86400,
+            snapshot_max_log_size: 0,
+            snapshot_min_interval: 60,
+            recovery_integrity_check: false,
+            divergence_check_interval: 3600,
+            divergence_resync: false,
+            #[cfg(feature = "sqlite")]
+            sql_functions: Default::default(),
+            #[cfg(feature = "sqlite")]
+            sqlite_config: Default::default(),
             raft_config: Self::default_raft_config(10_000),
             tls_raft: None,
             tls_api: None,
@@ -208,6 +355,22 @@ impl NodeConfig {
             filename_db: env::var("HQL_FILENAME_DB")
                 .unwrap_or_else(|_| "hiqlite.db".to_string())
                 .into(),
+            in_memory: env::var("HQL_IN_MEMORY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("Cannot parse HQL_IN_MEMORY as bool"),
+            databases: env::var("HQL_DATABASES")
+                .map(|v| {
+                    v.split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            shards: env::var("HQL_SHARDS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("Cannot parse HQL_SHARDS to u16"),
             log_statements: env::var("HQL_LOG_STATEMENTS")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
@@ -217,10 +380,70 @@ impl NodeConfig {
                 .unwrap_or_else(|_| "4".to_string())
                 .parse()
                 .expect("Cannot parse HQL_READ_POOL_SIZE to usize"),
+            read_pool_min: env::var("HQL_READ_POOL_MIN")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse()
+                .expect("Cannot parse HQL_READ_POOL_MIN to usize"),
+            query_timeout: env::var("HQL_QUERY_TIMEOUT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("Cannot parse HQL_QUERY_TIMEOUT to u64"),
+            wait_applied_timeout: env::var("HQL_WAIT_APPLIED_TIMEOUT")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()
+                .expect("Cannot parse HQL_WAIT_APPLIED_TIMEOUT to u64"),
             sync_immediate: env::var("HQL_SYNC_IMMEDIATE")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .expect("Cannot parse HQL_SYNC_IMMEDIATE as bool"),
+            lease_reads: env::var("HQL_LEASE_READS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("Cannot parse HQL_LEASE_READS as bool"),
+            lease_clock_drift: env::var("HQL_LEASE_CLOCK_DRIFT")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .expect("Cannot parse HQL_LEASE_CLOCK_DRIFT to u64"),
+            write_coalesce_window: env::var("HQL_WRITE_COALESCE_WINDOW")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("Cannot parse HQL_WRITE_COALESCE_WINDOW to u64"),
+            write_cost_limit: env::var("HQL_WRITE_COST_LIMIT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("Cannot parse HQL_WRITE_COST_LIMIT to u64"),
+            snapshot_compression: env::var("HQL_SNAPSHOT_COMPRESSION")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("Cannot parse HQL_SNAPSHOT_COMPRESSION as bool"),
+            snapshot_max_interval: env::var("HQL_SNAPSHOT_MAX_INTERVAL")
+                .unwrap_or_else(|_| "86400".to_string())
+                .parse()
+                .expect("Cannot parse HQL_SNAPSHOT_MAX_INTERVAL to u64"),
+            snapshot_max_log_size: env::var("HQL_SNAPSHOT_MAX_LOG_SIZE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("Cannot parse HQL_SNAPSHOT_MAX_LOG_SIZE to u64"),
+            snapshot_min_interval: env::var("HQL_SNAPSHOT_MIN_INTERVAL")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .expect("Cannot parse HQL_SNAPSHOT_MIN_INTERVAL to u64"),
+            recovery_integrity_check: env::var("HQL_RECOVERY_INTEGRITY_CHECK")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("Cannot parse HQL_RECOVERY_INTEGRITY_CHECK as bool"),
+            divergence_check_interval: env::var("HQL_DIVERGENCE_CHECK_INTERVAL")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()
+                .expect("Cannot parse HQL_DIVERGENCE_CHECK_INTERVAL to u64"),
+            divergence_resync: env::var("HQL_DIVERGENCE_RESYNC")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("Cannot parse HQL_DIVERGENCE_RESYNC as bool"),
+            #[cfg(feature = "sqlite")]
+            sql_functions: Default::default(),
+            #[cfg(feature = "sqlite")]
+            sqlite_config: crate::SqliteConfig::from_env(),
             raft_config: Self::default_raft_config(logs_keep),
             tls_raft: ServerTlsConfig::from_env("RAFT"),
             tls_api: ServerTlsConfig::from_env("API"),
@@ -288,6 +511,67 @@ impl NodeConfig {
             ));
         }
 
+        if self.lease_reads && self.lease_clock_drift >= self.raft_config.election_timeout_min {
+            return Err(Error::Config(
+                "'lease_clock_drift' must be lower than 'election_timeout_min'".into(),
+            ));
+        }
+
+        if self.snapshot_max_interval > 0 && self.snapshot_min_interval > self.snapshot_max_interval
+        {
+            return Err(Error::Config(
+                "'snapshot_min_interval' must be <= 'snapshot_max_interval'".into(),
+            ));
+        }
+
+        if self.read_pool_min < 1 || self.read_pool_min > self.read_pool_size {
+            return Err(Error::Config(
+                "'read_pool_min' must be >= 1 and <= 'read_pool_size'".into(),
+            ));
+        }
+
+        for (i, name) in self.databases.iter().enumerate() {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(Error::Config(
+                    format!(
+                        "invalid name in 'databases': '{}' - only a-z, 0-9 and _ are allowed",
+                        name
+                    )
+                    .into(),
+                ));
+            }
+            if name == "main" || name == "temp" {
+                return Err(Error::Config(
+                    format!("'{}' is reserved and cannot be used in 'databases'", name).into(),
+                ));
+            }
+            if format!("{}.db", name) == self.filename_db {
+                return Err(Error::Config(
+                    format!("'databases' entry '{}' conflicts with 'filename_db'", name).into(),
+                ));
+            }
+            if self.databases[..i].contains(name) {
+                return Err(Error::Config(
+                    format!("duplicate entry '{}' in 'databases'", name).into(),
+                ));
+            }
+        }
+
+        if self.shards > MAX_SHARDS {
+            return Err(Error::Config(
+                format!("'shards' must not be greater than {}", MAX_SHARDS).into(),
+            ));
+        }
+
+        #[cfg(feature = "sqlite")]
+        self.sql_functions.validate()?;
+        #[cfg(feature = "sqlite")]
+        self.sqlite_config.validate()?;
+
         #[cfg(feature = "dashboard")]
         if let Some(pwd) = &self.password_dashboard {
             if pwd.len() < 16 {
@@ -3,16 +3,16 @@ use crate::dashboard::table::Table;
 use crate::dashboard::{query, session};
 use crate::network::AppStateExt;
 use crate::query::rows::RowOwned;
-use crate::{Error, Node};
+use crate::{DivergenceMetrics, Error, Node, ReadPoolMetrics, SqliteConfig, WriteCostMetrics};
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::http::header::LOCATION;
 use axum::http::{HeaderMap, Method};
 use axum::response::Response;
 use axum::{body, Form, Json};
 use hyper::StatusCode;
 use openraft::RaftMetrics;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use spow::pow::Pow;
 
 pub async fn redirect_to_index() -> Response {
@@ -55,8 +55,29 @@ pub async fn get_pow() -> Result<String, Error> {
     Ok(pow.to_string())
 }
 
-pub async fn get_tables(state: AppStateExt, _: Session) -> Result<Json<Vec<Table>>, Error> {
-    let tables = Table::find_all(&state).await?;
+/// Selects one of the additional named databases via `?db=<name>`. Defaults to the main one.
+#[derive(Debug, Deserialize)]
+pub struct DbParam {
+    db: Option<String>,
+}
+
+pub async fn get_databases(state: AppStateExt, _: Session) -> Json<Vec<String>> {
+    let mut databases = state
+        .raft_db
+        .named_read_pools
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    databases.sort();
+    Json(databases)
+}
+
+pub async fn get_tables(
+    state: AppStateExt,
+    _: Session,
+    Query(param): Query<DbParam>,
+) -> Result<Json<Vec<Table>>, Error> {
+    let tables = Table::find_all(&state, param.db.as_deref()).await?;
     Ok(Json(tables))
 }
 
@@ -84,8 +105,9 @@ pub async fn get_tables_filtered(
     state: AppStateExt,
     _: Session,
     Path(filter): Path<TableFilterRequest>,
+    Query(param): Query<DbParam>,
 ) -> Result<Json<Vec<Table>>, Error> {
-    let tables = Table::find_all_filtered(&state, filter).await?;
+    let tables = Table::find_all_filtered(&state, param.db.as_deref(), filter).await?;
     Ok(Json(tables))
 }
 
@@ -93,15 +115,34 @@ pub async fn get_tables_filtered(
 pub async fn post_query(
     state: AppStateExt,
     _: Session,
+    Query(param): Query<DbParam>,
     body: body::Bytes,
 ) -> Result<Json<Vec<RowOwned>>, Error> {
     let binding = String::from_utf8_lossy(body.as_ref());
     let sql = binding.trim().to_string();
-    let res = query::dashboard_query_dynamic(state, sql).await?;
+    let res = query::dashboard_query_dynamic(state, param.db, sql).await?;
     Ok(Json(res))
 }
 
-pub async fn get_metrics(state: AppStateExt, _: Session) -> Json<RaftMetrics<u64, Node>> {
+/// The Raft metrics together with the SQLite settings, write cost and divergence check
+/// statistics of this node.
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    #[serde(flatten)]
+    raft: RaftMetrics<u64, Node>,
+    sqlite: SqliteConfig,
+    write_cost: WriteCostMetrics,
+    read_pool: ReadPoolMetrics,
+    divergence: DivergenceMetrics,
+}
+
+pub async fn get_metrics(state: AppStateExt, _: Session) -> Json<Metrics> {
     let metrics = state.raft_db.raft.metrics().borrow().clone();
-    Json(metrics)
+    Json(Metrics {
+        raft: metrics,
+        sqlite: state.raft_db.sqlite_config,
+        write_cost: state.raft_db.write_cost.metrics(),
+        read_pool: state.raft_db.read_pool.metrics(),
+        divergence: state.raft_db.checksums.metrics(),
+    })
 }
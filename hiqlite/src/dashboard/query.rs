@@ -1,14 +1,15 @@
 use crate::network::api::ApiStreamResponsePayload;
 use crate::network::AppStateExt;
 use crate::query::rows::{ColumnOwned, RowOwned, ValueOwned};
+use crate::query::with_read_conn;
 use crate::store::state_machine::sqlite::state_machine::{Query, QueryWrite};
 use crate::{params, Error};
 use tokio::sync::oneshot;
-use tokio::task;
 use tracing::info;
 
 pub(crate) async fn dashboard_query_dynamic(
     state: AppStateExt,
+    db: Option<String>,
     sql: String,
 ) -> Result<Vec<RowOwned>, Error> {
     if sql.len() < 8 {
@@ -27,9 +28,9 @@ pub(crate) async fn dashboard_query_dynamic(
         || sql_start.starts_with("pragma");
 
     if is_select {
-        let conn = state.raft_db.read_pool.get().await?;
+        let read_pool = state.raft_db.read_pool_for(db.as_deref())?;
 
-        task::spawn_blocking(move || {
+        with_read_conn(read_pool, state.raft_db.query_timeout, move |conn| {
             let mut stmt = conn.prepare(&sql)?;
 
             let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
@@ -40,9 +41,9 @@ pub(crate) async fn dashboard_query_dynamic(
                 rows_owned.push(RowOwned::from_row_column(row, &columns));
             }
 
-            Ok::<Vec<RowOwned>, Error>(rows_owned)
+            Ok(rows_owned)
         })
-        .await?
+        .await
     } else {
         let sql = Query {
             sql: sql.into(),
@@ -50,7 +51,7 @@ pub(crate) async fn dashboard_query_dynamic(
         };
 
         // TODO check for `RETURNING` to execute `query` instead
-        let rows_affected = match execute_dynamic(&state, sql.clone()).await {
+        let rows_affected = match execute_dynamic(&state, db.clone(), sql.clone()).await {
             Ok(r) => r,
             Err(err) => {
                 if let Some((id, node)) = err.is_forward_to_leader() {
@@ -62,7 +63,7 @@ pub(crate) async fn dashboard_query_dynamic(
                         )))
                         .await
                         .expect("Client Stream Manager to always be running");
-                    execute_dynamic(&state, sql.clone()).await?
+                    execute_dynamic(&state, db, sql).await?
                 } else {
                     return Err(err);
                 }
@@ -84,19 +85,47 @@ pub(crate) async fn dashboard_query_dynamic(
 }
 
 #[inline]
-async fn execute_dynamic(state: &AppStateExt, sql: Query) -> Result<usize, Error> {
+async fn execute_dynamic(
+    state: &AppStateExt,
+    db: Option<String>,
+    sql: Query,
+) -> Result<usize, Error> {
     if is_this_local_leader(state).await? {
         info!("Executing dynamic dashboard query as local leader");
         let res = state
             .raft_db
-            .raft
-            .client_write(QueryWrite::Execute(sql))
+            .client_write_db(db, QueryWrite::Execute(sql))
             .await?;
         let resp: crate::Response = res.data;
         match resp {
             crate::Response::Execute(res) => res.result,
             _ => unreachable!(),
         }
+    } else if let Some(db) = db {
+        info!("Executing dynamic dashboard query on remote leader");
+        let (ack, rx) = oneshot::channel();
+        state
+            .tx_client_stream
+            .send_async(crate::client::stream::ClientStreamReq::DbWrite(
+                crate::client::stream::ClientDbWritePayload {
+                    request_id: state.new_request_id(),
+                    db: Some(db),
+                    query: QueryWrite::Execute(sql),
+                    ack,
+                },
+            ))
+            .await
+            .expect("Client Stream Manager to always be running");
+        let res = rx
+            .await
+            .expect("To always receive an answer from Client Stream Manager")?;
+        match res {
+            ApiStreamResponsePayload::DbWrite(res) => match res?.value {
+                crate::Response::Execute(res) => res.result,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
     } else {
         info!("Executing dynamic dashboard query on remote leader");
         let (ack, rx) = oneshot::channel();
@@ -115,7 +144,7 @@ async fn execute_dynamic(state: &AppStateExt, sql: Query) -> Result<usize, Error
             .await
             .expect("To always receive an answer from Client Stream Manager")?;
         match res {
-            ApiStreamResponsePayload::Execute(res) => res,
+            ApiStreamResponsePayload::Execute(res) => res.map(|res| res.value),
             _ => unreachable!(),
         }
     }
@@ -1,6 +1,6 @@
 use crate::dashboard::handlers::TableFilterRequest;
 use crate::network::AppStateExt;
-use crate::query::query_map;
+use crate::query::query_owned_local;
 use crate::{params, Error, Param, Row};
 use serde::Serialize;
 
@@ -54,29 +54,40 @@ impl Table {
     //     .await
     // }
 
-    pub async fn find_all(state: &AppStateExt) -> Result<Vec<Self>, Error> {
-        let res: Vec<Self> = query_map(
-            state,
+    pub async fn find_all(state: &AppStateExt, db: Option<&str>) -> Result<Vec<Self>, Error> {
+        let rows = query_owned_local(
+            state.raft_db.log_statements,
+            state.raft_db.read_pool_for(db)?.clone(),
             "SELECT type,name,tbl_name,sql FROM sqlite_master",
             params!(),
+            state.raft_db.query_timeout,
         )
         .await?;
 
-        Ok(res)
+        Ok(rows
+            .into_iter()
+            .map(|row| Self::from(Row::Owned(row)))
+            .collect())
     }
 
     pub async fn find_all_filtered(
         state: &AppStateExt,
+        db: Option<&str>,
         filter: TableFilterRequest,
     ) -> Result<Vec<Self>, Error> {
-        let res: Vec<Self> = query_map(
-            state,
+        let rows = query_owned_local(
+            state.raft_db.log_statements,
+            state.raft_db.read_pool_for(db)?.clone(),
             "SELECT type,name,tbl_name,sql FROM sqlite_master WHERE type = $1",
             params!(filter.as_str()),
+            state.raft_db.query_timeout,
         )
         .await?;
 
-        Ok(res)
+        Ok(rows
+            .into_iter()
+            .map(|row| Self::from(Row::Owned(row)))
+            .collect())
     }
 }
 
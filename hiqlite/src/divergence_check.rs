@@ -0,0 +1,334 @@
+use crate::app_state::{AppState, RaftType, StateRaftDB};
+use crate::network::HEADER_NAME_SECRET;
+use crate::store::state_machine::sqlite::checksum::{self, DbChecksum};
+use crate::store::state_machine::sqlite::state_machine::QueryWrite;
+use crate::{Error, NodeId};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::{task, time};
+use tracing::{debug, error, info, warn};
+
+/// Remote nodes may lag behind a bit. They are asked for their checksum once per second for
+/// this amount of times.
+const FETCH_RETRIES: usize = 60;
+
+pub fn spawn(state: Arc<AppState>, tls: bool, tls_no_verify: bool, interval: u64, resync: bool) {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(tls_no_verify)
+        .build()
+        .expect("reqwest client to build");
+
+    for group in 0..=state.raft_db_shards.len() as u16 {
+        let raft_type = if group == 0 {
+            RaftType::Sqlite
+        } else {
+            RaftType::Shard(group)
+        };
+
+        if interval > 0 {
+            task::spawn(propose_checks(state.clone(), group, interval));
+        }
+        task::spawn(compare_checksums(
+            state.clone(),
+            client.clone(),
+            raft_type.clone(),
+            group,
+            tls,
+            resync,
+        ));
+        task::spawn(handle_resyncs(
+            state.clone(),
+            client.clone(),
+            raft_type,
+            group,
+            tls,
+        ));
+    }
+}
+
+#[inline]
+fn raft_db(state: &AppState, group: u16) -> &StateRaftDB {
+    state
+        .raft_db_group(group)
+        .expect("divergence check to only run for existing groups")
+}
+
+/// The leader proposes a `Checksum` entry in the given interval, which makes every node compute
+/// the hash of its data at exactly the same log index.
+async fn propose_checks(state: Arc<AppState>, group: u16, interval: u64) {
+    loop {
+        time::sleep(Duration::from_secs(interval)).await;
+
+        let raft_db = raft_db(&state, group);
+        if raft_db.raft.current_leader().await != Some(state.id) {
+            continue;
+        }
+
+        debug!("Proposing divergence check for Raft group {}", group);
+        if let Err(err) = raft_db.client_write(QueryWrite::Checksum).await {
+            error!("Error proposing divergence check: {}", err);
+        }
+    }
+}
+
+/// Compares each new local checksum with the ones of all other members.
+async fn compare_checksums(
+    state: Arc<AppState>,
+    client: reqwest::Client,
+    raft_type: RaftType,
+    group: u16,
+    tls: bool,
+    resync: bool,
+) {
+    let mut rx = raft_db(&state, group).checksums.subscribe();
+
+    while rx.changed().await.is_ok() {
+        let log_index = *rx.borrow_and_update();
+        if let Err(err) =
+            compare_checksum(&state, &client, &raft_type, group, tls, resync, log_index).await
+        {
+            error!("Error during divergence check: {}", err);
+        }
+    }
+}
+
+async fn compare_checksum(
+    state: &AppState,
+    client: &reqwest::Client,
+    raft_type: &RaftType,
+    group: u16,
+    tls: bool,
+    resync: bool,
+    log_index: u64,
+) -> Result<(), Error> {
+    let raft_db = raft_db(state, group);
+    let Some(local) = raft_db.checksums.get(log_index) else {
+        return Ok(());
+    };
+
+    let members = raft_db
+        .raft
+        .metrics()
+        .borrow()
+        .membership_config
+        .nodes()
+        .map(|(id, node)| (*id, node.addr_api.clone()))
+        .collect::<Vec<_>>();
+    let scheme = if tls { "https" } else { "http" };
+
+    let mut checksums = Vec::with_capacity(members.len());
+    let mut missing_nodes = Vec::new();
+    for (id, addr_api) in members {
+        if id == state.id {
+            checksums.push((id, local.clone()));
+            continue;
+        }
+
+        let url = format!(
+            "{}://{}/cluster/checksum/{}/{}",
+            scheme,
+            addr_api,
+            raft_type.as_str(),
+            log_index
+        );
+        match fetch_checksum(state, client, &url).await {
+            Some(checksum) => checksums.push((id, checksum)),
+            None => {
+                warn!(
+                    "Node {} did not return its checksum at index {}",
+                    id, log_index
+                );
+                missing_nodes.push(id);
+            }
+        }
+    }
+
+    // The majority needs to be built from all members, otherwise we cannot say which side is
+    // the divergent one.
+    let quorum = (checksums.len() + missing_nodes.len()) / 2 + 1;
+    let majority = checksums
+        .iter()
+        .map(|(_, checksum)| &checksum.hash)
+        .find(|hash| checksums.iter().filter(|(_, c)| &c.hash == *hash).count() >= quorum)
+        .and_then(|hash| checksums.iter().find(|(_, c)| &c.hash == hash))
+        .map(|(_, checksum)| checksum.clone());
+
+    let mut divergent_nodes = Vec::new();
+    let mut divergent_tables = BTreeSet::new();
+    match &majority {
+        Some(majority) => {
+            for (id, checksum) in &checksums {
+                if checksum.hash != majority.hash {
+                    divergent_nodes.push(*id);
+                    divergent_tables.extend(majority.diff(checksum));
+                }
+            }
+        }
+        None => warn!(
+            "No majority for the checksum at index {} - cannot check for divergence",
+            log_index
+        ),
+    }
+
+    if divergent_nodes.is_empty() {
+        debug!(
+            "Divergence check at index {} successful: {}",
+            log_index, local.hash
+        );
+    } else {
+        error!(
+            "Data diverged from the majority at index {} on nodes {:?} in tables {:?}",
+            log_index, divergent_nodes, divergent_tables
+        );
+    }
+
+    raft_db.checksums.update_metrics(|m| {
+        m.checks += 1;
+        m.last_log_index = Some(log_index);
+        m.last_hash = Some(local.hash.clone());
+        m.divergent_nodes = divergent_nodes.clone();
+        m.divergent_tables = divergent_tables.into_iter().collect();
+        m.missing_nodes = missing_nodes;
+    });
+
+    // Only a leader with the majority data may be the re-sync source, which makes sure that
+    // exactly one node proposes it.
+    if resync
+        && majority.is_some_and(|m| m.hash == local.hash)
+        && raft_db.raft.current_leader().await == Some(state.id)
+    {
+        for target in divergent_nodes {
+            info!("Proposing re-sync of divergent node {}", target);
+            raft_db
+                .client_write(QueryWrite::Resync {
+                    source: state.id,
+                    target,
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn fetch_checksum(
+    state: &AppState,
+    client: &reqwest::Client,
+    url: &str,
+) -> Option<DbChecksum> {
+    for _ in 0..FETCH_RETRIES {
+        let res = client
+            .get(url)
+            .header(HEADER_NAME_SECRET, &state.secret_api)
+            .send()
+            .await;
+
+        match res {
+            Ok(resp) if resp.status().is_success() => {
+                let bytes = resp.bytes().await.ok()?;
+                match bincode::deserialize::<Option<DbChecksum>>(&bytes) {
+                    Ok(Some(checksum)) => return Some(checksum),
+                    // the remote node has not applied the log index yet
+                    Ok(None) => {}
+                    Err(err) => {
+                        error!("Error deserializing checksum from {}: {}", url, err);
+                        return None;
+                    }
+                }
+            }
+            Ok(resp) => {
+                let err = resp.json::<Error>().await;
+                error!("Error checksum lookup to {}: {:?}", url, err);
+            }
+            Err(err) => debug!("Error checksum lookup to {}: {}", url, err),
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+    }
+
+    None
+}
+
+/// Downloads the database copy for each re-sync requested by the local state machine.
+async fn handle_resyncs(
+    state: Arc<AppState>,
+    client: reqwest::Client,
+    raft_type: RaftType,
+    group: u16,
+    tls: bool,
+) {
+    let raft_db = raft_db(&state, group);
+
+    loop {
+        let req = raft_db.checksums.next_resync().await;
+        let res = download_resync(
+            raft_db,
+            &client,
+            &state.secret_api,
+            &raft_type,
+            tls,
+            req.source,
+            req.log_index,
+        )
+        .await;
+        let is_ok = res.is_ok();
+
+        if req.ack.send(res).is_err() {
+            error!("State machine stopped waiting for the re-sync");
+        } else if is_ok {
+            // the local snapshot still contains the divergent data
+            if let Err(err) = raft_db.raft.trigger().snapshot().await {
+                error!("Error triggering snapshot after re-sync: {}", err);
+            }
+        }
+    }
+}
+
+async fn download_resync(
+    raft_db: &StateRaftDB,
+    client: &reqwest::Client,
+    secret_api: &str,
+    raft_type: &RaftType,
+    tls: bool,
+    source: NodeId,
+    log_index: u64,
+) -> Result<String, Error> {
+    let addr_api = raft_db
+        .raft
+        .metrics()
+        .borrow()
+        .membership_config
+        .nodes()
+        .find(|(id, _)| **id == source)
+        .map(|(_, node)| node.addr_api.clone())
+        .ok_or_else(|| Error::Divergence(format!("node {} is not a member", source).into()))?;
+
+    let scheme = if tls { "https" } else { "http" };
+    let url = format!(
+        "{}://{}/cluster/resync/{}/{}",
+        scheme,
+        addr_api,
+        raft_type.as_str(),
+        log_index
+    );
+    let mut resp = client
+        .get(&url)
+        .header(HEADER_NAME_SECRET, secret_api)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(resp.json::<Error>().await?);
+    }
+
+    let path = checksum::resync_path(&raft_db.path_snapshots, log_index);
+    let mut file = fs::File::create(&path).await?;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+
+    Ok(path)
+}
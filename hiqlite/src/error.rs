@@ -39,9 +39,17 @@ pub enum Error {
     /// Sqlite constraint violation
     #[error("Connect: {0}")]
     ConstraintViolation(String),
+    /// A write exceeded the `write_cost_limit` and has been aborted on all nodes.
+    #[cfg(feature = "sqlite")]
+    #[error("CostLimit: {0}")]
+    CostLimit(Cow<'static, str>),
     #[cfg(any(feature = "dashboard", feature = "s3"))]
     #[error("Cryptr: {0}")]
     Cryptr(String),
+    /// The data of at least one node differs from the others after applying the same logs.
+    #[cfg(feature = "sqlite")]
+    #[error("Divergence: {0}")]
+    Divergence(Cow<'static, str>),
     #[error("Error: {0}")]
     Error(Cow<'static, str>),
     #[error("InitializeError: {0}")]
@@ -49,6 +57,19 @@ pub enum Error {
     /// Error informing about a Raft leader change
     #[error("LeaderChange: {0}")]
     LeaderChange(Cow<'static, str>),
+    /// A write statement which would produce different results on each node, for instance
+    /// because of a timezone-dependent date and time modifier like `'localtime'`.
+    #[cfg(feature = "sqlite")]
+    #[error("NonDeterministic: {0}")]
+    NonDeterministic(Cow<'static, str>),
+    /// A `Guard` inside a conditional transaction did not match. `index` is the position of the
+    /// failing guard, counted over all guards of the transaction in the order they were given.
+    #[cfg(feature = "sqlite")]
+    #[error("PreconditionFailed: guard {index}: {reason}")]
+    PreconditionFailed {
+        index: usize,
+        reason: Cow<'static, str>,
+    },
     /// Error when the given query parameters could not be bound properly to the prepared statement.
     #[error("QueryParams: {0}")]
     QueryParams(Cow<'static, str>),
@@ -122,9 +143,17 @@ impl IntoResponse for Error {
             Error::Channel(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::CheckIsLeaderError(_) => StatusCode::CONFLICT,
             Error::ConstraintViolation(_) => StatusCode::BAD_REQUEST,
+            #[cfg(feature = "sqlite")]
+            Error::CostLimit(_) => StatusCode::BAD_REQUEST,
             #[cfg(any(feature = "dashboard", feature = "s3"))]
             Error::Cryptr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            #[cfg(feature = "sqlite")]
+            Error::Divergence(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::LeaderChange(_) => StatusCode::CONFLICT,
+            #[cfg(feature = "sqlite")]
+            Error::NonDeterministic(_) => StatusCode::BAD_REQUEST,
+            #[cfg(feature = "sqlite")]
+            Error::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
             Error::QueryParams(_) => StatusCode::BAD_REQUEST,
             Error::QueryReturnedNoRows(_) => StatusCode::NOT_FOUND,
             Error::PrepareStatement(_) => StatusCode::BAD_REQUEST,
@@ -283,14 +312,6 @@ impl From<rusqlite::Error> for Error {
 //     }
 // }
 
-#[cfg(feature = "sqlite")]
-impl From<deadpool::unmanaged::PoolError> for Error {
-    fn from(value: deadpool::unmanaged::PoolError) -> Self {
-        trace!("Sqlite: {}", value);
-        Self::Sqlite(value.to_string().into())
-    }
-}
-
 impl From<serde_json::Error> for Error {
     fn from(value: serde_json::Error) -> Self {
         trace!("BadRequest: {}", value);
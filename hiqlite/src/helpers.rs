@@ -11,8 +11,9 @@ pub async fn is_raft_initialized(
 ) -> Result<bool, Error> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => {
-            if !state.raft_db.raft.is_initialized().await? {
+        RaftType::Sqlite | RaftType::Shard(_) => {
+            let raft_db = state.raft_db_for(raft_type)?;
+            if !raft_db.raft.is_initialized().await? {
                 Ok(false)
             } else {
                 /*
@@ -26,7 +27,7 @@ pub async fn is_raft_initialized(
                 data like logs and membership config.
                  */
 
-                let metrics = state.raft_db.raft.server_metrics().borrow().clone();
+                let metrics = raft_db.raft.server_metrics().borrow().clone();
 
                 #[cfg(debug_assertions)]
                 if metrics.current_leader.is_none()
@@ -61,7 +62,14 @@ pub async fn is_raft_initialized(
 pub async fn get_raft_leader(state: &Arc<AppState>, raft_type: &RaftType) -> Option<u64> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => state.raft_db.raft.current_leader().await,
+        RaftType::Sqlite | RaftType::Shard(_) => {
+            state
+                .raft_db_for(raft_type)
+                .ok()?
+                .raft
+                .current_leader()
+                .await
+        }
         #[cfg(feature = "cache")]
         RaftType::Cache => state.raft_cache.raft.current_leader().await,
         RaftType::Unknown => panic!("neither `sqlite` nor `cache` feature enabled"),
@@ -71,12 +79,17 @@ pub async fn get_raft_leader(state: &Arc<AppState>, raft_type: &RaftType) -> Opt
 pub async fn get_raft_metrics(
     state: &Arc<AppState>,
     raft_type: &RaftType,
-) -> RaftMetrics<u64, Node> {
+) -> Result<RaftMetrics<u64, Node>, Error> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => state.raft_db.raft.metrics().borrow().clone(),
+        RaftType::Sqlite | RaftType::Shard(_) => Ok(state
+            .raft_db_for(raft_type)?
+            .raft
+            .metrics()
+            .borrow()
+            .clone()),
         #[cfg(feature = "cache")]
-        RaftType::Cache => state.raft_cache.raft.metrics().borrow().clone(),
+        RaftType::Cache => Ok(state.raft_cache.raft.metrics().borrow().clone()),
         RaftType::Unknown => panic!("neither `sqlite` nor `cache` feature enabled"),
     }
 }
@@ -86,12 +99,14 @@ pub async fn get_raft_metrics(
 pub async fn lock_raft<'a>(
     state: &'a Arc<AppState>,
     raft_type: &'a RaftType,
-) -> MutexGuard<'a, ()> {
+) -> Result<MutexGuard<'a, ()>, Error> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => state.raft_db.lock.lock().await,
+        RaftType::Sqlite | RaftType::Shard(_) => {
+            Ok(state.raft_db_for(raft_type)?.lock.lock().await)
+        }
         #[cfg(feature = "cache")]
-        RaftType::Cache => state.raft_cache.lock.lock().await,
+        RaftType::Cache => Ok(state.raft_cache.lock.lock().await),
         RaftType::Unknown => panic!("neither `sqlite` nor `cache` feature enabled"),
     }
 }
@@ -103,8 +118,12 @@ pub async fn add_new_learner(
 ) -> Result<(), Error> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => {
-            state.raft_db.raft.add_learner(node.id, node, true).await?;
+        RaftType::Sqlite | RaftType::Shard(_) => {
+            state
+                .raft_db_for(raft_type)?
+                .raft
+                .add_learner(node.id, node, true)
+                .await?;
             Ok(())
         }
         #[cfg(feature = "cache")]
@@ -128,9 +147,9 @@ pub async fn change_membership(
 ) -> Result<(), Error> {
     match raft_type {
         #[cfg(feature = "sqlite")]
-        RaftType::Sqlite => {
+        RaftType::Sqlite | RaftType::Shard(_) => {
             state
-                .raft_db
+                .raft_db_for(raft_type)?
                 .raft
                 .change_membership(members, retain)
                 .await?;
@@ -25,6 +25,7 @@ pub type IsPristineNode1 = bool;
 #[cfg(feature = "sqlite")]
 pub async fn init_pristine_node_1_db(
     raft: &openraft::Raft<TypeConfigSqlite>,
+    raft_type: &RaftType,
     this_node: u64,
     nodes: &[Node],
     secret_api: &str,
@@ -35,17 +36,19 @@ pub async fn init_pristine_node_1_db(
         let this_node = get_this_node(this_node, nodes);
 
         if is_initialized_timeout_sqlite(raft).await? {
-            info!("node 1 raft is already initialized");
+            info!("node 1 {} raft is already initialized", raft_type.as_str());
             return Ok(());
         }
 
-        if should_node_1_skip_init(&RaftType::Sqlite, nodes, secret_api, tls, tls_no_verify).await?
-        {
-            info!("node 1 (DB) should skip its own init - found existing cluster on remotes");
+        if should_node_1_skip_init(raft_type, nodes, secret_api, tls, tls_no_verify).await? {
+            info!(
+                "node 1 ({}) should skip its own init - found existing cluster on remotes",
+                raft_type.as_str()
+            );
             return Ok(());
         }
 
-        info!("initializing pristine node 1 raft");
+        info!("initializing pristine node 1 {} raft", raft_type.as_str());
         let mut nodes_set = BTreeMap::new();
         nodes_set.insert(this_node.id, this_node);
         raft.initialize(nodes_set).await?;
@@ -212,7 +215,8 @@ pub async fn become_cluster_member(
     // In this situation, the node will always be initialized but will fail joining its own,
     // not yet existent cluster later on in the client.
     #[cfg(feature = "sqlite")]
-    let check_init = raft_type == &RaftType::Sqlite || is_pristine_cache_node_1;
+    let check_init =
+        matches!(raft_type, RaftType::Sqlite | RaftType::Shard(_)) || is_pristine_cache_node_1;
     #[cfg(not(feature = "sqlite"))]
     let check_init = is_pristine_cache_node_1;
 
@@ -360,7 +364,8 @@ async fn try_become(
                             if leader_id == this_node {
                                 if !helpers::is_raft_initialized(state, raft_type).await? {
                                     let leader = helpers::get_raft_leader(state, raft_type).await;
-                                    let metrics = helpers::get_raft_metrics(state, raft_type).await;
+                                    let metrics =
+                                        helpers::get_raft_metrics(state, raft_type).await?;
 
                                     panic!(
                                         r#"
@@ -25,12 +25,33 @@ pub use strum::EnumIter;
 #[cfg(feature = "dlock")]
 pub use client::dlock::Lock;
 
+#[cfg(feature = "backup")]
+pub use crate::backup::BackupConfig;
+#[cfg(feature = "sqlite")]
+pub use crate::client::{db::ClientDb, query_stream::RowStream};
 #[cfg(feature = "sqlite")]
-pub use crate::query::rows::Row;
+pub use crate::query::{
+    rows::{FromRow, Row},
+    staleness::MaxLag,
+};
 #[cfg(feature = "sqlite")]
-pub use crate::store::state_machine::sqlite::{param::Param, state_machine::Params};
+pub use crate::store::state_machine::sqlite::{
+    checksum::{DbChecksum, DivergenceMetrics},
+    cost::WriteCostMetrics,
+    functions::SqlFunctions,
+    guard::{Guard, GuardExpect},
+    param::Param,
+    pragmas::{AutoVacuum, SqliteConfig, SqlitePragmas, SqlitePreset, Synchronous, TempStore},
+    reader::ReadPoolMetrics,
+    state_machine::{Indexed, Params},
+};
 #[cfg(feature = "sqlite")]
 pub use migration::AppliedMigration;
+#[cfg(feature = "sqlite")]
+pub use rusqlite::functions::{Aggregate, Context as FunctionContext};
+
+#[cfg(feature = "derive")]
+pub use hiqlite_macros::FromRow;
 
 // TODO remove after enough crash testing and making sure we can never get into a
 // split brain situation
@@ -63,7 +84,11 @@ mod backup;
 #[cfg(feature = "dashboard")]
 mod dashboard;
 #[cfg(feature = "sqlite")]
+mod divergence_check;
+#[cfg(feature = "sqlite")]
 mod migration;
+#[cfg(feature = "backup")]
+mod pitr;
 #[cfg(feature = "sqlite")]
 mod query;
 
@@ -108,6 +133,29 @@ macro_rules! params {
     };
 }
 
+/// Helper macro to create named Params, which are bound to the placeholder with the same name
+/// instead of their position. Names may be given with or without the `:`, `$` or `@` prefix.
+///
+/// ```rust, notest
+/// client.execute(
+///     "INSERT INTO test (id, name) VALUES (:id, :name)",
+///     named_params!("id" => 1, "name" => "Some Name"),
+/// ).await?;
+/// ```
+#[macro_export]
+macro_rules! named_params {
+    ( $( $name:expr => $param:expr ),* $(,)? ) => {
+        {
+            #[allow(unused_mut)]
+            let mut params = Vec::with_capacity(2);
+            $(
+                params.push(Param::named($name, $param));
+            )*
+            params
+        }
+    };
+}
+
 /// A Raft / Hiqlite node
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct Node {
@@ -26,9 +26,17 @@ use crate::store::state_machine::memory::dlock_handler::{
 
 #[cfg(feature = "sqlite")]
 use crate::{
+    app_state::StateRaftDB,
     migration::Migration,
-    query::{query_consistent_local, query_owned_local, rows::RowOwned},
-    store::state_machine::sqlite::state_machine::{Query, QueryWrite},
+    network::running::RunningQueries,
+    query::{
+        query_consistent_local, query_owned_local, query_stream_local,
+        rows::{ResultSet, RowOwned, ValueOwned},
+    },
+    store::state_machine::sqlite::{
+        guard::GuardedQuery,
+        state_machine::{Indexed, Query, QueryMany, QueryWrite},
+    },
 };
 
 #[cfg(feature = "listen_notify")]
@@ -265,6 +273,7 @@ pub async fn stream(
     Path(raft_type): Path<RaftType>,
     ws: upgrade::IncomingUpgrade,
 ) -> Result<impl IntoResponse, Error> {
+    state.check_raft_type(&raft_type)?;
     let (response, socket) = ws.upgrade()?;
 
     tokio::task::spawn(async move {
@@ -287,16 +296,43 @@ pub(crate) struct ApiStreamRequest {
 pub(crate) enum ApiStreamRequestPayload {
     #[cfg(feature = "sqlite")]
     Execute(Query),
+    /// Answered with `ApiStreamResponsePayload::Execute`
+    #[cfg(feature = "sqlite")]
+    ExecuteMany(QueryMany),
     #[cfg(feature = "sqlite")]
     ExecuteReturning(Query),
     #[cfg(feature = "sqlite")]
     Transaction(Vec<Query>),
     #[cfg(feature = "sqlite")]
+    TransactionGuarded(Vec<GuardedQuery>),
+    #[cfg(feature = "sqlite")]
     QueryConsistent(Query),
     #[cfg(feature = "sqlite")]
     Batch(std::borrow::Cow<'static, str>),
     #[cfg(feature = "sqlite")]
     Migrate(Vec<Migration>),
+    #[cfg(feature = "sqlite")]
+    QueryStream(QueryStreamReq),
+    /// A write for the named database
+    #[cfg(feature = "sqlite")]
+    DbWrite(Option<String>, QueryWrite),
+    /// Answered with `ApiStreamResponsePayload::Query`
+    #[cfg(feature = "sqlite")]
+    DbQuery(String, Query),
+    /// Answered with `ApiStreamResponsePayload::QueryConsistent`
+    #[cfg(feature = "sqlite")]
+    DbQueryConsistent(String, Query),
+    /// A query with a timeout in ms, answered with `ApiStreamResponsePayload::Query`
+    #[cfg(feature = "sqlite")]
+    QueryTimeout(Query, u64),
+    /// A consistent query with a timeout in ms, answered with
+    /// `ApiStreamResponsePayload::QueryConsistent`
+    #[cfg(feature = "sqlite")]
+    QueryConsistentTimeout(Query, u64),
+    /// Interrupts the running query with the same `request_id`, because the client is not
+    /// waiting for it anymore. It has no response.
+    #[cfg(feature = "sqlite")]
+    Cancel,
 
     #[cfg(feature = "backup")]
     Backup(crate::NodeId),
@@ -315,6 +351,22 @@ pub(crate) enum ApiStreamRequestPayload {
     Notify(CacheRequest),
 }
 
+#[cfg(feature = "sqlite")]
+impl ApiStreamRequestPayload {
+    /// Read-only requests, which can be cancelled by the client
+    pub(crate) fn is_query(&self) -> bool {
+        matches!(
+            self,
+            Self::Query(_)
+                | Self::QueryConsistent(_)
+                | Self::QueryTimeout(_, _)
+                | Self::QueryConsistentTimeout(_, _)
+                | Self::DbQuery(_, _)
+                | Self::DbQueryConsistent(_, _)
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct ApiStreamResponse {
     pub(crate) request_id: usize,
@@ -324,19 +376,24 @@ pub(crate) struct ApiStreamResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) enum ApiStreamResponsePayload {
     #[cfg(feature = "sqlite")]
-    Execute(Result<usize, Error>),
+    Execute(Result<Indexed<usize>, Error>),
+    #[cfg(feature = "sqlite")]
+    ExecuteReturning(Result<Indexed<ResultSet<Result<Vec<ValueOwned>, Error>>>, Error>),
+    #[cfg(feature = "sqlite")]
+    Transaction(Result<Indexed<Vec<Result<usize, Error>>>, Error>),
     #[cfg(feature = "sqlite")]
-    ExecuteReturning(Result<Vec<Result<RowOwned, Error>>, Error>),
+    Query(Result<ResultSet, Error>),
     #[cfg(feature = "sqlite")]
-    Transaction(Result<Vec<Result<usize, Error>>, Error>),
+    QueryConsistent(Result<ResultSet, Error>),
     #[cfg(feature = "sqlite")]
-    Query(Result<Vec<RowOwned>, Error>),
+    Batch(Result<Indexed<Vec<Result<usize, Error>>>, Error>),
     #[cfg(feature = "sqlite")]
-    QueryConsistent(Result<Vec<RowOwned>, Error>),
+    Migrate(Result<Indexed<()>, Error>),
+    /// The next chunk of a query stream, `None` if the stream has ended
     #[cfg(feature = "sqlite")]
-    Batch(Result<Vec<Result<usize, Error>>, Error>),
+    QueryStream(Result<Option<ResultSet>, Error>),
     #[cfg(feature = "sqlite")]
-    Migrate(Result<(), Error>),
+    DbWrite(Result<Indexed<crate::Response>, Error>),
 
     #[cfg(feature = "backup")]
     Backup(Result<(), Error>),
@@ -351,6 +408,24 @@ pub(crate) enum ApiStreamResponsePayload {
     Notify(Result<(), Error>),
 }
 
+/// Requests for a server side query cursor. The `stream_id` is chosen by the client and is only
+/// valid for the current WebSocket connection.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum QueryStreamReq {
+    Open {
+        stream_id: usize,
+        query: Query,
+        chunk_size: usize,
+    },
+    Next(usize),
+    Close(usize),
+}
+
+/// Open query cursors for a single WebSocket connection
+#[cfg(feature = "sqlite")]
+type QueryCursors = crate::network::cursors::QueryCursors<crate::query::QueryStreamRx>;
+
 #[derive(Debug)]
 pub(crate) enum WsWriteMsg {
     Payload(ApiStreamResponse),
@@ -386,9 +461,15 @@ async fn handle_socket_concurrent(
         // if we received one which is being executed and the TCP stream dies in between, we MUST
         // ENSURE that in case it was an Ok(_), the result gets to the client! Otherwise, with retry
         // logic we might end up modifying something twice!
-        let mut buf = {
-            let mut map = state.get_buf_lock(&raft_type).await;
-            map.remove(&client_id).unwrap_or_default()
+        let mut buf = match state.get_buf_lock(&raft_type).await {
+            Ok(mut map) => map.remove(&client_id).unwrap_or_default(),
+            Err(err) => {
+                error!("Error opening the client stream: {}", err);
+                write
+                    .write_frame(Frame::close(1000, b"Invalid Raft Type"))
+                    .await?;
+                return Ok(());
+            }
         };
 
         info!("Emptying buffered Client Stream responses");
@@ -404,6 +485,13 @@ async fn handle_socket_concurrent(
         }
     }
 
+    // Dropping the cursors when the connection is closed will cancel all running query streams.
+    // Each one keeps a reader busy, so at least one is always left for all other queries.
+    #[cfg(feature = "sqlite")]
+    let cursors = QueryCursors::new(state.raft_db.read_pool.max().saturating_sub(1));
+    #[cfg(feature = "sqlite")]
+    let running = RunningQueries::default();
+
     let st = state.clone();
     let handle_write = task::spawn(async move {
         let mut buf = VecDeque::default();
@@ -438,15 +526,17 @@ async fn handle_socket_concurrent(
             }
         }
 
-        {
-            let mut lock = st.get_buf_lock(&raft_type).await;
-            let old = lock.insert(client_id, buf);
-            assert!(
-                old.is_none() || old == Some(VecDeque::default()),
-                "client buffer for {} should never exist already when we insert a new one:\n{:?}",
-                raft_type.as_str(),
-                old
-            );
+        match st.get_buf_lock(&raft_type).await {
+            Ok(mut lock) => {
+                let old = lock.insert(client_id, buf);
+                assert!(
+                    old.is_none() || old == Some(VecDeque::default()),
+                    "client buffer for {} should never exist already when we insert a new one:\n{:?}",
+                    raft_type.as_str(),
+                    old
+                );
+            }
+            Err(err) => error!("Cannot buffer client stream responses: {}", err),
         }
 
         let _ = write
@@ -490,24 +580,51 @@ async fn handle_socket_concurrent(
             }
         };
 
+        #[cfg(feature = "sqlite")]
+        if let ApiStreamRequestPayload::Cancel = req.payload {
+            running.cancel(req.request_id);
+            continue;
+        }
+        #[cfg(feature = "sqlite")]
+        let is_query = req.payload.is_query();
+
         let state = state.clone();
+        let raft_type = raft_type.clone();
         let tx_write = tx_write.clone();
-        task::spawn(async move {
-            let request_id = req.request_id;
+        #[cfg(feature = "sqlite")]
+        let cursors = cursors.clone();
+        let request_id = req.request_id;
+        let fut = async move {
+            #[cfg(feature = "sqlite")]
+            let raft_db = match state.raft_db_for(&raft_type) {
+                Ok(raft_db) => raft_db,
+                Err(err) => {
+                    error!("{}", err);
+                    let _ = tx_write.send_async(WsWriteMsg::Break).await;
+                    return;
+                }
+            };
 
             let res = match req.payload {
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::Execute(sql) => {
-                    match state
-                        .raft_db
-                        .raft
-                        .client_write(QueryWrite::Execute(sql))
-                        .await
-                    {
+                    let res = raft_db.execute(sql).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::Execute(res),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::ExecuteMany(query) => {
+                    match raft_db.client_write(QueryWrite::ExecuteMany(query)).await {
                         Ok(resp) => {
+                            let log_index = resp.log_id.index;
                             let resp: crate::Response = resp.data;
                             let res = match resp {
-                                crate::Response::Execute(res) => res.result,
+                                crate::Response::Execute(res) => {
+                                    res.result.map(|res| Indexed::new(res, log_index))
+                                }
                                 _ => unreachable!(),
                             };
                             ApiStreamResponse {
@@ -524,13 +641,12 @@ async fn handle_socket_concurrent(
 
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::ExecuteReturning(sql) => {
-                    match state
-                        .raft_db
-                        .raft
+                    match raft_db
                         .client_write(QueryWrite::ExecuteReturning(sql))
                         .await
                     {
                         Ok(resp) => {
+                            let log_index = resp.log_id.index;
                             let resp: crate::Response = resp.data;
                             let res = match resp {
                                 crate::Response::ExecuteReturning(res) => res.result,
@@ -538,7 +654,9 @@ async fn handle_socket_concurrent(
                             };
                             ApiStreamResponse {
                                 request_id,
-                                result: ApiStreamResponsePayload::ExecuteReturning(res),
+                                result: ApiStreamResponsePayload::ExecuteReturning(
+                                    res.map(|rows| Indexed::new(ResultSet::from(rows), log_index)),
+                                ),
                             }
                         }
                         Err(err) => ApiStreamResponse {
@@ -552,16 +670,41 @@ async fn handle_socket_concurrent(
 
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::Transaction(queries) => {
-                    match state
-                        .raft_db
-                        .raft
-                        .client_write(QueryWrite::Transaction(queries))
+                    match raft_db.client_write(QueryWrite::Transaction(queries)).await {
+                        Ok(resp) => {
+                            let log_index = resp.log_id.index;
+                            let resp: crate::Response = resp.data;
+                            let res = match resp {
+                                crate::Response::Transaction(res) => {
+                                    res.map(|res| Indexed::new(res, log_index))
+                                }
+                                _ => unreachable!(),
+                            };
+                            ApiStreamResponse {
+                                request_id,
+                                result: ApiStreamResponsePayload::Transaction(res),
+                            }
+                        }
+                        Err(err) => ApiStreamResponse {
+                            request_id,
+                            result: ApiStreamResponsePayload::Transaction(Err(Error::from(err))),
+                        },
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::TransactionGuarded(queries) => {
+                    match raft_db
+                        .client_write(QueryWrite::TransactionGuarded(queries))
                         .await
                     {
                         Ok(resp) => {
+                            let log_index = resp.log_id.index;
                             let resp: crate::Response = resp.data;
                             let res = match resp {
-                                crate::Response::Transaction(res) => res,
+                                crate::Response::Transaction(res) => {
+                                    res.map(|res| Indexed::new(res, log_index))
+                                }
                                 _ => unreachable!(),
                             };
                             ApiStreamResponse {
@@ -579,37 +722,132 @@ async fn handle_socket_concurrent(
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::QueryConsistent(Query { sql, params }) => {
                     let res = query_consistent_local(
-                        &state.raft_db.raft,
-                        state.raft_db.log_statements,
-                        state.raft_db.read_pool.clone(),
+                        &raft_db.raft,
+                        raft_db.lease.as_deref(),
+                        raft_db.log_statements,
+                        raft_db.read_pool.clone(),
                         sql,
                         params,
+                        raft_db.query_timeout,
                     )
                     .await;
 
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::QueryConsistent(res),
+                        result: ApiStreamResponsePayload::QueryConsistent(res.map(ResultSet::from)),
                     }
                 }
 
                 #[cfg(feature = "sqlite")]
-                ApiStreamRequestPayload::Batch(sql) => {
-                    match state
-                        .raft_db
-                        .raft
-                        .client_write(QueryWrite::Batch(sql))
+                ApiStreamRequestPayload::DbWrite(db, query) => {
+                    let res = raft_db
+                        .client_write_db(db, query)
                         .await
-                    {
+                        .map(|resp| Indexed::new(resp.data, resp.log_id.index));
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::DbWrite(res),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::DbQuery(db, Query { sql, params }) => {
+                    let res = match raft_db.named_read_pool(&db) {
+                        Ok(read_pool) => {
+                            query_owned_local(
+                                raft_db.log_statements,
+                                read_pool.clone(),
+                                sql,
+                                params,
+                                raft_db.query_timeout,
+                            )
+                            .await
+                        }
+                        Err(err) => Err(err),
+                    };
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::Query(res.map(ResultSet::from)),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::DbQueryConsistent(db, Query { sql, params }) => {
+                    let res = match raft_db.named_read_pool(&db) {
+                        Ok(read_pool) => {
+                            query_consistent_local(
+                                &raft_db.raft,
+                                raft_db.lease.as_deref(),
+                                raft_db.log_statements,
+                                read_pool.clone(),
+                                sql,
+                                params,
+                                raft_db.query_timeout,
+                            )
+                            .await
+                        }
+                        Err(err) => Err(err),
+                    };
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::QueryConsistent(res.map(ResultSet::from)),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::QueryTimeout(Query { sql, params }, timeout) => {
+                    let res = query_owned_local(
+                        raft_db.log_statements,
+                        raft_db.read_pool.clone(),
+                        sql,
+                        params,
+                        Some(Duration::from_millis(timeout)),
+                    )
+                    .await;
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::Query(res.map(ResultSet::from)),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::QueryConsistentTimeout(Query { sql, params }, timeout) => {
+                    let res = query_consistent_local(
+                        &raft_db.raft,
+                        raft_db.lease.as_deref(),
+                        raft_db.log_statements,
+                        raft_db.read_pool.clone(),
+                        sql,
+                        params,
+                        Some(Duration::from_millis(timeout)),
+                    )
+                    .await;
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::QueryConsistent(res.map(ResultSet::from)),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::Batch(sql) => {
+                    match raft_db.client_write(QueryWrite::Batch(sql)).await {
                         Ok(resp) => {
+                            let log_index = resp.log_id.index;
                             let resp: crate::Response = resp.data;
                             let res = match resp {
-                                crate::Response::Batch(res) => res,
+                                crate::Response::Batch(res) => {
+                                    res.result.map(|res| Indexed::new(res, log_index))
+                                }
                                 _ => unreachable!(),
                             };
                             ApiStreamResponse {
                                 request_id,
-                                result: ApiStreamResponsePayload::Batch(res.result),
+                                result: ApiStreamResponsePayload::Batch(res),
                             }
                         }
                         Err(err) => ApiStreamResponse {
@@ -621,16 +859,17 @@ async fn handle_socket_concurrent(
 
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::Migrate(migrations) => {
-                    match state
-                        .raft_db
-                        .raft
+                    match raft_db
                         .client_write(QueryWrite::Migration(migrations))
                         .await
                     {
                         Ok(resp) => {
+                            let log_index = resp.log_id.index;
                             let resp: crate::Response = resp.data;
                             let res = match resp {
-                                crate::Response::Migrate(res) => res,
+                                crate::Response::Migrate(res) => {
+                                    res.map(|res| Indexed::new(res, log_index))
+                                }
                                 _ => unreachable!(),
                             };
                             ApiStreamResponse {
@@ -645,14 +884,25 @@ async fn handle_socket_concurrent(
                     }
                 }
 
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::QueryStream(stream_req) => {
+                    let res = query_stream_next(raft_db, &cursors, stream_req).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::QueryStream(
+                            res.map(|rows| rows.map(ResultSet::from)),
+                        ),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::Cancel => {
+                    unreachable!("Cancel is handled before spawning the request")
+                }
+
                 #[cfg(feature = "backup")]
                 ApiStreamRequestPayload::Backup(node_id) => {
-                    match state
-                        .raft_db
-                        .raft
-                        .client_write(QueryWrite::Backup(node_id))
-                        .await
-                    {
+                    match raft_db.client_write(QueryWrite::Backup(node_id)).await {
                         Ok(resp) => {
                             let resp: crate::Response = resp.data;
                             let res = match resp {
@@ -674,16 +924,17 @@ async fn handle_socket_concurrent(
                 #[cfg(feature = "sqlite")]
                 ApiStreamRequestPayload::Query(Query { sql, params }) => {
                     let res = query_owned_local(
-                        state.raft_db.log_statements,
-                        state.raft_db.read_pool.clone(),
+                        raft_db.log_statements,
+                        raft_db.read_pool.clone(),
                         sql,
                         params,
+                        raft_db.query_timeout,
                     )
                     .await;
 
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::Query(res),
+                        result: ApiStreamResponsePayload::Query(res.map(ResultSet::from)),
                     }
                 }
 
@@ -780,7 +1031,15 @@ async fn handle_socket_concurrent(
                     err
                 );
             }
-        });
+        };
+
+        // Only queries can be cancelled. Writes must always finish, even if the client is gone.
+        #[cfg(feature = "sqlite")]
+        if is_query {
+            running.spawn(request_id, fut);
+            continue;
+        }
+        task::spawn(fut);
     }
 
     // ignore the result in case the writer has already exited and drop the channel
@@ -792,3 +1051,54 @@ async fn handle_socket_concurrent(
 
     Ok(())
 }
+
+#[cfg(feature = "sqlite")]
+async fn query_stream_next(
+    raft_db: &StateRaftDB,
+    cursors: &QueryCursors,
+    req: QueryStreamReq,
+) -> Result<Option<Vec<RowOwned>>, Error> {
+    let (stream_id, rx) = match req {
+        QueryStreamReq::Open {
+            stream_id,
+            query,
+            chunk_size,
+        } => {
+            cursors.open(stream_id)?;
+            let rx = query_stream_local(
+                raft_db.log_statements,
+                raft_db.read_pool.clone(),
+                query.sql,
+                query.params,
+                chunk_size,
+                raft_db.query_timeout,
+            )
+            .await
+            .inspect_err(|_| cursors.remove(stream_id))?;
+            (stream_id, rx)
+        }
+
+        QueryStreamReq::Next(stream_id) => (stream_id, cursors.take(stream_id)?),
+
+        QueryStreamReq::Close(stream_id) => {
+            cursors.remove(stream_id);
+            return Ok(None);
+        }
+    };
+
+    match rx.recv().await {
+        Some(Ok(rows)) => {
+            cursors.put(stream_id, rx);
+            Ok(Some(rows))
+        }
+        Some(Err(err)) => {
+            cursors.remove(stream_id);
+            Err(err)
+        }
+        // the blocking task has exited after the last chunk
+        None => {
+            cursors.remove(stream_id);
+            Ok(None)
+        }
+    }
+}
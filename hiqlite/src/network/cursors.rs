@@ -0,0 +1,144 @@
+use crate::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::{task, time};
+
+/// Query streams which have not been polled for this long will be closed, which cancels the
+/// running query and frees up its read connection.
+pub(crate) const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Cursor<C> {
+    /// `None` while a chunk is being fetched
+    inner: Option<C>,
+    last_used: Instant,
+}
+
+type Cursors<C> = Arc<Mutex<HashMap<usize, Cursor<C>>>>;
+
+/// Open query stream cursors for a single WebSocket connection. Dropping them when the
+/// connection is closed will cancel all running query streams.
+pub(crate) struct QueryCursors<C> {
+    cursors: Cursors<C>,
+    max: usize,
+}
+
+impl<C> Clone for QueryCursors<C> {
+    fn clone(&self) -> Self {
+        Self {
+            cursors: self.cursors.clone(),
+            max: self.max,
+        }
+    }
+}
+
+impl<C: Send + 'static> QueryCursors<C> {
+    /// Spawns a task in the background, which closes idle cursors. It exits as soon as all
+    /// handles to these cursors have been dropped.
+    ///
+    /// `max` is the limit of open cursors. Each local cursor keeps a reader busy until it has
+    /// been closed, so it must be below the size of the read pool.
+    pub fn new(max: usize) -> Self {
+        let cursors: Cursors<C> = Default::default();
+        task::spawn(evict_idle(Arc::downgrade(&cursors)));
+        Self {
+            cursors,
+            max: max.max(1),
+        }
+    }
+
+    /// Reserves a slot for a new cursor, or returns an error if the limit has been reached.
+    pub fn open(&self, stream_id: usize) -> Result<(), Error> {
+        let mut cursors = self.lock();
+        if cursors.len() >= self.max {
+            return Err(Error::BadRequest(
+                format!("max {} open query streams per connection reached", self.max).into(),
+            ));
+        }
+        cursors.insert(
+            stream_id,
+            Cursor {
+                inner: None,
+                last_used: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Takes the cursor out to fetch the next chunk. It must be given back with `put()`.
+    pub fn take(&self, stream_id: usize) -> Result<C, Error> {
+        self.lock()
+            .get_mut(&stream_id)
+            .and_then(|c| c.inner.take())
+            .ok_or_else(|| {
+                Error::Connect(format!(
+                    "query stream {} does not exist on this connection",
+                    stream_id
+                ))
+            })
+    }
+
+    /// Gives back a cursor after a chunk has been fetched. If it has been closed in the
+    /// meantime, it will be dropped.
+    pub fn put(&self, stream_id: usize, inner: C) {
+        if let Some(cursor) = self.lock().get_mut(&stream_id) {
+            cursor.inner = Some(inner);
+            cursor.last_used = Instant::now();
+        }
+    }
+
+    pub fn remove(&self, stream_id: usize) {
+        self.lock().remove(&stream_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, Cursor<C>>> {
+        self.cursors
+            .lock()
+            .expect("QueryCursors lock to never be poisoned")
+    }
+}
+
+async fn evict_idle<C>(cursors: Weak<Mutex<HashMap<usize, Cursor<C>>>>) {
+    let mut interval = time::interval(CURSOR_IDLE_TIMEOUT / 4);
+    loop {
+        interval.tick().await;
+        let Some(cursors) = cursors.upgrade() else {
+            return;
+        };
+        // cursors which are fetching a chunk right now are never idle
+        cursors
+            .lock()
+            .expect("QueryCursors lock to never be poisoned")
+            .retain(|_, c| c.inner.is_none() || c.last_used.elapsed() < CURSOR_IDLE_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_cursors() {
+        let cursors = QueryCursors::<u8>::new(4);
+
+        cursors.open(1).unwrap();
+        // a cursor is not available until the first chunk has been fetched
+        assert!(cursors.take(1).is_err());
+        cursors.put(1, 10);
+        assert_eq!(cursors.take(1).unwrap(), 10);
+        // taken cursors are not available twice
+        assert!(cursors.take(1).is_err());
+
+        // closing an in-flight cursor drops it when it is given back
+        cursors.remove(1);
+        cursors.put(1, 10);
+        assert!(cursors.take(1).is_err());
+
+        for id in 0..4 {
+            cursors.open(id).unwrap();
+        }
+        assert!(cursors.open(4).is_err());
+        cursors.remove(0);
+        cursors.open(4).unwrap();
+    }
+}
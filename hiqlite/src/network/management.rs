@@ -5,7 +5,7 @@ use crate::{helpers, Node};
 use axum::body;
 use axum::extract::Path;
 use axum::http::HeaderMap;
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
 use openraft::error::{CheckIsLeaderError, ForwardToLeader, RaftError};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
@@ -13,6 +13,13 @@ use std::time::Duration;
 use tokio::time;
 use tracing::{debug, error, info};
 
+#[cfg(feature = "sqlite")]
+use crate::store::state_machine::sqlite::{checksum, state_machine::RESYNC_TIMEOUT};
+#[cfg(feature = "sqlite")]
+use tokio::fs;
+#[cfg(feature = "sqlite")]
+use tokio_util::io::ReaderStream;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LearnerReq {
     pub node_id: u64,
@@ -27,6 +34,7 @@ pub(crate) async fn add_learner(
     body: body::Bytes,
 ) -> Result<Response, Error> {
     validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
 
     if !helpers::is_raft_initialized(&state, &raft_type).await? {
         return Err(Error::Error("Raft is not initialized".into()));
@@ -34,7 +42,7 @@ pub(crate) async fn add_learner(
 
     if let Some(leader_id) = helpers::get_raft_leader(&state, &raft_type).await {
         if leader_id != state.id {
-            let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+            let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
             let members = metrics.membership_config;
             let leader = members
                 .nodes()
@@ -69,9 +77,9 @@ pub(crate) async fn add_learner(
     // -> remove the membership and re-add it as a new learner, so it can catch up again.
     {
         // hold this lock the whole time, even over await points, to never have race conditions here ...
-        let lock = helpers::lock_raft(&state, &raft_type).await;
+        let lock = helpers::lock_raft(&state, &raft_type).await?;
 
-        let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+        let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
 
         // if raft_type == RaftType::Cache {
         //     info!("\n\n\nMetrics before member check:\n{:?}\n\n", metrics);
@@ -125,7 +133,7 @@ pub(crate) async fn add_learner(
 
                     info!("Membership changed successfully");
 
-                    let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+                    let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
                     let members = metrics.membership_config;
                     info!(
                         r#"
@@ -168,14 +176,15 @@ pub(crate) async fn become_member(
     body: body::Bytes,
 ) -> Result<Response, Error> {
     validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
 
     let payload = get_payload::<Node>(&headers, body)?;
     info!("Node membership request: {:?}\n", payload);
 
     // we want to hold the lock until we finished to not end up with race conditions
-    let _lock = helpers::lock_raft(&state, &raft_type).await;
+    let _lock = helpers::lock_raft(&state, &raft_type).await?;
 
-    let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+    let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
     let members = metrics.membership_config;
 
     let mut nodes_set = BTreeSet::new();
@@ -203,18 +212,19 @@ pub(crate) async fn get_membership(
     Path(raft_type): Path<RaftType>,
 ) -> Result<Response, Error> {
     validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
 
     if !helpers::is_raft_initialized(&state, &raft_type).await? {
         return Err(Error::Config("Raft node has not been initialized".into()));
     }
 
-    let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+    let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
     let mut members = metrics.membership_config;
 
     // it is possible to end up in a race condition on rolling releases
     if members.nodes().count() == 0 {
         time::sleep(Duration::from_millis(1000)).await;
-        let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+        let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
         members = metrics.membership_config;
         debug!("Membership after 1000ms timeout: {:?}", members);
 
@@ -235,6 +245,7 @@ pub(crate) async fn post_membership(
     body: body::Bytes,
 ) -> Result<Response, Error> {
     validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
 
     let payload = get_payload::<BTreeSet<NodeId>>(&headers, body)?;
     helpers::change_membership(&state, &raft_type, payload, false).await?;
@@ -261,6 +272,18 @@ pub(crate) async fn post_membership(
 //     }
 // }
 
+/// Get the amount of shard Raft groups this node is running.
+pub(crate) async fn shards(state: AppStateExt, headers: HeaderMap) -> Result<Response, Error> {
+    validate_secret(&state, &headers)?;
+
+    #[cfg(feature = "sqlite")]
+    let shards = state.raft_db_shards.len() as u16;
+    #[cfg(not(feature = "sqlite"))]
+    let shards = 0u16;
+
+    fmt_ok(headers, shards)
+}
+
 /// Get the latest metrics of the cluster
 pub(crate) async fn metrics(
     state: AppStateExt,
@@ -268,7 +291,53 @@ pub(crate) async fn metrics(
     Path(raft_type): Path<RaftType>,
 ) -> Result<Response, Error> {
     validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
 
-    let metrics = helpers::get_raft_metrics(&state, &raft_type).await;
+    let metrics = helpers::get_raft_metrics(&state, &raft_type).await?;
     fmt_ok(headers, &metrics)
 }
+
+/// Get the local checksum at the given applied log index for the divergence check
+#[cfg(feature = "sqlite")]
+pub(crate) async fn checksum(
+    state: AppStateExt,
+    headers: HeaderMap,
+    Path((raft_type, index)): Path<(RaftType, u64)>,
+) -> Result<Response, Error> {
+    validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
+
+    let checksum = state.raft_db_for(&raft_type)?.checksums.get(index);
+    fmt_ok(headers, checksum)
+}
+
+/// Download the database copy for re-syncing a divergent node at the given log index. The copy
+/// is created when this node applies the `Resync` entry, which may happen after the request
+/// has been received.
+#[cfg(feature = "sqlite")]
+pub(crate) async fn resync(
+    state: AppStateExt,
+    headers: HeaderMap,
+    Path((raft_type, index)): Path<(RaftType, u64)>,
+) -> Result<Response, Error> {
+    validate_secret(&state, &headers)?;
+    state.check_raft_type(&raft_type)?;
+
+    let raft_db = state.raft_db_for(&raft_type)?;
+
+    // The writer may still be busy with earlier log entries, which is why we wait as long as
+    // the target does.
+    raft_db
+        .checksums
+        .wait_resync_copy(index, RESYNC_TIMEOUT)
+        .await?;
+
+    let path = checksum::resync_path(&raft_db.path_snapshots, index);
+    let file = fs::File::open(&path).await.map_err(|_| {
+        Error::Divergence(format!("no re-sync copy available for log index {}", index).into())
+    })?;
+    // the open file can still be streamed after it has been removed
+    let _ = fs::remove_file(&path).await;
+
+    Ok(body::Body::from_stream(ReaderStream::new(file)).into_response())
+}
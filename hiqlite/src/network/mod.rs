@@ -14,12 +14,18 @@ pub use raft_client_split::NetworkStreaming;
 
 pub(crate) mod api;
 mod challenge_response;
+#[cfg(feature = "sqlite")]
+pub(crate) mod cursors;
 pub(crate) mod handshake;
 pub(crate) mod management;
 mod raft_client;
 mod raft_client_split;
 pub(crate) mod raft_server;
 pub(crate) mod raft_server_split;
+#[cfg(feature = "sqlite")]
+pub(crate) mod running;
+#[cfg(feature = "sqlite")]
+pub(crate) mod snapshot_chunk;
 
 pub(crate) type AppStateExt = axum::extract::State<Arc<AppState>>;
 // pub(crate) type RaftWriteResponse = ClientWriteResponse<TypeConfigSqlite>;
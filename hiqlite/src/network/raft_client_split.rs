@@ -17,7 +17,7 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::ops::Deref;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::oneshot;
@@ -28,7 +28,10 @@ use tracing::{error, info, warn};
 use crate::store::state_machine::memory::TypeConfigKV;
 
 #[cfg(feature = "sqlite")]
-use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::{
+    network::snapshot_chunk::SnapshotChunk, query::lease::LeaderLease,
+    store::state_machine::sqlite::TypeConfigSqlite,
+};
 
 use crate::app_state::RaftType;
 #[cfg(any(feature = "cache", feature = "sqlite"))]
@@ -61,7 +64,16 @@ pub struct NetworkStreaming {
     pub tls_config: Option<Arc<rustls::ClientConfig>>,
     pub secret_raft: Vec<u8>,
     pub raft_type: RaftType,
+    /// The SQLite Raft group id, `0` for the main Raft
+    #[cfg(feature = "sqlite")]
+    pub group: u16,
     pub heartbeat_interval: u64,
+    /// Leader lease, which will be updated with each acknowledged `append_entries`
+    #[cfg(feature = "sqlite")]
+    pub lease: Option<Arc<LeaderLease>>,
+    /// Compress snapshot chunks with zstd before sending them
+    #[cfg(feature = "sqlite")]
+    pub snapshot_compression: bool,
     // pub sender: flume::Sender<RaftRequest>,
 }
 
@@ -78,6 +90,8 @@ impl RaftNetworkFactory<TypeConfigKV> for NetworkStreaming {
         let task = tokio::task::spawn(Self::ws_handler(
             self.node_id,
             self.raft_type.clone(),
+            #[cfg(feature = "sqlite")]
+            self.group,
             node.clone(),
             self.tls_config.clone(),
             self.secret_raft.clone(),
@@ -89,6 +103,10 @@ impl RaftNetworkFactory<TypeConfigKV> for NetworkStreaming {
             node: node.clone(),
             sender,
             task: Some(task),
+            #[cfg(feature = "sqlite")]
+            lease: None,
+            #[cfg(feature = "sqlite")]
+            snapshot_compression: false,
         }
     }
 }
@@ -99,13 +117,17 @@ impl RaftNetworkFactory<TypeConfigSqlite> for NetworkStreaming {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn new_client(&mut self, _target: NodeId, node: &Node) -> Self::Network {
-        info!("Building new Raft DB client with target {}", node);
+        info!(
+            "Building new Raft DB client for group {} with target {}",
+            self.group, node
+        );
 
         let (sender, rx) = flume::bounded(2);
 
         let task = tokio::task::spawn(Self::ws_handler(
             self.node_id,
             self.raft_type.clone(),
+            self.group,
             node.clone(),
             self.tls_config.clone(),
             self.secret_raft.clone(),
@@ -117,6 +139,8 @@ impl RaftNetworkFactory<TypeConfigSqlite> for NetworkStreaming {
             node: node.clone(),
             sender,
             task: Some(task),
+            lease: self.lease.clone(),
+            snapshot_compression: self.snapshot_compression,
         }
     }
 }
@@ -141,7 +165,7 @@ enum RaftRequest {
     SnapshotDB(
         (
             oneshot::Sender<Result<RaftStreamResponsePayload, Error>>,
-            InstallSnapshotRequest<TypeConfigSqlite>,
+            SnapshotChunk,
         ),
     ),
 
@@ -194,6 +218,7 @@ impl NetworkStreaming {
     async fn ws_handler(
         this_node: NodeId,
         raft_type: RaftType,
+        #[cfg(feature = "sqlite")] group: u16,
         node: Node,
         tls_config: Option<Arc<rustls::ClientConfig>>,
         secret: Vec<u8>,
@@ -305,15 +330,15 @@ impl NetworkStreaming {
                 let stream_req = match req {
                     #[cfg(feature = "sqlite")]
                     RaftRequest::AppendDB((ack, req)) => {
-                        Some((ack, RaftStreamRequest::AppendDB((request_id, req))))
+                        Some((ack, RaftStreamRequest::AppendDB((request_id, group, req))))
                     }
                     #[cfg(feature = "sqlite")]
                     RaftRequest::VoteDB((ack, req)) => {
-                        Some((ack, RaftStreamRequest::VoteDB((request_id, req))))
+                        Some((ack, RaftStreamRequest::VoteDB((request_id, group, req))))
                     }
                     #[cfg(feature = "sqlite")]
                     RaftRequest::SnapshotDB((ack, req)) => {
-                        Some((ack, RaftStreamRequest::SnapshotDB((request_id, req))))
+                        Some((ack, RaftStreamRequest::SnapshotDB((request_id, group, req))))
                     }
 
                     #[cfg(feature = "cache")]
@@ -514,6 +539,10 @@ pub struct NetworkConnectionStreaming {
     node: Node,
     sender: flume::Sender<RaftRequest>,
     task: Option<JoinHandle<()>>,
+    #[cfg(feature = "sqlite")]
+    lease: Option<Arc<LeaderLease>>,
+    #[cfg(feature = "sqlite")]
+    snapshot_compression: bool,
 }
 
 impl Drop for NetworkConnectionStreaming {
@@ -551,6 +580,24 @@ impl NetworkConnectionStreaming {
             .map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))?
             .map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))
     }
+
+    /// Returns `None` if the receiver needs the data of the chunk.
+    #[cfg(feature = "sqlite")]
+    async fn send_snapshot_chunk(
+        &mut self,
+        chunk: SnapshotChunk,
+    ) -> Result<
+        Option<InstallSnapshotResponse<NodeId>>,
+        RPCError<NodeId, Node, RaftError<NodeId, InstallSnapshotError>>,
+    > {
+        let (ack, rx) = oneshot::channel();
+        match self.send(RaftRequest::SnapshotDB((ack, chunk)), rx).await? {
+            RaftStreamResponsePayload::SnapshotDB(resp) => {
+                resp.map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))
+            }
+            _ => unreachable!(),
+        }
+    }
 }
 
 #[cfg(feature = "sqlite")]
@@ -561,13 +608,30 @@ impl RaftNetwork<TypeConfigSqlite> for NetworkConnectionStreaming {
         req: AppendEntriesRequest<TypeConfigSqlite>,
         _option: RPCOption,
     ) -> Result<AppendEntriesResponse<NodeId>, RPCError<NodeId, Node, RaftError<NodeId>>> {
+        let term = req.vote.leader_id().term;
+        let sent = Instant::now();
+
         let (ack, rx) = oneshot::channel();
-        match self.send(RaftRequest::AppendDB((ack, req)), rx).await? {
+        let resp = match self.send(RaftRequest::AppendDB((ack, req)), rx).await? {
             RaftStreamResponsePayload::AppendDB(resp) => {
                 resp.map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))
             }
             _ => unreachable!(),
+        };
+
+        // Anything but a higher vote means the follower accepted us as the leader.
+        if let Some(lease) = &self.lease {
+            if matches!(
+                resp,
+                Ok(AppendEntriesResponse::Success
+                    | AppendEntriesResponse::PartialSuccess(_)
+                    | AppendEntriesResponse::Conflict)
+            ) {
+                lease.ack(self.node.id, term, sent);
+            }
         }
+
+        resp
     }
 
     #[tracing::instrument(level = "debug", skip_all, err(Debug))]
@@ -579,12 +643,27 @@ impl RaftNetwork<TypeConfigSqlite> for NetworkConnectionStreaming {
         InstallSnapshotResponse<NodeId>,
         RPCError<NodeId, Node, RaftError<NodeId, InstallSnapshotError>>,
     > {
-        let (ack, rx) = oneshot::channel();
-        match self.send(RaftRequest::SnapshotDB((ack, req)), rx).await? {
-            RaftStreamResponsePayload::SnapshotDB(resp) => {
-                resp.map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))
-            }
-            _ => unreachable!(),
+        let (probe, data) = task::spawn_blocking(move || SnapshotChunk::probe(req))
+            .await
+            .map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))?;
+
+        // The receiver may already have this chunk from an interrupted transfer or an older
+        // snapshot, in which case we don't need to send the data at all.
+        if let Some(resp) = self.send_snapshot_chunk(probe.clone()).await? {
+            return Ok(resp);
+        }
+
+        let compress = self.snapshot_compression;
+        let chunk = task::spawn_blocking(move || probe.with_data(data, compress))
+            .await
+            .map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))?
+            .map_err(|err| RPCError::Unreachable(Unreachable::new(&err)))?;
+
+        match self.send_snapshot_chunk(chunk).await? {
+            Some(resp) => Ok(resp),
+            None => Err(RPCError::Unreachable(Unreachable::new(&Error::Connect(
+                "snapshot chunk has been rejected because of an invalid checksum".to_string(),
+            )))),
         }
     }
 
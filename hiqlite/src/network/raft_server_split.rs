@@ -2,33 +2,35 @@ use crate::network::handshake::HandshakeSecret;
 use crate::network::{AppStateExt, Error};
 use axum::response::IntoResponse;
 use fastwebsockets::{upgrade, FragmentCollectorRead, Frame, OpCode, Payload};
-use openraft::error::{InstallSnapshotError, RaftError};
+use openraft::error::{Fatal, InstallSnapshotError, RaftError};
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 use tokio::task;
 use tracing::{error, warn};
 
 #[cfg(feature = "cache")]
-use crate::store::state_machine::memory::TypeConfigKV;
+use {crate::store::state_machine::memory::TypeConfigKV, openraft::raft::InstallSnapshotRequest};
 
 #[cfg(feature = "sqlite")]
-use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::{
+    network::snapshot_chunk::SnapshotChunk, store::state_machine::sqlite::TypeConfigSqlite,
+};
 
 #[cfg(any(feature = "cache", feature = "sqlite"))]
 use openraft::raft::{
-    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
-    VoteRequest, VoteResponse,
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotResponse, VoteRequest, VoteResponse,
 };
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum RaftStreamRequest {
+    /// `(request_id, group, request)`
     #[cfg(feature = "sqlite")]
-    AppendDB((usize, AppendEntriesRequest<TypeConfigSqlite>)),
+    AppendDB((usize, u16, AppendEntriesRequest<TypeConfigSqlite>)),
     #[cfg(feature = "sqlite")]
-    VoteDB((usize, VoteRequest<u64>)),
+    VoteDB((usize, u16, VoteRequest<u64>)),
     #[cfg(feature = "sqlite")]
-    SnapshotDB((usize, InstallSnapshotRequest<TypeConfigSqlite>)),
+    SnapshotDB((usize, u16, SnapshotChunk)),
 
     #[cfg(feature = "cache")]
     AppendCache((usize, AppendEntriesRequest<TypeConfigKV>)),
@@ -63,8 +65,9 @@ pub enum RaftStreamResponsePayload {
     AppendDB(Result<AppendEntriesResponse<u64>, RaftError<u64>>),
     #[cfg(feature = "sqlite")]
     VoteDB(Result<VoteResponse<u64>, RaftError<u64>>),
+    /// `None` if the data of the chunk is missing and needs to be sent
     #[cfg(feature = "sqlite")]
-    SnapshotDB(Result<InstallSnapshotResponse<u64>, RaftError<u64, InstallSnapshotError>>),
+    SnapshotDB(Result<Option<InstallSnapshotResponse<u64>>, RaftError<u64, InstallSnapshotError>>),
 
     #[cfg(feature = "cache")]
     AppendCache(Result<AppendEntriesResponse<u64>, RaftError<u64>>),
@@ -183,8 +186,27 @@ async fn handle_socket(
         task::spawn(async move {
             let bytes = match req {
                 #[cfg(feature = "sqlite")]
-                RaftStreamRequest::AppendDB((request_id, req)) => {
-                    let res = state.raft_db.raft.append_entries(req).await;
+                RaftStreamRequest::AppendDB((request_id, group, req)) => {
+                    let Some(raft_db) = state.raft_db_group(group) else {
+                        error!(
+                            "Received AppendEntriesRequest for unknown Raft group {}",
+                            group
+                        );
+                        let resp = RaftStreamResponse {
+                            request_id,
+                            payload: RaftStreamResponsePayload::AppendDB(Err(unknown_group())),
+                        };
+                        let _ = tx_write
+                            .send_async(WsWriteMsg::Payload(bincode::serialize(&resp).unwrap()))
+                            .await;
+                        return;
+                    };
+                    let term = req.vote.leader_id.term;
+                    let leader_commit = req.leader_commit.map(|id| id.index).unwrap_or(0);
+                    let res = raft_db.raft.append_entries(req).await;
+                    if matches!(res, Ok(AppendEntriesResponse::Success)) {
+                        raft_db.leader_contact.update(term, leader_commit);
+                    }
                     let resp = RaftStreamResponse {
                         request_id,
                         payload: RaftStreamResponsePayload::AppendDB(res),
@@ -192,8 +214,19 @@ async fn handle_socket(
                     bincode::serialize(&resp).unwrap()
                 }
                 #[cfg(feature = "sqlite")]
-                RaftStreamRequest::VoteDB((request_id, req)) => {
-                    let res = state.raft_db.raft.vote(req).await;
+                RaftStreamRequest::VoteDB((request_id, group, req)) => {
+                    let Some(raft_db) = state.raft_db_group(group) else {
+                        error!("Received VoteRequest for unknown Raft group {}", group);
+                        let resp = RaftStreamResponse {
+                            request_id,
+                            payload: RaftStreamResponsePayload::VoteDB(Err(unknown_group())),
+                        };
+                        let _ = tx_write
+                            .send_async(WsWriteMsg::Payload(bincode::serialize(&resp).unwrap()))
+                            .await;
+                        return;
+                    };
+                    let res = raft_db.raft.vote(req).await;
                     let resp = RaftStreamResponse {
                         request_id,
                         payload: RaftStreamResponsePayload::VoteDB(res),
@@ -201,8 +234,34 @@ async fn handle_socket(
                     bincode::serialize(&resp).unwrap()
                 }
                 #[cfg(feature = "sqlite")]
-                RaftStreamRequest::SnapshotDB((request_id, req)) => {
-                    let res = state.raft_db.raft.install_snapshot(req).await;
+                RaftStreamRequest::SnapshotDB((request_id, group, chunk)) => {
+                    let Some(raft_db) = state.raft_db_group(group) else {
+                        error!(
+                            "Received InstallSnapshotRequest for unknown Raft group {}",
+                            group
+                        );
+                        let resp = RaftStreamResponse {
+                            request_id,
+                            payload: RaftStreamResponsePayload::SnapshotDB(Err(unknown_group())),
+                        };
+                        let _ = tx_write
+                            .send_async(WsWriteMsg::Payload(bincode::serialize(&resp).unwrap()))
+                            .await;
+                        return;
+                    };
+                    let path_snapshots = raft_db.path_snapshots.clone();
+                    let decoded = task::spawn_blocking(move || chunk.into_request(&path_snapshots))
+                        .await
+                        .map_err(Error::from)
+                        .and_then(|res| res);
+                    let res = match decoded {
+                        Ok(Some(req)) => raft_db.raft.install_snapshot(req).await.map(Some),
+                        Ok(None) => Ok(None),
+                        Err(err) => {
+                            error!("Error decoding snapshot chunk: {}", err);
+                            Ok(None)
+                        }
+                    };
                     let resp = RaftStreamResponse {
                         request_id,
                         payload: RaftStreamResponsePayload::SnapshotDB(res),
@@ -260,3 +319,10 @@ async fn handle_socket(
 
     Ok(())
 }
+
+/// The error a Raft group which does not exist on this node answers with. The remote node will
+/// treat it as unreachable instead of waiting for a response forever.
+#[cfg(feature = "sqlite")]
+fn unknown_group<E>() -> RaftError<u64, E> {
+    RaftError::Fatal(Fatal::Stopped)
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::task::{self, AbortHandle};
+
+/// Queries which are currently running for a single WebSocket connection. Cancelling one aborts
+/// its task, which drops the query future and interrupts the statement on the reader.
+#[derive(Clone, Default)]
+pub(crate) struct RunningQueries {
+    tasks: Arc<Mutex<HashMap<usize, AbortHandle>>>,
+}
+
+impl RunningQueries {
+    /// Spawns the task for the query with the given `request_id`, which removes itself when it
+    /// is done.
+    pub fn spawn<F>(&self, request_id: usize, fut: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let running = self.clone();
+        // keep the lock while spawning, so the task can never remove itself before it was added
+        let mut tasks = self.lock();
+        let handle = task::spawn(async move {
+            fut.await;
+            running.lock().remove(&request_id);
+        });
+        tasks.insert(request_id, handle.abort_handle());
+    }
+
+    /// Cancels the query with the given `request_id`, if it is still running.
+    pub fn cancel(&self, request_id: usize) {
+        if let Some(handle) = self.lock().remove(&request_id) {
+            handle.abort();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<usize, AbortHandle>> {
+        self.tasks
+            .lock()
+            .expect("RunningQueries lock to never be poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn test_running_queries() {
+        let running = RunningQueries::default();
+
+        // a finished query removes itself
+        running.spawn(1, async {});
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(running.lock().is_empty());
+
+        // a cancelled query drops its future
+        let (tx, rx) = oneshot::channel::<()>();
+        running.spawn(2, async move {
+            let _tx = tx;
+            time::sleep(Duration::from_secs(3600)).await;
+        });
+        running.cancel(2);
+        assert!(rx.await.is_err());
+        assert!(running.lock().is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::Error;
+use openraft::raft::InstallSnapshotRequest;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use tracing::{debug, warn};
+
+/// The zstd level used for compressed snapshot chunks
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChunkEncoding {
+    /// Only the checksum without any data. The receiver should use the bytes it has locally.
+    Probe,
+    Raw,
+    Zstd,
+}
+
+/// A single chunk of a SQLite snapshot on the wire.
+///
+/// openraft sends snapshots in chunks of `snapshot_max_chunk_size` at fixed offsets. Each chunk
+/// is first sent as a `Probe` with its SHA-256 checksum only. The receiver looks for the same
+/// bytes at the same offset inside the files of its snapshot folder, which are the partially
+/// received snapshot from an interrupted transfer and its own latest snapshot. Only if they
+/// cannot be found there, the data will be sent, optionally zstd-compressed, and verified
+/// against the checksum.
+///
+/// Snapshots are copied page by page with the online backup API, which means unchanged pages
+/// stay at the same offset and usually only modified chunks travel over the network.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    /// The request from openraft with the encoded data
+    req: InstallSnapshotRequest<TypeConfigSqlite>,
+    len: u64,
+    checksum: [u8; 32],
+    encoding: ChunkEncoding,
+}
+
+impl SnapshotChunk {
+    /// Splits the data off the request and builds the probe for it.
+    ///
+    /// MUST NOT be executed in async context!
+    pub fn probe(mut req: InstallSnapshotRequest<TypeConfigSqlite>) -> (Self, Vec<u8>) {
+        let data = std::mem::take(&mut req.data);
+        let slf = Self {
+            req,
+            len: data.len() as u64,
+            checksum: Sha256::digest(&data).into(),
+            encoding: ChunkEncoding::Probe,
+        };
+        (slf, data)
+    }
+
+    /// Attaches the data to a probe, which is needed if the receiver could not find it locally.
+    ///
+    /// MUST NOT be executed in async context!
+    pub fn with_data(mut self, data: Vec<u8>, compress: bool) -> Result<Self, Error> {
+        if compress {
+            self.req.data = zstd::bulk::compress(&data, ZSTD_LEVEL)?;
+            self.encoding = ChunkEncoding::Zstd;
+        } else {
+            self.req.data = data;
+            self.encoding = ChunkEncoding::Raw;
+        }
+        Ok(self)
+    }
+
+    /// Decodes and verifies the chunk. Returns `None` if the data is missing, either because a
+    /// probe could not be found locally or because the checksum does not match.
+    ///
+    /// MUST NOT be executed in async context!
+    pub fn into_request(
+        mut self,
+        path_snapshots: &str,
+    ) -> Result<Option<InstallSnapshotRequest<TypeConfigSqlite>>, Error> {
+        let data = match self.encoding {
+            ChunkEncoding::Probe => {
+                return Ok(self.find_local(path_snapshots).map(|data| {
+                    self.req.data = data;
+                    self.req
+                }));
+            }
+            ChunkEncoding::Raw => std::mem::take(&mut self.req.data),
+            ChunkEncoding::Zstd => zstd::bulk::decompress(&self.req.data, self.len as usize)?,
+        };
+
+        if data.len() as u64 != self.len || self.checksum != <[u8; 32]>::from(Sha256::digest(&data))
+        {
+            warn!(
+                "Received snapshot chunk at offset {} with an invalid checksum",
+                self.req.offset
+            );
+            return Ok(None);
+        }
+
+        self.req.data = data;
+        Ok(Some(self.req))
+    }
+
+    fn find_local(&self, path_snapshots: &str) -> Option<Vec<u8>> {
+        let entries = fs::read_dir(path_snapshots).ok()?;
+        let end = self.req.offset + self.len;
+
+        for entry in entries.flatten() {
+            let is_candidate = entry
+                .metadata()
+                .map(|meta| meta.is_file() && meta.len() >= end)
+                .unwrap_or(false);
+            if !is_candidate {
+                continue;
+            }
+
+            let mut file = match fs::File::open(entry.path()) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let mut data = vec![0; self.len as usize];
+            if file.seek(SeekFrom::Start(self.req.offset)).is_err()
+                || file.read_exact(&mut data).is_err()
+            {
+                continue;
+            }
+
+            if self.checksum == <[u8; 32]>::from(Sha256::digest(&data)) {
+                debug!(
+                    "Found snapshot chunk at offset {} locally in {:?}",
+                    self.req.offset,
+                    entry.file_name()
+                );
+                return Some(data);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openraft::{SnapshotMeta, Vote};
+
+    fn req(offset: u64, data: Vec<u8>) -> InstallSnapshotRequest<TypeConfigSqlite> {
+        InstallSnapshotRequest {
+            vote: Vote::default(),
+            meta: SnapshotMeta::default(),
+            offset,
+            data,
+            done: false,
+        }
+    }
+
+    #[test]
+    fn test_chunk_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("hiqlite_chunk_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+
+        let data = (0..4096u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut file = vec![0; 100];
+        file.extend_from_slice(&data);
+        fs::write(dir.join("temp.partial"), &file).unwrap();
+
+        // found locally at the same offset
+        let (probe, _) = SnapshotChunk::probe(req(100, data.clone()));
+        let res = probe.into_request(path).unwrap().unwrap();
+        assert_eq!(res.data, data);
+
+        // not found at another offset
+        let (probe, chunk_data) = SnapshotChunk::probe(req(0, data.clone()));
+        assert!(probe.clone().into_request(path).unwrap().is_none());
+
+        for compress in [false, true] {
+            let chunk = probe
+                .clone()
+                .with_data(chunk_data.clone(), compress)
+                .unwrap();
+            let res = chunk.into_request(path).unwrap().unwrap();
+            assert_eq!(res.data, data);
+        }
+
+        // invalid data is rejected
+        let mut chunk = probe.with_data(chunk_data, false).unwrap();
+        chunk.req.data[10] ^= 1;
+        assert!(chunk.into_request(path).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,927 @@
+use crate::app_state::AppState;
+use crate::backup;
+use crate::helpers::set_path_access;
+use crate::s3::S3Config;
+use crate::store::logs::rocksdb::LogStoreRocksdb;
+use crate::store::state_machine::sqlite::state_machine::{
+    PathBackups, PathDb, PathLockFile, PathSnapshots, QueryWrite, QueryWriteEntry,
+    StateMachineSqlite,
+};
+use crate::store::state_machine::sqlite::writer::{WriterRequest, META_KEY_BACKUP_INDEX};
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::{Error, NodeConfig, NodeId};
+use chrono::{DateTime, Utc};
+use openraft::storage::RaftStateMachine;
+use openraft::{EntryPayload, LogId, RaftLogReader};
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::{fs, task, time};
+use tracing::{debug, error, info, warn};
+
+type Entry = openraft::Entry<TypeConfigSqlite>;
+
+/// Closed segments are pushed to `pitr/<epoch>/<segment>` on S3.
+const PREFIX: &str = "pitr";
+
+/// The segment which is currently being written to.
+const SEGMENT_CURRENT: &str = "current";
+
+/// A segment is closed after this amount of entries, even if the archive interval has not
+/// passed yet.
+const SEGMENT_MAX_ENTRIES: usize = 10_000;
+
+/// Only the leader uploads segments. All other nodes keep theirs until the leader has confirmed
+/// the upload, so they can fill the gap if it goes down before. Each confirmation is a log entry,
+/// which ends up in the archive itself, so the leader only sends one after this many uploads.
+const CONFIRM_UPLOADS_SEGMENTS: usize = 16;
+
+/// The max amount of entries read from the log store at once, when the archiver catches up.
+const CATCH_UP_CHUNK: u64 = 1024;
+
+const UPLOAD_TICK: Duration = Duration::from_secs(1);
+
+/// Log indexes start over with each backup restore. All segments and backups are tagged with
+/// the epoch they have been created in, which is a new one after each restore.
+pub(crate) const META_KEY_EPOCH: &str = "epoch";
+
+/// The last log index of the archive, up to which the leader has pushed all segments to S3.
+pub(crate) const META_KEY_UPLOADED: &str = "archive_uploaded";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedEntry {
+    log_id: LogId<NodeId>,
+    /// `None` for blank and membership entries, which only keep the archived log continuous
+    entry: Option<QueryWriteEntry>,
+}
+
+impl From<&Entry> for ArchivedEntry {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            log_id: entry.log_id,
+            entry: match &entry.payload {
+                EntryPayload::Normal(entry) => Some(entry.clone()),
+                EntryPayload::Blank | EntryPayload::Membership(_) => None,
+            },
+        }
+    }
+}
+
+/// Metadata of the Raft log archive, which is written into `_metadata` on each node with
+/// `QueryWrite::ArchiveMeta`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ArchiveMeta {
+    /// Starts a new epoch, if none exists yet
+    Epoch(i64),
+    /// All segments up to this log index have been pushed to S3
+    Uploaded(u64),
+}
+
+impl ArchiveMeta {
+    pub(crate) fn apply(&self, conn: &rusqlite::Connection) -> Result<(), Error> {
+        match self {
+            Self::Epoch(epoch) => conn.execute(
+                "INSERT OR IGNORE INTO _metadata (key, data) VALUES ($1, $2)",
+                (META_KEY_EPOCH, epoch),
+            )?,
+            Self::Uploaded(index) => conn.execute(
+                r#"
+INSERT INTO _metadata (key, data) VALUES ($1, $2)
+ON CONFLICT (key) DO UPDATE SET data = max(data, excluded.data)"#,
+                (META_KEY_UPLOADED, *index as i64),
+            )?,
+        };
+        Ok(())
+    }
+}
+
+/// The point to recover to with `HQL_BACKUP_RESTORE=pitr:<target>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PitrTarget {
+    /// Replays all queries which have been proposed until this point in time
+    Timestamp(DateTime<Utc>),
+    /// Replays all log entries up to and including this index. Log indexes start over after
+    /// each restore, which means this always refers to the latest backups.
+    LogIndex(u64),
+}
+
+impl FromStr for PitrTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse::<u64>() {
+            return Ok(Self::LogIndex(index));
+        }
+
+        DateTime::parse_from_rfc3339(s)
+            .map(|dt| Self::Timestamp(dt.with_timezone(&Utc)))
+            .map_err(|_| {
+                Error::Config(
+                    format!(
+                        "point-in-time recovery target must be an RFC3339 timestamp or a log index: {}",
+                        s
+                    )
+                    .into(),
+                )
+            })
+    }
+}
+
+impl PitrTarget {
+    fn is_reached(&self, log_id: &LogId<NodeId>, entry: Option<&QueryWriteEntry>) -> bool {
+        match self {
+            Self::Timestamp(ts) => entry.is_some_and(|e| e.stamp.ts_millis > ts.timestamp_millis()),
+            Self::LogIndex(index) => log_id.index > *index,
+        }
+    }
+}
+
+/// The epoch and last applied log index of a backup, from where the archive will be replayed.
+#[derive(Debug)]
+pub(crate) struct BackupPoint {
+    epoch: i64,
+    log_index: u64,
+}
+
+pub(crate) fn archive_dir(data_dir: &str) -> String {
+    format!("{}/archive", StateMachineSqlite::path_base(data_dir))
+}
+
+/// Writes each applied log entry of the main SQLite Raft into local segments, which will be
+/// pushed to S3 by the leader.
+#[derive(Debug, Clone)]
+pub(crate) struct LogArchive {
+    tx: flume::Sender<ArchivedEntry>,
+    /// The highest log index which has been dropped because the archiver was behind
+    missed: Arc<AtomicU64>,
+}
+
+impl LogArchive {
+    pub async fn spawn(
+        data_dir: &str,
+        interval: Duration,
+        log_reader: LogStoreRocksdb,
+    ) -> Result<Self, Error> {
+        let dir = archive_dir(data_dir);
+        fs::create_dir_all(&dir).await?;
+        set_path_access(&dir, 0o700).await?;
+
+        let (tx, rx) = flume::bounded(1024);
+        let missed = Arc::new(AtomicU64::new(0));
+        let archiver = Archiver {
+            path_current: format!("{}/{}", dir, SEGMENT_CURRENT),
+            dir,
+            interval,
+            segment: None,
+            last: None,
+            missed: missed.clone(),
+            log_reader,
+            rt: Handle::current(),
+        };
+        thread::spawn(move || archiver.run(rx));
+
+        Ok(Self { tx, missed })
+    }
+
+    /// Never blocks the apply of the state machine. If the archiver is behind, the entry will be
+    /// dropped here and read from the log store later on.
+    pub fn push(&self, entry: &Entry) {
+        match self.tx.try_send(ArchivedEntry::from(entry)) {
+            Ok(_) => {}
+            Err(flume::TrySendError::Full(entry)) => {
+                self.missed.fetch_max(entry.log_id.index, Ordering::Relaxed);
+            }
+            Err(flume::TrySendError::Disconnected(_)) => {
+                error!("Raft log archive has been stopped - entry is missing in the archive");
+            }
+        }
+    }
+}
+
+struct Segment {
+    file: BufWriter<std::fs::File>,
+    first: u64,
+    last: u64,
+    entries: usize,
+    opened: Instant,
+}
+
+struct Archiver {
+    dir: String,
+    path_current: String,
+    interval: Duration,
+    segment: Option<Segment>,
+    /// The last archived log index
+    last: Option<u64>,
+    missed: Arc<AtomicU64>,
+    log_reader: LogStoreRocksdb,
+    rt: Handle,
+}
+
+impl Archiver {
+    fn run(mut self, rx: flume::Receiver<ArchivedEntry>) {
+        if let Err(err) = close_leftover(&self.dir, &self.path_current) {
+            error!("Error closing leftover Raft log archive segment: {}", err);
+        }
+
+        loop {
+            let res = match &self.segment {
+                Some(s) => rx.recv_timeout(self.interval.saturating_sub(s.opened.elapsed())),
+                None => rx.recv().map_err(|_| flume::RecvTimeoutError::Disconnected),
+            };
+
+            match res {
+                Ok(entry) => {
+                    if let Some(last) = self.last {
+                        if entry.log_id.index > last + 1 {
+                            self.catch_up(entry.log_id.index - 1);
+                        }
+                    }
+                    // Each segment must be continuous. This is not the case after a snapshot
+                    // has been installed, or if logs are applied again after a restart.
+                    if self
+                        .segment
+                        .as_ref()
+                        .is_some_and(|s| s.last + 1 != entry.log_id.index)
+                    {
+                        self.close();
+                    }
+                    self.append(&entry);
+                }
+                Err(flume::RecvTimeoutError::Timeout) => {
+                    self.catch_up(self.missed.load(Ordering::Relaxed));
+                    self.close();
+                }
+                Err(flume::RecvTimeoutError::Disconnected) => {
+                    self.catch_up(self.missed.load(Ordering::Relaxed));
+                    self.close();
+                    break;
+                }
+            }
+        }
+
+        debug!("State machine has been shut down - exiting Raft log archive");
+    }
+
+    fn append(&mut self, entry: &ArchivedEntry) {
+        match append(&mut self.segment, &self.path_current, entry) {
+            Ok(_) => {
+                self.last = Some(entry.log_id.index);
+                if self
+                    .segment
+                    .as_ref()
+                    .is_some_and(|s| s.entries >= SEGMENT_MAX_ENTRIES)
+                {
+                    self.close();
+                }
+            }
+            Err(err) => {
+                error!(
+                    "Error archiving Raft log entry {}: {}",
+                    entry.log_id.index, err
+                );
+                self.close();
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            close(&self.dir, &self.path_current, segment);
+        }
+    }
+
+    /// Reads the entries after the last archived one up to `until` from the log store. They are
+    /// missing, if they have been dropped while the archiver was behind. If the log store does
+    /// not have them anymore, because a snapshot has been installed in between, the segment
+    /// will be closed with the next entry.
+    fn catch_up(&mut self, until: u64) {
+        while let Some(last) = self.last.filter(|last| *last < until) {
+            let from = last + 1;
+            let to = until.min(last + CATCH_UP_CHUNK);
+            let entries = match self
+                .rt
+                .block_on(self.log_reader.try_get_log_entries(from..=to))
+            {
+                Ok(entries) => entries,
+                Err(err) => {
+                    error!("Error reading Raft log entries {}..={}: {}", from, to, err);
+                    return;
+                }
+            };
+            if entries.first().map(|e| e.log_id.index) != Some(from) {
+                debug!(
+                    "Raft log entries {}..={} do not exist in the log store for the archive",
+                    from, to
+                );
+                return;
+            }
+
+            debug!(
+                "Catching up with {} Raft log entries from the log store",
+                entries.len()
+            );
+            for entry in &entries {
+                if Some(entry.log_id.index) != self.last.map(|last| last + 1) {
+                    return;
+                }
+                self.append(&ArchivedEntry::from(entry));
+            }
+            if self.last == Some(last) {
+                // appending failed, the next entry will try again
+                return;
+            }
+        }
+    }
+}
+
+/// Each record is the length of the serialized entry as `u32` LE, followed by the entry itself.
+fn append(
+    segment: &mut Option<Segment>,
+    path_current: &str,
+    entry: &ArchivedEntry,
+) -> Result<(), Error> {
+    let bytes = bincode::serialize(entry)?;
+
+    if segment.is_none() {
+        *segment = Some(Segment {
+            file: BufWriter::new(std::fs::File::create(path_current)?),
+            first: entry.log_id.index,
+            last: entry.log_id.index,
+            entries: 0,
+            opened: Instant::now(),
+        });
+    }
+    let segment = segment.as_mut().unwrap();
+
+    segment
+        .file
+        .write_all(&(bytes.len() as u32).to_le_bytes())?;
+    segment.file.write_all(&bytes)?;
+    segment.last = entry.log_id.index;
+    segment.entries += 1;
+
+    Ok(())
+}
+
+fn close(dir: &str, path_current: &str, segment: Segment) {
+    let name = segment_name(segment.first, segment.last);
+    let res = segment
+        .file
+        .into_inner()
+        .map_err(|err| err.into_error())
+        .and_then(|file| file.sync_all())
+        .and_then(|_| std::fs::rename(path_current, format!("{}/{}", dir, name)));
+
+    match res {
+        Ok(_) => debug!("Closed Raft log archive segment {}", name),
+        Err(err) => error!("Error closing Raft log archive segment {}: {}", name, err),
+    }
+}
+
+/// A segment which has not been closed because of a crash may end with an incomplete record.
+/// It will be ignored when the segment is read.
+fn close_leftover(dir: &str, path_current: &str) -> Result<(), Error> {
+    if !Path::new(path_current).exists() {
+        return Ok(());
+    }
+
+    let entries = read_segment(path_current)?;
+    match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => {
+            let name = segment_name(first.log_id.index, last.log_id.index);
+            info!("Closing leftover Raft log archive segment {}", name);
+            std::fs::rename(path_current, format!("{}/{}", dir, name))?;
+        }
+        _ => std::fs::remove_file(path_current)?,
+    }
+
+    Ok(())
+}
+
+fn read_segment(path: &str) -> Result<Vec<ArchivedEntry>, Error> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut entries = Vec::new();
+
+    let mut len = [0u8; 4];
+    while reader.read_exact(&mut len).is_ok() {
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        if reader.read_exact(&mut buf).is_err() {
+            warn!(
+                "Raft log archive segment {} ends with an incomplete entry",
+                path
+            );
+            break;
+        }
+        entries.push(bincode::deserialize(&buf)?);
+    }
+
+    Ok(entries)
+}
+
+/// The zero-padded indexes keep the segments sorted by name. The timestamp is used for the
+/// cleanup of expired segments.
+fn segment_name(first: u64, last: u64) -> String {
+    format!("{:020}_{:020}_{}", first, last, Utc::now().timestamp())
+}
+
+/// Returns `(first, last, timestamp)` from a segment name or S3 object key.
+fn parse_segment_name(name: &str) -> Option<(u64, u64, i64)> {
+    let name = name.rsplit_once('/').map(|(_, n)| n).unwrap_or(name);
+    let mut split = name.split('_');
+    let first = split.next()?.parse().ok()?;
+    let last = split.next()?.parse().ok()?;
+    let ts = split.next()?.parse().ok()?;
+    Some((first, last, ts))
+}
+
+/// Returns the creation time of an archived segment on S3, or `None` for any other object.
+pub(crate) fn dt_from_segment_name(key: &str) -> Option<DateTime<Utc>> {
+    let name = key.strip_prefix(PREFIX)?.strip_prefix('/')?;
+    parse_segment_name(name).and_then(|(_, _, ts)| DateTime::from_timestamp(ts, 0))
+}
+
+/// Closed local segments, sorted by their first log index.
+async fn closed_segments(dir: &str) -> Result<Vec<String>, Error> {
+    let mut segments = Vec::new();
+    let mut list = fs::read_dir(dir).await?;
+    while let Some(entry) = list.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            if parse_segment_name(name).is_some() {
+                segments.push(name.to_string());
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+pub fn spawn_uploader(state: Arc<AppState>, s3_config: Arc<S3Config>, data_dir: &str) {
+    task::spawn(upload_segments(state, s3_config, archive_dir(data_dir)));
+}
+
+async fn upload_segments(state: Arc<AppState>, s3_config: Arc<S3Config>, dir: String) {
+    info!("Raft log archive upload task started");
+
+    // the uploads of this node since its last confirmation
+    let mut unconfirmed = 0;
+    let mut uploaded = 0;
+
+    loop {
+        time::sleep(UPLOAD_TICK).await;
+
+        let segments = match closed_segments(&dir).await {
+            Ok(segments) => segments,
+            Err(err) => {
+                error!("Error reading Raft log archive segments: {}", err);
+                continue;
+            }
+        };
+
+        if state.raft_db.raft.current_leader().await != Some(state.id) {
+            unconfirmed = 0;
+            if !segments.is_empty() {
+                if let Err(err) = remove_uploaded(&state, &dir, &segments).await {
+                    error!("Error removing uploaded Raft log archive segments: {}", err);
+                }
+            }
+            continue;
+        }
+
+        let epoch = match archive_epoch(&state).await {
+            Ok(Some(epoch)) => epoch,
+            Ok(None) => continue,
+            Err(err) => {
+                error!("Error reading the Raft log archive epoch: {}", err);
+                continue;
+            }
+        };
+
+        for name in segments {
+            let path = format!("{}/{}", dir, name);
+            let object = format!("{}/{}/{}", PREFIX, epoch, name);
+            if let Err(err) = s3_config.push(&path, &object).await {
+                error!("Error pushing Raft log archive segment {}: {}", object, err);
+                break;
+            }
+            debug!("Pushed Raft log archive segment {}", object);
+            let _ = fs::remove_file(path).await;
+
+            if let Some((_, last, _)) = parse_segment_name(&name) {
+                uploaded = last;
+                unconfirmed += 1;
+            }
+        }
+
+        if unconfirmed >= CONFIRM_UPLOADS_SEGMENTS {
+            let meta = QueryWrite::ArchiveMeta(ArchiveMeta::Uploaded(uploaded));
+            match state.raft_db.client_write(meta).await {
+                Ok(_) => unconfirmed = 0,
+                Err(err) => error!("Error confirming Raft log archive uploads: {}", err),
+            }
+        }
+    }
+}
+
+/// Removes all local segments, which the leader has confirmed as pushed to S3.
+async fn remove_uploaded(state: &AppState, dir: &str, segments: &[String]) -> Result<(), Error> {
+    let Some(uploaded) = read_meta(state, META_KEY_UPLOADED).await? else {
+        return Ok(());
+    };
+
+    for name in segments {
+        if parse_segment_name(name).is_some_and(|(_, last, _)| last <= uploaded as u64) {
+            debug!("Removing uploaded Raft log archive segment {}", name);
+            let _ = fs::remove_file(format!("{}/{}", dir, name)).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the current archive epoch. If it does not exist yet, the leader proposes a new one,
+/// which makes it part of each backup afterward.
+async fn archive_epoch(state: &AppState) -> Result<Option<i64>, Error> {
+    let epoch = read_meta(state, META_KEY_EPOCH).await?;
+
+    if epoch.is_none() {
+        info!("Starting a new Raft log archive epoch");
+        let meta = ArchiveMeta::Epoch(Utc::now().timestamp_millis());
+        state
+            .raft_db
+            .client_write(QueryWrite::ArchiveMeta(meta))
+            .await?;
+    }
+
+    Ok(epoch)
+}
+
+async fn read_meta(state: &AppState, key: &'static str) -> Result<Option<i64>, Error> {
+    state
+        .raft_db
+        .read_pool
+        .run(move |conn| {
+            conn.query_row("SELECT data FROM _metadata WHERE key = $1", [key], |row| {
+                row.get::<_, i64>(0)
+            })
+            .optional()
+            .map_err(Error::from)
+        })
+        .await
+}
+
+/// Pulls the latest backup before the `target` into `path`.
+pub(crate) async fn pull_backup(
+    s3_config: &S3Config,
+    target: &PitrTarget,
+    path: &str,
+) -> Result<BackupPoint, Error> {
+    let mut backups = Vec::new();
+    for list in s3_config.bucket.list("", None).await? {
+        for object in list.contents {
+            if let Some(dt) = backup::dt_from_backup_name(&object.key) {
+                backups.push((dt, object.key));
+            }
+        }
+    }
+    backups.sort();
+
+    let mut latest_epoch = None;
+    for (dt, name) in backups.into_iter().rev() {
+        // the timestamp in the name only has a precision of seconds
+        if let PitrTarget::Timestamp(ts) = target {
+            if dt + chrono::Duration::seconds(1) > *ts {
+                continue;
+            }
+        }
+
+        s3_config.pull(&name, path).await?;
+        let Some(point) = read_backup_point(path.to_string()).await? else {
+            warn!(
+                "Backup {} has been created without a Raft log archive - skipping it",
+                name
+            );
+            continue;
+        };
+
+        if let PitrTarget::LogIndex(index) = target {
+            if *latest_epoch.get_or_insert(point.epoch) != point.epoch {
+                break;
+            }
+            if point.log_index > *index {
+                continue;
+            }
+        }
+
+        info!(
+            "Using backup {} at log index {} for point-in-time recovery",
+            name, point.log_index
+        );
+        return Ok(point);
+    }
+
+    let _ = fs::remove_file(path).await;
+    Err(Error::S3(format!(
+        "No backup found for point-in-time recovery target {:?}",
+        target
+    )))
+}
+
+async fn read_backup_point(path: String) -> Result<Option<BackupPoint>, Error> {
+    task::spawn_blocking(move || {
+        let conn = rusqlite::Connection::open(path)?;
+        let mut stmt = conn.prepare("SELECT data FROM _metadata WHERE key = $1")?;
+        let epoch = stmt
+            .query_row([META_KEY_EPOCH], |row| row.get::<_, i64>(0))
+            .optional()?;
+        let log_index = stmt
+            .query_row([META_KEY_BACKUP_INDEX], |row| row.get::<_, i64>(0))
+            .optional()?;
+
+        Ok::<_, Error>(epoch.zip(log_index).map(|(epoch, log_index)| BackupPoint {
+            epoch,
+            log_index: log_index as u64,
+        }))
+    })
+    .await?
+}
+
+/// Replays the archived Raft logs on top of the backup, which must already be at its final
+/// place, until the `target` has been reached. Returns an error if the archive has a gap or
+/// ends before the target.
+///
+/// **CAUTION: This function MUST BE CALLED when the Raft is not running!**
+pub(crate) async fn replay(
+    node_config: &NodeConfig,
+    s3_config: &S3Config,
+    point: BackupPoint,
+    target: &PitrTarget,
+) -> Result<(), Error> {
+    let prefix = format!("{}/{}/", PREFIX, point.epoch);
+    let mut segments = Vec::new();
+    for list in s3_config.bucket.list(&prefix, None).await? {
+        for object in list.contents {
+            if let Some((first, last, _)) = parse_segment_name(&object.key) {
+                if last > point.log_index {
+                    segments.push((first, last, object.key));
+                }
+            }
+        }
+    }
+    // Overlapping segments from different leaders are fine. Already applied entries are skipped.
+    segments.sort();
+    info!(
+        "Replaying {} Raft log archive segments after log index {}",
+        segments.len(),
+        point.log_index
+    );
+
+    let (PathDb(_), PathBackups(path_backups), PathSnapshots(_), PathLockFile(path_lock_file)) =
+        StateMachineSqlite::build_folders(&node_config.data_dir, false).await;
+    let path_segment = format!("{}/pitr_segment", path_backups);
+
+    // only the main database is part of a backup
+    let mut state_machine = StateMachineSqlite::new(
+        &node_config.data_dir,
+        &node_config.filename_db,
+        false,
+        &[],
+        node_config.node_id,
+        node_config.log_statements,
+        node_config.sqlite_config,
+        node_config.write_cost_limit,
+        node_config.prepared_statement_cache_capacity,
+        node_config.read_pool_min,
+        node_config.read_pool_size,
+        false,
+        node_config.sql_functions.clone(),
+        None,
+        None,
+    )
+    .await
+    .map_err(|err| Error::Error(err.to_string().into()))?;
+
+    let res = replay_segments(
+        &mut state_machine,
+        s3_config,
+        segments,
+        point.log_index,
+        target,
+        &path_segment,
+    )
+    .await;
+    let _ = fs::remove_file(&path_segment).await;
+
+    let (ack, rx) = oneshot::channel();
+    state_machine
+        .write_tx
+        .send_async(WriterRequest::Shutdown(ack))
+        .await
+        .expect("sql writer to always be listening");
+    rx.await.expect("to always get a response from sql writer");
+    drop(state_machine);
+
+    // the writer persists its metadata and removes the lock file after the ack
+    while fs::try_exists(&path_lock_file).await.unwrap_or(false) {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let last_index = res?;
+    info!(
+        "Point-in-time recovery finished at log index {}",
+        last_index
+    );
+
+    Ok(())
+}
+
+/// Applies the entries of all `segments` after `last_index` until the `target` has been
+/// reached. Returns the last applied log index.
+async fn replay_segments(
+    state_machine: &mut StateMachineSqlite,
+    s3_config: &S3Config,
+    segments: Vec<(u64, u64, String)>,
+    mut last_index: u64,
+    target: &PitrTarget,
+    path_segment: &str,
+) -> Result<u64, Error> {
+    for (first, last, object) in segments {
+        if last <= last_index {
+            continue;
+        }
+        if first > last_index + 1 {
+            return Err(Error::S3(format!(
+                "Raft log archive has a gap after log index {}, the next segment starts at {}",
+                last_index, first
+            )));
+        }
+
+        s3_config.pull(&object, path_segment).await?;
+        let entries = {
+            let path = path_segment.to_string();
+            task::spawn_blocking(move || read_segment(&path)).await??
+        };
+
+        let mut target_reached = false;
+        let mut batch = Vec::with_capacity(entries.len());
+        for ArchivedEntry { log_id, entry } in entries {
+            if log_id.index <= last_index {
+                continue;
+            }
+            if target.is_reached(&log_id, entry.as_ref()) {
+                target_reached = true;
+                break;
+            }
+
+            batch.push(Entry {
+                log_id,
+                payload: replay_payload(entry),
+            });
+            last_index = log_id.index;
+        }
+
+        if !batch.is_empty() {
+            debug!("Applying {} entries from {}", batch.len(), object);
+            state_machine
+                .apply(batch)
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+        }
+        if target_reached {
+            return Ok(last_index);
+        }
+    }
+
+    // the archive may end exactly at the target index
+    if matches!(target, PitrTarget::LogIndex(index) if last_index >= *index) {
+        return Ok(last_index);
+    }
+
+    Err(Error::S3(format!(
+        "Reached the end of the Raft log archive at log index {} before the target {:?}",
+        last_index, target
+    )))
+}
+
+/// Queries on named databases are not part of a backup. Backups, checksums, re-syncs and the
+/// metadata of the old archive must not be executed again.
+fn replay_payload(entry: Option<QueryWriteEntry>) -> EntryPayload<TypeConfigSqlite> {
+    match entry {
+        Some(entry)
+            if entry.db.is_none()
+                && !matches!(
+                    entry.query,
+                    QueryWrite::Backup(_)
+                        | QueryWrite::Checksum
+                        | QueryWrite::Resync { .. }
+                        | QueryWrite::ArchiveMeta(_)
+                ) =>
+        {
+            EntryPayload::Normal(entry)
+        }
+        _ => EntryPayload::Blank,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openraft::CommittedLeaderId;
+
+    #[test]
+    fn test_pitr_target() {
+        assert_eq!(
+            PitrTarget::from_str("1337").unwrap(),
+            PitrTarget::LogIndex(1337)
+        );
+        assert_eq!(
+            PitrTarget::from_str("2025-03-01T12:30:00+01:00").unwrap(),
+            PitrTarget::Timestamp(DateTime::from_timestamp(1740828600, 0).unwrap())
+        );
+        assert!(PitrTarget::from_str("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_archive_meta() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE _metadata (key TEXT NOT NULL PRIMARY KEY, data BLOB NOT NULL)",
+            (),
+        )
+        .unwrap();
+        let get = |key: &str| -> i64 {
+            conn.query_row("SELECT data FROM _metadata WHERE key = $1", [key], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+
+        // an existing epoch is never replaced
+        ArchiveMeta::Epoch(1).apply(&conn).unwrap();
+        ArchiveMeta::Epoch(2).apply(&conn).unwrap();
+        assert_eq!(get(META_KEY_EPOCH), 1);
+
+        // a confirmation from an older leader never goes back
+        ArchiveMeta::Uploaded(100).apply(&conn).unwrap();
+        ArchiveMeta::Uploaded(50).apply(&conn).unwrap();
+        assert_eq!(get(META_KEY_UPLOADED), 100);
+        ArchiveMeta::Uploaded(200).apply(&conn).unwrap();
+        assert_eq!(get(META_KEY_UPLOADED), 200);
+    }
+
+    #[test]
+    fn test_segment_name() {
+        let name = segment_name(7, 1234);
+        let (first, last, _) = parse_segment_name(&name).unwrap();
+        assert_eq!((first, last), (7, 1234));
+
+        let key = format!("{}/1740828600000/{}", PREFIX, name);
+        assert!(dt_from_segment_name(&key).is_some());
+        assert!(dt_from_segment_name("backup_node_1_1740828600.sqlite").is_none());
+    }
+
+    #[test]
+    fn test_segment_read_incomplete() {
+        let dir = std::env::temp_dir().join(format!("hiqlite_pitr_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let path_current = format!("{}/{}", dir, SEGMENT_CURRENT);
+
+        let mut segment = None;
+        for index in 3..6 {
+            let entry = ArchivedEntry {
+                log_id: LogId::new(CommittedLeaderId::new(1, 1), index),
+                entry: None,
+            };
+            append(&mut segment, &path_current, &entry).unwrap();
+        }
+        let mut file = segment.unwrap().file.into_inner().unwrap();
+        // a crash in the middle of a record
+        file.write_all(&[16, 0, 0, 0, 1]).unwrap();
+        drop(file);
+
+        let entries = read_segment(&path_current).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.log_id.index).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+
+        close_leftover(dir, &path_current).unwrap();
+        let names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 1);
+        assert_eq!(parse_segment_name(&names[0]).unwrap().0, 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::NodeId;
+use openraft::Raft;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Leader lease for consistent reads without a `ensure_linearizable()` round trip.
+///
+/// The leader records the time it has sent each `append_entries` (which includes heartbeats) to
+/// a follower, once the follower acknowledged it. A follower will not start an election before
+/// `election_timeout_min` has passed since it received the last message from the leader. As long
+/// as a quorum acknowledged a message sent within `election_timeout_min - clock_drift`, no other
+/// node can have become leader and the local state machine can be read directly.
+#[derive(Debug)]
+pub struct LeaderLease {
+    clock_drift: Duration,
+    /// NodeId -> (term, time when the acknowledged request was sent)
+    acks: Mutex<HashMap<NodeId, (u64, Instant)>>,
+}
+
+impl LeaderLease {
+    pub fn new(clock_drift: Duration) -> Self {
+        Self {
+            clock_drift,
+            acks: Default::default(),
+        }
+    }
+
+    /// Records an acknowledged `append_entries` for the given follower.
+    pub fn ack(&self, node_id: NodeId, term: u64, sent: Instant) {
+        let mut acks = self
+            .acks
+            .lock()
+            .expect("LeaderLease lock to never be poisoned");
+        match acks.get_mut(&node_id) {
+            // requests may be answered out of order
+            Some((t, s)) if *t == term && *s > sent => {}
+            Some(ack) => *ack = (term, sent),
+            None => {
+                acks.insert(node_id, (term, sent));
+            }
+        }
+    }
+
+    /// Returns `true` if this node is the leader and currently holds a valid lease.
+    pub fn is_valid(&self, raft: &Raft<TypeConfigSqlite>) -> bool {
+        let Some(lease_timeout) =
+            Duration::from_millis(raft.config().election_timeout_min).checked_sub(self.clock_drift)
+        else {
+            return false;
+        };
+
+        let metrics = raft.metrics();
+        let metrics = metrics.borrow();
+
+        if metrics.current_leader != Some(metrics.id) {
+            return false;
+        }
+
+        // Each new leader appends a blank log in its own term. As soon as this one has been
+        // applied, all logs committed by previous leaders are applied as well.
+        let term = metrics.current_term;
+        match metrics.last_applied {
+            Some(log_id) if log_id.leader_id.term == term => {}
+            _ => return false,
+        }
+
+        let now = Instant::now();
+        let acks = self
+            .acks
+            .lock()
+            .expect("LeaderLease lock to never be poisoned");
+        // During membership changes, we need a quorum in each of the joint configs.
+        metrics
+            .membership_config
+            .membership()
+            .get_joint_config()
+            .iter()
+            .all(|voters| {
+                let quorum = voters.len() / 2 + 1;
+                let valid = voters
+                    .iter()
+                    .filter(|id| {
+                        **id == metrics.id
+                            || acks
+                                .get(id)
+                                .map(|(t, sent)| {
+                                    *t == term && now.duration_since(*sent) < lease_timeout
+                                })
+                                .unwrap_or(false)
+                    })
+                    .count();
+                valid >= quorum
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ack_keeps_latest() {
+        let lease = LeaderLease::new(Duration::from_millis(100));
+        let earlier = Instant::now();
+        let later = earlier + Duration::from_millis(10);
+
+        lease.ack(2, 1, later);
+        lease.ack(2, 1, earlier);
+        assert_eq!(lease.acks.lock().unwrap().get(&2), Some(&(1, later)));
+
+        // a new term always overwrites
+        lease.ack(2, 2, earlier);
+        assert_eq!(lease.acks.lock().unwrap().get(&2), Some(&(2, earlier)));
+    }
+}
@@ -1,16 +1,153 @@
 use crate::app_state::AppState;
+use crate::query::lease::LeaderLease;
 use crate::query::rows::{ColumnOwned, RowOwned};
+use crate::store::state_machine::sqlite::param::bind_params;
 use crate::store::state_machine::sqlite::state_machine::SqlitePool;
 use crate::store::state_machine::sqlite::TypeConfigSqlite;
 use crate::{Error, Params};
+use futures_util::stream::{self, Stream};
 use openraft::Raft;
+use rusqlite::{Connection, InterruptHandle};
 use serde::de::DeserializeOwned;
 use std::borrow::Cow;
-use std::sync::Arc;
-use tokio::task;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::info;
 
+pub mod lease;
 pub mod rows;
+pub mod staleness;
+
+/// The amount of SQLite VM instructions between two deadline checks of a query with a timeout.
+const PROGRESS_OPS: i32 = 1000;
+
+/// Shared between a query future and its read job. A job which starts after its query has been
+/// cancelled already will not run at all.
+#[derive(Default)]
+struct Interrupt {
+    cancelled: bool,
+    handle: Option<InterruptHandle>,
+}
+
+/// Interrupts the statement running on the connection, if dropped before the read job has
+/// finished, which happens when the future of a query has been cancelled.
+struct InterruptOnDrop(Arc<Mutex<Interrupt>>);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        let mut interrupt = self.0.lock().unwrap();
+        interrupt.cancelled = true;
+        if let Some(handle) = interrupt.handle.take() {
+            handle.interrupt();
+        }
+    }
+}
+
+/// Aborts the running statement via the progress handler as soon as the query has been busy
+/// for longer than its timeout. Time added with `pause()` does not count towards it.
+struct Deadline {
+    timeout: Duration,
+    limit_micros: Arc<AtomicU64>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Deadline {
+    fn install(conn: &Connection, timeout: Duration) -> Self {
+        let start = Instant::now();
+        let limit_micros = Arc::new(AtomicU64::new(timeout.as_micros() as u64));
+        let expired = Arc::new(AtomicBool::new(false));
+
+        {
+            let limit_micros = limit_micros.clone();
+            let expired = expired.clone();
+            conn.progress_handler(
+                PROGRESS_OPS,
+                Some(move || {
+                    if start.elapsed().as_micros() as u64 >= limit_micros.load(Ordering::Relaxed) {
+                        expired.store(true, Ordering::Relaxed);
+                        true
+                    } else {
+                        false
+                    }
+                }),
+            );
+        }
+
+        Self {
+            timeout,
+            limit_micros,
+            expired,
+        }
+    }
+
+    /// Moves the deadline forward by the time spent waiting outside the query.
+    fn pause(&self, waited: Duration) {
+        self.limit_micros
+            .fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Removes the progress handler and returns an `Error::Timeout` if the deadline has been
+    /// exceeded.
+    fn remove(self, conn: &Connection) -> Result<(), Error> {
+        conn.progress_handler(0, None::<fn() -> bool>);
+        if self.expired.load(Ordering::Relaxed) {
+            Err(Error::Timeout(format!(
+                "query has been interrupted after {} ms",
+                self.timeout.as_millis()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Runs `f` with a connection on one of the reader threads of the `read_pool`.
+///
+/// With a `timeout`, the running statement will be aborted via the progress handler as soon as
+/// the deadline is exceeded and an `Error::Timeout` is returned. If the returned future is
+/// dropped early, the statement will be interrupted, so the reader is free again as soon as
+/// possible instead of running until completion in the background.
+pub(crate) async fn with_read_conn<F, T>(
+    read_pool: &SqlitePool,
+    timeout: Option<Duration>,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&Connection) -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let interrupt = Arc::new(Mutex::new(Interrupt::default()));
+    let _guard = InterruptOnDrop(interrupt.clone());
+
+    read_pool
+        .run(move |conn| {
+            {
+                let mut interrupt = interrupt.lock().unwrap();
+                if interrupt.cancelled {
+                    return Err(Error::Timeout("query has been cancelled".to_string()));
+                }
+                interrupt.handle = Some(conn.get_interrupt_handle());
+            }
+
+            let deadline = timeout.map(|timeout| Deadline::install(conn, timeout));
+
+            let res = panic::catch_unwind(AssertUnwindSafe(|| f(conn)));
+
+            // The handle must be gone before the reader picks up the next job, so a late
+            // interrupt can never hit a query from someone else.
+            interrupt.lock().unwrap().handle.take();
+            let expired = deadline.map(|d| d.remove(conn)).unwrap_or(Ok(()));
+
+            let res = match res {
+                Ok(res) => res,
+                Err(payload) => panic::resume_unwind(payload),
+            };
+            expired.and(res)
+        })
+        .await
+}
 
 // pub(crate) async fn query_columns<S>(
 //     read_pool: &Arc<SqlitePool>,
@@ -32,16 +169,22 @@ pub mod rows;
 
 pub(crate) async fn query_consistent_local<S>(
     raft: &Raft<TypeConfigSqlite>,
+    lease: Option<&LeaderLease>,
     log_statements: bool,
     read_pool: SqlitePool,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Vec<RowOwned>, Error>
 where
     S: Into<Cow<'static, str>>,
 {
-    let _ = raft.ensure_linearizable().await?;
-    query_owned_local(log_statements, read_pool, stmt, params).await
+    // With a valid leader lease, no other node can have become leader in the meantime, and we
+    // can skip the heartbeat round to a quorum.
+    if !lease.map(|l| l.is_valid(raft)).unwrap_or(false) {
+        let _ = raft.ensure_linearizable().await?;
+    }
+    query_owned_local(log_statements, read_pool, stmt, params, timeout).await
 }
 
 pub(crate) async fn query_owned_local<S>(
@@ -49,6 +192,7 @@ pub(crate) async fn query_owned_local<S>(
     read_pool: SqlitePool,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Vec<RowOwned>, Error>
 where
     S: Into<Cow<'static, str>>,
@@ -58,17 +202,11 @@ where
         info!("query_owned_local:\n{}\n{:?}", stmt, params)
     }
 
-    let conn = read_pool.get().await?;
-
-    task::spawn_blocking(move || {
+    with_read_conn(&read_pool, timeout, move |conn| {
         let mut stmt = conn.prepare_cached(stmt.as_ref())?;
         let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
 
-        let mut idx = 1;
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+        bind_params(&mut stmt, &params)?;
 
         let mut rows = stmt.raw_query();
         let mut rows_owned = Vec::new();
@@ -76,9 +214,122 @@ where
             rows_owned.push(RowOwned::from_row_column(row, &columns));
         }
 
-        Ok::<Vec<RowOwned>, Error>(rows_owned)
+        Ok(rows_owned)
+    })
+    .await
+}
+
+/// The receiving end of a query stream. Dropping it interrupts the statement, even if the
+/// reader is busy in between two chunks.
+pub(crate) struct QueryStreamRx {
+    rx: flume::Receiver<Result<Vec<RowOwned>, Error>>,
+    _interrupt: InterruptOnDrop,
+}
+
+impl QueryStreamRx {
+    /// Receives the next chunk. Returns `None` after the last one.
+    pub async fn recv(&self) -> Option<Result<Vec<RowOwned>, Error>> {
+        self.rx.recv_async().await.ok()
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Vec<RowOwned>, Error>> + Send {
+        stream::unfold(self, |rx| async move {
+            let res = rx.recv().await?;
+            Some((res, rx))
+        })
+    }
+}
+
+/// Runs the query on one of the readers and sends the rows in chunks of `chunk_size`.
+///
+/// The channel is bounded, which means the reader will only read the next chunk when the
+/// previous one has been received. As soon as the receiver is dropped, the statement is
+/// interrupted and the reader is free again. The stream ends when the channel is disconnected.
+///
+/// The `timeout` works like for `with_read_conn()`, but only counts the time the reader is
+/// busy. Waiting for the receiver to take the next chunk is not included.
+pub(crate) async fn query_stream_local<S>(
+    log_statements: bool,
+    read_pool: SqlitePool,
+    stmt: S,
+    params: Params,
+    chunk_size: usize,
+    timeout: Option<Duration>,
+) -> Result<QueryStreamRx, Error>
+where
+    S: Into<Cow<'static, str>>,
+{
+    let stmt: Cow<'static, str> = stmt.into();
+    if log_statements {
+        info!("query_stream_local:\n{}\n{:?}", stmt, params)
+    }
+
+    let chunk_size = chunk_size.max(1);
+    let (tx, rx) = flume::bounded(1);
+    let interrupt = Arc::new(Mutex::new(Interrupt::default()));
+    let guard = InterruptOnDrop(interrupt.clone());
+
+    read_pool.spawn(move |conn| {
+        {
+            let mut interrupt = interrupt.lock().unwrap();
+            if interrupt.cancelled || tx.is_disconnected() {
+                return;
+            }
+            interrupt.handle = Some(conn.get_interrupt_handle());
+        }
+
+        let deadline = timeout.map(|timeout| Deadline::install(conn, timeout));
+        let send = |chunk| {
+            let waiting = Instant::now();
+            let res = tx.send(Ok(chunk));
+            if let Some(deadline) = &deadline {
+                deadline.pause(waiting.elapsed());
+            }
+            res.is_ok()
+        };
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut stmt = conn.prepare_cached(stmt.as_ref())?;
+            let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
+
+            bind_params(&mut stmt, &params)?;
+
+            let mut rows = stmt.raw_query();
+            let mut chunk = Vec::with_capacity(chunk_size);
+            while let Some(row) = rows.next()? {
+                chunk.push(RowOwned::from_row_column(row, &columns));
+                if chunk.len() == chunk_size {
+                    let next = Vec::with_capacity(chunk_size);
+                    if !send(std::mem::replace(&mut chunk, next)) {
+                        // the stream has been dropped
+                        return Ok(());
+                    }
+                }
+            }
+            if !chunk.is_empty() {
+                send(chunk);
+            }
+
+            Ok::<(), Error>(())
+        }));
+
+        // same as for `with_read_conn()`, the handle must not outlive this job
+        interrupt.lock().unwrap().handle.take();
+        let expired = deadline.map(|d| d.remove(conn)).unwrap_or(Ok(()));
+
+        let res = match res {
+            Ok(res) => res,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        if let Err(err) = expired.and(res) {
+            let _ = tx.send(Err(err));
+        }
+    })?;
+
+    Ok(QueryStreamRx {
+        rx,
+        _interrupt: guard,
     })
-    .await?
 }
 
 #[inline(always)]
@@ -86,6 +337,7 @@ pub(crate) async fn query_map<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Vec<T>, Error>
 where
     T: for<'r> From<rows::Row<'r>> + Send + 'static,
@@ -96,24 +348,20 @@ where
         info!("query_map_typed:\n{}\n{:?}", stmt, params)
     }
 
-    let conn = state.raft_db.read_pool.get().await?;
-    task::spawn_blocking(move || {
+    let timeout = timeout.or(state.raft_db.query_timeout);
+    with_read_conn(&state.raft_db.read_pool, timeout, move |conn| {
         let mut stmt = conn.prepare_cached(stmt.as_ref())?;
 
-        let mut idx = 1;
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+        bind_params(&mut stmt, &params)?;
 
         let mut rows = stmt.raw_query();
         let mut res = Vec::new();
         while let Ok(Some(row)) = rows.next() {
             res.push(T::from(rows::Row::Borrowed(row)));
         }
-        Ok::<Vec<T>, Error>(res)
+        Ok(res)
     })
-    .await?
+    .await
 }
 
 #[inline]
@@ -121,12 +369,13 @@ pub(crate) async fn query_map_one<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<T, Error>
 where
     T: for<'r> From<rows::Row<'r>> + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_map(state, stmt, params).await?;
+    let mut rows: Vec<T> = query_map(state, stmt, params, timeout).await?;
     if rows.is_empty() {
         Err(Error::QueryReturnedNoRows("no rows returned".into()))
     } else if rows.len() > 1 {
@@ -143,12 +392,13 @@ pub(crate) async fn query_map_optional<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Option<T>, Error>
 where
     T: for<'r> From<rows::Row<'r>> + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_map(state, stmt, params).await?;
+    let mut rows: Vec<T> = query_map(state, stmt, params, timeout).await?;
     if rows.is_empty() {
         Ok(None)
     } else {
@@ -161,6 +411,7 @@ pub(crate) async fn query_as<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Vec<T>, Error>
 where
     T: DeserializeOwned + Send + 'static,
@@ -171,24 +422,20 @@ where
         info!("query_as:\n{}\n{:?}", stmt, params)
     }
 
-    let conn = state.raft_db.read_pool.get().await?;
-    task::spawn_blocking(move || {
+    let timeout = timeout.or(state.raft_db.query_timeout);
+    with_read_conn(&state.raft_db.read_pool, timeout, move |conn| {
         let mut stmt = conn.prepare_cached(stmt.as_ref())?;
 
-        let mut idx = 1;
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+        bind_params(&mut stmt, &params)?;
 
         let mut rows = serde_rusqlite::from_rows::<T>(stmt.raw_query());
         let mut res = Vec::new();
         while let Some(Ok(ty)) = rows.next() {
             res.push(ty);
         }
-        Ok::<Vec<T>, Error>(res)
+        Ok(res)
     })
-    .await?
+    .await
 }
 
 #[inline]
@@ -196,12 +443,13 @@ pub(crate) async fn query_as_one<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<T, Error>
 where
     T: DeserializeOwned + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_as(state, stmt, params).await?;
+    let mut rows: Vec<T> = query_as(state, stmt, params, timeout).await?;
     if rows.is_empty() {
         Err(Error::QueryReturnedNoRows("no rows returned".into()))
     } else if rows.len() > 1 {
@@ -218,12 +466,13 @@ pub(crate) async fn query_as_optional<T, S>(
     state: &Arc<AppState>,
     stmt: S,
     params: Params,
+    timeout: Option<Duration>,
 ) -> Result<Option<T>, Error>
 where
     T: DeserializeOwned + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_as(state, stmt, params).await?;
+    let mut rows: Vec<T> = query_as(state, stmt, params, timeout).await?;
     if rows.is_empty() {
         Ok(None)
     } else {
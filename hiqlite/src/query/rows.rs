@@ -1,11 +1,12 @@
 use crate::Error;
 use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serde::de::value::MapDeserializer;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum Row<'a> {
     Borrowed(&'a rusqlite::Row<'a>),
-    // TODO we could only include ValueOwned + ref to ColumnInfo -> smaller payloads
     Owned(RowOwned),
 }
 
@@ -31,6 +32,17 @@ impl Row<'_> {
     }
 }
 
+/// Fallible mapping of a `Row` into `Self`, which works for both local and remote rows.
+///
+/// Usually, you would not implement this manually but use `#[derive(hiqlite::FromRow)]` with the
+/// `derive` feature, which implements `From<Row>` as well to make the type usable with
+/// `query_map()`.
+///
+/// Columns are taken out of `Row::Owned` on access and can only be read once.
+pub trait FromRow: Sized {
+    fn from_row(row: &mut Row<'_>) -> Result<Self, Error>;
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct RowOwned {
     pub(crate) columns: Vec<ColumnOwned>,
@@ -76,6 +88,19 @@ impl RowOwned {
 
         Self { columns: cols }
     }
+
+    #[inline(always)]
+    fn from_values(names: &[String], values: Vec<ValueOwned>) -> Self {
+        let columns = names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| ColumnOwned {
+                name: name.clone(),
+                value,
+            })
+            .collect();
+        Self { columns }
+    }
 }
 
 impl RowOwned {
@@ -101,6 +126,134 @@ impl RowOwned {
             format!("column '{}' not found", idx).into(),
         ))
     }
+
+    /// Deserializes this row with `serde` in the same way as `query_as()` does for local rows.
+    /// This makes it possible to use `serde` for rows fetched from a remote node as well.
+    pub(crate) fn deserialize_into<T: DeserializeOwned>(self) -> Result<T, Error> {
+        let map = MapDeserializer::new(
+            self.columns
+                .into_iter()
+                .map(|c| (c.name, ValueDeserializer(c.value))),
+        );
+        T::deserialize(map).map_err(|err: de::value::Error| Error::Sqlite(err.to_string().into()))
+    }
+}
+
+struct ValueDeserializer(ValueOwned);
+
+impl<'de> IntoDeserializer<'de, de::value::Error> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Null => visitor.visit_unit(),
+            ValueOwned::Integer(i) => visitor.visit_i64(i),
+            ValueOwned::Real(r) => visitor.visit_f64(r),
+            ValueOwned::Text(s) => visitor.visit_string(s),
+            ValueOwned::Blob(b) => visitor.visit_byte_buf(b),
+        }
+    }
+
+    // SQLite has no bool type, they are stored as integers
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Integer(i) => visitor.visit_bool(i != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Blob(b) => visitor.visit_seq(b.into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    de::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Columnar encoding of a result set for the network. The column names are included only once
+/// followed by the bare values for each row, instead of repeating them with every `RowOwned`.
+///
+/// `R` is the type of a single row, which is `Result<Vec<ValueOwned>, Error>` for
+/// `execute_returning()`, where each row may fail on its own.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ResultSet<R = Vec<ValueOwned>> {
+    columns: Vec<String>,
+    rows: Vec<R>,
+}
+
+impl From<Vec<RowOwned>> for ResultSet {
+    fn from(rows: Vec<RowOwned>) -> Self {
+        let mut columns = Vec::new();
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                if columns.is_empty() {
+                    columns = row.columns.iter().map(|c| c.name.clone()).collect();
+                }
+                row.columns.into_iter().map(|c| c.value).collect()
+            })
+            .collect();
+        Self { columns, rows }
+    }
+}
+
+impl From<Vec<Result<RowOwned, Error>>> for ResultSet<Result<Vec<ValueOwned>, Error>> {
+    fn from(rows: Vec<Result<RowOwned, Error>>) -> Self {
+        let mut columns = Vec::new();
+        let rows = rows
+            .into_iter()
+            .map(|res| {
+                res.map(|row| {
+                    if columns.is_empty() {
+                        columns = row.columns.iter().map(|c| c.name.clone()).collect();
+                    }
+                    row.columns.into_iter().map(|c| c.value).collect()
+                })
+            })
+            .collect();
+        Self { columns, rows }
+    }
+}
+
+impl ResultSet {
+    pub(crate) fn into_rows(self) -> Vec<RowOwned> {
+        let columns = self.columns;
+        self.rows
+            .into_iter()
+            .map(|values| RowOwned::from_values(&columns, values))
+            .collect()
+    }
+}
+
+impl ResultSet<Result<Vec<ValueOwned>, Error>> {
+    pub(crate) fn into_rows(self) -> Vec<Result<RowOwned, Error>> {
+        let columns = self.columns;
+        self.rows
+            .into_iter()
+            .map(|res| res.map(|values| RowOwned::from_values(&columns, values)))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -197,8 +350,8 @@ impl ColumnType {
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct ColumnOwned {
-    // TODO find a way to include all the column names only once at the very top level and
-    // somehow get a reference of them into a `From<_>` impl, probably with a new Trait.
+    // The column names are only sent once over the network with a `ResultSet`, but we still
+    // need them per row locally to be able to access the values by name.
     pub(crate) name: String,
     pub(crate) value: ValueOwned,
 }
@@ -490,3 +643,95 @@ impl TryFrom<ValueOwned> for serde_json::Value {
         Ok(slf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i64, name: &str) -> RowOwned {
+        RowOwned {
+            columns: vec![
+                ColumnOwned {
+                    name: "id".to_string(),
+                    value: ValueOwned::Integer(id),
+                },
+                ColumnOwned {
+                    name: "name".to_string(),
+                    value: ValueOwned::Text(name.to_string()),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_row_deserialize() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Entity {
+            id: u32,
+            name: String,
+            active: bool,
+            description: Option<String>,
+            data: Vec<u8>,
+        }
+
+        let mut row = row(1, "a");
+        row.columns.extend([
+            ColumnOwned {
+                name: "active".to_string(),
+                value: ValueOwned::Integer(1),
+            },
+            ColumnOwned {
+                name: "description".to_string(),
+                value: ValueOwned::Null,
+            },
+            ColumnOwned {
+                name: "data".to_string(),
+                value: ValueOwned::Blob(vec![1, 2, 3]),
+            },
+        ]);
+
+        let entity: Entity = row.deserialize_into().unwrap();
+        assert_eq!(
+            entity,
+            Entity {
+                id: 1,
+                name: "a".to_string(),
+                active: true,
+                description: None,
+                data: vec![1, 2, 3],
+            }
+        );
+
+        let res: Result<Entity, Error> = self::row(2, "b").deserialize_into();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_result_set_roundtrip() {
+        let rows = vec![row(1, "a"), row(2, "b"), row(3, "c")];
+        let set: ResultSet = ResultSet::from(vec![row(1, "a"), row(2, "b"), row(3, "c")]);
+        assert_eq!(set.columns, vec!["id".to_string(), "name".to_string()]);
+
+        let bytes = bincode::serialize(&set).unwrap();
+        assert!(bytes.len() < bincode::serialize(&rows).unwrap().len());
+
+        let set: ResultSet = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(set.into_rows(), rows);
+
+        let set: ResultSet = ResultSet::from(Vec::<RowOwned>::new());
+        assert!(set.into_rows().is_empty());
+    }
+
+    #[test]
+    fn test_result_set_returning_roundtrip() {
+        let set: ResultSet<Result<Vec<ValueOwned>, Error>> = ResultSet::from(vec![
+            Err(Error::Sqlite("first row failed".into())),
+            Ok(row(2, "b")),
+        ]);
+        assert_eq!(set.columns, vec!["id".to_string(), "name".to_string()]);
+
+        let rows = set.into_rows();
+        assert!(rows[0].is_err());
+        assert_eq!(rows[1].as_ref().unwrap(), &row(2, "b"));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::app_state::AppState;
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The max staleness a local read accepts for the `query_*_max_lag()` functions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaxLag {
+    /// The local data may be at most this old, measured from the last contact with the leader.
+    Duration(Duration),
+    /// The local node may be at most this many log entries behind the leaders commit index.
+    Logs(u64),
+}
+
+impl From<Duration> for MaxLag {
+    fn from(value: Duration) -> Self {
+        Self::Duration(value)
+    }
+}
+
+impl From<u64> for MaxLag {
+    fn from(value: u64) -> Self {
+        Self::Logs(value)
+    }
+}
+
+/// Tracks the time of the last successful `append_entries` from the Raft leader on a follower
+/// node. The leader sends them at least once per heartbeat interval, even if there is nothing
+/// to replicate. The `RaftMetrics` of a follower do not contain any timing information, which
+/// is the only thing this is needed for. Everything else is taken from the metrics.
+#[derive(Debug, Default)]
+pub struct LeaderContact {
+    /// unix timestamp in millis
+    ts_millis: AtomicI64,
+    /// the term of the leader which sent the last `append_entries`
+    term: AtomicU64,
+    /// the commit index of the leader at the time of the last contact
+    leader_commit: AtomicU64,
+}
+
+impl LeaderContact {
+    #[inline]
+    pub fn update(&self, term: u64, leader_commit: u64) {
+        self.term.store(term, Ordering::Relaxed);
+        self.leader_commit.store(leader_commit, Ordering::Relaxed);
+        self.ts_millis
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+}
+
+/// Checks if the local node is fresh enough to serve a read with the given `max_lag`.
+///
+/// The leader is only fresh while it has a recent quorum ack, which makes sure a deposed leader
+/// inside a network partition will not serve stale reads. A follower compares its
+/// `last_applied` with the leaders commit index from its last contact, but only if this contact
+/// happened in the current term.
+pub(crate) fn is_within(state: &AppState, max_lag: MaxLag) -> bool {
+    let raft = &state.raft_db.raft;
+    let metrics = raft.metrics().borrow().clone();
+    let election_timeout = raft.config().election_timeout_max;
+
+    if metrics.current_leader.is_none() {
+        return false;
+    }
+
+    if metrics.current_leader == Some(metrics.id) {
+        let Some(since_ack) = metrics.millis_since_quorum_ack else {
+            return false;
+        };
+        return match max_lag {
+            MaxLag::Duration(max) => since_ack <= max.as_millis() as u64,
+            MaxLag::Logs(_) => since_ack <= election_timeout,
+        };
+    }
+
+    let contact = &state.raft_db.leader_contact;
+    let ts_contact = contact.ts_millis.load(Ordering::Relaxed);
+    if ts_contact == 0 || contact.term.load(Ordering::Relaxed) != metrics.current_term {
+        return false;
+    }
+    let since_contact = Utc::now().timestamp_millis().saturating_sub(ts_contact) as u64;
+
+    let last_applied = metrics.last_applied.map(|id| id.index).unwrap_or(0);
+    let leader_commit = contact.leader_commit.load(Ordering::Relaxed);
+    let behind = leader_commit.saturating_sub(last_applied);
+
+    match max_lag {
+        MaxLag::Duration(max) => behind == 0 && since_contact <= max.as_millis() as u64,
+        // Without a recent contact, we cannot know the leaders commit index.
+        MaxLag::Logs(max) => behind <= max && since_contact <= election_timeout,
+    }
+}
@@ -129,12 +129,40 @@ HQL_DATA_DIR={data_dir}
 # default: hiqlite.db
 #HQL_FILENAME_DB=hiqlite.db
 
+# If set to `true`, the SQLite database will only live in memory and
+# never be written to disk. No data will be lost, because the Raft
+# logs and snapshots are always persisted, and the database will be
+# rebuilt from them after a restart. Only use it if your data fits
+# into memory.
+# default: false
+#HQL_IN_MEMORY=false
+
+# Additional named databases, separated by `,`. Each one gets its own
+# SQLite file `<name>.db` next to the main database, with its own
+# read pool, migrations and snapshots. All of them are replicated
+# through the same Raft. Names may only contain `a-z`, `0-9` and `_`
+# and must be the same on each node.
+# default: none
+#HQL_DATABASES=billing,audit
+
+# The amount of additional, independent SQLite Raft groups (shards).
+# Each one has its own leader, logs and database in
+# `<HQL_DATA_DIR>/shards/<group>`, so writes to different shards do
+# not block each other. Keys are mapped to shards by their hash.
+# Changing this value later on will NOT move existing data.
+# Must be the same on each node.
+# default: 0
+#HQL_SHARDS=0
+
 # If set to `true`, all SQL statements will be logged for debugging
 # purposes.
 # default: false
 #HQL_LOG_STATEMENTS=false
 
-# The size of the pooled connections for local database reads.
+# The max amount of connections for local database reads. Each
+# connection is owned by a dedicated reader thread. Readers are
+# started on demand when queries queue up and stopped again after
+# being idle for a while, down to `HQL_READ_POOL_MIN`.
 #
 # Do not confuse this with a pool size for network databases, as it
 # is much more efficient. You can't really translate between them,
@@ -149,6 +177,24 @@ HQL_DATA_DIR={data_dir}
 # default: 4
 #HQL_READ_POOL_SIZE=4
 
+# The amount of read connections which will always be kept open.
+# Must be between 1 and `HQL_READ_POOL_SIZE`.
+# default: 1
+#HQL_READ_POOL_MIN=1
+
+# The default timeout in ms for local reads on the read pool. A query
+# running longer will be interrupted and return a timeout error, which
+# makes sure a runaway `SELECT` cannot block one of the few read
+# connections forever. `0` disables the default timeout.
+# default: 0
+#HQL_QUERY_TIMEOUT=0
+
+# The max time in ms `wait_applied()` and the `query_*_after()`
+# functions wait for a Raft log index to be applied locally, before
+# they return a timeout error.
+# default: 10000
+#HQL_WAIT_APPLIED_TIMEOUT=10000
+
 # Enables immediate flush + sync to disk after each Log Store Batch.
 # The situations where you would need this are very rare, and you
 # should use it with care.
@@ -170,6 +216,86 @@ HQL_DATA_DIR={data_dir}
 # can pretty quickly kill your SSD for instance.
 #HQL_SYNC_IMMEDIATE=false
 
+# Enables leader lease reads for consistent queries.
+# By default, each consistent query makes the leader confirm its
+# leadership with a heartbeat round to a quorum of nodes. With a
+# valid lease, derived from heartbeat acknowledgements, the leader
+# can skip this round. As soon as the lease expires, reads fall
+# back to the default.
+# default: false
+#HQL_LEASE_READS=false
+
+# The max clock drift in ms between the nodes that a leader lease
+# will tolerate. Must be lower than the Raft `election_timeout_min`.
+# default: 100
+#HQL_LEASE_CLOCK_DRIFT=100
+
+# The window in microseconds for which the leader collects concurrent
+# `execute`s before it proposes them as a single Raft entry. Each
+# query still gets its own result. This greatly improves the write
+# throughput with many concurrent, small writes in exchange for a
+# slightly higher latency. `0` disables write coalescing.
+# default: 0
+#HQL_WRITE_COALESCE_WINDOW=0
+
+# The max amount of SQLite VM steps a single write may take inside
+# the writer. A write exceeding it will be aborted and rolled back on
+# all nodes, instead of stalling the apply of all following logs.
+# Writes using more than 80% of it will be logged. This must be the
+# same on each node. `0` disables the limit.
+# default: 0
+#HQL_WRITE_COST_LIMIT=0
+
+# Compress chunks of SQLite snapshots with zstd before sending them
+# to other nodes. Chunks which a follower already has locally from
+# an older snapshot or an interrupted transfer are never sent.
+# default: false
+#HQL_SNAPSHOT_COMPRESSION=false
+
+# After an unclean shutdown, the existing database is verified with
+# `PRAGMA quick_check` and kept if it is intact, which means only the
+# logs after the last applied one need to be replayed. Set to `true`
+# to run the much slower, full `PRAGMA integrity_check` instead.
+# With `HQL_SQLITE_SYNCHRONOUS` off, this only applies if the OS has
+# not been restarted since (Linux only). Otherwise, the database is
+# always rebuilt from the snapshot and the logs.
+# default: false
+#HQL_RECOVERY_INTEGRITY_CHECK=false
+
+# The interval in seconds for the cross-replica divergence check. The
+# leader proposes a checksum entry, at which each node computes a
+# content hash of all its tables, which are compared afterward.
+# Mismatches show up in the metrics, the dashboard and the logs.
+# `0` disables the check.
+# default: 3600
+#HQL_DIVERGENCE_CHECK_INTERVAL=3600
+
+# If a node diverges from the majority, re-sync it automatically with
+# a copy of the databases from the leader.
+# default: false
+#HQL_DIVERGENCE_RESYNC=false
+
+# The SQLite tuning preset for the writer and the read pool.
+# Can be one of:
+# - low-memory: small page cache, temp files on disk, frequent WAL
+#   checkpoints
+# - balanced: SQLite default page cache with tuned WAL settings
+# - throughput: 64 MiB page cache, 256 MiB mmap, in-memory temp
+#   storage, less frequent WAL checkpoints
+# default: balanced
+#HQL_SQLITE_PRESET=balanced
+
+# Overrides for single values from the preset above.
+# `synchronous` can be one of: off, normal, full
+# `temp_store` can be one of: default, file, memory
+# A positive cache size is in pages, a negative one in KiB.
+# The `mmap_size` is in bytes, `0` disables it.
+#HQL_SQLITE_SYNCHRONOUS=off
+#HQL_SQLITE_CACHE_SIZE_WRITER=-2000
+#HQL_SQLITE_CACHE_SIZE_READER=-2000
+#HQL_SQLITE_MMAP_SIZE=0
+#HQL_SQLITE_TEMP_STORE=default
+
 # Sets the limit when the Raft will trigger the creation of a new
 # state machine snapshot and purge all logs that are included in
 # the snapshot.
@@ -179,6 +305,27 @@ HQL_DATA_DIR={data_dir}
 # default: 10000
 HQL_LOGS_UNTIL_SNAPSHOT=10000
 
+# The max time in seconds between two snapshots. If any new logs
+# have been applied since the last one, a snapshot is built after
+# this time, so a quiet cluster does not keep old logs forever.
+# `0` disables it.
+# default: 86400
+#HQL_SNAPSHOT_MAX_INTERVAL=86400
+
+# The max size in bytes of the Raft logs written since the last
+# snapshot. A snapshot, and therefore a logs purge, is triggered as
+# soon as they grow above. `0` disables it.
+# default: 0
+#HQL_SNAPSHOT_MAX_LOG_SIZE=0
+
+# The min time in seconds between two snapshots. Snapshots are only
+# built after this time, even if `HQL_LOGS_UNTIL_SNAPSHOT` or
+# `HQL_SNAPSHOT_MAX_LOG_SIZE` would trigger earlier, which prevents
+# constant snapshots in bursty clusters. Must be <=
+# `HQL_SNAPSHOT_MAX_INTERVAL`. `0` disables it.
+# default: 60
+#HQL_SNAPSHOT_MIN_INTERVAL=60
+
 # If given, these keys / certificates will be used to establish
 # TLS connections between nodes.
 #HQL_TLS_RAFT_KEY=tls/key.pem
@@ -217,6 +364,15 @@ HQL_BACKUP_KEEP_DAYS=30
 # default: 3
 HQL_BACKUP_KEEP_DAYS_LOCAL=3
 
+# Enables continuous archiving of the Raft logs to S3 for
+# point-in-time recovery. All committed logs are collected into
+# segments, which are encrypted and pushed to S3 after the given
+# amount of seconds. Segments are cleaned up on S3 together with
+# the backups after `HQL_BACKUP_KEEP_DAYS`.
+# Set to `0` to disable archiving.
+# default: 0
+#HQL_BACKUP_ARCHIVE_INTERVAL=0
+
 # Access values for the S3 bucket where backups will be pushed to.
 #HQL_S3_URL=https://s3.example.com
 #HQL_S3_BUCKET=my_bucket
@@ -62,6 +62,8 @@ pub(crate) async fn metrics(
     let metrics = match raft_type {
         #[cfg(feature = "sqlite")]
         RaftType::Sqlite => state.client.metrics_db().await?,
+        #[cfg(feature = "sqlite")]
+        RaftType::Shard(group) => state.client.metrics_shard(group).await?,
         #[cfg(feature = "cache")]
         RaftType::Cache => state.client.metrics_cache().await?,
         RaftType::Unknown => panic!("neither `sqlite` nor `cache` feature enabled"),
@@ -1,13 +1,19 @@
 use crate::network::api::{
     ApiStreamRequest, ApiStreamRequestPayload, ApiStreamResponse, ApiStreamResponsePayload,
-    WsWriteMsg,
+    QueryStreamReq, WsWriteMsg,
 };
+use crate::network::cursors::QueryCursors;
 use crate::network::handshake::HandshakeSecret;
+use crate::network::running::RunningQueries;
+use crate::query::rows::{ResultSet, RowOwned};
 use crate::server::proxy::handlers::AppStateExt;
 use crate::store::state_machine::sqlite::state_machine::Query;
-use crate::{Client, Error};
+use crate::{Client, ClientDb, Error};
 use fastwebsockets::{upgrade, FragmentCollectorRead, Frame, OpCode, Payload};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
 use std::ops::Deref;
+use std::time::Duration;
 use tokio::task;
 use tracing::{error, warn};
 
@@ -34,6 +40,10 @@ pub async fn handle_socket(
     // IMPORTANT: the reader is NOT CANCEL SAFE in v0.8!
     let mut read = FragmentCollectorRead::new(rx);
 
+    // open query streams for this connection, which will be cancelled when it is closed
+    let streams = QueryStreams::new(MAX_STREAMS);
+    let running = RunningQueries::default();
+
     let handle_write = task::spawn(async move {
         while let Ok(req) = rx_write.recv_async().await {
             match req {
@@ -116,27 +126,67 @@ pub async fn handle_socket(
             }
         };
 
+        // Aborting the proxied query drops its client future, which cancels it upstream.
+        if let ApiStreamRequestPayload::Cancel = req.payload {
+            running.cancel(req.request_id);
+            continue;
+        }
+        let is_query = req.payload.is_query();
+
         let state = state.clone();
         let tx_write = tx_write.clone();
-        task::spawn(async move {
+        let streams = streams.clone();
+        // exchange orig req id for our own to avoid conflicts
+        let request_id = req.request_id;
+        let fut = async move {
             let client = &state.client;
-            // exchange orig req id for our own to avoid conflicts
-            let request_id = req.request_id;
 
             let res = match req.payload {
                 ApiStreamRequestPayload::Execute(sql) => {
-                    let res = client.execute(sql.sql, sql.params).await;
+                    let res = client.execute_indexed(sql.sql, sql.params).await;
                     ApiStreamResponse {
                         request_id,
                         result: ApiStreamResponsePayload::Execute(res),
                     }
                 }
 
+                ApiStreamRequestPayload::ExecuteMany(query) => {
+                    match client.execute_many_req(query.clone()).await {
+                        Ok(res) => ApiStreamResponse {
+                            request_id,
+                            result: ApiStreamResponsePayload::Execute(Ok(res)),
+                        },
+                        Err(err) => {
+                            if client
+                                .was_leader_update_error(
+                                    &err,
+                                    &client.inner.leader_db,
+                                    &client.inner.tx_client_db,
+                                )
+                                .await
+                            {
+                                let res = client.execute_many_req(query).await;
+                                ApiStreamResponse {
+                                    request_id,
+                                    result: ApiStreamResponsePayload::Execute(res),
+                                }
+                            } else {
+                                ApiStreamResponse {
+                                    request_id,
+                                    result: ApiStreamResponsePayload::Execute(Err(err)),
+                                }
+                            }
+                        }
+                    }
+                }
+
                 ApiStreamRequestPayload::ExecuteReturning(query) => {
                     match client.execute_returning_req(query.clone()).await {
                         Ok(res) => ApiStreamResponse {
                             request_id,
-                            result: ApiStreamResponsePayload::ExecuteReturning(Ok(res)),
+                            result: ApiStreamResponsePayload::ExecuteReturning(Ok(
+                                res.map(ResultSet::from)
+                            )),
                         },
                         Err(err) => {
                             if client
@@ -150,7 +200,9 @@ pub async fn handle_socket(
                                 let res = client.execute_returning_req(query).await;
                                 ApiStreamResponse {
                                     request_id,
-                                    result: ApiStreamResponsePayload::ExecuteReturning(res),
+                                    result: ApiStreamResponsePayload::ExecuteReturning(
+                                        res.map(|res| res.map(ResultSet::from)),
+                                    ),
                                 }
                             } else {
                                 ApiStreamResponse {
@@ -186,12 +238,63 @@ pub async fn handle_socket(
                     }
                 }
 
+                ApiStreamRequestPayload::TransactionGuarded(queries) => {
+                    let res = match client.txn_guarded_execute(queries.clone()).await {
+                        Ok(res) => Ok(res),
+                        Err(err) => {
+                            if client
+                                .was_leader_update_error(
+                                    &err,
+                                    &client.inner.leader_db,
+                                    &client.inner.tx_client_db,
+                                )
+                                .await
+                            {
+                                client.txn_guarded_execute(queries).await
+                            } else {
+                                Err(err)
+                            }
+                        }
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::Transaction(res),
+                    }
+                }
+
                 ApiStreamRequestPayload::QueryConsistent(q) => {
-                    query(client, request_id, q, true).await
+                    query(client, request_id, q, true, None).await
+                }
+
+                ApiStreamRequestPayload::QueryTimeout(q, timeout) => {
+                    let timeout = Duration::from_millis(timeout);
+                    query(client, request_id, q, false, Some(timeout)).await
+                }
+
+                ApiStreamRequestPayload::QueryConsistentTimeout(q, timeout) => {
+                    let timeout = Duration::from_millis(timeout);
+                    query(client, request_id, q, true, Some(timeout)).await
+                }
+
+                ApiStreamRequestPayload::DbWrite(db, query) => {
+                    let handle = ClientDb::new(client.clone(), 0, db);
+                    let res = handle.write(query).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::DbWrite(res),
+                    }
+                }
+
+                ApiStreamRequestPayload::DbQuery(db, q) => {
+                    query_db(client, request_id, db, q, false).await
+                }
+
+                ApiStreamRequestPayload::DbQueryConsistent(db, q) => {
+                    query_db(client, request_id, db, q, true).await
                 }
 
                 ApiStreamRequestPayload::Batch(sql) => {
-                    let res = client.batch(sql).await;
+                    let res = client.batch_indexed(sql).await;
                     ApiStreamResponse {
                         request_id,
                         result: ApiStreamResponsePayload::Batch(res),
@@ -222,6 +325,20 @@ pub async fn handle_socket(
                     }
                 }
 
+                ApiStreamRequestPayload::QueryStream(stream_req) => {
+                    let res = query_stream_next(client, &streams, stream_req).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::QueryStream(
+                            res.map(|rows| rows.map(ResultSet::from)),
+                        ),
+                    }
+                }
+
+                ApiStreamRequestPayload::Cancel => {
+                    unreachable!("Cancel is handled before spawning the request")
+                }
+
                 ApiStreamRequestPayload::Backup(_node_id) => {
                     let res = client.backup().await;
                     ApiStreamResponse {
@@ -230,7 +347,9 @@ pub async fn handle_socket(
                     }
                 }
 
-                ApiStreamRequestPayload::Query(q) => query(client, request_id, q, false).await,
+                ApiStreamRequestPayload::Query(q) => {
+                    query(client, request_id, q, false, None).await
+                }
 
                 ApiStreamRequestPayload::KV(cache_req) => {
                     let res = client.cache_req_retry(cache_req, false).await;
@@ -275,7 +394,13 @@ pub async fn handle_socket(
             if let Err(err) = tx_write.send_async(WsWriteMsg::Payload(res)).await {
                 error!("Error sending payload to tx_write: {}", err);
             }
-        });
+        };
+
+        if is_query {
+            running.spawn(request_id, fut);
+        } else {
+            task::spawn(fut);
+        }
     }
 
     // ignore the result in case the writer has already exited and drop the channel
@@ -294,20 +419,56 @@ async fn query(
     request_id: usize,
     query: Query,
     consistent: bool,
+    timeout: Option<Duration>,
 ) -> ApiStreamResponse {
-    let res = match client.query_remote_req(query.clone(), consistent).await {
+    let res = match client
+        .query_remote_req(query.clone(), consistent, timeout)
+        .await
+    {
         Ok(res) => Ok(res),
         Err(err) => {
             if client
                 .was_leader_update_error(&err, &client.inner.leader_db, &client.inner.tx_client_db)
                 .await
             {
-                client.query_remote_req(query, consistent).await
+                client.query_remote_req(query, consistent, timeout).await
             } else {
                 Err(err)
             }
         }
+    }
+    .map(ResultSet::from);
+
+    let result = if consistent {
+        ApiStreamResponsePayload::QueryConsistent(res)
+    } else {
+        ApiStreamResponsePayload::Query(res)
     };
+    ApiStreamResponse { request_id, result }
+}
+
+async fn query_db(
+    client: &Client,
+    request_id: usize,
+    db: String,
+    query: Query,
+    consistent: bool,
+) -> ApiStreamResponse {
+    let db = client.db(db);
+    let res = match db.query_remote_req(query.clone(), consistent).await {
+        Ok(res) => Ok(res),
+        Err(err) => {
+            if client
+                .was_leader_update_error(&err, &client.inner.leader_db, &client.inner.tx_client_db)
+                .await
+            {
+                db.query_remote_req(query, consistent).await
+            } else {
+                Err(err)
+            }
+        }
+    }
+    .map(ResultSet::from);
 
     let result = if consistent {
         ApiStreamResponsePayload::QueryConsistent(res)
@@ -316,3 +477,51 @@ async fn query(
     };
     ApiStreamResponse { request_id, result }
 }
+
+/// The max amount of open query streams per proxied connection. Each of them is a cursor on
+/// the leader, which limits them by the size of its read pool in addition.
+const MAX_STREAMS: usize = 64;
+
+type QueryStreams = QueryCursors<BoxStream<'static, Result<Vec<RowOwned>, Error>>>;
+
+async fn query_stream_next(
+    client: &Client,
+    streams: &QueryStreams,
+    req: QueryStreamReq,
+) -> Result<Option<Vec<RowOwned>>, Error> {
+    let (stream_id, mut stream) = match req {
+        QueryStreamReq::Open {
+            stream_id,
+            query,
+            chunk_size,
+        } => {
+            streams.open(stream_id)?;
+            (
+                stream_id,
+                client.query_stream_owned(query.sql, query.params, chunk_size),
+            )
+        }
+
+        QueryStreamReq::Next(stream_id) => (stream_id, streams.take(stream_id)?),
+
+        QueryStreamReq::Close(stream_id) => {
+            streams.remove(stream_id);
+            return Ok(None);
+        }
+    };
+
+    match stream.next().await {
+        Some(Ok(rows)) => {
+            streams.put(stream_id, stream);
+            Ok(Some(rows))
+        }
+        Some(Err(err)) => {
+            streams.remove(stream_id);
+            Err(err)
+        }
+        None => {
+            streams.remove(stream_id);
+            Ok(None)
+        }
+    }
+}
@@ -17,6 +17,10 @@ use tracing::{debug, info};
 use crate::backup;
 #[cfg(feature = "dashboard")]
 use crate::dashboard;
+#[cfg(feature = "sqlite")]
+use crate::divergence_check;
+#[cfg(feature = "backup")]
+use crate::pitr;
 #[cfg(feature = "s3")]
 use crate::s3;
 
@@ -58,7 +62,16 @@ where
     let raft_config = Arc::new(node_config.raft_config.clone().validate().unwrap());
 
     #[cfg(feature = "sqlite")]
-    let raft_db = store::start_raft_db(node_config.clone(), raft_config.clone()).await?;
+    let raft_db = store::start_raft_db(node_config.clone(), raft_config.clone(), 0).await?;
+    #[cfg(feature = "sqlite")]
+    let raft_db_shards = {
+        let mut shards = Vec::with_capacity(node_config.shards as usize);
+        for group in 1..=node_config.shards {
+            shards
+                .push(store::start_raft_db(node_config.clone(), raft_config.clone(), group).await?);
+        }
+        shards
+    };
     #[cfg(feature = "cache")]
     let (is_pristine_cache_node_1, raft_cache) =
         store::start_raft_cache::<C>(node_config.clone(), raft_config).await?;
@@ -90,6 +103,10 @@ where
         addr_api: api_addr.clone(),
         #[cfg(feature = "sqlite")]
         raft_db,
+        #[cfg(feature = "sqlite")]
+        client_buffers_shards: raft_db_shards.iter().map(|_| Default::default()).collect(),
+        #[cfg(feature = "sqlite")]
+        raft_db_shards,
         #[cfg(feature = "cache")]
         raft_cache,
         secret_api: node_config.secret_api,
@@ -115,6 +132,22 @@ where
         node_config.tls_api.is_some(),
     );
 
+    #[cfg(feature = "sqlite")]
+    divergence_check::spawn(
+        state.clone(),
+        node_config.tls_api.is_some(),
+        tls_no_verify,
+        node_config.divergence_check_interval,
+        node_config.divergence_resync,
+    );
+
+    #[cfg(feature = "backup")]
+    if let Some(s3_config) = &node_config.s3_config {
+        if node_config.backup_config.archive_interval().is_some() {
+            pitr::spawn_uploader(state.clone(), s3_config.clone(), &node_config.data_dir);
+        }
+    }
+
     #[cfg(all(feature = "backup", feature = "sqlite"))]
     if backup_applied {
         backup::restore_backup_finish(&state).await;
@@ -159,21 +192,25 @@ where
         }
     });
 
-    let default_routes = Router::new()
-        .nest(
-            "/cluster",
-            Router::new()
-                .route("/add_learner/{raft_type}", post(management::add_learner))
-                .route(
-                    "/become_member/{raft_type}",
-                    post(management::become_member),
-                )
-                .route(
-                    "/membership/{raft_type}",
-                    get(management::get_membership).post(management::post_membership),
-                )
-                .route("/metrics/{raft_type}", get(management::metrics)),
+    let router_cluster = Router::new()
+        .route("/add_learner/{raft_type}", post(management::add_learner))
+        .route(
+            "/become_member/{raft_type}",
+            post(management::become_member),
+        )
+        .route(
+            "/membership/{raft_type}",
+            get(management::get_membership).post(management::post_membership),
         )
+        .route("/metrics/{raft_type}", get(management::metrics))
+        .route("/shards", get(management::shards));
+    #[cfg(feature = "sqlite")]
+    let router_cluster = router_cluster
+        .route("/checksum/{raft_type}/{index}", get(management::checksum))
+        .route("/resync/{raft_type}/{index}", get(management::resync));
+
+    let default_routes = Router::new()
+        .nest("/cluster", router_cluster)
         // TODO
         // .route("/execute", post(api::execute))
         // TODO
@@ -198,6 +235,7 @@ where
                     .nest(
                         "/api",
                         Router::new()
+                            .route("/databases", get(dashboard::handlers::get_databases))
                             .route("/metrics", get(dashboard::handlers::get_metrics))
                             .route("/pow", get(dashboard::handlers::get_pow))
                             .route("/query", post(dashboard::handlers::post_query))
@@ -266,6 +304,30 @@ where
         })
     };
 
+    #[cfg(feature = "sqlite")]
+    let member_shards = (1..=node_config.shards)
+        .map(|group| {
+            let st = state.clone();
+            let node_id = node_config.node_id;
+            let nodes = node_config.nodes.clone();
+            let election_timeout_max = node_config.raft_config.election_timeout_max;
+
+            task::spawn(async move {
+                init::become_cluster_member(
+                    st,
+                    &crate::app_state::RaftType::Shard(group),
+                    node_id,
+                    &nodes,
+                    false,
+                    election_timeout_max,
+                    tls_raft,
+                    tls_no_verify,
+                )
+                .await
+            })
+        })
+        .collect::<Vec<_>>();
+
     #[cfg(feature = "cache")]
     let member_cache = {
         let st = state.clone();
@@ -288,6 +350,10 @@ where
 
     #[cfg(feature = "sqlite")]
     member_db.await??;
+    #[cfg(feature = "sqlite")]
+    for member_shard in member_shards {
+        member_shard.await??;
+    }
     #[cfg(feature = "cache")]
     member_cache.await??;
 
@@ -38,6 +38,7 @@ use std::future::Future;
 use std::io::Cursor;
 use std::ops::RangeBounds;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{oneshot, RwLock};
@@ -409,6 +410,8 @@ pub struct LogStoreRocksdb {
     db: Arc<DB>,
     pub(crate) tx_writer: flume::Sender<ActionWrite>,
     tx_reader: flume::Sender<ActionRead>,
+    /// The total size of all serialized entries appended since the start
+    appended_bytes: Arc<AtomicU64>,
 }
 
 impl LogStoreRocksdb {
@@ -475,8 +478,15 @@ impl LogStoreRocksdb {
             db,
             tx_writer,
             tx_reader,
+            appended_bytes: Default::default(),
         }
     }
+
+    /// Grows with each appended log entry by its serialized size. It never shrinks, because the
+    /// size of the logs written since some point is the difference of two values.
+    pub fn appended_bytes(&self) -> Arc<AtomicU64> {
+        self.appended_bytes.clone()
+    }
 }
 
 impl RaftLogReader<TypeConfigSqlite> for LogStoreRocksdb {
@@ -539,6 +549,7 @@ impl RaftLogStorage<TypeConfigSqlite> for LogStoreRocksdb {
             db: self.db.clone(),
             tx_writer: self.tx_writer.clone(),
             tx_reader,
+            appended_bytes: self.appended_bytes.clone(),
         }
     }
 
@@ -611,6 +622,8 @@ impl RaftLogStorage<TypeConfigSqlite> for LogStoreRocksdb {
         for entry in entries {
             let id = id_to_bin(entry.log_id.index);
             let data = bincode::serialize(&entry).unwrap();
+            self.appended_bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
 
             tx.send_async(Some((id, data)))
                 .await
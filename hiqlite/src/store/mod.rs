@@ -12,6 +12,7 @@ use std::borrow::Cow;
 use std::cmp::PartialEq;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 use strum::IntoEnumIterator;
 
 #[cfg(feature = "cache")]
@@ -20,10 +21,15 @@ use crate::{
     store::state_machine::memory::{state_machine::StateMachineMemory, TypeConfigKV},
 };
 
+#[cfg(feature = "backup")]
+use crate::pitr::LogArchive;
 #[cfg(feature = "sqlite")]
 use crate::{
     app_state::StateRaftDB,
+    query::lease::LeaderLease,
     store::state_machine::sqlite::{
+        coalesce::WriteCoalescer,
+        snapshot_schedule::SnapshotSchedule,
         state_machine::{SqlitePool, StateMachineSqlite},
         writer::WriterRequest,
         TypeConfigSqlite,
@@ -35,30 +41,80 @@ pub mod state_machine;
 
 pub type StorageResult<T> = Result<T, StorageError<NodeId>>;
 
+/// Starts the SQLite Raft with the given group id. `0` is the main Raft, while all others are
+/// additional shards, which live inside `<data_dir>/shards/<group>`.
 #[cfg(feature = "sqlite")]
 pub(crate) async fn start_raft_db(
     node_config: NodeConfig,
     raft_config: Arc<RaftConfig>,
+    group: u16,
 ) -> Result<StateRaftDB, Error> {
-    let log_store =
-        logs::rocksdb::LogStoreRocksdb::new(&node_config.data_dir, node_config.sync_immediate)
-            .await;
+    let (raft_type, data_dir) = if group == 0 {
+        (RaftType::Sqlite, node_config.data_dir.to_string())
+    } else {
+        (
+            RaftType::Shard(group),
+            format!("{}/shards/{}", node_config.data_dir, group),
+        )
+    };
+    // named databases only exist inside the main Raft
+    let databases: &[String] = if group == 0 {
+        &node_config.databases
+    } else {
+        &[]
+    };
+
+    let (snapshot_schedule, raft_config) = SnapshotSchedule::new(&node_config, raft_config);
+
+    let mut log_store =
+        logs::rocksdb::LogStoreRocksdb::new(&data_dir, node_config.sync_immediate).await;
+
+    // only the main Raft is part of the backups
+    #[cfg(feature = "backup")]
+    let archive = match node_config.backup_config.archive_interval() {
+        Some(interval) if group == 0 && node_config.s3_config.is_some() => {
+            let log_reader = log_store.get_log_reader().await;
+            Some(LogArchive::spawn(&data_dir, interval, log_reader).await?)
+        }
+        _ => None,
+    };
     let state_machine_store = StateMachineSqlite::new(
-        &node_config.data_dir,
+        &data_dir,
         &node_config.filename_db,
+        node_config.in_memory,
+        databases,
         node_config.node_id,
         node_config.log_statements,
+        node_config.sqlite_config,
+        node_config.write_cost_limit,
         node_config.prepared_statement_cache_capacity,
+        node_config.read_pool_min,
         node_config.read_pool_size,
+        node_config.recovery_integrity_check,
+        node_config.sql_functions,
         #[cfg(feature = "s3")]
         node_config.s3_config,
+        #[cfg(feature = "backup")]
+        archive,
     )
     .await
     .unwrap();
 
     let logs_writer = log_store.tx_writer.clone();
+    let appended_bytes = log_store.appended_bytes();
     let sql_writer = state_machine_store.write_tx.clone();
     let read_pool = state_machine_store.read_pool.clone();
+    let named_read_pools = state_machine_store.named_read_pools.clone();
+    let write_cost = state_machine_store.write_cost.clone();
+    let checksums = state_machine_store.checksums.clone();
+    let path_snapshots = state_machine_store.path_snapshots.clone();
+    let lease = if node_config.lease_reads {
+        Some(Arc::new(LeaderLease::new(Duration::from_millis(
+            node_config.lease_clock_drift,
+        ))))
+    } else {
+        None
+    };
 
     // Create the network layer that will connect and communicate the raft instances and
     // will be used in conjunction with the store created above.
@@ -67,7 +123,10 @@ pub(crate) async fn start_raft_db(
         tls_config: node_config.tls_raft.as_ref().map(|tls| tls.client_config()),
         secret_raft: node_config.secret_raft.as_bytes().to_vec(),
         raft_type: RaftType::Sqlite,
+        group,
         heartbeat_interval: node_config.raft_config.heartbeat_interval,
+        lease: lease.clone(),
+        snapshot_compression: node_config.snapshot_compression,
     };
 
     // Create a local raft instance.
@@ -83,6 +142,7 @@ pub(crate) async fn start_raft_db(
 
     init::init_pristine_node_1_db(
         &raft,
+        &raft_type,
         node_config.node_id,
         &node_config.nodes,
         &node_config.secret_api,
@@ -95,13 +155,37 @@ pub(crate) async fn start_raft_db(
     )
     .await?;
 
+    if let Some(schedule) = snapshot_schedule {
+        schedule.spawn(raft.clone(), appended_bytes);
+    }
+
+    let coalescer = if node_config.write_coalesce_window > 0 {
+        Some(WriteCoalescer::spawn(
+            raft.clone(),
+            Duration::from_micros(node_config.write_coalesce_window),
+        ))
+    } else {
+        None
+    };
+
     Ok(StateRaftDB {
         raft,
         lock: Default::default(),
         logs_writer,
         sql_writer,
         read_pool,
+        named_read_pools,
         log_statements: node_config.log_statements,
+        query_timeout: (node_config.query_timeout > 0)
+            .then(|| Duration::from_millis(node_config.query_timeout)),
+        wait_applied_timeout: Duration::from_millis(node_config.wait_applied_timeout),
+        sqlite_config: node_config.sqlite_config,
+        write_cost,
+        checksums,
+        path_snapshots,
+        leader_contact: Default::default(),
+        lease,
+        coalescer,
     })
 }
 
@@ -121,7 +205,13 @@ where
         tls_config: node_config.tls_raft.as_ref().map(|tls| tls.client_config()),
         secret_raft: node_config.secret_raft.as_bytes().to_vec(),
         raft_type: RaftType::Cache,
+        #[cfg(feature = "sqlite")]
+        group: 0,
         heartbeat_interval: node_config.raft_config.heartbeat_interval,
+        #[cfg(feature = "sqlite")]
+        lease: None,
+        #[cfg(feature = "sqlite")]
+        snapshot_compression: false,
     };
 
     let tx_caches = state_machine_store.tx_caches.clone();
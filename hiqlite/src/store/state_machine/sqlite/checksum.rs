@@ -0,0 +1,521 @@
+use crate::{Error, NodeId};
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch, Notify};
+use tokio::time;
+
+/// The amount of local checksums kept for the comparison with other nodes.
+const CHECKSUMS_KEEP: usize = 16;
+
+/// Tables which are expected to differ between nodes. `_metadata` contains node-local state and
+/// `_migrations` the local time each migration has been applied at.
+const TABLES_SKIP: [&str; 2] = ["_metadata", "_migrations"];
+
+/// The key for the hash over the schema of each database.
+const KEY_SCHEMA: &str = "sqlite_schema";
+
+/// The query planner statistics written by `ANALYZE` and `PRAGMA optimize`.
+const TABLE_STATS: &str = "sqlite_stat1";
+
+/// The content hash of all databases of a node at a specific applied log index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DbChecksum {
+    pub log_index: u64,
+    /// SHA-256 over all `tables`, hex-encoded
+    pub hash: String,
+    /// The hash for each table and the schema, with `<db>.<table>` for named databases
+    pub tables: BTreeMap<String, String>,
+}
+
+impl DbChecksum {
+    /// Hashes the schema and all rows of each table. Rows are read in primary key order for
+    /// `WITHOUT ROWID` tables and tables with an `INTEGER PRIMARY KEY`. All other tables are
+    /// hashed independently of the row order, because their `rowid` is not part of the data
+    /// and may be renumbered by a `VACUUM`. This makes the result independent of the physical
+    /// layout of the database file.
+    ///
+    /// With `stats`, `sqlite_stat1` is included. The statistics decide about the query plans
+    /// and with that the VM steps of each write, which must be the same on all nodes with the
+    /// write cost guard.
+    ///
+    /// MUST NOT be executed in async context!
+    pub(crate) fn compute(
+        log_index: u64,
+        main: &Connection,
+        named: &[(&str, &Connection)],
+        stats: bool,
+    ) -> Result<Self, Error> {
+        let mut tables = BTreeMap::new();
+        hash_db(main, None, stats, &mut tables)?;
+        for (name, conn) in named {
+            hash_db(conn, Some(name), stats, &mut tables)?;
+        }
+
+        let mut hasher = Sha256::new();
+        for (table, hash) in &tables {
+            hasher.update(table.as_bytes());
+            hasher.update(hash.as_bytes());
+        }
+
+        Ok(Self {
+            log_index,
+            hash: hex::encode(hasher.finalize()),
+            tables,
+        })
+    }
+
+    /// Returns all tables with a different hash, including the ones which exist on one side only.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut diff = self
+            .tables
+            .iter()
+            .filter(|(table, hash)| other.tables.get(*table) != Some(hash))
+            .map(|(table, _)| table.clone())
+            .collect::<Vec<_>>();
+        for table in other.tables.keys() {
+            if !self.tables.contains_key(table) {
+                diff.push(table.clone());
+            }
+        }
+        diff.sort();
+        diff
+    }
+}
+
+fn hash_db(
+    conn: &Connection,
+    db: Option<&str>,
+    stats: bool,
+    tables: &mut BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let key = |table: &str| match db {
+        None => table.to_string(),
+        Some(db) => format!("{}.{}", db, table),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut names = Vec::new();
+    let mut stmt = conn.prepare(
+        r#"
+SELECT type, name, tbl_name, sql
+FROM sqlite_master
+WHERE name NOT LIKE 'sqlite_%'
+ORDER BY type, name"#,
+    )?;
+    let mut rows = stmt.query(())?;
+    while let Some(row) = rows.next()? {
+        for i in 0..4 {
+            hash_value(&mut hasher, row.get_ref(i)?);
+        }
+        let name: String = row.get(1)?;
+        if row.get_ref(0)?.as_str().map_err(rusqlite::Error::from)? == "table"
+            && !TABLES_SKIP.contains(&name.as_str())
+        {
+            names.push(name);
+        }
+    }
+    tables.insert(key(KEY_SCHEMA), hex::encode(hasher.finalize()));
+
+    // only exists after the first `ANALYZE`
+    if stats
+        && conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = $1",
+                [TABLE_STATS],
+                |_| Ok(()),
+            )
+            .is_ok()
+    {
+        names.push(TABLE_STATS.to_string());
+    }
+
+    for name in names {
+        let hash = hash_table(conn, &name)?;
+        tables.insert(key(&name), hash);
+    }
+
+    Ok(())
+}
+
+fn hash_table(conn: &Connection, table: &str) -> Result<String, Error> {
+    let without_rowid: bool = conn.query_row(
+        "SELECT wr FROM pragma_table_list WHERE schema = 'main' AND name = $1",
+        [table],
+        |row| row.get(0),
+    )?;
+    let pk = conn
+        .prepare("SELECT name, type FROM pragma_table_info($1) WHERE pk > 0 ORDER BY pk")?
+        .query_map([table], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    // Only an `INTEGER PRIMARY KEY` is an alias for the `rowid`, all other primary keys of
+    // `rowid` tables may contain multiple `NULL`s.
+    let is_ordered = without_rowid || (pk.len() == 1 && pk[0].1.eq_ignore_ascii_case("INTEGER"));
+
+    let sql = if is_ordered {
+        let order = pk
+            .iter()
+            .map(|(name, _)| quote_ident(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("SELECT * FROM {} ORDER BY {}", quote_ident(table), order)
+    } else {
+        format!("SELECT * FROM {}", quote_ident(table))
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let columns = stmt.column_count();
+    let mut rows = stmt.query(())?;
+
+    if is_ordered {
+        let mut hasher = Sha256::new();
+        while let Some(row) = rows.next()? {
+            for i in 0..columns {
+                hash_value(&mut hasher, row.get_ref(i)?);
+            }
+        }
+        return Ok(hex::encode(hasher.finalize()));
+    }
+
+    // Each row is hashed on its own and all row hashes are summed up, which gives the same
+    // result in any order, while duplicate rows still count.
+    let mut sum = [0u64; 4];
+    let mut count = 0u64;
+    while let Some(row) = rows.next()? {
+        let mut hasher = Sha256::new();
+        for i in 0..columns {
+            hash_value(&mut hasher, row.get_ref(i)?);
+        }
+        for (lane, bytes) in sum.iter_mut().zip(hasher.finalize().chunks_exact(8)) {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            *lane = lane.wrapping_add(u64::from_le_bytes(buf));
+        }
+        count += 1;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(count.to_le_bytes());
+    for lane in sum {
+        hasher.update(lane.to_le_bytes());
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[inline]
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Each value is prefixed with its type and variable length values with their length, which
+/// makes the encoding unambiguous.
+#[inline]
+fn hash_value(hasher: &mut Sha256, value: ValueRef<'_>) {
+    match value {
+        ValueRef::Null => hasher.update([0u8]),
+        ValueRef::Integer(i) => {
+            hasher.update([1u8]);
+            hasher.update(i.to_le_bytes());
+        }
+        ValueRef::Real(f) => {
+            hasher.update([2u8]);
+            hasher.update(f.to_bits().to_le_bytes());
+        }
+        ValueRef::Text(bytes) => {
+            hasher.update([3u8]);
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        }
+        ValueRef::Blob(bytes) => {
+            hasher.update([4u8]);
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        }
+    }
+}
+
+/// Statistics of the cross-replica divergence checks on this node.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DivergenceMetrics {
+    /// The amount of comparisons with the other nodes since the start
+    pub checks: u64,
+    /// The applied log index of the last comparison
+    pub last_log_index: Option<u64>,
+    /// The local hash at `last_log_index`
+    pub last_hash: Option<String>,
+    /// Nodes with data different from the majority at `last_log_index`. This includes the local
+    /// node, if it is the one diverging.
+    pub divergent_nodes: Vec<NodeId>,
+    /// Tables which differ on at least one of the `divergent_nodes`
+    pub divergent_tables: Vec<String>,
+    /// Nodes which did not return their checksum for `last_log_index` in time
+    pub missing_nodes: Vec<NodeId>,
+    /// The amount of times this node has been re-synced from another one
+    pub resyncs: u64,
+}
+
+/// The database copy for a re-sync at `log_index`, which is created on the source and
+/// downloaded to the same path on the divergent node.
+pub(crate) fn resync_path(path_snapshots: &str, log_index: u64) -> String {
+    format!("{}/temp_resync_{}", path_snapshots, log_index)
+}
+
+/// A divergent node waits at the `Resync` log entry until the database copy of `source` has
+/// been downloaded to the returned path.
+#[derive(Debug)]
+pub(crate) struct ResyncRequest {
+    pub log_index: u64,
+    pub source: NodeId,
+    pub ack: oneshot::Sender<Result<String, Error>>,
+}
+
+/// The state of the last database copy for a re-sync on the source.
+#[derive(Debug, Default, Clone, Copy)]
+struct ResyncCopy {
+    log_index: u64,
+    ok: bool,
+}
+
+/// The local checksums of a single SQLite Raft and the result of the last comparison.
+#[derive(Debug)]
+pub(crate) struct Checksums {
+    local: Mutex<VecDeque<DbChecksum>>,
+    tx_latest: watch::Sender<u64>,
+    metrics: Mutex<DivergenceMetrics>,
+    resync: Mutex<Option<ResyncRequest>>,
+    resync_notify: Notify,
+    tx_resync_copy: watch::Sender<ResyncCopy>,
+}
+
+impl Checksums {
+    pub fn new() -> Self {
+        Self {
+            local: Mutex::new(VecDeque::with_capacity(CHECKSUMS_KEEP)),
+            tx_latest: watch::channel(0).0,
+            metrics: Default::default(),
+            resync: Default::default(),
+            resync_notify: Notify::new(),
+            tx_resync_copy: watch::channel(ResyncCopy::default()).0,
+        }
+    }
+
+    pub fn insert(&self, checksum: DbChecksum) {
+        let log_index = checksum.log_index;
+        {
+            let mut local = self.local.lock().unwrap();
+            if local.len() >= CHECKSUMS_KEEP {
+                local.pop_front();
+            }
+            local.push_back(checksum);
+        }
+        self.tx_latest.send_replace(log_index);
+    }
+
+    pub fn get(&self, log_index: u64) -> Option<DbChecksum> {
+        self.local
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.log_index == log_index)
+            .cloned()
+    }
+
+    /// Changes with each new local checksum to its log index.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.tx_latest.subscribe()
+    }
+
+    pub fn metrics(&self) -> DivergenceMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    pub fn update_metrics<F>(&self, f: F)
+    where
+        F: FnOnce(&mut DivergenceMetrics),
+    {
+        f(&mut self.metrics.lock().unwrap());
+    }
+
+    pub fn request_resync(
+        &self,
+        log_index: u64,
+        source: NodeId,
+    ) -> oneshot::Receiver<Result<String, Error>> {
+        let (ack, rx) = oneshot::channel();
+        *self.resync.lock().unwrap() = Some(ResyncRequest {
+            log_index,
+            source,
+            ack,
+        });
+        self.resync_notify.notify_one();
+        rx
+    }
+
+    /// Must be called on the source, as soon as the database copy for the re-sync at
+    /// `log_index` has been moved to its final path, or when creating it has failed.
+    pub fn resync_copy_done(&self, log_index: u64, ok: bool) {
+        self.tx_resync_copy
+            .send_replace(ResyncCopy { log_index, ok });
+    }
+
+    /// Waits until the source has created the database copy for `log_index`.
+    pub async fn wait_resync_copy(&self, log_index: u64, timeout: Duration) -> Result<(), Error> {
+        let mut rx = self.tx_resync_copy.subscribe();
+        let copy = time::timeout(timeout, rx.wait_for(|copy| copy.log_index >= log_index))
+            .await
+            .ok()
+            .and_then(|res| res.ok().map(|copy| *copy));
+
+        match copy {
+            // a newer copy does not tell anything about this one, opening it will
+            Some(copy) if copy.ok || copy.log_index > log_index => Ok(()),
+            Some(_) => Err(Error::Divergence(
+                format!(
+                    "creating the re-sync copy for log index {} has failed",
+                    log_index
+                )
+                .into(),
+            )),
+            None => Err(Error::Timeout(format!(
+                "no re-sync copy for log index {} after {} s",
+                log_index,
+                timeout.as_secs()
+            ))),
+        }
+    }
+
+    pub async fn next_resync(&self) -> ResyncRequest {
+        loop {
+            if let Some(req) = self.resync.lock().unwrap().take() {
+                return req;
+            }
+            self.resync_notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(rows: &[(i64, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);
+CREATE TABLE kv (k TEXT PRIMARY KEY, v BLOB) WITHOUT ROWID;
+CREATE TABLE _metadata (key TEXT PRIMARY KEY, data BLOB);
+"#,
+        )
+        .unwrap();
+        for (id, value) in rows {
+            conn.execute("INSERT INTO test VALUES ($1, $2)", (id, value))
+                .unwrap();
+            conn.execute("INSERT INTO kv VALUES ($1, $2)", (value, id))
+                .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_checksum() {
+        let a = db(&[(1, "a"), (2, "b")]);
+        // same content, inserted in another order
+        let b = db(&[(2, "b"), (1, "a")]);
+        b.execute("INSERT INTO _metadata VALUES ('meta', x'01')", ())
+            .unwrap();
+
+        let sum_a = DbChecksum::compute(7, &a, &[], false).unwrap();
+        let sum_b = DbChecksum::compute(7, &b, &[], false).unwrap();
+        assert_eq!(sum_a, sum_b);
+        assert!(!sum_a.tables.contains_key("_metadata"));
+
+        b.execute("UPDATE kv SET v = 3 WHERE k = 'b'", ()).unwrap();
+        let sum_b = DbChecksum::compute(7, &b, &[], false).unwrap();
+        assert_ne!(sum_a.hash, sum_b.hash);
+        assert_eq!(sum_a.diff(&sum_b), vec!["kv".to_string()]);
+
+        let named = db(&[]);
+        let sum_named = DbChecksum::compute(7, &a, &[("other", &named)], false).unwrap();
+        assert_eq!(
+            sum_a.diff(&sum_named),
+            vec![
+                "other.kv".to_string(),
+                "other.sqlite_schema".to_string(),
+                "other.test".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checksum_stats() {
+        let a = db(&[(1, "a"), (2, "b")]);
+        let b = db(&[(1, "a"), (2, "b")]);
+        a.execute("ANALYZE", ()).unwrap();
+
+        assert_eq!(
+            DbChecksum::compute(7, &a, &[], false).unwrap(),
+            DbChecksum::compute(7, &b, &[], false).unwrap()
+        );
+        let sum_a = DbChecksum::compute(7, &a, &[], true).unwrap();
+        let sum_b = DbChecksum::compute(7, &b, &[], true).unwrap();
+        assert_eq!(sum_a.diff(&sum_b), vec!["sqlite_stat1".to_string()]);
+
+        b.execute("ANALYZE", ()).unwrap();
+        assert_eq!(sum_a, DbChecksum::compute(7, &b, &[], true).unwrap());
+    }
+
+    #[test]
+    fn test_checksum_vacuum() {
+        let schema = r#"
+CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);
+CREATE TABLE log (msg TEXT);
+CREATE TABLE tags (tag TEXT PRIMARY KEY, n INTEGER);
+"#;
+        let a = Connection::open_in_memory().unwrap();
+        a.execute_batch(schema).unwrap();
+        for i in 0..10 {
+            a.execute("INSERT INTO test (value) VALUES ($1)", [i])
+                .unwrap();
+            a.execute("INSERT INTO log VALUES ($1)", [i % 3]).unwrap();
+            a.execute("INSERT INTO tags VALUES ($1, $2)", (i.to_string(), i))
+                .unwrap();
+        }
+        a.execute_batch(
+            r#"
+DELETE FROM test WHERE id <= 4;
+DELETE FROM log WHERE rowid <= 4;
+DELETE FROM tags WHERE n < 4;
+"#,
+        )
+        .unwrap();
+
+        // the same rows written in another order with other rowids
+        let b = Connection::open_in_memory().unwrap();
+        b.execute_batch(schema).unwrap();
+        for i in (4..10).rev() {
+            b.execute("INSERT INTO test VALUES ($1, $2)", (i + 1, i))
+                .unwrap();
+            b.execute("INSERT INTO log VALUES ($1)", [i % 3]).unwrap();
+            b.execute("INSERT INTO tags VALUES ($1, $2)", (i.to_string(), i))
+                .unwrap();
+        }
+
+        let sum_a = DbChecksum::compute(7, &a, &[], false).unwrap();
+        assert_eq!(sum_a, DbChecksum::compute(7, &b, &[], false).unwrap());
+
+        // renumbers the rowids of `log` and `tags`
+        a.execute("VACUUM", ()).unwrap();
+        assert_eq!(sum_a, DbChecksum::compute(7, &a, &[], false).unwrap());
+
+        // duplicate rows must still count
+        b.execute("INSERT INTO log VALUES (0)", ()).unwrap();
+        let sum_b = DbChecksum::compute(7, &b, &[], false).unwrap();
+        assert_eq!(sum_a.diff(&sum_b), vec!["log".to_string()]);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::store::state_machine::sqlite::deterministic::WriteStamp;
+use crate::store::state_machine::sqlite::state_machine::{
+    Indexed, Query, QueryWrite, QueryWriteEntry,
+};
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::{Error, Response};
+use openraft::Raft;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio::{task, time};
+use tracing::error;
+
+/// The max amount of queries packed into a single Raft entry.
+const COALESCE_MAX_QUERIES: usize = 256;
+
+struct CoalesceRequest {
+    query: Query,
+    ack: oneshot::Sender<Result<Indexed<usize>, Error>>,
+}
+
+/// Queues concurrent `execute()`s on the leader for a short window and proposes them as a
+/// single `QueryWrite::ExecuteMulti` to the Raft. Each query is still applied with its own
+/// savepoint and gets its own result, but the whole batch only needs one Raft round trip.
+#[derive(Debug, Clone)]
+pub struct WriteCoalescer {
+    tx: flume::Sender<CoalesceRequest>,
+}
+
+impl WriteCoalescer {
+    pub fn spawn(raft: Raft<TypeConfigSqlite>, window: Duration) -> Self {
+        let (tx, rx) = flume::unbounded();
+        task::spawn(Self::run(raft, window, rx));
+        Self { tx }
+    }
+
+    /// Queues the query and waits until the batch it ends up in has been applied.
+    /// The query must have been validated already.
+    pub async fn execute(&self, query: Query) -> Result<Indexed<usize>, Error> {
+        let (ack, rx) = oneshot::channel();
+        self.tx
+            .send_async(CoalesceRequest { query, ack })
+            .await
+            .expect("WriteCoalescer to always be running");
+        rx.await
+            .expect("To always receive an answer from the WriteCoalescer")
+    }
+
+    async fn run(
+        raft: Raft<TypeConfigSqlite>,
+        window: Duration,
+        rx: flume::Receiver<CoalesceRequest>,
+    ) {
+        while let Ok(first) = rx.recv_async().await {
+            let mut batch = Vec::with_capacity(8);
+            batch.push(first);
+
+            let deadline = Instant::now() + window;
+            while batch.len() < COALESCE_MAX_QUERIES {
+                match time::timeout_at(deadline, rx.recv_async()).await {
+                    Ok(Ok(req)) => batch.push(req),
+                    // window elapsed or shutting down
+                    _ => break,
+                }
+            }
+
+            // write in the background to collect the next batch in the meantime
+            task::spawn(Self::write(raft.clone(), batch));
+        }
+    }
+
+    async fn write(raft: Raft<TypeConfigSqlite>, batch: Vec<CoalesceRequest>) {
+        let (mut queries, acks): (Vec<_>, Vec<_>) =
+            batch.into_iter().map(|req| (req.query, req.ack)).unzip();
+
+        let query = if queries.len() == 1 {
+            QueryWrite::Execute(queries.remove(0))
+        } else {
+            QueryWrite::ExecuteMulti(queries)
+        };
+        let entry = QueryWriteEntry {
+            stamp: WriteStamp::now(),
+            db: None,
+            query,
+        };
+
+        let resp = match raft.client_write(entry).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                for ack in acks {
+                    let _ = ack.send(Err(Error::from(err.clone())));
+                }
+                return;
+            }
+        };
+
+        let log_index = resp.log_id.index;
+        let results = match resp.data {
+            Response::Execute(res) => vec![res.result],
+            Response::ExecuteMulti(results) => results,
+            _ => {
+                error!("Received an unexpected Response for a coalesced write");
+                Vec::default()
+            }
+        };
+
+        if results.len() != acks.len() {
+            error!(
+                "Received {} results for {} coalesced queries at log index {}",
+                results.len(),
+                acks.len(),
+                log_index
+            );
+            for ack in acks {
+                let _ = ack.send(Err(Error::Error(
+                    format!(
+                        "Cannot map the results of the coalesced write at log index {}",
+                        log_index
+                    )
+                    .into(),
+                )));
+            }
+            return;
+        }
+
+        for (ack, res) in acks.into_iter().zip(results) {
+            // the caller may have been dropped in the meantime
+            let _ = ack.send(res.map(|res| Indexed::new(res, log_index)));
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::Error;
+use rusqlite::StatementStatus;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+/// The max amount of SQLite VM steps between two checks of the budget.
+const PROGRESS_STEPS: u64 = 1000;
+
+/// Writes using at least this percentage of the limit will be counted and logged.
+const NEAR_LIMIT_PERCENT: u64 = 80;
+
+/// Statistics of the write cost guard on this node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WriteCostMetrics {
+    /// The max amount of SQLite VM steps for a single write, `0` if the guard is disabled
+    pub limit: u64,
+    /// The most steps a single write has used since the start
+    pub max_steps: u64,
+    /// The amount of writes which used at least 80% of the limit
+    pub near_limit: u64,
+    /// The amount of writes which have been aborted because they exceeded the limit
+    pub aborted: u64,
+}
+
+/// Limits the amount of SQLite VM steps a single write may take inside the writer.
+///
+/// The exact steps of each statement are added to the budget with `count()` after it has
+/// finished. The progress handler only interrupts single statements, which run for too long on
+/// their own.
+///
+/// A step budget does not depend on the hardware or the load of a node, which means that a write
+/// will either fail or succeed on all replicas in the same way, as long as they use the same
+/// limit. Time-based limits would make the state machines diverge. The steps also depend on the
+/// query plans, which is why `PRAGMA optimize` only runs inside log entries while the guard is
+/// enabled, and `sqlite_stat1` is part of the checksums for the divergence check.
+#[derive(Debug, Default)]
+pub(crate) struct WriteCostGuard {
+    limit: u64,
+    active: AtomicBool,
+    /// The exact steps of all finished statements of the current write
+    steps: AtomicU64,
+    /// The steps of the currently running statement, as counted by the progress handler
+    stmt_steps: AtomicU64,
+    max_steps: AtomicU64,
+    near_limit: AtomicU64,
+    aborted: AtomicU64,
+}
+
+impl WriteCostGuard {
+    pub fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            ..Default::default()
+        })
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Installs the progress handler on the given writer connection. Steps are only counted
+    /// between `start()` and `finish()`, so internal statements like snapshots are never aborted.
+    pub fn register(self: &Arc<Self>, conn: &rusqlite::Connection) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let interval = PROGRESS_STEPS.min(self.limit);
+        let slf = self.clone();
+        conn.progress_handler(
+            interval as i32,
+            Some(move || {
+                if !slf.active.load(Ordering::Relaxed) {
+                    return false;
+                }
+                let stmt_steps = slf.stmt_steps.fetch_add(interval, Ordering::Relaxed) + interval;
+                slf.steps.load(Ordering::Relaxed) + stmt_steps > slf.limit
+            }),
+        );
+    }
+
+    /// Resets the budget for the next write.
+    #[inline]
+    pub fn start(&self) {
+        if self.is_enabled() {
+            self.steps.store(0, Ordering::Relaxed);
+            self.stmt_steps.store(0, Ordering::Relaxed);
+            self.active.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Adds the exact VM steps of a finished statement to the budget of the current write and
+    /// returns an `Error::CostLimit` if it has been exceeded. The progress handler only fires
+    /// every `PROGRESS_STEPS`, which many small statements would never reach on their own.
+    ///
+    /// Must be called after each execution, successful or not, because it resets the counter
+    /// of the cached statement.
+    pub fn count(&self, stmt: &rusqlite::Statement<'_>) -> Result<(), Error> {
+        let steps = stmt.reset_status(StatementStatus::VmStep) as u64;
+        if !self.is_enabled() || !self.active.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        self.stmt_steps.store(0, Ordering::Relaxed);
+        if self.steps.fetch_add(steps, Ordering::Relaxed) + steps > self.limit {
+            Err(self.err_limit())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stops counting steps for the current write and returns an `Error::CostLimit`, if it has
+    /// been aborted because of the limit. `sql` is only used for logging.
+    pub fn finish(&self, sql: &str) -> Option<Error> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        self.active.store(false, Ordering::Relaxed);
+        let steps = self.steps.load(Ordering::Relaxed) + self.stmt_steps.load(Ordering::Relaxed);
+        self.max_steps.fetch_max(steps, Ordering::Relaxed);
+
+        if steps > self.limit {
+            self.aborted.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Write has been aborted after exceeding the cost limit of {} VM steps:\n{}",
+                self.limit, sql
+            );
+            Some(self.err_limit())
+        } else {
+            if steps * 100 >= self.limit * NEAR_LIMIT_PERCENT {
+                self.near_limit.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Write used {} of the max {} VM steps:\n{}",
+                    steps, self.limit, sql
+                );
+            }
+            None
+        }
+    }
+
+    pub fn err_limit(&self) -> Error {
+        Error::CostLimit(
+            format!(
+                "write exceeded the cost limit of {} SQLite VM steps",
+                self.limit
+            )
+            .into(),
+        )
+    }
+
+    pub fn metrics(&self) -> WriteCostMetrics {
+        WriteCostMetrics {
+            limit: self.limit,
+            max_steps: self.max_steps.load(Ordering::Relaxed),
+            near_limit: self.near_limit.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Batch, Connection};
+
+    fn db(guard: &Arc<WriteCostGuard>) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)", ())
+            .unwrap();
+        guard.register(&conn);
+        conn
+    }
+
+    #[test]
+    fn test_many_small_statements() {
+        let guard = WriteCostGuard::new(20_000);
+        let conn = db(&guard);
+
+        // each of these is far below PROGRESS_STEPS, the progress handler will never fire
+        let sql = (0..5000)
+            .map(|id| format!("INSERT INTO test VALUES ({});", id))
+            .collect::<String>();
+        let mut batch = Batch::new(&conn, &sql);
+
+        guard.start();
+        let mut exceeded_at = None;
+        let mut idx = 0;
+        while let Some(mut stmt) = batch.next().unwrap() {
+            stmt.execute([]).unwrap();
+            if guard.count(&stmt).is_err() && exceeded_at.is_none() {
+                exceeded_at = Some(idx);
+            }
+            idx += 1;
+        }
+        let exceeded_at = exceeded_at.expect("the limit to be exceeded");
+        assert!(exceeded_at > 0 && exceeded_at < 5000);
+
+        assert!(matches!(guard.finish(""), Some(Error::CostLimit(_))));
+        let metrics = guard.metrics();
+        assert_eq!(metrics.aborted, 1);
+        assert!(metrics.max_steps > 20_000);
+
+        // a new write starts with a fresh budget
+        guard.start();
+        let mut stmt = conn.prepare("INSERT INTO test VALUES (5000)").unwrap();
+        stmt.execute([]).unwrap();
+        guard.count(&stmt).unwrap();
+        assert!(guard.finish("").is_none());
+    }
+
+    #[test]
+    fn test_single_large_statement() {
+        let guard = WriteCostGuard::new(20_000);
+        let conn = db(&guard);
+
+        guard.start();
+        let mut stmt = conn
+            .prepare(
+                r#"
+INSERT INTO test
+WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+SELECT x FROM c"#,
+            )
+            .unwrap();
+        // interrupted by the progress handler
+        assert!(stmt.execute([]).is_err());
+        assert!(guard.count(&stmt).is_err());
+        assert!(matches!(guard.finish(""), Some(Error::CostLimit(_))));
+
+        // the statement has been rolled back, and reads outside of a write are never aborted
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM test", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
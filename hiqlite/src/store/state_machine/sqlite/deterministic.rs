@@ -0,0 +1,607 @@
+use crate::store::state_machine::sqlite::guard::GuardedQuery;
+use crate::store::state_machine::sqlite::state_machine::{Query, QueryMany, QueryWrite};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// SQLite date and time functions which may read the current time via `'now'`.
+/// The number is the index of the first time-value argument.
+static TIME_FNS: [(&str, usize); 7] = [
+    ("date", 0),
+    ("time", 0),
+    ("datetime", 0),
+    ("julianday", 0),
+    ("unixepoch", 0),
+    ("strftime", 1),
+    ("timediff", 0),
+];
+
+/// Date and time modifiers that depend on the local timezone of each node.
+static NON_DETERMINISTIC_MODIFIERS: [&str; 2] = ["localtime", "utc"];
+
+/// `(name, builtin to evaluate)` for the SQL keywords `CURRENT_*`, which SQLite resolves
+/// as zero-argument function calls.
+static CURRENT_FNS: [(&str, &str); 3] = [
+    ("current_timestamp", "datetime"),
+    ("current_date", "date"),
+    ("current_time", "time"),
+];
+
+const SPLITMIX_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Stamped onto each `QueryWrite` by the leader when it is proposed to the Raft. Every node
+/// uses the same values for `'now'` and `random()` when applying the log entry, which makes
+/// these functions produce the same rows on every replica.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct WriteStamp {
+    /// Unix timestamp in milliseconds
+    pub ts_millis: i64,
+    pub seed: u64,
+}
+
+impl WriteStamp {
+    pub fn now() -> Self {
+        let mut buf = [0u8; 8];
+        getrandom::getrandom(&mut buf).expect("getrandom to always work");
+
+        Self {
+            ts_millis: Utc::now().timestamp_millis(),
+            seed: u64::from_le_bytes(buf),
+        }
+    }
+}
+
+/// The state shared between the SQLite writer and the overridden SQL functions on its
+/// connection. The writer updates it before each applied log entry.
+#[derive(Debug, Default)]
+pub struct DeterministicState {
+    ts_millis: AtomicI64,
+    rng: AtomicU64,
+}
+
+impl DeterministicState {
+    #[inline]
+    pub fn apply(&self, stamp: &WriteStamp) {
+        self.ts_millis.store(stamp.ts_millis, Ordering::Relaxed);
+        self.rng.store(stamp.seed, Ordering::Relaxed);
+    }
+
+    /// The stamped timestamp in the same format SQLite would resolve `'now'` to internally.
+    #[inline]
+    fn now(&self) -> String {
+        DateTime::<Utc>::from_timestamp_millis(self.ts_millis.load(Ordering::Relaxed))
+            .unwrap_or_default()
+            .format("%F %T%.3f")
+            .to_string()
+    }
+
+    /// splitmix64 - fast, good enough for SQL `random()` and it only depends on the seed
+    #[inline]
+    fn next_u64(&self) -> u64 {
+        let mut z = self
+            .rng
+            .fetch_add(SPLITMIX_GAMMA, Ordering::Relaxed)
+            .wrapping_add(SPLITMIX_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Overrides all SQL functions on the given connection which would produce different results
+/// on each node: the date and time functions when called with `'now'`, `CURRENT_TIMESTAMP`,
+/// `CURRENT_DATE`, `CURRENT_TIME`, `random()` and `randomblob()`.
+///
+/// The original date and time functions are evaluated on a private in-memory connection after
+/// `'now'` has been replaced with the stamped timestamp, so all modifiers keep working.
+pub fn register(conn: &rusqlite::Connection) -> Result<Arc<DeterministicState>, rusqlite::Error> {
+    let state = Arc::new(DeterministicState::default());
+    let builtins = Arc::new(Mutex::new(rusqlite::Connection::open_in_memory()?));
+
+    // The same flags as the builtins. The date and time functions are deterministic as long as
+    // they are not called with `'now'`, which keeps them usable in CHECK constraints, expression
+    // indexes and generated columns. `validate_sql()` rejects `'now'` in these places.
+    let flags_det = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    let flags = FunctionFlags::SQLITE_UTF8;
+
+    for (name, ts_idx) in TIME_FNS {
+        let st = state.clone();
+        let builtins = builtins.clone();
+        conn.create_scalar_function(name, -1, flags_det, move |ctx| {
+            let mut args = Vec::with_capacity(ctx.len().max(ts_idx + 1));
+            for i in 0..ctx.len() {
+                let value = Value::from(ctx.get_raw(i));
+                if i > ts_idx {
+                    check_modifier(&value)?;
+                }
+                args.push(value);
+            }
+
+            if name == "timediff" {
+                for arg in args.iter_mut() {
+                    replace_now(arg, &st);
+                }
+            } else if args.len() > ts_idx {
+                replace_now(&mut args[ts_idx], &st);
+            } else if args.len() == ts_idx {
+                // a missing time-value means 'now'
+                args.push(Value::Text(st.now()));
+            }
+
+            eval_builtin(&builtins, name, args)
+        })?;
+    }
+
+    for (name, builtin) in CURRENT_FNS {
+        let st = state.clone();
+        let builtins = builtins.clone();
+        conn.create_scalar_function(name, 0, flags, move |_ctx: &Context| {
+            let args = vec![Value::Text(st.now())];
+            eval_builtin(&builtins, builtin, args)
+        })?;
+    }
+
+    // `random()` must not be flagged as deterministic, or SQLite would only evaluate it once
+    let st = state.clone();
+    conn.create_scalar_function("random", 0, flags, move |_ctx| Ok(st.next_u64() as i64))?;
+
+    let st = state.clone();
+    conn.create_scalar_function("randomblob", 1, flags, move |ctx| {
+        let len = ctx.get::<i64>(0).unwrap_or(1).max(1) as usize;
+        let mut blob = Vec::with_capacity(len + 8);
+        while blob.len() < len {
+            blob.extend_from_slice(&st.next_u64().to_le_bytes());
+        }
+        blob.truncate(len);
+        Ok(blob)
+    })?;
+
+    Ok(state)
+}
+
+/// Returns `true` if the given function name is overridden by `register()`.
+pub fn is_overridden(name: &str) -> bool {
+    TIME_FNS.iter().any(|(n, _)| n.eq_ignore_ascii_case(name))
+        || CURRENT_FNS
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(name))
+        || name.eq_ignore_ascii_case("random")
+        || name.eq_ignore_ascii_case("randomblob")
+}
+
+#[inline]
+fn replace_now(value: &mut Value, state: &DeterministicState) {
+    if let Value::Text(s) = value {
+        if s.trim().eq_ignore_ascii_case("now") {
+            *s = state.now();
+        }
+    }
+}
+
+#[inline]
+fn check_modifier(value: &Value) -> Result<(), rusqlite::Error> {
+    if let Value::Text(s) = value {
+        let s = s.trim();
+        if NON_DETERMINISTIC_MODIFIERS
+            .iter()
+            .any(|m| s.eq_ignore_ascii_case(m))
+        {
+            return Err(rusqlite::Error::UserFunctionError(
+                format!("modifier '{}' is not deterministic across nodes", s).into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn eval_builtin(
+    builtins: &Mutex<rusqlite::Connection>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, rusqlite::Error> {
+    let placeholders = (1..=args.len())
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!("SELECT {}({})", name, placeholders);
+
+    let conn = builtins
+        .lock()
+        .expect("builtins connection to never be poisoned");
+    let mut stmt = conn.prepare_cached(&sql)?;
+    stmt.query_row(rusqlite::params_from_iter(args), |row| {
+        row.get::<_, Value>(0)
+    })
+}
+
+/// Makes sure a `QueryWrite` can be applied in the same way on every node. Statements which
+/// cannot be made deterministic are rejected before they reach the Raft.
+pub fn validate_query_write(query: &QueryWrite) -> Result<(), Error> {
+    match query {
+        QueryWrite::Execute(Query { sql, .. }) => validate_sql(sql),
+        QueryWrite::ExecuteMany(QueryMany { sql, .. }) => validate_sql(sql),
+        QueryWrite::ExecuteMulti(queries) => {
+            for Query { sql, .. } in queries {
+                validate_sql(sql)?;
+            }
+            Ok(())
+        }
+        QueryWrite::ExecuteReturning(Query { sql, .. }) => validate_sql(sql),
+        QueryWrite::Transaction(queries) => {
+            for Query { sql, .. } in queries {
+                validate_sql(sql)?;
+            }
+            Ok(())
+        }
+        QueryWrite::TransactionGuarded(queries) => {
+            for GuardedQuery { guards, query } in queries {
+                for guard in guards {
+                    validate_sql(&guard.sql)?;
+                }
+                validate_sql(&query.sql)?;
+            }
+            Ok(())
+        }
+        QueryWrite::Batch(sql) => validate_sql(sql),
+        QueryWrite::Migration(migrations) => {
+            for migration in migrations {
+                validate_sql(&String::from_utf8_lossy(&migration.content))?;
+            }
+            Ok(())
+        }
+        #[cfg(feature = "backup")]
+        QueryWrite::Backup(_) => Ok(()),
+        QueryWrite::RTT => Ok(()),
+        QueryWrite::Checksum => Ok(()),
+        QueryWrite::Resync { .. } => Ok(()),
+        #[cfg(feature = "backup")]
+        QueryWrite::ArchiveMeta(_) => Ok(()),
+    }
+}
+
+/// The kind of statement, which decides if expressions may be stored inside the schema.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StmtKind {
+    Other,
+    /// `CREATE TABLE` or `ALTER TABLE` with CHECK constraints and generated columns
+    Table,
+    /// All expressions of an index are stored
+    Index,
+}
+
+impl StmtKind {
+    fn from_idents(idents: &[&str]) -> Self {
+        match idents {
+            ["alter", "table", ..] => Self::Table,
+            ["create", rest @ ..] => {
+                match rest
+                    .iter()
+                    .find(|i| !matches!(**i, "temp" | "temporary" | "unique"))
+                {
+                    Some(&"table") => Self::Table,
+                    Some(&"index") => Self::Index,
+                    _ => Self::Other,
+                }
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Checks all date and time function calls for modifiers that depend on the local timezone.
+/// Values bound via params cannot be checked here. They will produce an error inside the
+/// writer, which is the same on each node.
+///
+/// The date and time functions are flagged as deterministic, so `'now'` and `CURRENT_*` are
+/// rejected in CHECK constraints, generated columns and indexes. Their stored values would
+/// depend on the log entry which wrote them and could not be verified again.
+pub fn validate_sql(sql: &str) -> Result<(), Error> {
+    let sql = sql.to_ascii_lowercase();
+    let bytes = sql.as_bytes();
+    let len = bytes.len();
+
+    // the first identifiers of the current statement
+    let mut stmt_idents: Vec<&str> = Vec::with_capacity(4);
+    let mut kind = StmtKind::Other;
+    // the identifier in front of each currently open parenthesis
+    let mut parens: Vec<Option<&str>> = Vec::new();
+    let mut prev_ident: Option<&str> = None;
+
+    let mut i = 0;
+    while i < len {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => {
+                i = skip_quoted(bytes, i);
+                prev_ident = None;
+            }
+            b'(' => {
+                parens.push(prev_ident.take());
+                i += 1;
+            }
+            b')' => {
+                parens.pop();
+                prev_ident = None;
+                i += 1;
+            }
+            b';' => {
+                stmt_idents.clear();
+                kind = StmtKind::Other;
+                parens.clear();
+                prev_ident = None;
+                i += 1;
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < len && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < len && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = i;
+                while i < len && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let ident = &sql[start..i];
+                prev_ident = Some(ident);
+
+                if stmt_idents.len() < 4 {
+                    stmt_idents.push(ident);
+                    kind = StmtKind::from_idents(&stmt_idents);
+                }
+                let is_stored = match kind {
+                    StmtKind::Other => false,
+                    StmtKind::Table => parens
+                        .iter()
+                        .any(|p| matches!(p, Some("check") | Some("as"))),
+                    StmtKind::Index => true,
+                };
+
+                if is_stored && CURRENT_FNS.iter().any(|(name, _)| *name == ident) {
+                    return Err(err_stored_now(ident));
+                }
+
+                if let Some((_, ts_idx)) = TIME_FNS.iter().find(|(name, _)| *name == ident) {
+                    let mut j = i;
+                    while j < len && bytes[j].is_ascii_whitespace() {
+                        j += 1;
+                    }
+                    if j < len && bytes[j] == b'(' {
+                        let end = find_closing_paren(bytes, j);
+                        let args = &sql[j..end];
+                        if is_stored
+                            && (args.contains("'now'") || count_args(bytes, j, end) <= *ts_idx)
+                        {
+                            return Err(err_stored_now(ident));
+                        }
+                        for modifier in NON_DETERMINISTIC_MODIFIERS {
+                            if args.contains(&format!("'{}'", modifier)) {
+                                return Err(Error::NonDeterministic(
+                                    format!(
+                                        "the '{}' modifier in '{}()' depends on the timezone \
+                                        of each node and cannot be used in replicated writes",
+                                        modifier, ident
+                                    )
+                                    .into(),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                prev_ident = None;
+                i += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn err_stored_now(ident: &str) -> Error {
+    Error::NonDeterministic(
+        format!(
+            "the current time in '{}' cannot be used in CHECK constraints, generated columns \
+            or indexes",
+            ident
+        )
+        .into(),
+    )
+}
+
+/// Counts the top-level arguments inside the parentheses from `start` to `end`.
+#[inline]
+fn count_args(bytes: &[u8], start: usize, end: usize) -> usize {
+    let inner = &bytes[start + 1..end.saturating_sub(1).max(start + 1)];
+    if inner.iter().all(|b| b.is_ascii_whitespace()) {
+        return 0;
+    }
+
+    let mut count = 1;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < inner.len() {
+        match inner[i] {
+            b'\'' | b'"' | b'`' => {
+                i = skip_quoted(inner, i);
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b',' if depth == 0 => count += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    count
+}
+
+/// Returns the index after the closing quote. Doubled quotes are SQL escapes.
+#[inline]
+fn skip_quoted(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Returns the index after the parenthesis closing the one at `start`.
+#[inline]
+fn find_closing_paren(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => {
+                i = skip_quoted(bytes, i);
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_sql() {
+        assert!(validate_sql("INSERT INTO t (ts) VALUES (datetime('now'))").is_ok());
+        assert!(validate_sql("INSERT INTO t (ts) VALUES (CURRENT_TIMESTAMP)").is_ok());
+        assert!(validate_sql("INSERT INTO t (tz) VALUES ('utc')").is_ok());
+        assert!(validate_sql("INSERT INTO t (d) VALUES (date('now', '+1 day'))").is_ok());
+        assert!(validate_sql("-- datetime('now', 'localtime')\nSELECT 1").is_ok());
+
+        assert!(validate_sql("INSERT INTO t (ts) VALUES (datetime('now', 'localtime'))").is_err());
+        assert!(validate_sql("UPDATE t SET ts = strftime('%s', 'now', 'UTC')").is_err());
+        assert!(validate_sql("SELECT date (unixepoch('now', 'localtime'))").is_err());
+    }
+
+    #[test]
+    fn test_validate_sql_schema() {
+        // evaluated when a row is written, with the stamp of the log entry
+        assert!(validate_sql("CREATE TABLE t (ts TEXT DEFAULT CURRENT_TIMESTAMP)").is_ok());
+        assert!(validate_sql("CREATE TABLE t (ts TEXT DEFAULT (datetime('now')))").is_ok());
+        assert!(validate_sql(
+            "CREATE TRIGGER t_ts AFTER INSERT ON t BEGIN \
+            UPDATE t SET ts = datetime('now') WHERE id = new.id; END"
+        )
+        .is_ok());
+        assert!(validate_sql("CREATE INDEX t_month ON t (date(ts, 'start of month'))").is_ok());
+        assert!(validate_sql("CREATE TABLE t (ts TEXT, d TEXT AS (date(ts)))").is_ok());
+        assert!(
+            validate_sql("CREATE TABLE t (ts TEXT CHECK (ts > datetime('2000-01-01')))").is_ok()
+        );
+        assert!(validate_sql("SELECT 1; CREATE INDEX t_ts ON t (ts); SELECT date('now')").is_ok());
+
+        assert!(validate_sql("CREATE TABLE t (ts TEXT, CHECK (ts < datetime('now')))").is_err());
+        assert!(validate_sql("CREATE TABLE t (ts TEXT CHECK (ts < CURRENT_TIMESTAMP))").is_err());
+        assert!(validate_sql("CREATE TABLE t (ts TEXT, d TEXT AS (date()))").is_err());
+        assert!(validate_sql("ALTER TABLE t ADD COLUMN s TEXT AS (strftime('%s'))").is_err());
+        assert!(validate_sql("CREATE INDEX t_new ON t (ts) WHERE ts > date('now')").is_err());
+        assert!(validate_sql("SELECT 1; CREATE UNIQUE INDEX t_d ON t (julianday('NOW'))").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let stamp = WriteStamp {
+            ts_millis: 1_700_000_000_123,
+            seed: 1337,
+        };
+
+        let conn_1 = rusqlite::Connection::open_in_memory().unwrap();
+        let conn_2 = rusqlite::Connection::open_in_memory().unwrap();
+        let state_1 = register(&conn_1).unwrap();
+        let state_2 = register(&conn_2).unwrap();
+        state_1.apply(&stamp);
+        state_2.apply(&stamp);
+
+        let sql = "SELECT datetime('now'), CURRENT_TIMESTAMP, strftime('%s'), random(), \
+            hex(randomblob(16)), date('now', '+1 day')";
+        let query = |conn: &rusqlite::Connection| {
+            conn.query_row(sql, (), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })
+            .unwrap()
+        };
+
+        let res_1 = query(&conn_1);
+        let res_2 = query(&conn_2);
+        assert_eq!(res_1, res_2);
+        assert_eq!(res_1.0, "2023-11-14 22:13:20");
+        assert_eq!(res_1.1, "2023-11-14 22:13:20");
+        assert_eq!(res_1.2, "1700000000");
+        assert_eq!(res_1.5, "2023-11-15");
+
+        assert!(conn_1
+            .query_row("SELECT datetime('now', ?1)", ["localtime"], |row| {
+                row.get::<_, String>(0)
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_overrides_in_schema() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        register(&conn).unwrap();
+
+        conn.execute_batch(
+            r#"
+CREATE TABLE t (
+    ts TEXT CHECK (ts > datetime('2000-01-01')),
+    month TEXT AS (date(ts, 'start of month'))
+);
+CREATE INDEX t_month ON t (date(ts, 'start of month'));
+INSERT INTO t (ts) VALUES ('2024-05-17 13:37:00');
+"#,
+        )
+        .unwrap();
+
+        let month: String = conn
+            .query_row(
+                "SELECT month FROM t WHERE date(ts, 'start of month') = '2024-05-01'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(month, "2024-05-01");
+    }
+}
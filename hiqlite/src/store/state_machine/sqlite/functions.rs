@@ -0,0 +1,237 @@
+use crate::store::state_machine::sqlite::deterministic;
+use crate::Error;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags, SqlFnOutput};
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+
+type RegisterFn = Arc<dyn Fn(&rusqlite::Connection) -> Result<(), rusqlite::Error> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Scalar,
+    Aggregate,
+    Collation,
+}
+
+#[derive(Clone)]
+struct Registration {
+    kind: Kind,
+    name: String,
+    register: RegisterFn,
+}
+
+/// Custom SQL functions and collations, which will be registered on every SQLite connection:
+/// the writer, the read pool and all internal connections for snapshots and backups.
+///
+/// Scalar and aggregate functions are flagged as `SQLITE_DETERMINISTIC`, which allows their
+/// usage in replicated writes, indexes and generated columns. Because each node applies every
+/// write on its own, you must make sure that a function always returns the same result for the
+/// same input on every node. Do not read the current time, randomness or any other local state
+/// inside them.
+///
+/// The registry must be the same on each node.
+///
+/// ```rust, notest
+/// let sql_functions = SqlFunctions::new()
+///     .scalar("slugify", 1, |ctx| {
+///         let s = ctx.get::<String>(0)?;
+///         Ok(s.to_lowercase().replace(' ', "-"))
+///     })
+///     .collation("nocase_rev", |a, b| b.to_lowercase().cmp(&a.to_lowercase()));
+///
+/// let config = NodeConfig {
+///     sql_functions,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Default)]
+pub struct SqlFunctions {
+    registrations: Vec<Registration>,
+}
+
+impl Debug for SqlFunctions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.registrations
+                    .iter()
+                    .map(|r| format!("{:?}({})", r.kind, r.name)),
+            )
+            .finish()
+    }
+}
+
+impl SqlFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a deterministic scalar function. `n_arg` is the number of arguments, or `-1`
+    /// for a variable amount.
+    pub fn scalar<F, T>(mut self, name: impl Into<String>, n_arg: i32, x_func: F) -> Self
+    where
+        F: Fn(&Context<'_>) -> rusqlite::Result<T> + Send + Sync + 'static,
+        T: SqlFnOutput,
+    {
+        let name = name.into();
+        let fn_name = name.clone();
+        let x_func = Arc::new(x_func);
+        self.registrations.push(Registration {
+            kind: Kind::Scalar,
+            name,
+            register: Arc::new(move |conn| {
+                let x_func = x_func.clone();
+                conn.create_scalar_function(&fn_name, n_arg, Self::flags(), move |ctx| x_func(ctx))
+            }),
+        });
+        self
+    }
+
+    /// Registers a deterministic aggregate function. `n_arg` is the number of arguments, or
+    /// `-1` for a variable amount.
+    pub fn aggregate<A, D, T>(mut self, name: impl Into<String>, n_arg: i32, aggr: D) -> Self
+    where
+        A: RefUnwindSafe + UnwindSafe,
+        D: Aggregate<A, T> + Clone + Send + Sync + 'static,
+        T: SqlFnOutput,
+    {
+        let name = name.into();
+        let fn_name = name.clone();
+        self.registrations.push(Registration {
+            kind: Kind::Aggregate,
+            name,
+            register: Arc::new(move |conn| {
+                conn.create_aggregate_function(&fn_name, n_arg, Self::flags(), aggr.clone())
+            }),
+        });
+        self
+    }
+
+    /// Registers a collation, which can be used with `COLLATE name`.
+    pub fn collation<C>(mut self, name: impl Into<String>, x_compare: C) -> Self
+    where
+        C: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let coll_name = name.clone();
+        let x_compare = Arc::new(x_compare);
+        self.registrations.push(Registration {
+            kind: Kind::Collation,
+            name,
+            register: Arc::new(move |conn| {
+                let x_compare = x_compare.clone();
+                conn.create_collation(&coll_name, move |a, b| x_compare(a, b))
+            }),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registrations.is_empty()
+    }
+
+    #[inline]
+    fn flags() -> FunctionFlags {
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC
+    }
+
+    /// Registers all functions and collations on the given connection.
+    pub(crate) fn apply(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        for reg in &self.registrations {
+            (reg.register)(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure that no function would be shadowed by the deterministic overrides on the
+    /// writer connection, which would make reads and writes behave differently.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        for reg in &self.registrations {
+            if reg.name.is_empty() {
+                return Err(Error::Config(
+                    "'sql_functions' names must not be empty".into(),
+                ));
+            }
+            if reg.kind != Kind::Collation && deterministic::is_overridden(&reg.name) {
+                return Err(Error::Config(
+                    format!(
+                        "'sql_functions' cannot override the built-in function '{}'",
+                        reg.name
+                    )
+                    .into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Concat;
+
+    impl Aggregate<String, String> for Concat {
+        fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<String> {
+            Ok(String::new())
+        }
+
+        fn step(&self, ctx: &mut Context<'_>, acc: &mut String) -> rusqlite::Result<()> {
+            acc.push_str(&ctx.get::<String>(0)?);
+            Ok(())
+        }
+
+        fn finalize(
+            &self,
+            _ctx: &mut Context<'_>,
+            acc: Option<String>,
+        ) -> rusqlite::Result<String> {
+            Ok(acc.unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_apply_functions() {
+        let functions = SqlFunctions::new()
+            .scalar("slugify", 1, |ctx| {
+                Ok(ctx.get::<String>(0)?.to_lowercase().replace(' ', "-"))
+            })
+            .aggregate("concat_all", 1, Concat)
+            .collation("reverse", |a, b| b.cmp(a));
+        functions.validate().unwrap();
+
+        // every connection must get its own registration
+        for _ in 0..2 {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            functions.apply(&conn).unwrap();
+
+            let slug: String = conn
+                .query_row("SELECT slugify('Hello World')", (), |row| row.get(0))
+                .unwrap();
+            assert_eq!(slug, "hello-world");
+
+            let concat: String = conn
+                .query_row(
+                    "SELECT concat_all(v) FROM (SELECT 'b' AS v UNION ALL SELECT 'a' ORDER BY v COLLATE reverse)",
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(concat, "ba");
+        }
+    }
+
+    #[test]
+    fn test_validate_overrides() {
+        let functions = SqlFunctions::new().scalar("random", 0, |_| Ok(4));
+        assert!(functions.validate().is_err());
+
+        // collations live in a different namespace
+        let functions = SqlFunctions::new().collation("random", |a, b| a.cmp(b));
+        assert!(functions.validate().is_ok());
+    }
+}
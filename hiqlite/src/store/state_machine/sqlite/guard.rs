@@ -0,0 +1,191 @@
+use crate::store::state_machine::sqlite::cost::WriteCostGuard;
+use crate::store::state_machine::sqlite::param::{bind_params, Param};
+use crate::store::state_machine::sqlite::state_machine::{Params, Query};
+use crate::Error;
+use rusqlite::types::ValueRef;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// A precondition for a statement inside a conditional transaction.
+///
+/// Guards are evaluated by the writer inside the same SQLite transaction, right before the
+/// statement they belong to. If any guard does not match, the whole transaction will be rolled
+/// back with an `Error::PreconditionFailed`.
+///
+/// ```rust, notest
+/// let guard = Guard::scalar("SELECT balance FROM account WHERE id = $1", params!(1), 100)?;
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Guard {
+    pub sql: Cow<'static, str>,
+    pub params: Params,
+    pub expect: GuardExpect,
+}
+
+/// The expected result of a `Guard` query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GuardExpect {
+    /// The query must return exactly this amount of rows.
+    RowCount(usize),
+    /// The query must return at least one row.
+    Exists,
+    /// The query must not return any rows.
+    NotExists,
+    /// The first column of the first row must be equal to this value.
+    /// Values are compared with their SQLite type: `Integer(1)` does not match `Real(1.0)`.
+    Scalar(Param),
+}
+
+/// A single statement of a conditional transaction together with its guards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardedQuery {
+    pub guards: Vec<Guard>,
+    pub query: Query,
+}
+
+impl Guard {
+    pub fn row_count<C: Into<Cow<'static, str>>>(sql: C, params: Params, count: usize) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+            expect: GuardExpect::RowCount(count),
+        }
+    }
+
+    pub fn exists<C: Into<Cow<'static, str>>>(sql: C, params: Params) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+            expect: GuardExpect::Exists,
+        }
+    }
+
+    pub fn not_exists<C: Into<Cow<'static, str>>>(sql: C, params: Params) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+            expect: GuardExpect::NotExists,
+        }
+    }
+
+    /// Returns an `Error::QueryParams` if the expected value is a `Param::Named`, which can
+    /// never match a column value.
+    pub fn scalar<C, P>(sql: C, params: Params, value: P) -> Result<Self, Error>
+    where
+        C: Into<Cow<'static, str>>,
+        P: Into<Param>,
+    {
+        let value = value.into();
+        if let Param::Named(name, _) = &value {
+            return Err(Error::QueryParams(
+                format!(
+                    "the expected value of a scalar guard cannot be the named param '{}'",
+                    name
+                )
+                .into(),
+            ));
+        }
+
+        Ok(Self {
+            sql: sql.into(),
+            params,
+            expect: GuardExpect::Scalar(value),
+        })
+    }
+
+    /// Evaluates the guard on the given connection. `index` is the position of this guard
+    /// inside the whole transaction and will be included in the error.
+    pub(crate) fn check(
+        &self,
+        conn: &rusqlite::Connection,
+        index: usize,
+        cost: &WriteCostGuard,
+    ) -> Result<(), Error> {
+        let mut stmt = conn
+            .prepare_cached(self.sql.as_ref())
+            .map_err(|err| Error::PrepareStatement(err.to_string().into()))?;
+
+        bind_params(&mut stmt, &self.params)?;
+
+        let reason = self.evaluate(&mut stmt);
+        cost.count(&stmt)?;
+
+        match reason? {
+            None => Ok(()),
+            Some(reason) => Err(Error::PreconditionFailed { index, reason }),
+        }
+    }
+
+    /// Returns the reason, if the guard does not match.
+    fn evaluate(
+        &self,
+        stmt: &mut rusqlite::Statement<'_>,
+    ) -> Result<Option<Cow<'static, str>>, Error> {
+        let mut rows = stmt.raw_query();
+        let reason = match &self.expect {
+            GuardExpect::RowCount(expected) => {
+                let expected = *expected;
+                let mut count = 0;
+                // we can stop early as soon as we know that the count does not match
+                while count <= expected && rows.next()?.is_some() {
+                    count += 1;
+                }
+                if count == expected {
+                    None
+                } else if count > expected {
+                    Some(format!("expected {} rows, got more", expected).into())
+                } else {
+                    Some(format!("expected {} rows, got {}", expected, count).into())
+                }
+            }
+
+            GuardExpect::Exists => {
+                if rows.next()?.is_some() {
+                    None
+                } else {
+                    Some("expected rows to exist".into())
+                }
+            }
+
+            GuardExpect::NotExists => {
+                if rows.next()?.is_none() {
+                    None
+                } else {
+                    Some("expected no rows to exist".into())
+                }
+            }
+
+            // the fields are public, which means this may not have been built with `scalar()`
+            GuardExpect::Scalar(Param::Named(name, _)) => {
+                return Err(Error::QueryParams(
+                    format!(
+                        "the expected value of a scalar guard cannot be the named param '{}'",
+                        name
+                    )
+                    .into(),
+                ));
+            }
+
+            GuardExpect::Scalar(expected) => match rows.next()? {
+                None => Some("expected a scalar value, got no rows".into()),
+                Some(row) => {
+                    let actual = match row.get_ref(0)? {
+                        ValueRef::Null => Param::Null,
+                        ValueRef::Integer(i) => Param::Integer(i),
+                        ValueRef::Real(r) => Param::Real(r),
+                        ValueRef::Text(t) => Param::Text(String::from_utf8_lossy(t).into_owned()),
+                        ValueRef::Blob(b) => Param::Blob(b.to_vec()),
+                    };
+
+                    if actual == *expected {
+                        None
+                    } else {
+                        Some(format!("expected {:?}, got {:?}", expected, actual).into())
+                    }
+                }
+            },
+        };
+
+        Ok(reason)
+    }
+}
@@ -3,19 +3,27 @@
 // This allow can be removed as soon as `openraft` is > 0.9.17
 #![allow(unexpected_cfgs)]
 
-use crate::store::state_machine::sqlite::state_machine::QueryWrite;
+use crate::store::state_machine::sqlite::state_machine::QueryWriteEntry;
 use crate::Node;
 use crate::Response;
 
+pub mod checksum;
+pub mod coalesce;
+pub mod cost;
+pub mod deterministic;
+pub mod functions;
+pub mod guard;
 pub mod param;
+pub mod pragmas;
 pub mod reader;
 pub mod snapshot_builder;
+pub mod snapshot_schedule;
 pub mod state_machine;
 pub mod writer;
 
 openraft::declare_raft_types!(
     pub TypeConfigSqlite:
-        D = QueryWrite,
+        D = QueryWriteEntry,
         R = Response,
         Node = Node,
         SnapshotData = tokio::fs::File,
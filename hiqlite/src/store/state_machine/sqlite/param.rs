@@ -1,6 +1,8 @@
+use crate::Error;
 use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
-use rusqlite::types::{ToSqlOutput, Value};
+use rusqlite::types::{ToSqlOutput, ValueRef};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Param {
@@ -14,6 +16,10 @@ pub enum Param {
     Text(String),
     /// The value is a blob of data
     Blob(Vec<u8>),
+    /// The value will be bound to the placeholder with this name instead of by its position.
+    /// The name may contain the prefix (`:name`, `$name` or `@name`). Without a prefix, any of
+    /// them will match. Use `named_params!` to create these.
+    Named(Cow<'static, str>, Box<Param>),
 }
 
 // impl ToSql for Param {
@@ -23,16 +29,94 @@ pub enum Param {
 // }
 
 impl Param {
-    pub(crate) fn into_sql<'a>(self) -> ToSqlOutput<'a> {
+    /// Creates a param which is bound by the name of its placeholder.
+    pub fn named<N, P>(name: N, value: P) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+        P: Into<Param>,
+    {
+        Self::Named(name.into(), Box::new(value.into()))
+    }
+
+    /// A rough estimate of the serialized size in bytes.
+    pub(crate) fn size_hint(&self) -> usize {
+        match self {
+            Param::Null => 4,
+            Param::Integer(_) | Param::Real(_) => 12,
+            Param::Text(s) => s.len() + 12,
+            Param::Blob(b) => b.len() + 12,
+            Param::Named(name, param) => name.len() + 12 + param.size_hint(),
+        }
+    }
+
+    pub(crate) fn to_sql(&self) -> ToSqlOutput<'_> {
         let value = match self {
-            Param::Null => Value::Null,
-            Param::Integer(i) => Value::Integer(i),
-            Param::Real(r) => Value::Real(r),
-            Param::Text(t) => Value::Text(t),
-            Param::Blob(b) => Value::Blob(b),
+            Param::Null => ValueRef::Null,
+            Param::Integer(i) => ValueRef::Integer(*i),
+            Param::Real(r) => ValueRef::Real(*r),
+            Param::Text(t) => ValueRef::Text(t.as_bytes()),
+            Param::Blob(b) => ValueRef::Blob(b),
+            Param::Named(_, param) => return param.to_sql(),
         };
-        ToSqlOutput::Owned(value)
+        ToSqlOutput::Borrowed(value)
+    }
+}
+
+/// Binds all `params` to the statement. Positional params are bound in order, while named params
+/// are bound to the placeholder with the same name. Both cannot be mixed, because SQLite gives
+/// named placeholders a position as well, which would silently overwrite each other.
+pub(crate) fn bind_params(
+    stmt: &mut rusqlite::Statement<'_>,
+    params: &[Param],
+) -> Result<(), Error> {
+    let named = params
+        .iter()
+        .filter(|p| matches!(p, Param::Named(_, _)))
+        .count();
+    if named > 0 && named < params.len() {
+        return Err(Error::QueryParams(
+            "named and positional params cannot be mixed".into(),
+        ));
     }
+
+    let mut idx = 1;
+    for param in params {
+        match param {
+            Param::Named(name, param) => {
+                let Some(named_idx) = named_index(stmt, name)? else {
+                    return Err(Error::QueryParams(
+                        format!("no placeholder found for named param '{}'", name).into(),
+                    ));
+                };
+                stmt.raw_bind_parameter(named_idx, param.to_sql())
+                    .map_err(|err| {
+                        Error::QueryParams(format!("named param '{}': {}", name, err).into())
+                    })?;
+            }
+            param => {
+                stmt.raw_bind_parameter(idx, param.to_sql())
+                    .map_err(|err| {
+                        Error::QueryParams(format!("param on position {}: {}", idx, err).into())
+                    })?;
+                idx += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn named_index(stmt: &rusqlite::Statement<'_>, name: &str) -> Result<Option<usize>, Error> {
+    if name.starts_with([':', '$', '@']) {
+        return Ok(stmt.parameter_index(name)?);
+    }
+
+    for prefix in [':', '$', '@'] {
+        if let Some(idx) = stmt.parameter_index(&format!("{}{}", prefix, name))? {
+            return Ok(Some(idx));
+        }
+    }
+    Ok(None)
 }
 
 impl From<rusqlite::types::Null> for Param {
@@ -233,3 +317,44 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_named_params() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare("SELECT :a || $b || @c AS res").unwrap();
+
+        let params = vec![
+            Param::named("c", "3"),
+            Param::named(":a", "1"),
+            Param::named("$b", "2"),
+        ];
+        bind_params(&mut stmt, &params).unwrap();
+
+        let mut rows = stmt.raw_query();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<_, String>(0).unwrap(), "123");
+    }
+
+    #[test]
+    fn test_bind_named_params_mixed() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare("SELECT :a || ?2 AS res").unwrap();
+
+        // `:a` has the position 1 and would be overwritten by the positional param
+        let res = bind_params(&mut stmt, &[Param::named("a", "1"), Param::from("2")]);
+        assert!(matches!(res, Err(Error::QueryParams(_))));
+    }
+
+    #[test]
+    fn test_bind_named_params_missing() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare("SELECT :a").unwrap();
+
+        let res = bind_params(&mut stmt, &[Param::named("b", 1)]);
+        assert!(matches!(res, Err(Error::QueryParams(_))));
+    }
+}
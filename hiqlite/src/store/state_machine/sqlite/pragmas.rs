@@ -0,0 +1,395 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::str::FromStr;
+
+/// SQLite tuning for the writer connections and the read pool.
+///
+/// The `Default` is the `SqlitePreset::Balanced`, which matches the settings of older versions.
+///
+/// ```rust, notest
+/// let mut sqlite_config = SqliteConfig::preset(SqlitePreset::Throughput);
+/// // 32 MiB page cache for each reader
+/// sqlite_config.reader.cache_size = -32 * 1024;
+///
+/// let config = NodeConfig {
+///     sqlite_config,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SqliteConfig {
+    /// The preset this config has been built from. It is informational only.
+    pub preset: SqlitePreset,
+    /// Pragmas for the writer connection of each database
+    pub writer: SqlitePragmas,
+    /// Pragmas for each connection inside the read pools
+    pub reader: SqlitePragmas,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self::preset(SqlitePreset::Balanced)
+    }
+}
+
+impl SqliteConfig {
+    /// Builds the config for the given preset.
+    pub fn preset(preset: SqlitePreset) -> Self {
+        let pragmas = match preset {
+            SqlitePreset::LowMemory => SqlitePragmas {
+                cache_size: -512,
+                mmap_size: 0,
+                temp_store: TempStore::File,
+                wal_autocheckpoint: 1_000,
+                ..SqlitePragmas::default()
+            },
+            SqlitePreset::Balanced => SqlitePragmas::default(),
+            SqlitePreset::Throughput => SqlitePragmas {
+                cache_size: -64 * 1024,
+                mmap_size: 256 * 1024 * 1024,
+                temp_store: TempStore::Memory,
+                journal_size_limit: 64 * 1024 * 1024,
+                wal_autocheckpoint: 10_000,
+                ..SqlitePragmas::default()
+            },
+        };
+
+        Self {
+            preset,
+            writer: pragmas,
+            reader: pragmas,
+        }
+    }
+
+    /// Builds the config from `HQL_SQLITE_PRESET` with optional overrides for single pragmas.
+    pub(crate) fn from_env() -> Self {
+        let preset = env::var("HQL_SQLITE_PRESET")
+            .unwrap_or_else(|_| "balanced".to_string())
+            .parse()
+            .expect("Cannot parse HQL_SQLITE_PRESET");
+        let mut slf = Self::preset(preset);
+
+        if let Ok(v) = env::var("HQL_SQLITE_SYNCHRONOUS") {
+            let synchronous = v.parse().expect("Cannot parse HQL_SQLITE_SYNCHRONOUS");
+            slf.writer.synchronous = synchronous;
+            slf.reader.synchronous = synchronous;
+        }
+        if let Ok(v) = env::var("HQL_SQLITE_CACHE_SIZE_WRITER") {
+            slf.writer.cache_size = v
+                .parse()
+                .expect("Cannot parse HQL_SQLITE_CACHE_SIZE_WRITER to i64");
+        }
+        if let Ok(v) = env::var("HQL_SQLITE_CACHE_SIZE_READER") {
+            slf.reader.cache_size = v
+                .parse()
+                .expect("Cannot parse HQL_SQLITE_CACHE_SIZE_READER to i64");
+        }
+        if let Ok(v) = env::var("HQL_SQLITE_MMAP_SIZE") {
+            let mmap_size = v.parse().expect("Cannot parse HQL_SQLITE_MMAP_SIZE to u64");
+            slf.writer.mmap_size = mmap_size;
+            slf.reader.mmap_size = mmap_size;
+        }
+        if let Ok(v) = env::var("HQL_SQLITE_TEMP_STORE") {
+            let temp_store = v.parse().expect("Cannot parse HQL_SQLITE_TEMP_STORE");
+            slf.writer.temp_store = temp_store;
+            slf.reader.temp_store = temp_store;
+        }
+
+        slf
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        self.writer.validate("writer")?;
+        self.reader.validate("reader")
+    }
+}
+
+/// Named presets for the `SqliteConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SqlitePreset {
+    /// Small page cache, temp files on disk and frequent WAL checkpoints
+    LowMemory,
+    /// The SQLite defaults for the page cache with tuned WAL settings
+    Balanced,
+    /// Large page cache, memory mapped I/O, in-memory temp storage and fewer checkpoints
+    Throughput,
+}
+
+impl FromStr for SqlitePreset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low-memory" => Ok(Self::LowMemory),
+            "balanced" => Ok(Self::Balanced),
+            "throughput" => Ok(Self::Throughput),
+            _ => Err(Error::Config(
+                format!(
+                    "invalid SQLite preset '{}' - expected one of: low-memory, balanced, throughput",
+                    s
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// The pragmas, which will be applied to each new connection.
+///
+/// `page_size` and `auto_vacuum` only have an effect when a database is created.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SqlitePragmas {
+    /// With `OFF`, an OS crash or power loss may corrupt the database in ways `PRAGMA
+    /// quick_check` does not detect. A node will therefore rebuild it from the latest snapshot
+    /// and the Raft logs, if the OS has been restarted since the last start, which is detected
+    /// via the kernel boot id on Linux. After a crash of the process only, or with `NORMAL` or
+    /// `FULL`, it will be verified and kept instead.
+    pub synchronous: Synchronous,
+    /// Must be a power of 2 between 512 and 65536.
+    pub page_size: u32,
+    /// The max size of the WAL file in bytes after a checkpoint. `-1` means no limit.
+    pub journal_size_limit: i64,
+    /// The WAL size in pages, which triggers an automatic checkpoint. Must be > 0.
+    pub wal_autocheckpoint: u32,
+    pub auto_vacuum: AutoVacuum,
+    /// The page cache size. Positive values are in pages, negative ones in KiB, which is the
+    /// same as the SQLite `cache_size` pragma. Must not be `0`.
+    pub cache_size: i64,
+    /// The max amount of bytes for memory mapped I/O. `0` disables it.
+    pub mmap_size: u64,
+    pub temp_store: TempStore,
+}
+
+impl Default for SqlitePragmas {
+    fn default() -> Self {
+        Self {
+            synchronous: Synchronous::Off,
+            page_size: 4096,
+            journal_size_limit: 16384,
+            wal_autocheckpoint: 4_000,
+            auto_vacuum: AutoVacuum::Incremental,
+            // the SQLite default
+            cache_size: -2000,
+            mmap_size: 0,
+            temp_store: TempStore::Default,
+        }
+    }
+}
+
+impl SqlitePragmas {
+    fn validate(&self, typ: &str) -> Result<(), Error> {
+        if !self.page_size.is_power_of_two() || !(512..=65536).contains(&self.page_size) {
+            return Err(Error::Config(
+                format!(
+                    "'sqlite_config.{}.page_size' must be a power of 2 between 512 and 65536",
+                    typ
+                )
+                .into(),
+            ));
+        }
+        if self.journal_size_limit < -1 {
+            return Err(Error::Config(
+                format!("'sqlite_config.{}.journal_size_limit' must be >= -1", typ).into(),
+            ));
+        }
+        if self.wal_autocheckpoint == 0 {
+            return Err(Error::Config(
+                format!("'sqlite_config.{}.wal_autocheckpoint' must be > 0", typ).into(),
+            ));
+        }
+        if self.cache_size == 0 {
+            return Err(Error::Config(
+                format!("'sqlite_config.{}.cache_size' must not be 0", typ).into(),
+            ));
+        }
+        if self.mmap_size > i64::MAX as u64 {
+            return Err(Error::Config(
+                format!("'sqlite_config.{}.mmap_size' is too big", typ).into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies all pragmas except for the `journal_mode`, which depends on the type of database.
+    pub(crate) fn apply(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        conn.pragma_update(None, "synchronous", self.synchronous.as_str())?;
+        conn.pragma_update(None, "page_size", self.page_size)?;
+        conn.pragma_update(None, "journal_size_limit", self.journal_size_limit)?;
+        conn.pragma_update(None, "wal_autocheckpoint", self.wal_autocheckpoint)?;
+        // Not configurable, because it changes the result of writes, which must be the same on
+        // each node.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "auto_vacuum", self.auto_vacuum.as_str())?;
+        conn.pragma_update(None, "cache_size", self.cache_size)?;
+        conn.pragma_update(None, "mmap_size", self.mmap_size as i64)?;
+        conn.pragma_update(None, "temp_store", self.temp_store.as_str())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+}
+
+impl Synchronous {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "OFF",
+            Self::Normal => "NORMAL",
+            Self::Full => "FULL",
+        }
+    }
+
+    /// Whether a database which passes the recovery check after an OS crash can be trusted.
+    /// With `NORMAL` in WAL mode, the last commits may be lost, but the database always stays
+    /// consistent with its metadata.
+    pub(crate) fn is_crash_safe(&self) -> bool {
+        match self {
+            Self::Off => false,
+            Self::Normal | Self::Full => true,
+        }
+    }
+}
+
+impl FromStr for Synchronous {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "normal" => Ok(Self::Normal),
+            "full" => Ok(Self::Full),
+            _ => Err(Error::Config(
+                format!("invalid value for 'synchronous': '{}'", s).into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoVacuum {
+    None,
+    Full,
+    Incremental,
+}
+
+impl AutoVacuum {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "NONE",
+            Self::Full => "FULL",
+            Self::Incremental => "INCREMENTAL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TempStore {
+    Default,
+    File,
+    Memory,
+}
+
+impl TempStore {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Default => "DEFAULT",
+            Self::File => "FILE",
+            Self::Memory => "MEMORY",
+        }
+    }
+}
+
+impl FromStr for TempStore {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Self::Default),
+            "file" => Ok(Self::File),
+            "memory" => Ok(Self::Memory),
+            _ => Err(Error::Config(
+                format!("invalid value for 'temp_store': '{}'", s).into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_valid() {
+        for preset in [
+            SqlitePreset::LowMemory,
+            SqlitePreset::Balanced,
+            SqlitePreset::Throughput,
+        ] {
+            let config = SqliteConfig::preset(preset);
+            assert_eq!(config.preset, preset);
+            assert!(config.validate().is_ok());
+        }
+        assert_eq!(
+            SqliteConfig::default(),
+            SqliteConfig::preset(SqlitePreset::Balanced)
+        );
+    }
+
+    #[test]
+    fn test_preset_from_str() {
+        assert_eq!(
+            "low-memory".parse::<SqlitePreset>().unwrap(),
+            SqlitePreset::LowMemory
+        );
+        assert_eq!(
+            "throughput".parse::<SqlitePreset>().unwrap(),
+            SqlitePreset::Throughput
+        );
+        assert!("fast".parse::<SqlitePreset>().is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = SqliteConfig::default();
+        config.reader.page_size = 1000;
+        assert!(config.validate().is_err());
+
+        let mut config = SqliteConfig::default();
+        config.writer.cache_size = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = SqliteConfig::default();
+        config.writer.wal_autocheckpoint = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = SqliteConfig::default();
+        config.reader.journal_size_limit = -1;
+        config.reader.mmap_size = 1024 * 1024;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_apply() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let pragmas = SqliteConfig::preset(SqlitePreset::Throughput).writer;
+        pragmas.apply(&conn).unwrap();
+
+        let cache_size: i64 = conn
+            .query_row("PRAGMA cache_size", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(cache_size, -64 * 1024);
+        let temp_store: i64 = conn
+            .query_row("PRAGMA temp_store", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(temp_store, 2);
+    }
+}
@@ -1,116 +1,284 @@
-// use crate::store::sqlite_writer::WriterRequest;
-// use crate::store::state_machine_sqlite::{Params, Query, Sql, StateMachineStore};
-// use crate::ApiError;
-// use std::fmt::Debug;
-// use std::thread;
-// use std::time::Duration;
-// use tokio::sync::oneshot;
-//
-// pub trait SqlReader {
-//     type F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<Self::T> + Send;
-//     type T: Debug + Send + 'static;
-// }
-//
-// // pub type SqlReader {
-// //     F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T> + Send,
-// //     T: Debug + Send + 'static,
-// // }
-//
-// // pub type QueryMapRow =
-// //     dyn FnOnce(&rusqlite::Row<'_>) -> Result<QueryMapResult, rusqlite::Error> + Send;
-// // pub type QueryMapResult = dyn Debug + Send;
-//
-// #[derive(Debug)]
-// pub enum ReaderRequest<
-//     F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T> + Send,
-//     T: Debug + Send + 'static,
-// > {
-//     QueryMap(QueryMap<F, T>),
-//     // QueryAs(QueryAs),
-// }
-//
-// // #[derive(Debug)]
-// // pub enum ReaderRequest<
-// //     F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T> + Send,
-// //     T: Debug + Send + 'static,
-// // > {
-// //     QueryMap(QueryMap<F, T>),
-// //     // QueryAs(QueryAs),
-// // }
-//
-// #[derive(Debug)]
-// pub struct QueryMap<
-//     F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T> + Send,
-//     T: Debug + Send + 'static,
-// > {
-//     sql: Sql,
-//     callback: F,
-//     ack: oneshot::Sender<Result<T, ApiError>>,
-// }
-//
-// #[derive(Debug)]
-// pub struct QueryAs {}
-//
-// pub fn spawn_reader<F, T>(
-//     path_db: String,
-//     filename_db: String,
-//     in_memory: bool,
-// ) -> flume::Sender<ReaderRequest<F, T>>
-// where
-//     F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T> + Send + 'static,
-//     T: Debug + Send + 'static,
-// {
-//     let (tx, rx) = flume::bounded::<ReaderRequest<F, T>>(2);
-//
-//     let _handle = thread::spawn(move || {
-//         let conn = wait_for_connection(&path_db, &filename_db, in_memory);
-//
-//         while let Ok(req) = rx.recv() {
-//             match req {
-//                 ReaderRequest::QueryMap(QueryMap { sql, callback, ack }) => {
-//                     let mut stmt = conn.prepare_cached(&sql.sql).unwrap();
-//                     let r = stmt.query_row((), callback).map_err(ApiError::from);
-//                     ack.send(r).unwrap()
-//                 } // ReaderRequest::QueryAs(Sql { sql, params }) => {}
-//             }
-//         }
-//     });
-//
-//     tx
-// }
-//
-// // pub fn spawn_reader(
-// //     path_db: String,
-// //     filename_db: String,
-// //     in_memory: bool,
-// // ) -> flume::Sender<ReaderRequest<F, T>> {
-// //     let (tx, rx) = flume::bounded::<ReaderRequest<F, T>>(2);
-// //
-// //     let _handle = thread::spawn(move || {
-// //         let conn = wait_for_connection(&path_db, &filename_db, in_memory);
-// //
-// //         while let Ok(req) = rx.recv() {
-// //             match req {
-// //                 ReaderRequest::QueryMap(QueryMap { sql, callback, ack }) => {
-// //                     let mut stmt = conn.prepare_cached(&sql.sql).unwrap();
-// //                     let r = stmt.query_row((), callback).map_err(ApiError::from);
-// //                     ack.send(r).unwrap()
-// //                 } // ReaderRequest::QueryAs(Sql { sql, params }) => {}
-// //             }
-// //         }
-// //     });
-// //
-// //     tx
-// // }
-//
-// /// MUST NOT be executed in async context!
-// fn wait_for_connection(path_db: &str, filename_db: &str, in_memory: bool) -> rusqlite::Connection {
-//     let mut conn = StateMachineStore::connect(path_db, filename_db, in_memory, true);
-//     // we connect in a loop here to not get into trouble with the writer setting up a new DB from scratch which is
-//     // not ready yet and therefore locked
-//     while conn.is_err() {
-//         thread::sleep(Duration::from_millis(100));
-//         conn = StateMachineStore::connect(&path_db, &filename_db, in_memory, true);
-//     }
-//     conn.unwrap()
-// }
+use crate::Error;
+use flume::RecvTimeoutError;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::{debug, error, warn};
+
+/// Readers above the min pool size will be stopped after being idle for this long.
+const READER_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a new reader retries to open its connection before giving up.
+const CONNECT_RETRIES: usize = 100;
+
+type ReadJob = Box<dyn FnOnce(&Connection) + Send>;
+
+pub(crate) type ConnectFn = dyn Fn() -> Result<Connection, Error> + Send + Sync;
+
+struct ReaderRequest {
+    job: ReadJob,
+    queued: Instant,
+}
+
+/// Statistics of a read pool on this node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReadPoolMetrics {
+    /// The amount of currently running readers, each with its own connection
+    pub readers: usize,
+    /// The amount of readers waiting for work
+    pub idle: usize,
+    /// The amount of readers which will always be kept running
+    pub min: usize,
+    /// The max amount of readers
+    pub max: usize,
+    /// The amount of queries currently waiting for a free reader
+    pub queue_len: usize,
+    /// The amount of queries which have been picked up by a reader since the start
+    pub queries: u64,
+    /// The average time in microseconds a query had to wait for a reader
+    pub wait_avg_micros: u64,
+    /// The longest time in microseconds a query had to wait for a reader
+    pub wait_max_micros: u64,
+}
+
+/// Pool of dedicated reader threads for a single database, mirroring the design of the writer.
+///
+/// Queries are sent as jobs through a `flume` channel and executed on the reader threads
+/// directly, without a `spawn_blocking` hop. When jobs queue up because no reader is idle, new
+/// readers will be started up to `max`. Readers above `min` stop again after
+/// `READER_IDLE_TIMEOUT`. As soon as all handles to the pool are dropped, all readers exit and
+/// close their connections.
+#[derive(Clone)]
+pub struct ReadPool {
+    tx: flume::Sender<ReaderRequest>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    name: String,
+    rx: flume::Receiver<ReaderRequest>,
+    connect: Box<ConnectFn>,
+    min: usize,
+    max: usize,
+    readers: AtomicUsize,
+    idle: AtomicUsize,
+    queries: AtomicU64,
+    wait_total_micros: AtomicU64,
+    wait_max_micros: AtomicU64,
+}
+
+impl ReadPool {
+    /// Starts a reader for each of the given connections, which will always be kept running.
+    /// `connect` is used to open connections for additional readers up to `max`.
+    pub(crate) fn new(
+        name: String,
+        conns: Vec<Connection>,
+        max: usize,
+        connect: Box<ConnectFn>,
+    ) -> Self {
+        let min = conns.len();
+        debug_assert!(min >= 1 && min <= max);
+
+        let (tx, rx) = flume::unbounded();
+        let shared = Arc::new(Shared {
+            name,
+            rx,
+            connect,
+            min,
+            max,
+            readers: AtomicUsize::new(min),
+            idle: AtomicUsize::new(0),
+            queries: AtomicU64::new(0),
+            wait_total_micros: AtomicU64::new(0),
+            wait_max_micros: AtomicU64::new(0),
+        });
+
+        for conn in conns {
+            Shared::spawn_reader(shared.clone(), Some(conn));
+        }
+
+        Self { tx, shared }
+    }
+
+    /// Executes `f` on the next free reader and returns its result.
+    ///
+    /// A panic inside `f` will not kill the reader and is returned as an error.
+    pub(crate) async fn run<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (ack, rx) = oneshot::channel();
+        self.spawn(move |conn| {
+            // the caller is gone already while the job was queued
+            if ack.is_closed() {
+                return;
+            }
+            let _ = ack.send(f(conn));
+        })?;
+
+        rx.await
+            .map_err(|_| Error::Error("read job panicked inside the reader".into()))?
+    }
+
+    /// Queues `f` for the next free reader without waiting for it.
+    pub(crate) fn spawn<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&Connection) + Send + 'static,
+    {
+        self.tx
+            .send(ReaderRequest {
+                job: Box::new(f),
+                queued: Instant::now(),
+            })
+            .map_err(|_| Error::Channel("read pool has been shut down".to_string()))?;
+
+        // Only start a new reader when the queue grows beyond what the idle readers can pick up.
+        if self.tx.len() > self.shared.idle.load(Ordering::Relaxed) {
+            self.shared.try_grow();
+        }
+
+        Ok(())
+    }
+
+    /// The max amount of readers
+    pub fn max(&self) -> usize {
+        self.shared.max
+    }
+
+    pub fn metrics(&self) -> ReadPoolMetrics {
+        let queries = self.shared.queries.load(Ordering::Relaxed);
+        let wait_total = self.shared.wait_total_micros.load(Ordering::Relaxed);
+
+        ReadPoolMetrics {
+            readers: self.shared.readers.load(Ordering::Relaxed),
+            idle: self.shared.idle.load(Ordering::Relaxed),
+            min: self.shared.min,
+            max: self.shared.max,
+            queue_len: self.tx.len(),
+            queries,
+            wait_avg_micros: if queries > 0 { wait_total / queries } else { 0 },
+            wait_max_micros: self.shared.wait_max_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for ReadPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadPool")
+            .field("name", &self.shared.name)
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+impl Shared {
+    fn try_grow(self: &Arc<Self>) {
+        let res = self
+            .readers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |readers| {
+                (readers < self.max).then_some(readers + 1)
+            });
+
+        if let Ok(readers) = res {
+            debug!(
+                "Starting reader {} / {} for {}",
+                readers + 1,
+                self.max,
+                self.name
+            );
+            Self::spawn_reader(self.clone(), None);
+        }
+    }
+
+    /// Returns `true` if the calling reader may stop, because there are more than `min` readers.
+    fn try_shrink(&self) -> bool {
+        self.readers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |readers| {
+                (readers > self.min).then_some(readers - 1)
+            })
+            .is_ok()
+    }
+
+    fn record_wait(&self, queued: Instant) {
+        let micros = queued.elapsed().as_micros() as u64;
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.wait_total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.wait_max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn spawn_reader(slf: Arc<Self>, conn: Option<Connection>) {
+        let name = format!("hiqlite-reader-{}", slf.name);
+        let readers = slf.clone();
+        let res = thread::Builder::new().name(name).spawn(move || {
+            let conn = match conn {
+                Some(conn) => conn,
+                None => match slf.connect_retry() {
+                    Some(conn) => conn,
+                    None => {
+                        slf.readers.fetch_sub(1, Ordering::AcqRel);
+                        return;
+                    }
+                },
+            };
+
+            slf.run_reader(conn);
+        });
+
+        if let Err(err) = res {
+            error!("Cannot spawn reader thread: {}", err);
+            readers.readers.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// MUST NOT be executed in async context!
+    fn connect_retry(&self) -> Option<Connection> {
+        for _ in 0..CONNECT_RETRIES {
+            match (self.connect)() {
+                Ok(conn) => return Some(conn),
+                Err(err) => {
+                    debug!("Error opening read connection to {}: {}", self.name, err);
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        error!(
+            "Cannot open an additional read connection to {} - running with fewer readers",
+            self.name
+        );
+        None
+    }
+
+    fn run_reader(&self, conn: Connection) {
+        loop {
+            self.idle.fetch_add(1, Ordering::AcqRel);
+            let res = self.rx.recv_timeout(READER_IDLE_TIMEOUT);
+            self.idle.fetch_sub(1, Ordering::AcqRel);
+
+            match res {
+                Ok(ReaderRequest { job, queued }) => {
+                    self.record_wait(queued);
+                    if panic::catch_unwind(AssertUnwindSafe(|| job(&conn))).is_err() {
+                        warn!("Read job panicked inside reader for {}", self.name);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.try_shrink() {
+                        debug!("Stopping idle reader for {}", self.name);
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.readers.fetch_sub(1, Ordering::AcqRel);
+                    return;
+                }
+            }
+        }
+    }
+}
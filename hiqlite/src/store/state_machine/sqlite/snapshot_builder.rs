@@ -101,7 +101,8 @@ async fn snapshots_cleanup(
             continue;
         }
 
-        if name != keep_id {
+        // `temp` files belong to a snapshot which is currently being received
+        if name != keep_id && !name.starts_with("temp") {
             deletes.push(name.to_string());
         }
     }
@@ -0,0 +1,206 @@
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::{NodeConfig, RaftConfig};
+use openraft::{Raft, SnapshotPolicy};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::{task, time};
+use tracing::{debug, error, info};
+
+/// The interval in which the schedule is checked.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A triggered snapshot is built in the background. It will only be triggered again after this
+/// time, if the Raft metrics did not show a new snapshot in the meantime.
+const TRIGGER_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trigger {
+    Logs,
+    MaxInterval,
+    LogSize,
+}
+
+/// Snapshot scheduling on top of the `SnapshotPolicy` from the `RaftConfig`. All snapshots are
+/// still built by the `SQLiteSnapshotBuilder` via `raft.trigger().snapshot()`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SnapshotSchedule {
+    max_interval: Option<Duration>,
+    max_log_size: Option<u64>,
+    min_interval: Duration,
+    /// Only set if the `LogsSinceLast` policy has been taken over from the `RaftConfig`
+    logs_since_last: Option<u64>,
+}
+
+impl SnapshotSchedule {
+    /// Returns the schedule together with the `RaftConfig` to use for the SQLite Raft. With a
+    /// `snapshot_min_interval`, the `LogsSinceLast` policy cannot be left to the Raft anymore,
+    /// because it would ignore the min interval. It is handled by the schedule in that case.
+    pub fn new(
+        node_config: &NodeConfig,
+        raft_config: Arc<RaftConfig>,
+    ) -> (Option<Self>, Arc<RaftConfig>) {
+        let (logs_since_last, raft_config) = match raft_config.snapshot_policy {
+            SnapshotPolicy::LogsSinceLast(logs) if node_config.snapshot_min_interval > 0 => {
+                let config = RaftConfig {
+                    snapshot_policy: SnapshotPolicy::Never,
+                    ..(*raft_config).clone()
+                };
+                (Some(logs), Arc::new(config))
+            }
+            _ => (None, raft_config),
+        };
+
+        let slf = Self {
+            max_interval: (node_config.snapshot_max_interval > 0)
+                .then(|| Duration::from_secs(node_config.snapshot_max_interval)),
+            max_log_size: (node_config.snapshot_max_log_size > 0)
+                .then_some(node_config.snapshot_max_log_size),
+            min_interval: Duration::from_secs(node_config.snapshot_min_interval),
+            logs_since_last,
+        };
+
+        if slf.max_interval.is_none() && slf.max_log_size.is_none() && logs_since_last.is_none() {
+            (None, raft_config)
+        } else {
+            (Some(slf), raft_config)
+        }
+    }
+
+    fn check(&self, since_last: Duration, logs: u64, log_size: u64) -> Option<Trigger> {
+        if logs == 0 || since_last < self.min_interval {
+            return None;
+        }
+
+        if self.logs_since_last.is_some_and(|limit| logs >= limit) {
+            Some(Trigger::Logs)
+        } else if self.max_log_size.is_some_and(|limit| log_size > limit) {
+            Some(Trigger::LogSize)
+        } else if self.max_interval.is_some_and(|limit| since_last >= limit) {
+            Some(Trigger::MaxInterval)
+        } else {
+            None
+        }
+    }
+
+    /// `appended_bytes` comes from the logs store. The log size is the amount of bytes appended
+    /// since the last snapshot. Purged logs never shrink the files of the logs store right away,
+    /// which is why its size on disk can't be used.
+    pub fn spawn(self, raft: Raft<TypeConfigSqlite>, appended_bytes: Arc<AtomicU64>) {
+        task::spawn(self.run(raft, appended_bytes));
+    }
+
+    async fn run(self, raft: Raft<TypeConfigSqlite>, appended_bytes: Arc<AtomicU64>) {
+        let mut snapshot = raft.metrics().borrow().snapshot;
+        // We do not know when the current snapshot has been built, or how big the logs after it
+        // are. Starting now only delays the next one after a restart.
+        let mut snapshot_at = Instant::now();
+        let mut snapshot_at_bytes = appended_bytes.load(Ordering::Relaxed);
+        let mut triggered_at: Option<Instant> = None;
+
+        loop {
+            time::sleep(TICK).await;
+
+            let (last_applied, current) = {
+                let metrics = raft.metrics();
+                let metrics = metrics.borrow();
+                (metrics.last_applied, metrics.snapshot)
+            };
+            if current != snapshot {
+                snapshot = current;
+                snapshot_at = Instant::now();
+                snapshot_at_bytes = appended_bytes.load(Ordering::Relaxed);
+                triggered_at = None;
+            }
+            if triggered_at.is_some_and(|at| at.elapsed() < TRIGGER_RETRY) {
+                continue;
+            }
+
+            let logs = last_applied
+                .map(|id| id.index + 1)
+                .unwrap_or(0)
+                .saturating_sub(snapshot.map(|id| id.index + 1).unwrap_or(0));
+            let log_size = appended_bytes
+                .load(Ordering::Relaxed)
+                .saturating_sub(snapshot_at_bytes);
+
+            if let Some(trigger) = self.check(snapshot_at.elapsed(), logs, log_size) {
+                info!(
+                    "Triggering snapshot ({:?}) with {} new logs and {} bytes of logs since the \
+                    last one",
+                    trigger, logs, log_size
+                );
+                match raft.trigger().snapshot().await {
+                    Ok(_) => triggered_at = Some(Instant::now()),
+                    Err(err) => {
+                        error!("Error triggering snapshot: {}", err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        debug!("Raft has been shut down - exiting snapshot schedule");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_schedule() {
+        let schedule = SnapshotSchedule {
+            max_interval: Some(Duration::from_secs(3600)),
+            max_log_size: Some(1024),
+            min_interval: Duration::from_secs(60),
+            logs_since_last: Some(100),
+        };
+        let secs = Duration::from_secs;
+
+        // never without new logs
+        assert_eq!(schedule.check(secs(7200), 0, 2048), None);
+        // never before the min interval
+        assert_eq!(schedule.check(secs(59), 1000, 2048), None);
+
+        assert_eq!(schedule.check(secs(60), 100, 0), Some(Trigger::Logs));
+        assert_eq!(schedule.check(secs(60), 99, 0), None);
+        assert_eq!(schedule.check(secs(60), 99, 1025), Some(Trigger::LogSize));
+        assert_eq!(schedule.check(secs(60), 99, 1024), None);
+        assert_eq!(schedule.check(secs(3600), 1, 0), Some(Trigger::MaxInterval));
+
+        let schedule = SnapshotSchedule {
+            max_interval: None,
+            max_log_size: None,
+            min_interval: Duration::ZERO,
+            logs_since_last: None,
+        };
+        assert_eq!(
+            schedule.check(secs(u32::MAX as u64), 1_000_000, u64::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn test_snapshot_schedule_raft_config() {
+        let raft_config = Arc::new(NodeConfig::default_raft_config(1000));
+
+        let config = NodeConfig {
+            snapshot_max_interval: 0,
+            snapshot_min_interval: 0,
+            ..Default::default()
+        };
+        let (schedule, raft) = SnapshotSchedule::new(&config, raft_config.clone());
+        assert!(schedule.is_none());
+        assert!(matches!(
+            raft.snapshot_policy,
+            SnapshotPolicy::LogsSinceLast(1000)
+        ));
+
+        // the default min interval takes over the policy
+        let (schedule, raft) = SnapshotSchedule::new(&NodeConfig::default(), raft_config);
+        assert_eq!(schedule.unwrap().logs_since_last, Some(1000));
+        assert!(matches!(raft.snapshot_policy, SnapshotPolicy::Never));
+    }
+}
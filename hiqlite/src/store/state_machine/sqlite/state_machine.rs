@@ -3,15 +3,24 @@
 use crate::helpers::set_path_access;
 use crate::migration::Migration;
 use crate::query::rows::RowOwned;
+use crate::store::state_machine::sqlite::checksum::{self, Checksums};
+use crate::store::state_machine::sqlite::cost::WriteCostGuard;
+use crate::store::state_machine::sqlite::deterministic::WriteStamp;
+use crate::store::state_machine::sqlite::functions::SqlFunctions;
+use crate::store::state_machine::sqlite::guard::GuardedQuery;
 use crate::store::state_machine::sqlite::param::Param;
+use crate::store::state_machine::sqlite::pragmas::{SqliteConfig, SqlitePragmas, Synchronous};
+use crate::store::state_machine::sqlite::reader::ReadPool;
 use crate::store::state_machine::sqlite::snapshot_builder::SQLiteSnapshotBuilder;
 use crate::store::state_machine::sqlite::writer::WriterRequest::MetadataRead;
 use crate::store::state_machine::sqlite::writer::{
-    self, MetaPersistRequest, SqlBatch, SqlTransaction, WriterRequest,
+    self, LockFile, MetaPersistRequest, QueryGroup, SqlBatch, SqlTransaction,
+    SqlTransactionGuarded, WriterRequest,
 };
-use crate::store::state_machine::sqlite::{reader, TypeConfigSqlite};
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
 use crate::store::{logs, StorageResult};
 use crate::{Error, Node, NodeId};
+use chrono::Utc;
 use openraft::storage::RaftStateMachine;
 use openraft::{
     EntryPayload, LogId, OptionalSend, Snapshot, SnapshotId, SnapshotMeta, StorageError,
@@ -21,6 +30,7 @@ use rusqlite::{OpenFlags, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::clone::Clone;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::oneshot;
@@ -32,25 +42,79 @@ use uuid::Uuid;
 type Entry = openraft::Entry<TypeConfigSqlite>;
 type SnapshotData = tokio::fs::File;
 
-pub type SqlitePool = deadpool::unmanaged::Pool<rusqlite::Connection>;
+/// The pool of dedicated reader threads for local reads on a single database.
+pub type SqlitePool = ReadPool;
 
 pub type Params = Vec<Param>;
 
+/// The max amount of queries the writer will apply inside a single SQLite transaction.
+const GROUP_COMMIT_MAX_ENTRIES: usize = 1024;
+
+/// A `Resync` entry older than this is only being replayed after a restart and will be skipped.
+const RESYNC_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// How long a divergent node waits for the database copy during a re-sync.
+pub(crate) const RESYNC_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long the writer of an in-memory database waits for active readers before it gives up.
+const IN_MEMORY_WRITER_BUSY_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
 pub struct PathDb(pub String);
 pub struct PathBackups(pub String);
 pub struct PathSnapshots(pub String);
 pub struct PathLockFile(pub String);
 
+/// The payload of each Raft log entry. The `stamp` is set by the leader when the entry is
+/// proposed and makes time and randomness inside SQL functions the same on every node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryWriteEntry {
+    pub stamp: WriteStamp,
+    /// The named database this query targets, `None` for the main one
+    pub db: Option<String>,
+    pub query: QueryWrite,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum QueryWrite {
     Execute(Query),
+    /// Independent queries, coalesced into a single Raft entry. Each one has its own result.
+    ExecuteMulti(Vec<Query>),
+    ExecuteMany(QueryMany),
     ExecuteReturning(Query),
     Transaction(Vec<Query>),
+    TransactionGuarded(Vec<GuardedQuery>),
     Batch(Cow<'static, str>),
     Migration(Vec<Migration>),
     #[cfg(feature = "backup")]
     Backup(NodeId),
     RTT,
+    /// Computes the checksum of all databases on each node at this log index.
+    Checksum,
+    /// Replaces the databases of `target` with a copy of the ones from `source` at this log
+    /// index after a detected divergence.
+    Resync {
+        source: NodeId,
+        target: NodeId,
+    },
+    /// Internal metadata of the Raft log archive, which can't be written with user queries.
+    #[cfg(feature = "backup")]
+    ArchiveMeta(crate::pitr::ArchiveMeta),
+}
+
+impl QueryWrite {
+    /// If this write can be applied inside a group of other writes with its own savepoint.
+    #[inline]
+    fn is_groupable(&self) -> bool {
+        matches!(
+            self,
+            Self::Execute(_)
+                | Self::ExecuteMulti(_)
+                | Self::ExecuteMany(_)
+                | Self::ExecuteReturning(_)
+                | Self::Transaction(_)
+                | Self::TransactionGuarded(_)
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,10 +123,21 @@ pub struct Query {
     pub params: Params,
 }
 
+/// A single statement, which will be executed once for each row of params.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryMany {
+    pub sql: Cow<'static, str>,
+    /// The amount of params for each execution
+    pub width: usize,
+    /// All params, row by row, in one flat `Vec` to keep the Raft entries compact
+    pub params: Params,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Empty,
     Execute(ResponseExecute),
+    ExecuteMulti(Vec<Result<usize, Error>>),
     ExecuteReturning(ResponseExecuteReturning),
     Transaction(Result<Vec<Result<usize, Error>>, Error>),
     Batch(ResponseBatch),
@@ -71,6 +146,29 @@ pub enum Response {
     RTT,
 }
 
+/// The result of a write together with the Raft log index it has been applied at.
+///
+/// The index can be passed into the `query_*_after()` functions to make sure a node has applied
+/// this write before a local query runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Indexed<T> {
+    pub value: T,
+    pub log_index: u64,
+}
+
+impl<T> Indexed<T> {
+    pub(crate) fn new(value: T, log_index: u64) -> Self {
+        Self { value, log_index }
+    }
+
+    pub(crate) fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Indexed<U> {
+        Indexed {
+            value: f(self.value),
+            log_index: self.log_index,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseExecute {
     pub result: Result<usize, Error>,
@@ -86,6 +184,56 @@ pub struct ResponseBatch {
     pub result: Result<Vec<Result<usize, Error>>, Error>,
 }
 
+/// Consecutive queries from a single `apply()` for the same database, which will be committed
+/// together.
+#[derive(Default)]
+struct WriteGroup {
+    db: Option<String>,
+    queries: Vec<writer::Query>,
+    /// One for each applied entry in order, including the ones without a query.
+    pending: Vec<PendingResponse>,
+}
+
+impl WriteGroup {
+    #[inline]
+    fn push(&mut self, query: writer::Query, pending: PendingResponse) {
+        self.queries.push(query);
+        self.pending.push(pending);
+    }
+}
+
+enum PendingResponse {
+    Ready(Response),
+    Execute(oneshot::Receiver<Result<usize, Error>>),
+    ExecuteMulti(Vec<oneshot::Receiver<Result<usize, Error>>>),
+    ExecuteReturning(oneshot::Receiver<Result<Vec<Result<RowOwned, Error>>, Error>>),
+    Transaction(oneshot::Receiver<Result<Vec<Result<usize, Error>>, Error>>),
+}
+
+impl PendingResponse {
+    async fn recv(self) -> Response {
+        match self {
+            Self::Ready(resp) => resp,
+            Self::Execute(rx) => Response::Execute(ResponseExecute {
+                result: rx.await.expect("to always get a response from sql writer"),
+            }),
+            Self::ExecuteMulti(rxs) => {
+                let mut results = Vec::with_capacity(rxs.len());
+                for rx in rxs {
+                    results.push(rx.await.expect("to always get a response from sql writer"));
+                }
+                Response::ExecuteMulti(results)
+            }
+            Self::ExecuteReturning(rx) => Response::ExecuteReturning(ResponseExecuteReturning {
+                result: rx.await.expect("to always get a response from sql writer"),
+            }),
+            Self::Transaction(rx) => {
+                Response::Transaction(rx.await.expect("to always get a response from sql writer"))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredSnapshot {
     pub meta: SnapshotMeta<NodeId, Node>,
@@ -103,31 +251,48 @@ pub struct StateMachineData {
 pub struct StateMachineSqlite {
     // pub data: StateMachineData,
     this_node: NodeId,
-    path_snapshots: String,
+    pub(crate) path_snapshots: String,
     #[cfg(feature = "backup")]
     path_backups: String,
     path_lock_file: String,
+    sql_functions: SqlFunctions,
 
     #[cfg(feature = "s3")]
     s3_config: Option<Arc<crate::s3::S3Config>>,
+    #[cfg(feature = "backup")]
+    archive: Option<crate::pitr::LogArchive>,
 
     pub read_pool: SqlitePool,
+    pub named_read_pools: HashMap<String, SqlitePool>,
     pub(crate) write_tx: flume::Sender<WriterRequest>,
+    pub(crate) write_cost: Arc<WriteCostGuard>,
+    pub(crate) checksums: Arc<Checksums>,
 }
 
 impl StateMachineSqlite {
     pub(crate) async fn new(
         data_dir: &str,
         filename_db: &str,
+        in_memory: bool,
+        databases: &[String],
         this_node: NodeId,
         log_statements: bool,
+        sqlite_config: SqliteConfig,
+        write_cost_limit: u64,
         prepared_statement_cache_capacity: usize,
+        read_pool_min: usize,
         read_pool_size: usize,
+        recovery_integrity_check: bool,
+        sql_functions: SqlFunctions,
         #[cfg(feature = "s3")] s3_config: Option<Arc<crate::s3::S3Config>>,
+        #[cfg(feature = "backup")] archive: Option<crate::pitr::LogArchive>,
     ) -> Result<StateMachineSqlite, StorageError<NodeId>> {
         // IMPORTANT: Do NOT change the order of the db exists check!
         // DB recovery will fail otherwise!
-        let mut db_exists = Self::db_exists(data_dir, filename_db).await;
+        let db_file_exists = Self::db_exists(data_dir, filename_db).await;
+        // An in-memory DB must always be rebuilt. A DB file can only exist in this case if it
+        // has just been put in place by a backup restore.
+        let mut db_exists = db_file_exists && !in_memory;
         info!("db_exists in stage_machine::new(): {}", db_exists);
 
         let (
@@ -137,33 +302,104 @@ impl StateMachineSqlite {
             PathLockFile(path_lock_file),
         ) = Self::build_folders(data_dir, true).await;
 
-        Self::check_set_lock_file(&path_lock_file, &path_db, &mut db_exists).await;
+        // an in-memory DB can't be left in a broken state after a crash
+        let recovered = if in_memory {
+            None
+        } else {
+            let filenames = std::iter::once(filename_db.to_string())
+                .chain(databases.iter().map(|name| Self::filename_named_db(name)))
+                .collect();
+            Self::check_set_lock_file(
+                &path_lock_file,
+                &path_db,
+                filenames,
+                sqlite_config.writer.synchronous,
+                recovery_integrity_check,
+                &mut db_exists,
+            )
+            .await
+        };
 
         // Always start the writer first! -> creates mandatory tables
         let conn = Self::connect(
             path_db.to_string(),
             filename_db.to_string(),
             false,
+            in_memory,
+            sqlite_config.writer,
             prepared_statement_cache_capacity,
+            sql_functions.clone(),
         )
         .await
         .map_err(|err| StorageError::IO {
             source: StorageIOError::write(&err),
         })?;
-        let write_tx =
-            writer::spawn_writer(conn, this_node, path_lock_file.clone(), log_statements);
+
+        let mut named_conns = Vec::with_capacity(databases.len());
+        for name in databases {
+            let conn = Self::connect(
+                path_db.to_string(),
+                Self::filename_named_db(name),
+                false,
+                in_memory,
+                sqlite_config.writer,
+                prepared_statement_cache_capacity,
+                sql_functions.clone(),
+            )
+            .await
+            .map_err(|err| StorageError::IO {
+                source: StorageIOError::write(&err),
+            })?;
+            named_conns.push((name.clone(), conn));
+        }
+
+        let write_cost = WriteCostGuard::new(write_cost_limit);
+        let checksums = Arc::new(Checksums::new());
+        let write_tx = writer::spawn_writer(
+            conn,
+            named_conns,
+            this_node,
+            path_lock_file.clone(),
+            log_statements,
+            sql_functions.clone(),
+            write_cost.clone(),
+            checksums.clone(),
+        );
 
         let read_pool = Self::connect_read_pool(
             path_db.as_ref(),
             filename_db,
+            in_memory,
+            sqlite_config.reader,
             prepared_statement_cache_capacity,
+            read_pool_min,
             read_pool_size,
+            &sql_functions,
         )
         .await
         .map_err(|err| StorageError::IO {
             source: StorageIOError::read(&err),
         })?;
 
+        let mut named_read_pools = HashMap::with_capacity(databases.len());
+        for name in databases {
+            let pool = Self::connect_read_pool(
+                path_db.as_ref(),
+                &Self::filename_named_db(name),
+                in_memory,
+                sqlite_config.reader,
+                prepared_statement_cache_capacity,
+                read_pool_min,
+                read_pool_size,
+                &sql_functions,
+            )
+            .await
+            .map_err(|err| StorageError::IO {
+                source: StorageIOError::read(&err),
+            })?;
+            named_read_pools.insert(name.clone(), pool);
+        }
+
         let mut slf = Self {
             // data: state_machine_data,
             this_node,
@@ -171,16 +407,39 @@ impl StateMachineSqlite {
             #[cfg(feature = "backup")]
             path_backups,
             path_lock_file,
+            sql_functions,
             #[cfg(feature = "s3")]
             s3_config,
+            #[cfg(feature = "backup")]
+            archive,
             read_pool,
+            named_read_pools,
             write_tx,
+            write_cost,
+            checksums,
         };
 
-        if !db_exists {
+        if in_memory && db_file_exists {
+            let path_db_full = format!("{}/{}", path_db, filename_db);
+            info!("Loading restored backup {} into memory", path_db_full);
+            slf.update_state_machine_(path_db_full.clone()).await?;
+            let _ = fs::remove_file(path_db_full).await;
+        } else if !db_exists {
             if let Some(snapshot) = slf.read_current_snapshot().await? {
                 slf.update_state_machine_(snapshot.path).await?;
             }
+        } else if let Some(last_applied) = recovered {
+            // Logs up to the latest snapshot may have been purged already. This can only happen
+            // if the last commits before the crash did not make it to disk.
+            if let Some(snapshot) = slf.read_current_snapshot().await? {
+                if snapshot.meta.last_log_id > Some(last_applied) {
+                    warn!(
+                        "Recovered database is behind the latest snapshot {:?} - restoring it",
+                        snapshot.meta.last_log_id
+                    );
+                    slf.update_state_machine_(snapshot.path).await?;
+                }
+            }
         }
 
         Ok(slf)
@@ -200,6 +459,12 @@ impl StateMachineSqlite {
         format!("{}/db", Self::path_base(data_dir))
     }
 
+    /// Named databases live next to the main one inside the `db` folder.
+    #[inline]
+    fn filename_named_db(name: &str) -> String {
+        format!("{}.db", name)
+    }
+
     pub async fn build_folders(
         data_dir: &str,
         create: bool,
@@ -244,10 +509,56 @@ impl StateMachineSqlite {
         )
     }
 
-    async fn check_set_lock_file(path_lock_file: &str, path_db: &str, db_exists: &mut bool) {
-        let is_locked = fs::File::open(path_lock_file).await.is_ok();
+    /// The lock file only exists at startup if the node did not shut down gracefully. In this
+    /// case, the existing database will be verified and kept if it is intact and the writer
+    /// runs with `synchronous` at least `NORMAL`. Returns its last applied log id, after which
+    /// the logs will be replayed. Otherwise, the state machine is rebuilt from the latest
+    /// snapshot and logs with the `auto-heal` feature.
+    async fn check_set_lock_file(
+        path_lock_file: &str,
+        path_db: &str,
+        filenames: Vec<String>,
+        synchronous: Synchronous,
+        integrity_check: bool,
+        db_exists: &mut bool,
+    ) -> Option<LogId<NodeId>> {
+        if let Ok(content) = fs::read_to_string(path_lock_file).await {
+            let lock = LockFile::parse(&content);
+            if lock.needs_rebuild {
+                warn!("Node crashed while the database was not in sync with its metadata");
+            } else if *db_exists && !synchronous.is_crash_safe() && !lock.is_same_boot() {
+                // an OS crash may have left corruption behind that a quick check would not find
+                warn!(
+                    "The OS has been restarted and the database can't be verified with \
+                    'synchronous' OFF - rebuilding it"
+                );
+            } else if *db_exists {
+                info!(
+                    "Lock file already exists: {}\n\
+                    Node did not shut down gracefully - verifying the database",
+                    path_lock_file
+                );
+
+                let path = path_db.to_string();
+                let res = task::spawn_blocking(move || {
+                    Self::verify_db(&path, &filenames, integrity_check)
+                })
+                .await
+                .expect("database verification to never panic");
+
+                match res {
+                    Ok(last_applied) => {
+                        info!(
+                            "Database verified successfully - replaying logs after {}",
+                            last_applied
+                        );
+                        Self::write_lock_file(path_lock_file).await;
+                        return Some(last_applied);
+                    }
+                    Err(err) => warn!("Database verification failed: {}", err),
+                }
+            }
 
-        if is_locked {
             #[cfg(feature = "auto-heal")]
             {
                 warn!(
@@ -265,6 +576,8 @@ impl StateMachineSqlite {
                 if let Err(err) = fs::create_dir_all(path_db).await {
                     panic!("Cannot re-create DB folder {}: {}", path_db, err);
                 }
+                // resets a possible rebuild marker
+                Self::write_lock_file(path_lock_file).await;
 
                 *db_exists = false;
             }
@@ -275,46 +588,157 @@ impl StateMachineSqlite {
                 Node did not shut down gracefully - needs manual interaction",
                 path_lock_file
             );
-        } else if let Err(err) = fs::File::create(path_lock_file).await {
+        } else {
+            Self::write_lock_file(path_lock_file).await;
+        }
+
+        None
+    }
+
+    /// Writes a new lock file with the current boot id and without the rebuild marker.
+    async fn write_lock_file(path_lock_file: &str) {
+        let path = path_lock_file.to_string();
+        let res = task::spawn_blocking(move || LockFile::new(false).write(&path))
+            .await
+            .expect("lock file creation to never panic");
+        if let Err(err) = res {
             panic!("Error creating lock file {}: {}", path_lock_file, err);
         }
     }
 
+    /// Runs `PRAGMA quick_check`, or the full `PRAGMA integrity_check`, on each of the database
+    /// files and reads the last applied log id from the metadata of the main one, which always
+    /// comes first.
+    ///
+    /// MUST NOT be executed in async context!
+    fn verify_db(
+        path_db: &str,
+        filenames: &[String],
+        integrity_check: bool,
+    ) -> Result<LogId<NodeId>, Error> {
+        let pragma = if integrity_check {
+            "PRAGMA integrity_check"
+        } else {
+            "PRAGMA quick_check"
+        };
+
+        let mut last_applied = None;
+        for filename in filenames {
+            let path = format!("{}/{}", path_db, filename);
+            // do not create missing databases
+            let conn =
+                rusqlite::Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+
+            let res: String = conn.query_row(pragma, (), |row| row.get(0))?;
+            if res != "ok" {
+                return Err(Error::Sqlite(
+                    format!("'{}' failed for {}: {}", pragma, filename, res).into(),
+                ));
+            }
+
+            if last_applied.is_none() {
+                let bytes: Vec<u8> =
+                    conn.query_row("SELECT data FROM _metadata WHERE key = 'meta'", (), |row| {
+                        row.get(0)
+                    })?;
+                let metadata: StateMachineData = bincode::deserialize(&bytes)?;
+                last_applied =
+                    Some(metadata.last_applied_log_id.ok_or_else(|| {
+                        Error::Sqlite("no last applied log id in metadata".into())
+                    })?);
+            }
+        }
+
+        last_applied.ok_or_else(|| Error::Sqlite("no database to verify".into()))
+    }
+
     pub(crate) fn remove_lock_file(path: &str) {
         let _ = std::fs::remove_file(path);
     }
 
+    /// Opens a connection to `{path}/{filename_db}`.
+    ///
+    /// With `in_memory`, the path is only used as the name of an in-memory database in the
+    /// `memdb` VFS, which all connections to it share. It lives as long as at least one
+    /// connection is open.
     pub async fn connect(
         path: String,
         filename_db: String,
         read_only: bool,
+        in_memory: bool,
+        pragmas: SqlitePragmas,
         prepared_statement_cache_capacity: usize,
+        sql_functions: SqlFunctions,
     ) -> Result<rusqlite::Connection, Error> {
         task::spawn_blocking(move || {
-            let path_full = format!("{}/{}", path, filename_db);
-            let conn = rusqlite::Connection::open(path_full)?;
-            Self::apply_pragmas(&conn, read_only, prepared_statement_cache_capacity)?;
-            Ok(conn)
+            Self::connect_sync(
+                &path,
+                &filename_db,
+                read_only,
+                in_memory,
+                &pragmas,
+                prepared_statement_cache_capacity,
+                &sql_functions,
+            )
         })
         .await?
     }
 
-    /// TODO provide a way to pass in conn pool size
+    /// MUST NOT be executed in async context!
+    fn connect_sync(
+        path: &str,
+        filename_db: &str,
+        read_only: bool,
+        in_memory: bool,
+        pragmas: &SqlitePragmas,
+        prepared_statement_cache_capacity: usize,
+        sql_functions: &SqlFunctions,
+    ) -> Result<rusqlite::Connection, Error> {
+        let path_full = if in_memory {
+            // A shared cache would make the writer fail with SQLITE_LOCKED as soon as a reader
+            // is active, which does not respect the busy timeout. The `memdb` VFS uses normal
+            // file locking instead. Names must start with a single `/` to be shared.
+            format!(
+                "file:/{}/{}?vfs=memdb",
+                path.trim_start_matches('/'),
+                filename_db
+            )
+        } else {
+            format!("{}/{}", path, filename_db)
+        };
+        let conn = rusqlite::Connection::open(path_full)?;
+        Self::apply_pragmas(
+            &conn,
+            read_only,
+            in_memory,
+            pragmas,
+            prepared_statement_cache_capacity,
+        )?;
+        sql_functions.apply(&conn)?;
+        Ok(conn)
+    }
+
+    /// Opens the `pool_min` connections which will always be kept open and starts the readers.
     async fn connect_read_pool(
         path: &str,
         filename_db: &str,
+        in_memory: bool,
+        pragmas: SqlitePragmas,
         prepared_statement_cache_capacity: usize,
-        pool_size: usize,
+        pool_min: usize,
+        pool_max: usize,
+        sql_functions: &SqlFunctions,
     ) -> Result<SqlitePool, Error> {
-        let path_full = format!("{}/{}", path, filename_db);
-
-        let mut conns = Vec::with_capacity(pool_size);
-        for _ in 0..pool_size {
+        let mut conns = Vec::with_capacity(pool_min);
+        for _ in 0..pool_min {
             let mut conn = Self::connect(
                 path.to_string(),
                 filename_db.to_string(),
                 true,
+                in_memory,
+                pragmas,
                 prepared_statement_cache_capacity,
+                sql_functions.clone(),
             )
             .await;
             while conn.is_err() {
@@ -323,42 +747,40 @@ impl StateMachineSqlite {
                     path.to_string(),
                     filename_db.to_string(),
                     true,
+                    in_memory,
+                    pragmas,
                     prepared_statement_cache_capacity,
+                    sql_functions.clone(),
                 )
                 .await;
             }
             conns.push(conn?);
         }
 
-        let pool = deadpool::unmanaged::Pool::from(conns);
-
-        // let pool = config
-        //     .builder(deadpool_sqlite::Runtime::Tokio1)
-        //     .unwrap()
-        //     .post_create(deadpool_sqlite::Hook::async_fn(
-        //         |conn: &mut deadpool_sync::SyncWrapper<rusqlite::Connection>, _| {
-        //             Box::pin(async move {
-        //                 conn.interact(|conn| {
-        //                     Self::apply_pragmas(conn, true).map_err(|_| InteractError::Aborted)
-        //                 })
-        //                 .await
-        //                 .map_err(|err| deadpool_sqlite::HookError::Message(err.to_string().into()))?
-        //                 .map_err(|err| {
-        //                     deadpool_sqlite::HookError::Message(err.to_string().into())
-        //                 })?;
-        //                 Ok(())
-        //             })
-        //         },
-        //     ))
-        //     .build()?;
-
-        let conn = pool.get().await?;
-        task::spawn_blocking(move || {
+        let connect = {
+            let path = path.to_string();
+            let filename_db = filename_db.to_string();
+            let sql_functions = sql_functions.clone();
+            Box::new(move || {
+                Self::connect_sync(
+                    &path,
+                    &filename_db,
+                    true,
+                    in_memory,
+                    &pragmas,
+                    prepared_statement_cache_capacity,
+                    &sql_functions,
+                )
+            })
+        };
+        let pool = ReadPool::new(filename_db.to_string(), conns, pool_max, connect);
+
+        pool.run(|conn| {
             let _ = conn.query_row("SELECT 1", (), |row| {
                 let res: i64 = row.get(0)?;
                 Ok(res)
             })?;
-            Ok::<(), Error>(())
+            Ok(())
         })
         .await?;
 
@@ -368,29 +790,20 @@ impl StateMachineSqlite {
     fn apply_pragmas(
         conn: &rusqlite::Connection,
         read_only: bool,
+        in_memory: bool,
+        pragmas: &SqlitePragmas,
         prepared_statement_cache_capacity: usize,
     ) -> Result<(), rusqlite::Error> {
-        conn.pragma_update(None, "journal_mode", "WAL")?;
-        // synchronous set to OFF is not an issue in our case.
-        // If the OS crashes before it could flush any buffers to disk, we will rebuild the DB
-        // anyway from the logs store just to be 100% sure that all cluster members are in a
-        // consistent state. Setting it to OFF here gives us an ~18% boost compared to NORMAL while
-        // not having any disadvantage with the Raft setup.
-        conn.pragma_update(None, "synchronous", "OFF")?;
-
-        conn.pragma_update(None, "page_size", 4096)?;
-        conn.pragma_update(None, "journal_size_limit", 16384)?;
-        conn.pragma_update(None, "wal_autocheckpoint", 4_000)?;
-
-        // setting in-memory temp_store actually slows down SELECTs a little bit
-        // conn.pragma_update(None, "temp_store", "memory")?;
-
-        conn.pragma_update(None, "foreign_keys", "ON")?;
-        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
-        conn.pragma_update(None, "optimize", "0x10002")?;
-
-        // note:
-        // in tests, `mmap_size` did not show any performance benefit with the settings above
+        // in-memory DBs always use an in-memory journal and can't use WAL
+        if !in_memory {
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+        } else if !read_only {
+            // Without WAL, the writer has to wait until active readers are done. It must never
+            // give up, because that would make this node skip a Raft log entry. Long-running
+            // reads can be limited with the `query_timeout`.
+            conn.busy_timeout(IN_MEMORY_WRITER_BUSY_TIMEOUT)?;
+        }
+        pragmas.apply(conn)?;
 
         // only allow select statements
         if read_only {
@@ -405,6 +818,25 @@ impl StateMachineSqlite {
         Ok(())
     }
 
+    /// Sends all grouped queries to the writer and collects their responses in order.
+    async fn flush_group(&self, group: &mut WriteGroup, replies: &mut Vec<Response>) {
+        if !group.queries.is_empty() {
+            let req = WriterRequest::QueryGroup(QueryGroup {
+                db: group.db.clone(),
+                queries: std::mem::take(&mut group.queries),
+            });
+
+            self.write_tx
+                .send_async(req)
+                .await
+                .expect("sql writer to always be listening");
+        }
+
+        for pending in group.pending.drain(..) {
+            replies.push(pending.recv().await);
+        }
+    }
+
     async fn update_state_machine_(
         &mut self,
         snapshot_path: String,
@@ -420,6 +852,79 @@ impl StateMachineSqlite {
         Ok(())
     }
 
+    /// The `source` creates a copy of its databases at this log index, while the divergent
+    /// `target` waits for it, and restores it before it applies any further logs.
+    async fn apply_resync(
+        &self,
+        source: NodeId,
+        target: NodeId,
+        stamp: &WriteStamp,
+        last_applied_log_id: Option<LogId<NodeId>>,
+    ) {
+        let log_index = last_applied_log_id.map(|id| id.index).unwrap_or(0);
+        let path = checksum::resync_path(&self.path_snapshots, log_index);
+
+        if source == self.this_node {
+            let (ack, rx) = oneshot::channel();
+            let req = WriterRequest::ResyncSource(writer::ResyncSourceRequest {
+                path: path.clone(),
+                last_applied_log_id,
+                ack,
+            });
+
+            self.write_tx
+                .send_async(req)
+                .await
+                .expect("sql writer to always be listening");
+            rx.await.expect("to always get a response from sql writer");
+
+            // in case the target never picks it up
+            task::spawn(async move {
+                time::sleep(RESYNC_TIMEOUT).await;
+                let _ = fs::remove_file(path).await;
+            });
+            return;
+        }
+
+        let age = Utc::now().timestamp_millis() - stamp.ts_millis;
+        if target == self.this_node && age < RESYNC_MAX_AGE.as_millis() as i64 {
+            warn!(
+                "Data diverged from the cluster - re-syncing from node {} at log index {}",
+                source, log_index
+            );
+            let rx = self.checksums.request_resync(log_index, source);
+            match time::timeout(RESYNC_TIMEOUT, rx).await {
+                Ok(Ok(Ok(path))) => {
+                    let (ack, rx) = oneshot::channel();
+                    self.write_tx
+                        .send_async(WriterRequest::SnapshotApply((path.clone(), ack)))
+                        .await
+                        .expect("sql writer to always be listening");
+                    rx.await.expect("to always get a response from sql writer");
+                    let _ = fs::remove_file(path).await;
+
+                    self.checksums.update_metrics(|m| m.resyncs += 1);
+                    info!("Re-sync from node {} finished", source);
+                    return;
+                }
+                Ok(Ok(Err(err))) => error!("Error during re-sync from node {}: {}", source, err),
+                Ok(Err(_)) => error!("Re-sync from node {} has been cancelled", source),
+                Err(_) => error!("Timeout during re-sync from node {}", source),
+            }
+        }
+
+        // all other nodes only need to update the last applied log id
+        let (ack, rx) = oneshot::channel();
+        self.write_tx
+            .send_async(WriterRequest::RTT(writer::RTTRequest {
+                last_applied_log_id,
+                ack,
+            }))
+            .await
+            .expect("sql writer to always be listening");
+        rx.await.expect("to always get a response from sql writer");
+    }
+
     async fn read_current_snapshot(&mut self) -> StorageResult<Option<StoredSnapshot>> {
         let mut list = tokio::fs::read_dir(&self.path_snapshots)
             .await
@@ -468,11 +973,19 @@ impl StateMachineSqlite {
         let filename_db = id.to_string();
 
         // open a DB connection to read out the metadata
-        let conn = Self::connect(db_path, filename_db, false, 2)
-            .await
-            .map_err(|err| StorageError::IO {
-                source: StorageIOError::write(&err),
-            })?;
+        let conn = Self::connect(
+            db_path,
+            filename_db,
+            false,
+            false,
+            SqlitePragmas::default(),
+            2,
+            self.sql_functions.clone(),
+        )
+        .await
+        .map_err(|err| StorageError::IO {
+            source: StorageIOError::write(&err),
+        })?;
 
         // let path_snapshot_clone = path_snapshot.clone();
         let path_dbg = path_snapshot.clone();
@@ -579,92 +1092,159 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
             + 1;
         let mut replies = Vec::with_capacity(entries_len);
 
+        // consecutive queries will be handed to the writer as one group
+        let mut group = WriteGroup::default();
+
         for entry in entries {
-            let last_applied_log_id = Some(entry.log_id);
+            if group.queries.len() >= GROUP_COMMIT_MAX_ENTRIES {
+                self.flush_group(&mut group, &mut replies).await;
+            }
 
-            // TODO if we always collect 1 in-flight req in a temp var to always have 1 req prepared
-            // before we await the rx before, we could probably improve the throughput here a bit
-            // in exchange for a more complicated logic -> test!
+            #[cfg(feature = "backup")]
+            if let Some(archive) = &self.archive {
+                archive.push(&entry);
+            }
 
-            let resp = match entry.payload {
+            let last_applied_log_id = Some(entry.log_id);
+
+            let (stamp, db, payload) = match entry.payload {
                 // TODO we probably need to update the log id in writer in case of ::Empty?
-                EntryPayload::Blank => Response::Empty,
+                EntryPayload::Blank => {
+                    group.pending.push(PendingResponse::Ready(Response::Empty));
+                    continue;
+                }
+                EntryPayload::Normal(QueryWriteEntry { stamp, db, query }) => (stamp, db, query),
+                EntryPayload::Membership(mem) => {
+                    self.flush_group(&mut group, &mut replies).await;
 
-                EntryPayload::Normal(QueryWrite::Execute(Query { sql, params })) => {
-                    let (tx, rx) = oneshot::channel();
-                    let query = writer::Query::Execute(writer::SqlExecute {
-                        sql,
-                        params,
+                    let (ack, rx) = oneshot::channel();
+                    let req = WriterRequest::MetadataMembership(writer::MetaMembershipRequest {
+                        last_membership: StoredMembership::new(Some(entry.log_id), mem),
                         last_applied_log_id,
-                        tx,
+                        ack,
                     });
 
                     self.write_tx
-                        .send_async(WriterRequest::Query(query))
+                        .send_async(req)
                         .await
                         .expect("sql writer to always be listening");
 
-                    let result = rx
-                        .await
-                        .expect("to always get a response from sql writer")
-                        .map_err(Error::from);
-                    Response::Execute(ResponseExecute { result })
+                    rx.await.expect("to always get a response from sql writer");
+
+                    replies.push(Response::Empty);
+                    continue;
                 }
+            };
 
-                EntryPayload::Normal(QueryWrite::ExecuteReturning(Query { sql, params })) => {
-                    let (tx, rx) = oneshot::channel();
-                    let query = writer::Query::ExecuteReturning(writer::SqlExecuteReturning {
-                        sql,
-                        params,
-                        last_applied_log_id,
-                        tx,
-                    });
+            if !payload.is_groupable() {
+                // all other requests cannot be grouped and must see the results of previous ones
+                self.flush_group(&mut group, &mut replies).await;
+            } else if group.db != db {
+                // a group is always committed on a single database
+                self.flush_group(&mut group, &mut replies).await;
+                group.db = db.clone();
+            }
 
-                    self.write_tx
-                        .send_async(WriterRequest::Query(query))
-                        .await
-                        .expect("sql writer to always be listening");
+            let resp = match payload {
+                QueryWrite::Execute(Query { sql, params }) => {
+                    let (tx, rx) = oneshot::channel();
+                    group.push(
+                        writer::Query::Execute(writer::SqlExecute {
+                            sql,
+                            params,
+                            stamp,
+                            last_applied_log_id,
+                            tx,
+                        }),
+                        PendingResponse::Execute(rx),
+                    );
+                    continue;
+                }
 
-                    let result = rx
-                        .await
-                        .expect("to always get a response from sql writer")
-                        .map_err(Error::from);
-                    Response::ExecuteReturning(ResponseExecuteReturning { result })
+                QueryWrite::ExecuteMulti(queries) => {
+                    let mut rxs = Vec::with_capacity(queries.len());
+                    for Query { sql, params } in queries {
+                        let (tx, rx) = oneshot::channel();
+                        group
+                            .queries
+                            .push(writer::Query::Execute(writer::SqlExecute {
+                                sql,
+                                params,
+                                stamp,
+                                last_applied_log_id,
+                                tx,
+                            }));
+                        rxs.push(rx);
+                    }
+                    group.pending.push(PendingResponse::ExecuteMulti(rxs));
+                    continue;
                 }
 
-                EntryPayload::Normal(QueryWrite::Transaction(queries)) => {
+                QueryWrite::ExecuteMany(QueryMany { sql, width, params }) => {
                     let (tx, rx) = oneshot::channel();
-                    let req = WriterRequest::Query(writer::Query::Transaction(SqlTransaction {
-                        queries,
-                        last_applied_log_id,
-                        tx,
-                    }));
+                    group.push(
+                        writer::Query::ExecuteMany(writer::SqlExecuteMany {
+                            sql,
+                            width,
+                            params,
+                            stamp,
+                            last_applied_log_id,
+                            tx,
+                        }),
+                        PendingResponse::Execute(rx),
+                    );
+                    continue;
+                }
 
-                    self.write_tx
-                        .send_async(req)
-                        .await
-                        .expect("sql writer to always be listening");
+                QueryWrite::ExecuteReturning(Query { sql, params }) => {
+                    let (tx, rx) = oneshot::channel();
+                    group.push(
+                        writer::Query::ExecuteReturning(writer::SqlExecuteReturning {
+                            sql,
+                            params,
+                            stamp,
+                            last_applied_log_id,
+                            tx,
+                        }),
+                        PendingResponse::ExecuteReturning(rx),
+                    );
+                    continue;
+                }
 
-                    let result = rx.await.expect("to always get a response from sql writer");
+                QueryWrite::Transaction(queries) => {
+                    let (tx, rx) = oneshot::channel();
+                    group.push(
+                        writer::Query::Transaction(SqlTransaction {
+                            queries,
+                            stamp,
+                            last_applied_log_id,
+                            tx,
+                        }),
+                        PendingResponse::Transaction(rx),
+                    );
+                    continue;
+                }
 
-                    let resp = match result {
-                        Ok(res) => {
-                            let mapped = res
-                                .into_iter()
-                                .map(|res| res.map_err(Error::from))
-                                .collect();
-                            Ok(mapped)
-                        }
-                        Err(err) => Err(err),
-                    };
-
-                    Response::Transaction(resp)
+                QueryWrite::TransactionGuarded(queries) => {
+                    let (tx, rx) = oneshot::channel();
+                    group.push(
+                        writer::Query::TransactionGuarded(SqlTransactionGuarded {
+                            queries,
+                            stamp,
+                            last_applied_log_id,
+                            tx,
+                        }),
+                        PendingResponse::Transaction(rx),
+                    );
+                    continue;
                 }
 
-                EntryPayload::Normal(QueryWrite::Batch(sql)) => {
+                QueryWrite::Batch(sql) => {
                     let (tx, rx) = oneshot::channel();
                     let req = WriterRequest::Query(writer::Query::Batch(SqlBatch {
+                        db,
                         sql,
+                        stamp,
                         last_applied_log_id,
                         tx,
                     }));
@@ -679,7 +1259,7 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                 }
 
                 #[cfg(feature = "backup")]
-                EntryPayload::Normal(QueryWrite::Backup(node_id)) => {
+                QueryWrite::Backup(node_id) => {
                     let (ack, rx) = oneshot::channel();
                     let req = WriterRequest::Backup(writer::BackupRequest {
                         node_id,
@@ -699,10 +1279,12 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                     Response::Backup(result)
                 }
 
-                EntryPayload::Normal(QueryWrite::Migration(migrations)) => {
+                QueryWrite::Migration(migrations) => {
                     let (tx, rx) = oneshot::channel();
                     let req = WriterRequest::Migrate(writer::Migrate {
+                        db,
                         migrations,
+                        stamp,
                         last_applied_log_id,
                         tx,
                     });
@@ -716,7 +1298,7 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                     Response::Migrate(result)
                 }
 
-                EntryPayload::Normal(QueryWrite::RTT) => {
+                QueryWrite::RTT => {
                     let (ack, rx) = oneshot::channel();
                     let req = WriterRequest::RTT(writer::RTTRequest {
                         last_applied_log_id,
@@ -732,10 +1314,9 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                     Response::RTT
                 }
 
-                EntryPayload::Membership(mem) => {
+                QueryWrite::Checksum => {
                     let (ack, rx) = oneshot::channel();
-                    let req = WriterRequest::MetadataMembership(writer::MetaMembershipRequest {
-                        last_membership: StoredMembership::new(Some(entry.log_id), mem),
+                    let req = WriterRequest::Checksum(writer::ChecksumRequest {
                         last_applied_log_id,
                         ack,
                     });
@@ -746,7 +1327,30 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                         .expect("sql writer to always be listening");
 
                     rx.await.expect("to always get a response from sql writer");
+                    Response::Empty
+                }
+
+                QueryWrite::Resync { source, target } => {
+                    self.apply_resync(source, target, &stamp, last_applied_log_id)
+                        .await;
+                    Response::Empty
+                }
+
+                #[cfg(feature = "backup")]
+                QueryWrite::ArchiveMeta(meta) => {
+                    let (ack, rx) = oneshot::channel();
+                    let req = WriterRequest::ArchiveMeta(writer::ArchiveMetaRequest {
+                        meta,
+                        last_applied_log_id,
+                        ack,
+                    });
 
+                    self.write_tx
+                        .send_async(req)
+                        .await
+                        .expect("sql writer to always be listening");
+
+                    rx.await.expect("to always get a response from sql writer");
                     Response::Empty
                 }
             };
@@ -754,6 +1358,8 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
             replies.push(resp);
         }
 
+        self.flush_group(&mut group, &mut replies).await;
+
         Ok(replies)
     }
 
@@ -773,8 +1379,9 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
     async fn begin_receiving_snapshot(&mut self) -> Result<Box<fs::File>, StorageError<NodeId>> {
         let path = format!("{}/temp", self.path_snapshots);
 
-        // clean up possible existing old data
-        let _ = fs::remove_file(&path).await;
+        // Keep the data of a possibly interrupted transfer. Chunks which are the same in the new
+        // snapshot will be taken from it instead of being sent again.
+        let _ = fs::rename(&path, format!("{}/temp.partial", self.path_snapshots)).await;
 
         match fs::File::create(path).await {
             Ok(file) => Ok(Box::new(file)),
@@ -799,6 +1406,7 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
         fs::remove_file(src).await.map_err(|err| StorageError::IO {
             source: StorageIOError::write(&err),
         })?;
+        let _ = fs::remove_file(format!("{}/temp.partial", self.path_snapshots)).await;
 
         self.update_state_machine_(tar).await?;
 
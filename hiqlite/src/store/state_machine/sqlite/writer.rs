@@ -1,6 +1,12 @@
 use crate::migration::Migration;
 use crate::query::rows::{ColumnOwned, RowOwned, ValueOwned};
 use crate::store::logs;
+use crate::store::state_machine::sqlite::checksum::{Checksums, DbChecksum};
+use crate::store::state_machine::sqlite::cost::WriteCostGuard;
+use crate::store::state_machine::sqlite::deterministic::{self, DeterministicState, WriteStamp};
+use crate::store::state_machine::sqlite::functions::SqlFunctions;
+use crate::store::state_machine::sqlite::guard::GuardedQuery;
+use crate::store::state_machine::sqlite::param::{bind_params, Param};
 use crate::store::state_machine::sqlite::state_machine;
 use crate::store::state_machine::sqlite::state_machine::{
     Params, StateMachineData, StateMachineSqlite, StoredSnapshot,
@@ -9,12 +15,15 @@ use crate::{AppliedMigration, Error, Node, NodeId};
 use chrono::Utc;
 use flume::RecvError;
 use openraft::{LogId, SnapshotMeta, StorageError, StorageIOError, StoredMembership};
-use rusqlite::backup::Progress;
+use rusqlite::backup::{Backup, Progress};
 use rusqlite::fallible_iterator::FallibleIterator;
-use rusqlite::{Batch, DatabaseName, Transaction};
+use rusqlite::{Batch, DatabaseName, OpenFlags, Transaction};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::default::Default;
+use std::io::{Read, Write};
 use std::ops::Sub;
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
@@ -25,6 +34,9 @@ use uuid::Uuid;
 #[derive(Debug)]
 pub enum WriterRequest {
     Query(Query),
+    /// Consecutive queries from committed Raft entries, which will be applied inside a single
+    /// SQLite transaction with a savepoint for each of them. Must never contain a `Query::Batch`.
+    QueryGroup(QueryGroup),
     Migrate(Migrate),
     Snapshot(SnapshotRequest),
     SnapshotApply((String, oneshot::Sender<()>)),
@@ -37,13 +49,26 @@ pub enum WriterRequest {
     Shutdown(oneshot::Sender<()>),
     #[allow(clippy::upper_case_acronyms)]
     RTT(RTTRequest),
+    Checksum(ChecksumRequest),
+    ResyncSource(ResyncSourceRequest),
+    #[cfg(feature = "backup")]
+    ArchiveMeta(ArchiveMetaRequest),
+}
+
+#[derive(Debug)]
+pub struct QueryGroup {
+    /// The named database, `None` for the main one
+    pub db: Option<String>,
+    pub queries: Vec<Query>,
 }
 
 #[derive(Debug)]
 pub enum Query {
     Execute(SqlExecute),
+    ExecuteMany(SqlExecuteMany),
     ExecuteReturning(SqlExecuteReturning),
     Transaction(SqlTransaction),
+    TransactionGuarded(SqlTransactionGuarded),
     Batch(SqlBatch),
 }
 
@@ -51,6 +76,17 @@ pub enum Query {
 pub struct SqlExecute {
     pub sql: Cow<'static, str>,
     pub params: Params,
+    pub stamp: WriteStamp,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub tx: oneshot::Sender<Result<usize, Error>>,
+}
+
+#[derive(Debug)]
+pub struct SqlExecuteMany {
+    pub sql: Cow<'static, str>,
+    pub width: usize,
+    pub params: Params,
+    pub stamp: WriteStamp,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub tx: oneshot::Sender<Result<usize, Error>>,
 }
@@ -59,6 +95,7 @@ pub struct SqlExecute {
 pub struct SqlExecuteReturning {
     pub sql: Cow<'static, str>,
     pub params: Params,
+    pub stamp: WriteStamp,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub tx: oneshot::Sender<Result<Vec<Result<RowOwned, Error>>, Error>>,
 }
@@ -66,20 +103,33 @@ pub struct SqlExecuteReturning {
 #[derive(Debug)]
 pub struct SqlTransaction {
     pub queries: Vec<state_machine::Query>,
+    pub stamp: WriteStamp,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub tx: oneshot::Sender<Result<Vec<Result<usize, Error>>, Error>>,
+}
+
+#[derive(Debug)]
+pub struct SqlTransactionGuarded {
+    pub queries: Vec<GuardedQuery>,
+    pub stamp: WriteStamp,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub tx: oneshot::Sender<Result<Vec<Result<usize, Error>>, Error>>,
 }
 
 #[derive(Debug)]
 pub struct SqlBatch {
+    pub db: Option<String>,
     pub sql: Cow<'static, str>,
+    pub stamp: WriteStamp,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub tx: oneshot::Sender<Result<Vec<Result<usize, Error>>, Error>>,
 }
 
 #[derive(Debug)]
 pub struct Migrate {
+    pub db: Option<String>,
     pub migrations: Vec<Migration>,
+    pub stamp: WriteStamp,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub tx: oneshot::Sender<Result<(), Error>>,
 }
@@ -127,6 +177,156 @@ pub struct RTTRequest {
     pub ack: oneshot::Sender<()>,
 }
 
+/// Computes the checksum of all databases at the applied log index in the background.
+#[derive(Debug)]
+pub struct ChecksumRequest {
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub ack: oneshot::Sender<()>,
+}
+
+/// Copies all databases at the applied log index into `path` in the background for a divergent
+/// node. `Checksums::resync_copy_done()` is called when the copy is complete or has failed.
+#[derive(Debug)]
+pub struct ResyncSourceRequest {
+    pub path: String,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub ack: oneshot::Sender<()>,
+}
+
+/// Writes the metadata of the Raft log archive together with the state machine metadata.
+#[cfg(feature = "backup")]
+#[derive(Debug)]
+pub struct ArchiveMetaRequest {
+    pub meta: crate::pitr::ArchiveMeta,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub ack: oneshot::Sender<()>,
+}
+
+/// The max size of a single chunk when named databases are embedded into a snapshot.
+const SNAPSHOT_DB_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// The amount of pages copied per step of the online backup when building a snapshot.
+const SNAPSHOT_BACKUP_STEP_PAGES: i32 = 1024;
+
+/// Written into the lock file while applied writes are not covered by the persisted metadata,
+/// which makes the crash recovery fall back to a full rebuild of the state machine.
+pub(crate) const LOCK_NEEDS_REBUILD: &str = "needs-rebuild";
+
+/// Changes with each boot of the Linux kernel.
+const PATH_BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// The content of the lock file: the boot id of the OS on the first line, followed by
+/// `LOCK_NEEDS_REBUILD` on the second one while the rebuild marker is set.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct LockFile {
+    /// `None` if the OS does not provide one, or for lock files of older versions
+    pub boot_id: Option<String>,
+    pub needs_rebuild: bool,
+}
+
+impl LockFile {
+    pub fn new(needs_rebuild: bool) -> Self {
+        Self {
+            boot_id: boot_id(),
+            needs_rebuild,
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let boot_id = content
+            .lines()
+            .next()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && *line != LOCK_NEEDS_REBUILD)
+            .map(String::from);
+        let needs_rebuild = content
+            .lines()
+            .any(|line| line.trim() == LOCK_NEEDS_REBUILD);
+        Self {
+            boot_id,
+            needs_rebuild,
+        }
+    }
+
+    /// If the boot id is the same as when the lock file has been written, only the process
+    /// has crashed. All writes have reached the page cache of the OS, which will still flush
+    /// them, even with `synchronous` `OFF`.
+    pub fn is_same_boot(&self) -> bool {
+        self.boot_id.is_some() && self.boot_id == boot_id()
+    }
+
+    /// MUST NOT be executed in async context!
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut content = self.boot_id.clone().unwrap_or_default();
+        if self.needs_rebuild {
+            content.push('\n');
+            content.push_str(LOCK_NEEDS_REBUILD);
+        }
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    }
+}
+
+fn boot_id() -> Option<String> {
+    std::fs::read_to_string(PATH_BOOT_ID)
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// The `_metadata` key for the log index a backup has been created at, which is the starting
+/// point for the replay of archived logs.
+pub(crate) const META_KEY_BACKUP_INDEX: &str = "backup_index";
+
+/// The main database persists its metadata inside the same transaction as the data, which
+/// makes it safe to keep after a crash. Batches, named databases and snapshot restores cannot
+/// be committed together with it and set this marker until the metadata has been persisted
+/// again.
+struct RebuildMarker {
+    path_lock_file: String,
+    is_set: bool,
+}
+
+impl RebuildMarker {
+    fn new(path_lock_file: String) -> Self {
+        let is_set = std::fs::read_to_string(&path_lock_file)
+            .map(|content| LockFile::parse(&content).needs_rebuild)
+            .unwrap_or(false);
+        Self {
+            path_lock_file,
+            is_set,
+        }
+    }
+
+    fn set(&mut self) {
+        if self.is_set {
+            return;
+        }
+        LockFile::new(true)
+            .write(&self.path_lock_file)
+            .expect("lock file to be writable");
+        self.is_set = true;
+    }
+
+    /// Must only be called after the metadata has been persisted.
+    fn clear(&mut self) {
+        if !self.is_set {
+            return;
+        }
+        if let Err(err) = LockFile::new(false).write(&self.path_lock_file) {
+            error!("Error resetting lock file: {}", err);
+        }
+        self.is_set = false;
+    }
+}
+
+/// The dedicated writer connection of a named database.
+struct NamedDb {
+    conn: rusqlite::Connection,
+    det_state: Arc<DeterministicState>,
+}
+
 // #[derive(Debug)]
 // pub struct BackupApplyRequest {
 //     pub src: String,
@@ -136,9 +336,13 @@ pub struct RTTRequest {
 #[allow(clippy::blocks_in_conditions)]
 pub fn spawn_writer(
     mut conn: rusqlite::Connection,
+    named: Vec<(String, rusqlite::Connection)>,
     this_node: NodeId,
     path_lock_file: String,
     log_statements: bool,
+    sql_functions: SqlFunctions,
+    cost: Arc<WriteCostGuard>,
+    checksums: Arc<Checksums>,
 ) -> flume::Sender<WriterRequest> {
     let (tx, rx) = flume::bounded::<WriterRequest>(2);
 
@@ -146,6 +350,7 @@ pub fn spawn_writer(
     task::spawn_blocking(move || {
         let mut sm_data = StateMachineData::default();
         let mut ts_last_backup = None;
+        let mut marker = RebuildMarker::new(path_lock_file.clone());
 
         // TODO should we maybe save a backup task handle in case of shutdown overlap?
 
@@ -163,249 +368,157 @@ CREATE TABLE IF NOT EXISTS _metadata
         )
         .expect("_metadata table creation to always succeed");
 
-        'main: while let Ok(req) = rx.recv() {
-            match req {
-                WriterRequest::Query(query) => match query {
-                    Query::Execute(q) => {
-                        sm_data.last_applied_log_id = q.last_applied_log_id;
-
-                        if log_statements {
-                            info!("Query::Execute:\n{}\n{:?}", q.sql, q.params);
-                        }
-
-                        let res = {
-                            let mut stmt = match conn.prepare_cached(q.sql.as_ref()) {
-                                Ok(stmt) => stmt,
-                                Err(err) => {
-                                    error!("Preparing cached query {}: {:?}", q.sql, err);
-                                    q.tx.send(Err(Error::PrepareStatement(err.to_string().into())))
-                                        .expect("oneshot tx to never be dropped");
-                                    continue;
-                                }
-                            };
-
-                            // let params_len = q.params.len();
-                            let mut params_err = None;
-                            let mut idx = 1;
-                            for param in q.params {
-                                if let Err(err) = stmt.raw_bind_parameter(idx, param.into_sql()) {
-                                    error!(
-                                        "Error binding param on position {} to query {}: {:?}",
-                                        idx, q.sql, err
-                                    );
-                                    params_err = Some(Error::QueryParams(err.to_string().into()));
-                                    break;
-                                }
+        let det_state = deterministic::register(&conn)
+            .expect("deterministic SQL functions registration to always succeed");
+        cost.register(&conn);
+
+        let mut named = named
+            .into_iter()
+            .map(|(name, conn)| {
+                let det_state = deterministic::register(&conn)
+                    .expect("deterministic SQL functions registration to always succeed");
+                cost.register(&conn);
+                (name, NamedDb { conn, det_state })
+            })
+            .collect::<HashMap<_, _>>();
 
-                                idx += 1;
-                            }
+        // checks all tables after opening, see `optimize_local()`
+        if !cost.is_enabled() {
+            for conn in std::iter::once(&conn).chain(named.values().map(|db| &db.conn)) {
+                if let Err(err) = conn.execute("PRAGMA optimize=0x10002", []) {
+                    error!("Error during 'PRAGMA optimize': {}", err);
+                }
+            }
+        }
 
-                            if let Some(err) = params_err {
-                                q.tx.send(Err(err)).expect("oneshot tx to never be dropped");
+        'main: while let Ok(req) = rx.recv() {
+            match req {
+                WriterRequest::Query(Query::Batch(req)) => {
+                    sm_data.last_applied_log_id = req.last_applied_log_id;
+                    let (conn, det_state) =
+                        match target(&mut conn, &det_state, &mut named, req.db.as_deref()) {
+                            Ok(target) => target,
+                            Err(err) => {
+                                req.tx
+                                    .send(Err(err))
+                                    .expect("oneshot tx to never be dropped");
                                 continue;
                             }
-
-                            stmt.raw_execute().map_err(Error::from)
                         };
+                    det_state.apply(&req.stamp);
 
-                        q.tx.send(res).expect("oneshot tx to never be dropped");
+                    if log_statements {
+                        info!("Query::Batch:\n{}", req.sql);
                     }
 
-                    Query::ExecuteReturning(q) => {
-                        sm_data.last_applied_log_id = q.last_applied_log_id;
+                    // statements in a batch are committed one by one
+                    marker.set();
 
-                        if log_statements {
-                            info!("Query::ExecuteReturning:\n{}\n{:?}", q.sql, q.params);
-                        }
+                    let mut batch = Batch::new(conn, req.sql.as_ref());
+                    // we can at least assume 2 statements in a batch execute
+                    let mut res = Vec::with_capacity(2);
 
-                        let res = {
-                            let mut stmt = match conn.prepare_cached(q.sql.as_ref()) {
-                                Ok(stmt) => stmt,
-                                Err(err) => {
-                                    error!("Preparing cached query {}: {:?}", q.sql, err);
-                                    q.tx.send(Err(Error::PrepareStatement(err.to_string().into())))
-                                        .expect("oneshot tx to never be dropped");
-                                    continue;
-                                }
-                            };
-
-                            let columns = match ColumnOwned::mapping_cols_from_stmt(stmt.columns())
-                            {
-                                Ok(c) => c,
-                                Err(err) => {
-                                    q.tx.send(Err(Error::PrepareStatement(err.to_string().into())))
-                                        .expect("oneshot tx to never be dropped");
+                    let mut err = None;
+
+                    // The whole batch shares a single budget. Statements are committed one by
+                    // one, so all statements after the one exceeding it will be skipped.
+                    cost.start();
+                    let mut exhausted = false;
+                    loop {
+                        match batch.next() {
+                            Ok(Some(mut stmt)) => {
+                                if exhausted {
+                                    res.push(Err(cost.err_limit()));
                                     continue;
                                 }
-                            };
-
-                            // let params_len = q.params.len();
-                            let mut params_err = None;
-                            let mut idx = 1;
-                            for param in q.params {
-                                if let Err(err) = stmt.raw_bind_parameter(idx, param.into_sql()) {
-                                    error!(
-                                        "Error binding param on position {} to query {}: {:?}",
-                                        idx, q.sql, err
-                                    );
-                                    params_err = Some(Error::QueryParams(err.to_string().into()));
-                                    break;
-                                }
-
-                                idx += 1;
-                            }
-
-                            if let Some(err) = params_err {
-                                q.tx.send(Err(err)).expect("oneshot tx to never be dropped");
-                                continue;
-                            }
-
-                            let mut rows = stmt.raw_query();
-                            let mut res = Vec::new();
-                            loop {
-                                match rows.next() {
-                                    Ok(Some(row)) => {
-                                        res.push(Ok(RowOwned::from_row_column(row, &columns)));
-                                    }
-                                    Ok(None) => {
-                                        break;
+                                let r = stmt.execute([]).map_err(Error::from);
+                                match (cost.count(&stmt), r) {
+                                    // interrupted by the progress handler and rolled back
+                                    (Err(err), Err(_)) => {
+                                        exhausted = true;
+                                        res.push(Err(err));
                                     }
-                                    Err(err) => {
-                                        res.push(Err(Error::Sqlite(err.to_string().into())));
+                                    // already committed
+                                    (Err(_), Ok(rows)) => {
+                                        exhausted = true;
+                                        res.push(Ok(rows));
                                     }
+                                    (Ok(()), r) => res.push(r),
                                 }
                             }
-
-                            Ok(res)
-                        };
-
-                        q.tx.send(res).expect("oneshot tx to never be dropped");
-                    }
-
-                    Query::Transaction(req) => {
-                        sm_data.last_applied_log_id = req.last_applied_log_id;
-
-                        let txn = match conn.transaction() {
-                            Ok(txn) => txn,
-                            Err(err) => {
-                                error!("Opening database transaction: {:?}", err);
-                                req.tx
-                                    .send(Err(Error::Transaction(err.to_string().into())))
-                                    .expect("oneshot tx to never be dropped");
-                                continue;
-                            }
-                        };
-
-                        let mut results = Vec::with_capacity(req.queries.len());
-                        let mut query_err = None;
-
-                        'outer: for state_machine::Query { sql, params } in req.queries {
-                            if log_statements {
-                                info!("Query::Transaction:\n{}\n{:?}", sql, params);
-                            }
-
-                            let mut stmt = match txn.prepare_cached(sql.as_ref()) {
-                                Ok(stmt) => stmt,
-                                Err(err) => {
-                                    let err = format!("Preparing cached query {}: {:?}", sql, err);
-                                    query_err =
-                                        Some(Error::PrepareStatement(err.to_string().into()));
-                                    break;
-                                }
-                            };
-
-                            let mut idx = 1;
-                            for param in params {
-                                if let Err(err) = stmt.raw_bind_parameter(idx, param.into_sql()) {
-                                    let err = format!(
-                                        "Error binding param on position {} to query {}: {:?}",
-                                        idx, sql, err
-                                    );
-                                    query_err = Some(Error::QueryParams(err.to_string().into()));
-                                    break 'outer;
-                                }
-
-                                idx += 1;
-                            }
-
-                            let res = stmt.raw_execute().map_err(Error::from);
-                            match res {
-                                Ok(r) => results.push(Ok(r)),
-                                Err(err) => {
-                                    query_err = Some(Error::Transaction(err.to_string().into()));
-                                    break;
-                                }
-                            }
-                        }
-
-                        if let Some(err) = query_err {
-                            if let Err(e) = txn.rollback() {
-                                error!("Error during txn rollback: {:?}", e);
-                            }
-                            req.tx
-                                .send(Err(err))
-                                .expect("oneshot tx to never be dropped");
-                        } else {
-                            match txn.commit() {
-                                Ok(()) => {
-                                    req.tx
-                                        .send(Ok(results))
-                                        .expect("oneshot tx to never be dropped");
-                                }
-                                Err(err) => {
-                                    req.tx
-                                        .send(Err(Error::Transaction(err.to_string().into())))
-                                        .expect("oneshot tx to never be dropped");
-                                }
+                            Ok(None) => break,
+                            Err(e) => {
+                                err = Some(Error::Sqlite(e.to_string().into()));
+                                break;
                             }
                         }
                     }
+                    // only updates the metrics, the errors are already part of the results
+                    let _ = cost.finish(req.sql.as_ref());
 
-                    Query::Batch(req) => {
-                        sm_data.last_applied_log_id = req.last_applied_log_id;
-
-                        if log_statements {
-                            info!("Query::Batch:\n{}", req.sql);
-                        }
-
-                        let mut batch = Batch::new(&conn, req.sql.as_ref());
-                        // we can at least assume 2 statements in a batch execute
-                        let mut res = Vec::with_capacity(2);
+                    if let Some(err) = err {
+                        req.tx
+                            .send(Err(err))
+                            .expect("oneshot tx to never be dropped");
+                    } else {
+                        req.tx
+                            .send(Ok(res))
+                            .expect("oneshot tx to never be dropped");
+                    }
+                }
 
-                        let mut err = None;
+                WriterRequest::Query(_) => {
+                    unreachable!("single queries are always sent inside a QueryGroup")
+                }
 
-                        loop {
-                            match batch.next() {
-                                Ok(Some(mut stmt)) => {
-                                    res.push(stmt.execute([]).map_err(Error::from));
-                                }
-                                Ok(None) => break,
-                                Err(e) => {
-                                    err = Some(Error::Sqlite(e.to_string().into()));
-                                    break;
-                                }
+                WriterRequest::QueryGroup(QueryGroup { db, queries }) => {
+                    let is_main = db.is_none();
+                    match target(&mut conn, &det_state, &mut named, db.as_deref()) {
+                        Ok((conn, det_state)) => {
+                            if !is_main {
+                                marker.set();
+                            }
+                            let persisted = apply_group(
+                                conn,
+                                queries,
+                                &mut sm_data,
+                                det_state,
+                                &cost,
+                                log_statements,
+                                is_main,
+                            );
+                            if persisted {
+                                marker.clear();
                             }
                         }
-
-                        if let Some(err) = err {
-                            req.tx
-                                .send(Err(err))
-                                .expect("oneshot tx to never be dropped");
-                        } else {
-                            req.tx
-                                .send(Ok(res))
-                                .expect("oneshot tx to never be dropped");
+                        Err(_) => {
+                            for query in queries {
+                                sm_data.last_applied_log_id = query.last_applied_log_id();
+                                Reply::from_err(&query, err_unknown_db(db.as_deref())).send(query);
+                            }
                         }
                     }
-                },
+                }
 
                 WriterRequest::Migrate(req) => {
                     sm_data.last_applied_log_id = req.last_applied_log_id;
+                    let (conn, det_state) =
+                        match target(&mut conn, &det_state, &mut named, req.db.as_deref()) {
+                            Ok(target) => target,
+                            Err(err) => {
+                                req.tx.send(Err(err)).unwrap();
+                                continue;
+                            }
+                        };
+                    det_state.apply(&req.stamp);
 
                     // TODO should be maybe always panic if migrations throw an error?
-                    let res = migrate(&mut conn, req.migrations).map_err(Error::from);
+                    let res = migrate(conn, req.migrations).map_err(Error::from);
 
-                    if let Err(err) = conn.execute("PRAGMA optimize", []) {
+                    // Inside a log entry, which makes the statistics the same on all nodes. All
+                    // tables are checked, because the tables used by this connection so far
+                    // are node-local.
+                    if let Err(err) = conn.execute("PRAGMA optimize=0x10002", []) {
                         error!("Error during 'PRAGMA optimize': {}", err);
                     }
 
@@ -420,48 +533,48 @@ CREATE TABLE IF NOT EXISTS _metadata
                 }) => {
                     sm_data.last_snapshot_id = Some(snapshot_id.to_string());
                     persist_metadata(&conn, &sm_data).expect("Metadata persist to never fail");
-
-                    match create_snapshot(
-                        &conn,
-                        // snapshot_id,
-                        path,
-                        // sm_data.last_applied_log_id,
-                        // sm_data.last_membership.clone(),
-                    ) {
-                        Ok(_) => {
-                            if let Err(err) = conn.execute("PRAGMA optimize", []) {
-                                error!("Error during 'PRAGMA optimize': {}", err);
-                            }
-
-                            ack.send(Ok(SnapshotResponse {
-                                meta: sm_data.clone(),
-                            }))
+                    marker.clear();
+                    let meta = sm_data.clone();
+
+                    match SnapshotSource::begin(&conn, &named) {
+                        // The copy runs on its own read transactions, which pin the current
+                        // state, while the writer can go on applying logs in the meantime.
+                        Ok(Some(source)) => {
+                            thread::spawn(move || {
+                                let res = source.copy_into(&path);
+                                send_snapshot_response(ack, meta, res);
+                            });
                         }
-                        Err(err) => {
-                            error!("Error creating new snapshot: {:?}", err);
-                            ack.send(Err(StorageError::IO {
-                                source: StorageIOError::write(&err),
-                            }))
+                        // in-memory DBs can't use WAL, where an open read transaction would
+                        // block the writer
+                        Ok(None) => {
+                            let res = create_snapshot(&conn, &named, path);
+                            send_snapshot_response(ack, meta, res);
                         }
+                        Err(err) => send_snapshot_response(ack, meta, Err(err)),
                     }
-                    .expect("snapshot listener to always exists");
+
+                    optimize_local(&conn, &cost);
                 }
 
                 WriterRequest::SnapshotApply((path, ack)) => {
                     let start = Instant::now();
                     info!("Starting snapshot restore from {}", path);
-                    conn.restore(
-                        DatabaseName::Main,
-                        path,
+                    // the main and named databases are restored one after another
+                    marker.set();
+                    restore_db(
+                        &mut conn,
+                        &path,
                         Some(|p: Progress| {
                             println!("Database restore remaining: {}", p.remaining);
                         }),
                     )
                     .expect("SnapshotApply to always succeed in sql writer");
 
-                    if let Err(err) = conn.execute("PRAGMA optimize", []) {
-                        error!("Error during 'PRAGMA optimize': {}", err);
-                    }
+                    restore_named_dbs(&conn, &mut named, &path)
+                        .expect("SnapshotApply for named databases to always succeed");
+
+                    optimize_local(&conn, &cost);
 
                     info!(
                         "Snapshot restore finished after {} ms",
@@ -476,6 +589,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                             Ok(metadata)
                         })
                         .expect("Metadata query to always succeed");
+                    marker.clear();
 
                     ack.send(()).unwrap()
                 }
@@ -541,7 +655,9 @@ CREATE TABLE IF NOT EXISTS _metadata
 
                     if let Err(err) = create_backup(
                         &conn,
+                        &sql_functions,
                         req.node_id,
+                        req.last_applied_log_id,
                         req.target_folder.clone(),
                         #[cfg(feature = "s3")]
                         s3_config,
@@ -560,9 +676,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                         }
                     });
 
-                    if let Err(err) = conn.execute("PRAGMA optimize", []) {
-                        error!("Error during 'PRAGMA optimize': {}", err);
-                    }
+                    optimize_local(&conn, &cost);
 
                     ts_last_backup = Some(now);
                     req.ack.send(Ok(()));
@@ -573,6 +687,90 @@ CREATE TABLE IF NOT EXISTS _metadata
                     req.ack.send(()).unwrap();
                 }
 
+                #[cfg(feature = "backup")]
+                WriterRequest::ArchiveMeta(req) => {
+                    sm_data.last_applied_log_id = req.last_applied_log_id;
+                    let res = conn.transaction().map_err(Error::from).and_then(|txn| {
+                        req.meta.apply(&txn)?;
+                        persist_metadata(&txn, &sm_data)?;
+                        txn.commit()?;
+                        Ok(())
+                    });
+                    match res {
+                        Ok(_) => marker.clear(),
+                        Err(err) => error!("Error writing Raft log archive metadata: {}", err),
+                    }
+                    req.ack.send(()).unwrap();
+                }
+
+                WriterRequest::Checksum(req) => {
+                    sm_data.last_applied_log_id = req.last_applied_log_id;
+                    let log_index = req.last_applied_log_id.map(|id| id.index).unwrap_or(0);
+
+                    match SnapshotSource::begin(&conn, &named) {
+                        Ok(Some(source)) => {
+                            let checksums = checksums.clone();
+                            let stats = cost.is_enabled();
+                            thread::spawn(move || {
+                                let res = source.checksum(log_index, stats);
+                                store_checksum(&checksums, log_index, res);
+                            });
+                        }
+                        Ok(None) => {
+                            let named = named
+                                .iter()
+                                .map(|(name, db)| (name.as_str(), &db.conn))
+                                .collect::<Vec<_>>();
+                            let res =
+                                DbChecksum::compute(log_index, &conn, &named, cost.is_enabled());
+                            store_checksum(&checksums, log_index, res);
+                        }
+                        Err(err) => error!("Error opening databases for checksum: {}", err),
+                    }
+
+                    req.ack.send(()).unwrap();
+                }
+
+                WriterRequest::ResyncSource(req) => {
+                    sm_data.last_applied_log_id = req.last_applied_log_id;
+                    let log_index = req.last_applied_log_id.map(|id| id.index).unwrap_or(0);
+                    // the copy must contain the metadata for this exact log index
+                    persist_metadata(&conn, &sm_data).expect("Metadata persist to never fail");
+                    marker.clear();
+
+                    info!("Creating database copy for re-sync into {}", req.path);
+                    match SnapshotSource::begin(&conn, &named) {
+                        Ok(Some(source)) => {
+                            let checksums = checksums.clone();
+                            let path = req.path.clone();
+                            thread::spawn(move || {
+                                let res = source.copy_into(&path);
+                                if let Err(err) = &res {
+                                    error!("Error creating database copy for re-sync: {}", err);
+                                }
+                                checksums.resync_copy_done(log_index, res.is_ok());
+                            });
+                        }
+                        Ok(None) => {
+                            let path_build = format!("{}.build", req.path);
+                            let res =
+                                create_snapshot(&conn, &named, path_build.clone()).and_then(|_| {
+                                    std::fs::rename(&path_build, &req.path).map_err(Error::from)
+                                });
+                            if let Err(err) = &res {
+                                error!("Error creating database copy for re-sync: {}", err);
+                            }
+                            checksums.resync_copy_done(log_index, res.is_ok());
+                        }
+                        Err(err) => {
+                            error!("Error opening databases for re-sync: {}", err);
+                            checksums.resync_copy_done(log_index, false);
+                        }
+                    }
+
+                    req.ack.send(()).unwrap();
+                }
+
                 WriterRequest::Shutdown(ack) => {
                     let _ = ack.send(());
                     break;
@@ -584,10 +782,12 @@ CREATE TABLE IF NOT EXISTS _metadata
 
         // make sure metadata is persisted before shutting down
         persist_metadata(&conn, &sm_data).expect("Error persisting metadata");
+        marker.clear();
 
-        if let Err(err) = conn.execute("PRAGMA optimize", []) {
-            error!("Error during 'PRAGMA optimize': {}", err);
+        for db in named.values() {
+            optimize_local(&db.conn, &cost);
         }
+        optimize_local(&conn, &cost);
 
         StateMachineSqlite::remove_lock_file(&path_lock_file);
     });
@@ -595,33 +795,768 @@ CREATE TABLE IF NOT EXISTS _metadata
     tx
 }
 
+/// Resolves the writer connection and deterministic state for the given named database, or the
+/// main one for `None`.
+fn target<'a>(
+    conn: &'a mut rusqlite::Connection,
+    det_state: &'a DeterministicState,
+    named: &'a mut HashMap<String, NamedDb>,
+    db: Option<&str>,
+) -> Result<(&'a mut rusqlite::Connection, &'a DeterministicState), Error> {
+    match db {
+        None => Ok((conn, det_state)),
+        Some(name) => named
+            .get_mut(name)
+            .map(|db| (&mut db.conn, db.det_state.as_ref()))
+            .ok_or_else(|| err_unknown_db(db)),
+    }
+}
+
 #[inline]
-fn persist_metadata(
+fn err_unknown_db(db: Option<&str>) -> Error {
+    Error::BadRequest(format!("database '{}' does not exist", db.unwrap_or("main")).into())
+}
+
+impl Query {
+    #[inline]
+    fn stamp(&self) -> &WriteStamp {
+        match self {
+            Query::Execute(q) => &q.stamp,
+            Query::ExecuteMany(q) => &q.stamp,
+            Query::ExecuteReturning(q) => &q.stamp,
+            Query::Transaction(q) => &q.stamp,
+            Query::TransactionGuarded(q) => &q.stamp,
+            Query::Batch(q) => &q.stamp,
+        }
+    }
+
+    /// The SQL of the query for logging. Transactions return their first statement.
+    fn sql_hint(&self) -> &str {
+        match self {
+            Query::Execute(q) => &q.sql,
+            Query::ExecuteMany(q) => &q.sql,
+            Query::ExecuteReturning(q) => &q.sql,
+            Query::Transaction(q) => q.queries.first().map(|q| q.sql.as_ref()).unwrap_or(""),
+            Query::TransactionGuarded(q) => q
+                .queries
+                .first()
+                .map(|q| q.query.sql.as_ref())
+                .unwrap_or(""),
+            Query::Batch(q) => &q.sql,
+        }
+    }
+
+    #[inline]
+    fn last_applied_log_id(&self) -> Option<LogId<NodeId>> {
+        match self {
+            Query::Execute(q) => q.last_applied_log_id,
+            Query::ExecuteMany(q) => q.last_applied_log_id,
+            Query::ExecuteReturning(q) => q.last_applied_log_id,
+            Query::Transaction(q) => q.last_applied_log_id,
+            Query::TransactionGuarded(q) => q.last_applied_log_id,
+            Query::Batch(q) => q.last_applied_log_id,
+        }
+    }
+}
+
+type ResultTxn = Result<Vec<Result<usize, Error>>, Error>;
+type ResultReturning = Result<Vec<Result<RowOwned, Error>>, Error>;
+
+/// The result of a query inside a group. It will only be sent back after the whole group has
+/// been committed, so that a finished write is always visible to the read pool.
+enum Reply {
+    Execute(Result<usize, Error>),
+    ExecuteReturning(ResultReturning),
+    Transaction(ResultTxn),
+}
+
+impl Reply {
+    fn from_err(query: &Query, err: Error) -> Self {
+        match query {
+            Query::Execute(_) | Query::ExecuteMany(_) => Self::Execute(Err(err)),
+            Query::ExecuteReturning(_) => Self::ExecuteReturning(Err(err)),
+            Query::Transaction(_) | Query::TransactionGuarded(_) => Self::Transaction(Err(err)),
+            Query::Batch(_) => unreachable!("Query::Batch is never applied inside a group"),
+        }
+    }
+
+    #[inline]
+    fn is_err(&self) -> bool {
+        match self {
+            Self::Execute(res) => res.is_err(),
+            Self::ExecuteReturning(res) => res.is_err(),
+            Self::Transaction(res) => res.is_err(),
+        }
+    }
+
+    #[inline]
+    fn set_err(&mut self, err: Error) {
+        match self {
+            Self::Execute(res) => *res = Err(err),
+            Self::ExecuteReturning(res) => *res = Err(err),
+            Self::Transaction(res) => *res = Err(err),
+        }
+    }
+
+    /// Sends the result back to the caller of the `query` it belongs to.
+    #[inline]
+    fn send(self, query: Query) {
+        let sent = match (query, self) {
+            (Query::Execute(q), Self::Execute(res)) => q.tx.send(res).is_ok(),
+            (Query::ExecuteMany(q), Self::Execute(res)) => q.tx.send(res).is_ok(),
+            (Query::ExecuteReturning(q), Self::ExecuteReturning(res)) => q.tx.send(res).is_ok(),
+            (Query::Transaction(q), Self::Transaction(res)) => q.tx.send(res).is_ok(),
+            (Query::TransactionGuarded(q), Self::Transaction(res)) => q.tx.send(res).is_ok(),
+            _ => unreachable!("a reply to always match the type of its query"),
+        };
+        assert!(sent, "oneshot tx to never be dropped");
+    }
+}
+
+/// Applies all queries inside a single SQLite transaction. Each query gets its own savepoint,
+/// which will be rolled back on error without affecting the others. This gives each query the
+/// same result it would have had with its own transaction, while paying the commit only once.
+///
+/// With `persist_meta`, the metadata is committed inside the same transaction. Returns `true`
+/// if this has been successful.
+fn apply_group(
+    conn: &mut rusqlite::Connection,
+    queries: Vec<Query>,
+    sm_data: &mut StateMachineData,
+    det_state: &DeterministicState,
+    cost: &WriteCostGuard,
+    log_statements: bool,
+    persist_meta: bool,
+) -> bool {
+    let mut txn = match conn.transaction() {
+        Ok(txn) => txn,
+        Err(err) => {
+            error!("Opening database transaction: {:?}", err);
+            for query in queries {
+                Reply::from_err(&query, Error::Transaction(err.to_string().into())).send(query);
+            }
+            return false;
+        }
+    };
+
+    // The queries are kept until the end to send the replies, which means they can be applied
+    // again without a copy.
+    let mut replies: Vec<Reply> = Vec::with_capacity(queries.len());
+    for (idx, query) in queries.iter().enumerate() {
+        sm_data.last_applied_log_id = query.last_applied_log_id();
+        det_state.apply(query.stamp());
+
+        let mut sp = match txn.savepoint() {
+            Ok(sp) => sp,
+            Err(err) => {
+                error!("Creating savepoint: {:?}", err);
+                replies.push(Reply::from_err(
+                    query,
+                    Error::Transaction(err.to_string().into()),
+                ));
+                continue;
+            }
+        };
+
+        cost.start();
+        let mut reply = apply_query(&sp, query, cost, log_statements);
+        if let Some(err) = cost.finish(query.sql_hint()) {
+            reply.set_err(err);
+            // When the progress handler aborts a write, SQLite rolls back the whole transaction.
+            // Groups differ between nodes, so all queries applied before must be replayed.
+            if sp.is_autocommit() {
+                drop(sp);
+                drop(txn);
+                let applied = queries[..idx]
+                    .iter()
+                    .zip(&replies)
+                    .filter(|(_, reply)| !reply.is_err())
+                    .map(|(query, _)| query);
+                txn = replay_group(conn, applied, det_state, cost)
+                    .expect("replaying a write group to always succeed");
+                replies.push(reply);
+                continue;
+            }
+        }
+
+        if reply.is_err() {
+            if let Err(err) = sp.rollback() {
+                error!("Error during savepoint rollback: {:?}", err);
+            }
+        }
+        // releases the savepoint, which keeps its changes after a successful query
+        if let Err(err) = sp.commit() {
+            error!("Error releasing savepoint: {:?}", err);
+            reply.set_err(Error::Transaction(err.to_string().into()));
+        }
+        replies.push(reply);
+    }
+
+    if persist_meta {
+        persist_metadata(&txn, sm_data).expect("Metadata persist to never fail");
+    }
+
+    let committed = match txn.commit() {
+        Ok(_) => true,
+        Err(err) => {
+            error!("Error committing write group: {:?}", err);
+            for reply in replies.iter_mut() {
+                reply.set_err(Error::Transaction(err.to_string().into()));
+            }
+            false
+        }
+    };
+
+    for (query, reply) in queries.into_iter().zip(replies) {
+        reply.send(query);
+    }
+
+    persist_meta && committed
+}
+
+/// Opens a new transaction and applies the given queries again, each with its own savepoint.
+/// Their replies have not been sent yet and will be the same as for the first apply.
+fn replay_group<'a, 'q>(
+    conn: &'a mut rusqlite::Connection,
+    applied: impl Iterator<Item = &'q Query>,
+    det_state: &DeterministicState,
+    cost: &WriteCostGuard,
+) -> Result<Transaction<'a>, rusqlite::Error> {
+    let mut txn = conn.transaction()?;
+    let mut replayed = 0;
+    for query in applied {
+        det_state.apply(query.stamp());
+
+        let mut sp = txn.savepoint()?;
+        if apply_query(&sp, query, cost, false).is_err() {
+            sp.rollback()?;
+        }
+        sp.commit()?;
+        replayed += 1;
+    }
+
+    warn!("Replayed {} writes after an aborted write", replayed);
+    Ok(txn)
+}
+
+fn apply_query(
+    conn: &rusqlite::Connection,
+    query: &Query,
+    cost: &WriteCostGuard,
+    log_statements: bool,
+) -> Reply {
+    match query {
+        Query::Execute(q) => {
+            if log_statements {
+                info!("Query::Execute:\n{}\n{:?}", q.sql, q.params);
+            }
+            Reply::Execute(execute(conn, &q.sql, &q.params, cost))
+        }
+        Query::ExecuteMany(q) => {
+            if log_statements {
+                info!(
+                    "Query::ExecuteMany:\n{}\n{} params with width {}",
+                    q.sql,
+                    q.params.len(),
+                    q.width
+                );
+            }
+            Reply::Execute(execute_many(conn, &q.sql, q.width, &q.params, cost))
+        }
+        Query::ExecuteReturning(q) => {
+            if log_statements {
+                info!("Query::ExecuteReturning:\n{}\n{:?}", q.sql, q.params);
+            }
+            Reply::ExecuteReturning(execute_returning(conn, &q.sql, &q.params, cost))
+        }
+        Query::Transaction(req) => {
+            Reply::Transaction(execute_txn(conn, &req.queries, cost, log_statements))
+        }
+        Query::TransactionGuarded(req) => Reply::Transaction(execute_txn_guarded(
+            conn,
+            &req.queries,
+            cost,
+            log_statements,
+        )),
+        Query::Batch(_) => unreachable!("Query::Batch is never applied inside a group"),
+    }
+}
+
+fn execute(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[Param],
+    cost: &WriteCostGuard,
+) -> Result<usize, Error> {
+    let mut stmt = conn.prepare_cached(sql).map_err(|err| {
+        error!("Preparing cached query {}: {:?}", sql, err);
+        Error::PrepareStatement(err.to_string().into())
+    })?;
+
+    if let Err(err) = bind_params(&mut stmt, params) {
+        error!("Error binding params to query {}: {:?}", sql, err);
+        return Err(err);
+    }
+
+    let res = stmt.raw_execute().map_err(Error::from);
+    cost.count(&stmt)?;
+    res
+}
+
+/// Executes the statement once for each row of params and returns the sum of all affected
+/// rows. Stops at the first error. The caller is responsible for the rollback.
+fn execute_many(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    width: usize,
+    params: &[Param],
+    cost: &WriteCostGuard,
+) -> Result<usize, Error> {
+    if width == 0 || params.len() % width != 0 {
+        return Err(Error::QueryParams(
+            format!(
+                "cannot split {} params into rows of width {}",
+                params.len(),
+                width
+            )
+            .into(),
+        ));
+    }
+
+    let mut stmt = conn.prepare_cached(sql).map_err(|err| {
+        error!("Preparing cached query {}: {:?}", sql, err);
+        Error::PrepareStatement(err.to_string().into())
+    })?;
+
+    let mut rows_affected = 0;
+    for row in params.chunks(width) {
+        bind_params(&mut stmt, row)?;
+        let res = stmt.raw_execute();
+        // each row only takes a few steps, but there may be a lot of them
+        cost.count(&stmt)?;
+        rows_affected += res?;
+    }
+
+    Ok(rows_affected)
+}
+
+fn execute_returning(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[Param],
+    cost: &WriteCostGuard,
+) -> ResultReturning {
+    let mut stmt = conn.prepare_cached(sql).map_err(|err| {
+        error!("Preparing cached query {}: {:?}", sql, err);
+        Error::PrepareStatement(err.to_string().into())
+    })?;
+
+    let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())
+        .map_err(|err| Error::PrepareStatement(err.to_string().into()))?;
+
+    if let Err(err) = bind_params(&mut stmt, params) {
+        error!("Error binding params to query {}: {:?}", sql, err);
+        return Err(err);
+    }
+
+    let mut rows = stmt.raw_query();
+    let mut res = Vec::new();
+    loop {
+        match rows.next() {
+            Ok(Some(row)) => {
+                res.push(Ok(RowOwned::from_row_column(row, &columns)));
+            }
+            Ok(None) => {
+                break;
+            }
+            Err(err) => {
+                res.push(Err(Error::Sqlite(err.to_string().into())));
+            }
+        }
+    }
+    drop(rows);
+    cost.count(&stmt)?;
+
+    Ok(res)
+}
+
+/// Executes all queries and stops at the first error. The caller is responsible for the
+/// rollback.
+fn execute_txn(
+    conn: &rusqlite::Connection,
+    queries: &[state_machine::Query],
+    cost: &WriteCostGuard,
+    log_statements: bool,
+) -> ResultTxn {
+    let mut results = Vec::with_capacity(queries.len());
+
+    for state_machine::Query { sql, params } in queries {
+        if log_statements {
+            info!("Query::Transaction:\n{}\n{:?}", sql, params);
+        }
+        results.push(Ok(execute_txn_query(conn, sql, params, cost)?));
+    }
+
+    Ok(results)
+}
+
+/// Checks all guards and executes all queries. Stops at the first error or failed guard. The
+/// caller is responsible for the rollback.
+fn execute_txn_guarded(
+    conn: &rusqlite::Connection,
+    queries: &[GuardedQuery],
+    cost: &WriteCostGuard,
+    log_statements: bool,
+) -> ResultTxn {
+    let mut results = Vec::with_capacity(queries.len());
+    let mut guard_idx = 0;
+
+    for GuardedQuery { guards, query } in queries {
+        for guard in guards {
+            if log_statements {
+                info!(
+                    "Query::TransactionGuarded guard {}:\n{}\n{:?}\n{:?}",
+                    guard_idx, guard.sql, guard.params, guard.expect
+                );
+            }
+
+            guard.check(conn, guard_idx, cost)?;
+            guard_idx += 1;
+        }
+
+        let state_machine::Query { sql, params } = query;
+        if log_statements {
+            info!("Query::TransactionGuarded:\n{}\n{:?}", sql, params);
+        }
+        results.push(Ok(execute_txn_query(conn, sql, params, cost)?));
+    }
+
+    Ok(results)
+}
+
+#[inline]
+fn execute_txn_query(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &[Param],
+    cost: &WriteCostGuard,
+) -> Result<usize, Error> {
+    let mut stmt = conn.prepare_cached(sql).map_err(|err| {
+        let err = format!("Preparing cached query {}: {:?}", sql, err);
+        Error::PrepareStatement(err.into())
+    })?;
+
+    if let Err(err) = bind_params(&mut stmt, params) {
+        let err = format!("Error binding params to query {}: {}", sql, err);
+        return Err(Error::QueryParams(err.into()));
+    }
+
+    let res = stmt
+        .raw_execute()
+        .map_err(|err| Error::Transaction(err.to_string().into()));
+    cost.count(&stmt)?;
+    res
+}
+
+#[inline]
+pub(crate) fn persist_metadata(
     conn: &rusqlite::Connection,
     metadata: &StateMachineData,
 ) -> Result<(), rusqlite::Error> {
     let meta_bytes = bincode::serialize(metadata).unwrap();
-    let mut stmt = conn.prepare("REPLACE INTO _metadata (key, data) VALUES ('meta', $1)")?;
+    let mut stmt = conn.prepare_cached("REPLACE INTO _metadata (key, data) VALUES ('meta', $1)")?;
     stmt.execute([meta_bytes])?;
     Ok(())
 }
 
-#[inline]
-fn create_snapshot(conn: &rusqlite::Connection, path: String) -> Result<(), rusqlite::Error> {
+/// Runs `PRAGMA optimize` at a node-local moment, outside of any log entry.
+///
+/// This is skipped with the write cost guard. `sqlite_stat1` decides about the query plans and
+/// with that the VM steps of each write, so it must be the same on all nodes.
+fn optimize_local(conn: &rusqlite::Connection, cost: &WriteCostGuard) {
+    if cost.is_enabled() {
+        return;
+    }
+    if let Err(err) = conn.execute("PRAGMA optimize", []) {
+        error!("Error during 'PRAGMA optimize': {}", err);
+    }
+}
+
+fn store_checksum(checksums: &Checksums, log_index: u64, res: Result<DbChecksum, Error>) {
+    match res {
+        Ok(checksum) => {
+            debug!("Checksum at log index {}: {}", log_index, checksum.hash);
+            checksums.insert(checksum);
+        }
+        Err(err) => error!(
+            "Error computing checksum at log index {}: {}",
+            log_index, err
+        ),
+    }
+}
+
+fn send_snapshot_response(
+    ack: oneshot::Sender<Result<SnapshotResponse, StorageError<NodeId>>>,
+    meta: StateMachineData,
+    res: Result<(), Error>,
+) {
+    let resp = match res {
+        Ok(_) => Ok(SnapshotResponse { meta }),
+        Err(err) => {
+            error!("Error creating new snapshot: {:?}", err);
+            Err(StorageError::IO {
+                source: StorageIOError::write(&err),
+            })
+        }
+    };
+    ack.send(resp).expect("snapshot listener to always exists");
+}
+
+/// Read transactions on the main and all named databases, which have been started at the same
+/// point inside the writer. A snapshot can be copied from them with the online backup API
+/// without blocking the writer, because a read transaction in WAL mode keeps seeing the state
+/// from its start.
+struct SnapshotSource {
+    main: rusqlite::Connection,
+    named: Vec<(String, rusqlite::Connection)>,
+}
+
+impl SnapshotSource {
+    /// Returns `None` for in-memory databases, which can't use WAL.
+    fn begin(
+        conn: &rusqlite::Connection,
+        named: &HashMap<String, NamedDb>,
+    ) -> Result<Option<Self>, Error> {
+        let journal_mode: String =
+            conn.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            return Ok(None);
+        }
+        let Some(path) = conn.path().filter(|p| !p.is_empty()) else {
+            return Ok(None);
+        };
+
+        let main = Self::read_txn(path)?;
+        let mut named_src = Vec::with_capacity(named.len());
+        for (name, db) in named {
+            let path = db.conn.path().unwrap_or_default();
+            named_src.push((name.clone(), Self::read_txn(path)?));
+        }
+
+        Ok(Some(Self {
+            main,
+            named: named_src,
+        }))
+    }
+
+    fn read_txn(path: &str) -> Result<rusqlite::Connection, Error> {
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        // a deferred transaction only takes its read snapshot with the first read
+        conn.execute_batch("BEGIN")?;
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", (), |row| {
+            row.get::<_, i64>(0)
+        })?;
+        Ok(conn)
+    }
+
+    /// MUST NOT be executed in async context!
+    fn checksum(&self, log_index: u64, stats: bool) -> Result<DbChecksum, Error> {
+        let named = self
+            .named
+            .iter()
+            .map(|(name, conn)| (name.as_str(), conn))
+            .collect::<Vec<_>>();
+        DbChecksum::compute(log_index, &self.main, &named, stats)
+    }
+
+    /// Copies the snapshot into a temporary file first, which is renamed to `path` when done, so
+    /// an unfinished snapshot can never be picked up as the latest one.
+    ///
+    /// MUST NOT be executed in async context!
+    fn copy_into(self, path: &str) -> Result<(), Error> {
+        let path_build = format!("{}.build", path);
+
+        backup_db(&self.main, &path_build)?;
+        let mut named_paths = Vec::with_capacity(self.named.len());
+        for (name, conn) in &self.named {
+            let path_db = format!("{}.{}", path_build, name);
+            backup_db(conn, &path_db)?;
+            named_paths.push((name.as_str(), path_db));
+        }
+        embed_named_dbs(&path_build, &named_paths)?;
+
+        std::fs::rename(&path_build, path)?;
+        Ok(())
+    }
+}
+
+/// Copies the database of `src` into a new file at `path` page by page.
+fn backup_db(src: &rusqlite::Connection, path: &str) -> Result<(), Error> {
+    let _ = std::fs::remove_file(path);
+    let mut dst = rusqlite::Connection::open(path)?;
+    {
+        let backup = Backup::new(src, &mut dst)?;
+        backup.run_to_completion(SNAPSHOT_BACKUP_STEP_PAGES, Duration::ZERO, None)?;
+    }
+    // the copy has the WAL mode of the source, while a snapshot must be a single file
+    dst.pragma_update(None, "journal_mode", "DELETE")?;
+    Ok(())
+}
+
+/// Creates a snapshot with `VACUUM INTO` directly on the writer connection.
+fn create_snapshot(
+    conn: &rusqlite::Connection,
+    named: &HashMap<String, NamedDb>,
+    path: String,
+) -> Result<(), Error> {
     let q = format!("VACUUM main INTO '{}'", path);
     conn.execute(&q, ())?;
+
+    let mut named_paths = Vec::with_capacity(named.len());
+    for (name, db) in named {
+        let path_db = format!("{}.{}", path, name);
+        db.conn
+            .execute(&format!("VACUUM main INTO '{}'", path_db), ())?;
+        named_paths.push((name.as_str(), path_db));
+    }
+
+    embed_named_dbs(&path, &named_paths)
+}
+
+/// A Raft snapshot is a single file. Named databases are embedded into the snapshot of the
+/// main database in chunks and will be extracted again in `restore_named_dbs()`. The given
+/// database files are removed afterward.
+fn embed_named_dbs(path: &str, named_paths: &[(&str, String)]) -> Result<(), Error> {
+    if named_paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut snapshot = rusqlite::Connection::open(path)?;
+    let txn = snapshot.transaction()?;
+    txn.execute(
+        r#"
+CREATE TABLE _snapshot_databases
+(
+    name TEXT    NOT NULL,
+    idx  INTEGER NOT NULL,
+    data BLOB    NOT NULL,
+    CONSTRAINT _snapshot_databases_pk
+        PRIMARY KEY (name, idx)
+)"#,
+        (),
+    )?;
+
+    {
+        let mut stmt =
+            txn.prepare("INSERT INTO _snapshot_databases (name, idx, data) VALUES ($1, $2, $3)")?;
+
+        for (name, path_db) in named_paths {
+            let mut file = std::fs::File::open(path_db)?;
+            let mut chunk = Vec::with_capacity(SNAPSHOT_DB_CHUNK_SIZE as usize);
+            let mut idx = 0;
+            loop {
+                chunk.clear();
+                (&mut file)
+                    .take(SNAPSHOT_DB_CHUNK_SIZE)
+                    .read_to_end(&mut chunk)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                stmt.execute((name, idx, &chunk))?;
+                idx += 1;
+            }
+
+            std::fs::remove_file(path_db)?;
+        }
+    }
+
+    txn.commit()?;
     Ok(())
 }
 
+/// Restores all named databases from the chunks embedded into the just restored main database.
+/// A named database, which does not exist in the snapshot, will be reset to an empty one.
+fn restore_named_dbs(
+    conn: &rusqlite::Connection,
+    named: &mut HashMap<String, NamedDb>,
+    path: &str,
+) -> Result<(), Error> {
+    let is_embedded = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_snapshot_databases'",
+        (),
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+
+    for (name, db) in named.iter_mut() {
+        let path_db = format!("{}.{}", path, name);
+
+        {
+            let mut file = std::fs::File::create(&path_db)?;
+            if is_embedded {
+                let mut stmt = conn
+                    .prepare("SELECT data FROM _snapshot_databases WHERE name = $1 ORDER BY idx")?;
+                let mut rows = stmt.query([name])?;
+                while let Some(row) = rows.next()? {
+                    let data = row.get_ref(0)?.as_blob().map_err(rusqlite::Error::from)?;
+                    file.write_all(data)?;
+                }
+            }
+            // an empty file is a valid, empty database
+            file.sync_all()?;
+        }
+
+        info!("Restoring named database '{}' from snapshot", name);
+        restore_db(&mut db.conn, &path_db, None::<fn(Progress)>)?;
+        std::fs::remove_file(&path_db)?;
+    }
+
+    if is_embedded {
+        conn.execute("DROP TABLE _snapshot_databases", ())?;
+    }
+
+    Ok(())
+}
+
+/// Restores the main database of `conn` from the given file.
+///
+/// In-memory databases can't use WAL, which means the restore fails as long as a reader holds a
+/// lock, instead of waiting for it. We simply try again in this case.
+fn restore_db<F>(
+    conn: &mut rusqlite::Connection,
+    path: &str,
+    progress: Option<F>,
+) -> Result<(), rusqlite::Error>
+where
+    F: Fn(Progress) + Copy,
+{
+    loop {
+        match conn.restore(DatabaseName::Main, path, progress) {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::DatabaseBusy
+                    || err.code == rusqlite::ErrorCode::DatabaseLocked =>
+            {
+                debug!("Database is locked during restore - retrying");
+                thread::sleep(Duration::from_millis(10));
+            }
+            res => return res,
+        }
+    }
+}
+
 fn create_backup(
     conn: &rusqlite::Connection,
+    sql_functions: &SqlFunctions,
     node_id: NodeId,
+    last_applied_log_id: Option<LogId<NodeId>>,
     target_folder: String,
     #[cfg(feature = "s3")] s3_config: Option<std::sync::Arc<crate::s3::S3Config>>,
 ) -> Result<(), Error> {
     // - build target db file name with node id and timestamp
     // - vacuum into target file
-    // - connect to vacuumed db and reset metadata
+    // - connect to vacuumed db, reset metadata and save the log index of the backup
     // - if we have an s3 target, encrypt and push it
 
     let file = format!("backup_node_{}_{}.sqlite", node_id, Utc::now().timestamp());
@@ -634,7 +1569,14 @@ fn create_backup(
     // make sure connection is dropped before starting encrypt + push
     {
         let conn_bkp = rusqlite::Connection::open(&path_full)?;
-        persist_metadata(&conn_bkp, &StateMachineData::default());
+        sql_functions.apply(&conn_bkp)?;
+        persist_metadata(&conn_bkp, &StateMachineData::default())?;
+        if let Some(log_id) = last_applied_log_id {
+            conn_bkp.execute(
+                "REPLACE INTO _metadata (key, data) VALUES ($1, $2)",
+                (META_KEY_BACKUP_INDEX, log_id.index as i64),
+            )?;
+        }
     }
 
     info!("Database backup finished");
@@ -823,3 +1765,33 @@ fn apply_migration(txn: rusqlite::Transaction, migration: Migration) -> Result<(
     txn.commit()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file() {
+        // older versions created an empty lock file and wrote only the marker
+        assert_eq!(LockFile::parse(""), LockFile::default());
+        assert_eq!(
+            LockFile::parse(LOCK_NEEDS_REBUILD),
+            LockFile {
+                boot_id: None,
+                needs_rebuild: true,
+            }
+        );
+        assert!(!LockFile::parse("").is_same_boot());
+
+        let path = std::env::temp_dir().join(format!("hiqlite_lock_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        for needs_rebuild in [false, true] {
+            let lock = LockFile::new(needs_rebuild);
+            lock.write(path).unwrap();
+            let parsed = LockFile::parse(&std::fs::read_to_string(path).unwrap());
+            assert_eq!(parsed, lock);
+            assert_eq!(parsed.is_same_boot(), lock.boot_id.is_some());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -11,12 +11,19 @@ pub async fn start_test_cluster_with_backup(
 ) -> Result<(Client, Client, Client), Error> {
     if from_fs {
         env::set_var("HQL_BACKUP_SKIP_VALIDATION", "true");
-        env::set_var("HQL_BACKUP_RESTORE", format!("file:{}", BACKUP_PATH_FILE));
+        start_test_cluster_with_restore(format!("file:{}", BACKUP_PATH_FILE)).await
     } else {
         let path = backup::find_backup_file(1).await;
         let (_path, backup_name) = path.rsplit_once('/').unwrap();
-        env::set_var("HQL_BACKUP_RESTORE", format!("s3:{}", backup_name));
+        start_test_cluster_with_restore(format!("s3:{}", backup_name)).await
     }
+}
+
+/// Starts the cluster with the given `HQL_BACKUP_RESTORE` value.
+pub async fn start_test_cluster_with_restore(
+    restore: String,
+) -> Result<(Client, Client, Client), Error> {
+    env::set_var("HQL_BACKUP_RESTORE", restore);
 
     let handle_client_2 = task::spawn(start_node_with_cache::<Cache>(build_config(2).await));
     let handle_client_3 = task::spawn(start_node_with_cache::<Cache>(build_config(3).await));
@@ -0,0 +1,74 @@
+use crate::log;
+use futures_util::future::join_all;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_coalesce(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    client_1
+        .execute(
+            "CREATE TABLE coalesce (id INTEGER NOT NULL PRIMARY KEY, grp INTEGER NOT NULL)",
+            params!(),
+        )
+        .await?;
+    // group `g` has exactly `g` rows
+    let rows = (1..=10).flat_map(|grp| (0..grp).map(move |i| params!(grp * 100 + i, grp)));
+    client_1
+        .execute_many("INSERT INTO coalesce (id, grp) VALUES ($1, $2)", rows)
+        .await?;
+
+    log("Make sure each caller gets its own result from a coalesced write");
+    // All nodes run with a coalesce window, which means these concurrent executes will end
+    // up in a few shared Raft entries on the leader.
+    let mut handles = Vec::with_capacity(11);
+    for grp in 1..=10 {
+        let client = [client_1, client_2, client_3][grp as usize % 3].clone();
+        handles.push(tokio::spawn(async move {
+            let res = client
+                .execute_indexed("UPDATE coalesce SET grp = grp WHERE grp = $1", params!(grp))
+                .await;
+            (grp, res)
+        }));
+    }
+    // a failing query in between must not affect the others of the same batch
+    let client = client_2.clone();
+    handles.push(tokio::spawn(async move {
+        let res = client
+            .execute_indexed(
+                "INSERT INTO coalesce (id, grp) VALUES ($1, $2)",
+                params!(100, 0),
+            )
+            .await;
+        (0, res)
+    }));
+
+    for res in join_all(handles).await {
+        let (grp, res) = res.expect("task to not panic");
+        if grp == 0 {
+            assert!(res.is_err());
+        } else {
+            let res = res?;
+            assert_eq!(res.value, grp as usize);
+            assert!(res.log_index > 0);
+        }
+    }
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    for client in [client_1, client_2, client_3] {
+        let count: i64 = client
+            .query_raw_one("SELECT COUNT(*) AS count FROM coalesce", params!())
+            .await?
+            .get("count");
+        assert_eq!(count, 55);
+    }
+
+    client_1.execute("DROP TABLE coalesce", params!()).await?;
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::execute_query::TestData;
+use crate::log;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_deterministic(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    // we re-use the test table from the simple insert / query tests here
+    log("Inserting rows with time and random functions");
+
+    let rows_affected = client_1
+        .execute(
+            "INSERT INTO test VALUES ($1, unixepoch('now'), datetime('now', '+1 day'))",
+            params!(31),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+
+    let rows_affected = client_2
+        .execute(
+            "INSERT INTO test VALUES ($1, random(), hex(randomblob(16)) || CURRENT_TIMESTAMP)",
+            params!(32),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+
+    log("Make sure all nodes applied the same values");
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    let sql = "SELECT * FROM test WHERE id > 30 ORDER BY id";
+    let data_1: Vec<TestData> = client_1.query_map(sql, params!()).await?;
+    let data_2: Vec<TestData> = client_2.query_map(sql, params!()).await?;
+    let data_3: Vec<TestData> = client_3.query_map(sql, params!()).await?;
+    assert_eq!(data_1.len(), 2);
+    assert_eq!(data_1, data_2);
+    assert_eq!(data_1, data_3);
+
+    log("Make sure timezone dependent modifiers are rejected");
+    let res = client_3
+        .execute(
+            "INSERT INTO test VALUES ($1, $2, datetime('now', 'localtime'))",
+            params!(33, 0),
+        )
+        .await;
+    assert!(matches!(res, Err(Error::NonDeterministic(_))));
+
+    log("Custom functions and collations from the NodeConfig");
+    let rows_affected = client_3
+        .execute(
+            "INSERT INTO test VALUES ($1, $2, slugify($3))",
+            params!(34, 0, " Hello Custom Function"),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+    let rows_affected = client_3
+        .execute(
+            "INSERT INTO test VALUES ($1, $2, slugify($3))",
+            params!(35, 0, "Another One"),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+    time::sleep(Duration::from_millis(100)).await;
+
+    let sql = "SELECT * FROM test WHERE id > 33 ORDER BY description COLLATE reverse";
+    for client in [client_1, client_2, client_3] {
+        let data: Vec<TestData> = client.query_map(sql, params!()).await?;
+        let descriptions = data
+            .into_iter()
+            .map(|d| d.description.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(descriptions, vec!["hello-custom-function", "another-one"]);
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id > $1", params!(30))
+        .await?;
+
+    Ok(())
+}
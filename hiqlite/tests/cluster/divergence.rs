@@ -0,0 +1,118 @@
+use crate::start::SECRET_API;
+use crate::{log, start, TEST_DATA_DIR};
+use hiqlite::{params, Client, DivergenceMetrics, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_divergence(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    client_1
+        .execute(
+            r#"
+    CREATE TABLE IF NOT EXISTS divergence
+    (
+        id    INTEGER NOT NULL
+                CONSTRAINT divergence_pk
+                    PRIMARY KEY,
+        value TEXT    NOT NULL
+    )"#,
+            params!(),
+        )
+        .await?;
+    client_1
+        .execute(
+            "INSERT INTO divergence (id, value) VALUES ($1, $2)",
+            params!(1, "original"),
+        )
+        .await?;
+
+    log("All nodes agree on their data");
+    for client in [client_1, client_2, client_3] {
+        let before = client.metrics_divergence()?.checks;
+        let metrics = wait_for_metrics(client, |m| m.checks > before).await?;
+        assert!(metrics.divergent_nodes.is_empty(), "{:?}", metrics);
+        assert!(metrics.missing_nodes.is_empty(), "{:?}", metrics);
+        assert!(metrics.last_hash.is_some());
+        client.check_divergence()?;
+    }
+
+    // node 3 runs in-memory -> modify the DB file of the other follower
+    let leader = client_1.metrics_db().await?.current_leader.unwrap();
+    let (node_id, client) = if leader == 1 {
+        (2, client_2)
+    } else {
+        (1, client_1)
+    };
+    let resyncs = client.metrics_divergence()?.resyncs;
+
+    log(format!(
+        "Changing data behind the Raft on follower {}",
+        node_id
+    ));
+    let path_db = format!(
+        "{}/node_{}/state_machine/db/hiqlite.db",
+        TEST_DATA_DIR, node_id
+    );
+    let conn = rusqlite::Connection::open(path_db)?;
+    conn.execute("UPDATE divergence SET value = 'diverged' WHERE id = 1", [])?;
+    drop(conn);
+
+    log("The divergent node is detected and re-synced");
+    let metrics = wait_for_metrics(client, |m| {
+        m.divergent_nodes.contains(&node_id) || m.resyncs > resyncs
+    })
+    .await?;
+    if !metrics.divergent_nodes.is_empty() {
+        assert_eq!(metrics.divergent_nodes, vec![node_id]);
+        assert_eq!(metrics.divergent_tables, vec!["divergence".to_string()]);
+        assert!(matches!(
+            client.check_divergence(),
+            Err(Error::Divergence(_))
+        ));
+    }
+    wait_for_metrics(client, |m| m.resyncs > resyncs).await?;
+
+    let value: String = client
+        .query_raw_one("SELECT value FROM divergence WHERE id = 1", params!())
+        .await?
+        .get("value");
+    assert_eq!(value, "original");
+
+    log("Checks are clean again after the re-sync");
+    for client in [client_1, client_2, client_3] {
+        let checks = client.metrics_divergence()?.checks;
+        let metrics = wait_for_metrics(client, |m| m.checks > checks).await?;
+        assert!(metrics.divergent_nodes.is_empty(), "{:?}", metrics);
+    }
+
+    log("Divergence metrics only exist on local clients");
+    let nodes = start::nodes()
+        .into_iter()
+        .map(|n| n.addr_api)
+        .collect::<Vec<_>>();
+    let client_remote = Client::remote(nodes, false, false, SECRET_API.to_string(), false).await?;
+    let res = client_remote.metrics_divergence();
+    assert!(matches!(res, Err(Error::Config(_))), "{:?}", res);
+
+    Ok(())
+}
+
+pub async fn wait_for_metrics<F>(client: &Client, f: F) -> Result<DivergenceMetrics, Error>
+where
+    F: Fn(&DivergenceMetrics) -> bool,
+{
+    for _ in 0..600 {
+        let metrics = client.metrics_divergence()?;
+        if f(&metrics) {
+            return Ok(metrics);
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!(
+        "divergence metrics not as expected after 60s: {:?}",
+        client.metrics_divergence()?
+    );
+}
@@ -0,0 +1,74 @@
+use crate::execute_query::TestData;
+use crate::log;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_execute_many(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Bulk insert with execute_many");
+    let rows_affected = client_1
+        .execute_many(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            (101..=200).map(|id| params!(id, id * 2, "execute many")),
+        )
+        .await?;
+    assert_eq!(rows_affected, 100);
+
+    log("Bulk insert with execute_many from another node");
+    let rows_affected = client_2
+        .execute_many(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            (201..=300).map(|id| params!(id, id * 2, "execute many")),
+        )
+        .await?;
+    assert_eq!(rows_affected, 100);
+
+    log("Make sure rows with a different amount of params are rejected");
+    let res = client_3
+        .execute_many(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            [params!(301, 0, "ok"), params!(302, 0)],
+        )
+        .await;
+    assert!(res.is_err());
+
+    // rows without params would be silently dropped otherwise
+    let res = client_3
+        .execute_many(
+            "INSERT INTO test VALUES (303, 0, 'no params')",
+            [params!(), params!()],
+        )
+        .await;
+    assert!(matches!(res, Err(Error::QueryParams(_))));
+
+    log("Make sure a failing row rolls back the whole entry");
+    let res = client_3
+        .execute_many(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            [params!(301, 0, "rolled back"), params!(150, 0, "duplicate")],
+        )
+        .await;
+    assert!(res.is_err());
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    let sql = "SELECT * FROM test WHERE id > 100 ORDER BY id";
+    for client in [client_1, client_2, client_3] {
+        let rows: Vec<TestData> = client.query_map(sql, params!()).await?;
+        assert_eq!(rows.len(), 200);
+        assert_eq!(rows[0].id, 101);
+        assert_eq!(rows[199].id, 300);
+        assert_eq!(rows[49].description.as_deref(), Some("execute many"));
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id > $1", params!(100))
+        .await?;
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::log;
 use chrono::Utc;
-use hiqlite::{params, Client, Error, Param};
+use hiqlite::{named_params, params, Client, Error, Param};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time;
@@ -211,5 +211,133 @@ pub async fn test_execute_query(
     assert_eq!(row.ts, data.ts);
     assert_eq!(row.description, data.description);
 
+    log("Test read-your-writes with the applied log index");
+    let data = TestData {
+        id: 9,
+        ts: Utc::now().timestamp(),
+        description: Some("Read your writes".to_string()),
+    };
+    let res = client_2
+        .execute_indexed(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            params!(data.id, data.ts, data.description.clone()),
+        )
+        .await?;
+    assert_eq!(res.value, 1);
+
+    // no sleep here - the `_after` queries must wait for the log index on their own
+    let row: TestData = client_3
+        .query_map_one_after(
+            res.log_index,
+            "SELECT * FROM test WHERE id = $1",
+            params!(9),
+        )
+        .await?;
+    assert_eq!(row, data);
+    let row: TestData = client_1
+        .query_as_one_after(
+            res.log_index,
+            "SELECT * FROM test WHERE id = $1",
+            params!(9),
+        )
+        .await?;
+    assert_eq!(row, data);
+
+    let res_del = client_3
+        .execute_indexed("DELETE FROM test WHERE id = $1", params!(9))
+        .await?;
+    assert_eq!(res_del.value, 1);
+    assert!(res_del.log_index > res.log_index);
+
+    let row: Option<TestData> = client_2
+        .query_map_optional_after(
+            res_del.log_index,
+            "SELECT * FROM test WHERE id = $1",
+            params!(9),
+        )
+        .await?;
+    assert!(row.is_none());
+
+    let res_ret = client_1
+        .execute_returning_indexed(
+            "INSERT INTO test VALUES ($1, $2, $3) RETURNING id",
+            params!(data.id, data.ts, data.description.clone()),
+        )
+        .await?;
+    assert_eq!(res_ret.value.len(), 1);
+    assert!(res_ret.log_index > res_del.log_index);
+    let row: TestData = client_2
+        .query_map_one_after(
+            res_ret.log_index,
+            "SELECT * FROM test WHERE id = $1",
+            params!(9),
+        )
+        .await?;
+    assert_eq!(row, data);
+    let res_del = client_2
+        .execute_indexed("DELETE FROM test WHERE id = $1", params!(9))
+        .await?;
+    client_2.wait_applied(res_del.log_index).await?;
+
+    let res = client_3
+        .wait_applied_timeout(u64::MAX, Duration::from_millis(100))
+        .await;
+    assert!(matches!(res, Err(Error::Timeout(_))));
+
+    log("Test bounded staleness reads");
+    let select = "SELECT * FROM test WHERE id = $1";
+    let rows: Vec<TestData> = client_2
+        .query_map_max_lag(Duration::from_secs(1), select, params!(9))
+        .await?;
+    assert!(rows.is_empty());
+    let rows: Vec<TestData> = client_2.query_map_max_lag(0, select, params!(3)).await?;
+    assert_eq!(rows.len(), 1);
+    let row: TestData = client_3
+        .query_map_one_max_lag(Duration::from_millis(500), select, params!(3))
+        .await?;
+    assert_eq!(row.id, 3);
+    let rows: Vec<TestData> = client_3
+        .query_as_max_lag(Duration::from_secs(1), select, params!(3))
+        .await?;
+    assert_eq!(rows.len(), 1);
+    let row: Option<TestData> = client_1
+        .query_as_optional_max_lag(0, select, params!(9))
+        .await?;
+    assert!(row.is_none());
+
+    log("Test named params");
+    let data = TestData {
+        id: 10,
+        ts: Utc::now().timestamp(),
+        description: Some("Named Params".to_string()),
+    };
+    let rows_affected = client_2
+        .execute(
+            "INSERT INTO test VALUES (:id, :ts, :description)",
+            named_params!(
+                ":id" => data.id,
+                "ts" => data.ts,
+                "description" => data.description.clone(),
+            ),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+
+    let rows: Vec<TestData> = client_3
+        .query_consistent_map(
+            "SELECT * FROM test WHERE id = :id AND ts = :ts",
+            named_params!("ts" => data.ts, "id" => data.id),
+        )
+        .await?;
+    assert_eq!(rows, vec![data.clone()]);
+
+    let rows_affected = client_1
+        .execute(
+            "DELETE FROM test WHERE id = $id",
+            named_params!("id" => data.id),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+
     Ok(())
 }
@@ -0,0 +1,90 @@
+use crate::execute_query::TestData;
+use crate::log;
+use futures_util::future::join_all;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_group_commit(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Concurrent executes with failing queries in between");
+
+    // ids 51 - 100, while each 10th one is inserted twice
+    let mut handles = Vec::with_capacity(55);
+    for id in 51..=100 {
+        let client = client_1.clone();
+        handles.push(tokio::spawn(async move {
+            client
+                .execute(
+                    "INSERT INTO test VALUES ($1, $2, $3)",
+                    params!(id, 0, "group commit"),
+                )
+                .await
+        }));
+
+        if id % 10 == 0 {
+            let client = client_2.clone();
+            handles.push(tokio::spawn(async move {
+                client
+                    .execute(
+                        "INSERT INTO test VALUES ($1, $2, $3)",
+                        params!(id, 0, "group commit"),
+                    )
+                    .await
+            }));
+        }
+    }
+
+    let mut ok = 0;
+    let mut err = 0;
+    for res in join_all(handles).await {
+        match res.expect("task to not panic") {
+            Ok(rows_affected) => {
+                assert_eq!(rows_affected, 1);
+                ok += 1;
+            }
+            Err(_) => err += 1,
+        }
+    }
+    assert_eq!(ok, 50);
+    assert_eq!(err, 5);
+
+    log("Make sure failed transactions inside a group are rolled back");
+    let txn = client_3.txn([
+        (
+            "UPDATE test SET description = $1 WHERE id = $2",
+            params!("rolled back", 51),
+        ),
+        (
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            params!(52, 0, "duplicate"),
+        ),
+    ]);
+    let execute = client_1.execute(
+        "UPDATE test SET description = $1 WHERE id = $2",
+        params!("updated", 53),
+    );
+    let (res_txn, res_execute) = tokio::join!(txn, execute);
+    assert!(res_txn.is_err());
+    assert_eq!(res_execute?, 1);
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    let sql = "SELECT * FROM test WHERE id > 50 ORDER BY id";
+    for client in [client_1, client_2, client_3] {
+        let rows: Vec<TestData> = client.query_map(sql, params!()).await?;
+        assert_eq!(rows.len(), 50);
+        assert_eq!(rows[0].description.as_deref(), Some("group commit"));
+        assert_eq!(rows[2].description.as_deref(), Some("updated"));
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id > $1", params!(50))
+        .await?;
+
+    Ok(())
+}
@@ -15,11 +15,24 @@ mod backup;
 mod backup_restore;
 mod batch;
 mod check;
+mod coalesce;
+mod deterministic;
+mod divergence;
+mod execute_many;
 mod execute_query;
+mod group_commit;
 mod migration;
+mod named_db;
+mod pitr;
+mod query_stream;
+mod query_timeout;
+mod read_pool;
 mod self_heal;
+mod sharding;
+mod snapshot_schedule;
 mod start;
 mod transaction;
+mod write_cost;
 
 mod cache;
 mod dlock;
@@ -97,6 +110,58 @@ async fn exec_tests() -> Result<(), Error> {
     batch::test_batch(&client_1, &client_2, &client_3).await?;
     log("Batch tests finished");
 
+    log("Starting group commit tests");
+    group_commit::test_group_commit(&client_1, &client_2, &client_3).await?;
+    log("Group commit tests finished");
+
+    log("Starting write coalescing tests");
+    coalesce::test_coalesce(&client_1, &client_2, &client_3).await?;
+    log("Write coalescing tests finished");
+
+    log("Starting execute many tests");
+    execute_many::test_execute_many(&client_1, &client_2, &client_3).await?;
+    log("Execute many tests finished");
+
+    log("Starting in-memory reads during writes tests");
+    start::test_in_memory_reads_during_writes(&client_1, &client_2, &client_3).await?;
+    log("In-memory reads during writes tests finished");
+
+    log("Starting write cost guard tests");
+    write_cost::test_write_cost(&client_1, &client_2, &client_3).await?;
+    log("Write cost guard tests finished");
+
+    log("Starting named database tests");
+    named_db::test_named_db(&client_1, &client_2, &client_3).await?;
+    log("Named database tests finished");
+
+    log("Starting sharding tests");
+    sharding::test_sharding(&client_1, &client_2, &client_3).await?;
+    log("Sharding tests finished");
+
+    log("Starting deterministic SQL functions tests");
+    deterministic::test_deterministic(&client_1, &client_2, &client_3).await?;
+    log("Deterministic SQL functions tests finished");
+
+    log("Starting query stream tests");
+    query_stream::test_query_stream(&client_1).await?;
+    log("Query stream tests finished");
+
+    log("Starting query timeout tests");
+    query_timeout::test_query_timeout(&client_1, &client_2).await?;
+    log("Query timeout tests finished");
+
+    log("Starting read pool tests");
+    read_pool::test_read_pool(&client_1).await?;
+    log("Read pool tests finished");
+
+    log("Starting divergence check tests");
+    divergence::test_divergence(&client_1, &client_2, &client_3).await?;
+    log("Divergence check tests finished");
+
+    log("Starting snapshot schedule tests");
+    snapshot_schedule::test_snapshot_schedule(&client_1, &client_2, &client_3).await?;
+    log("Snapshot schedule tests finished");
+
     log("Starting SQL type conversion tests");
     type_conversions::test_type_conversions(&client_1).await?;
     log("SQL type conversion tests finished");
@@ -205,6 +270,10 @@ async fn exec_tests() -> Result<(), Error> {
     backup_restore::test_db_is_healthy_after_restore(&client_2).await?;
     backup_restore::test_db_is_healthy_after_restore(&client_3).await?;
 
+    log("Starting point-in-time recovery tests");
+    let (client_1, client_2, client_3) = pitr::test_pitr(client_1, client_2, client_3).await?;
+    log("Point-in-time recovery tests finished");
+
     // we need to wait a bit until all backup nodes have created a new snapshot
     time::sleep(Duration::from_millis(1000)).await;
 
@@ -0,0 +1,91 @@
+use crate::log;
+use hiqlite::{params, Client, Error, Param, Row};
+use std::time::Duration;
+use tokio::time;
+
+#[derive(Debug)]
+struct Invoice {
+    id: i64,
+    amount: i64,
+}
+
+impl<'r> From<Row<'r>> for Invoice {
+    fn from(mut row: Row<'r>) -> Self {
+        Self {
+            id: row.get("id"),
+            amount: row.get("amount"),
+        }
+    }
+}
+
+pub async fn test_named_db(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Create a table inside the named database");
+    let billing = client_1.db("billing");
+    assert_eq!(billing.name(), Some("billing"));
+    billing
+        .batch("CREATE TABLE invoice (id INTEGER PRIMARY KEY, amount INTEGER NOT NULL);")
+        .await?
+        .remove(0)?;
+
+    log("Execute on the named database from all nodes");
+    let sql = "INSERT INTO invoice (id, amount) VALUES ($1, $2)";
+    for (id, client) in [client_1, client_2, client_3].into_iter().enumerate() {
+        let rows_affected = client
+            .db("billing")
+            .execute(sql, params!(id as i64, 100 * id as i64))
+            .await?;
+        assert_eq!(rows_affected, 1);
+    }
+
+    let res = client_2
+        .db("billing")
+        .txn([(sql, params!(3, 300)), (sql, params!(4, 400))])
+        .await?;
+    for inner in res {
+        assert_eq!(inner?, 1);
+    }
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    log("Make sure all nodes see the same data in the named database");
+    for client in [client_1, client_2, client_3] {
+        let db = client.db("billing");
+        let rows: Vec<Invoice> = db
+            .query_map("SELECT * FROM invoice ORDER BY id", params!())
+            .await?;
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[4].id, 4);
+        assert_eq!(rows[4].amount, 400);
+
+        let row: Invoice = db
+            .query_map_one("SELECT * FROM invoice WHERE id = $1", params!(2))
+            .await?;
+        assert_eq!(row.amount, 200);
+
+        let rows: Vec<Invoice> = db
+            .query_consistent_map("SELECT * FROM invoice", params!())
+            .await?;
+        assert_eq!(rows.len(), 5);
+    }
+
+    log("Make sure the main database is not affected");
+    let res = client_1.query_raw("SELECT * FROM invoice", params!()).await;
+    assert!(res.is_err());
+
+    log("Make sure unknown databases are rejected");
+    for client in [client_1, client_2, client_3] {
+        let db = client.db("unknown");
+        assert!(db.execute(sql, params!(10, 10)).await.is_err());
+        assert!(db
+            .query_map::<Invoice, _>("SELECT * FROM invoice", params!())
+            .await
+            .is_err());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use crate::{backup_restore, log, start};
+use chrono::Utc;
+use futures_util::future::join_all;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_pitr(
+    client_1: Client,
+    client_2: Client,
+    client_3: Client,
+) -> Result<(Client, Client, Client), Error> {
+    log("Wait for a new Raft log archive epoch after the last restore");
+    wait_for_epoch(&client_1).await?;
+
+    log("Creating the base backup");
+    client_1.backup().await?;
+    // the timestamp in the backup name only has a precision of seconds
+    time::sleep(Duration::from_millis(2100)).await;
+
+    client_1
+        .execute(
+            "CREATE TABLE pitr (id INTEGER NOT NULL PRIMARY KEY)",
+            params!(),
+        )
+        .await?;
+    client_1
+        .execute("INSERT INTO pitr (id) VALUES ($1)", params!(1))
+        .await?;
+
+    time::sleep(Duration::from_millis(100)).await;
+    let target = Utc::now();
+    time::sleep(Duration::from_millis(100)).await;
+
+    client_1
+        .execute("INSERT INTO pitr (id) VALUES ($1)", params!(2))
+        .await?;
+
+    log("Wait for the archived logs to be pushed to S3");
+    time::sleep(Duration::from_secs(5)).await;
+
+    log("Shutting down nodes");
+    join_all([
+        client_1.shutdown(),
+        client_2.shutdown(),
+        client_3.shutdown(),
+    ])
+    .await;
+    time::sleep(Duration::from_millis(1000)).await;
+
+    log(format!(
+        "Starting the cluster again with point-in-time recovery to {}",
+        target.to_rfc3339()
+    ));
+    let (client_1, client_2, client_3) =
+        backup_restore::start_test_cluster_with_restore(format!("pitr:{}", target.to_rfc3339()))
+            .await?;
+    start::wait_for_healthy_cluster(&client_1, &client_2, &client_3).await?;
+    log("Cluster is healthy again");
+
+    log("Make sure only the writes before the target have been replayed");
+    for client in [&client_1, &client_2, &client_3] {
+        let ids = client
+            .query_raw("SELECT id FROM pitr ORDER BY id", params!())
+            .await?
+            .into_iter()
+            .map(|mut row| row.get::<i64>("id"))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1]);
+    }
+
+    Ok((client_1, client_2, client_3))
+}
+
+async fn wait_for_epoch(client: &Client) -> Result<(), Error> {
+    for _ in 0..100 {
+        let rows = client
+            .query_raw("SELECT data FROM _metadata WHERE key = 'epoch'", params!())
+            .await?;
+        if !rows.is_empty() {
+            return Ok(());
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("no Raft log archive epoch after 10s");
+}
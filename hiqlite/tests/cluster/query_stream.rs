@@ -0,0 +1,62 @@
+use crate::start::SECRET_API;
+use crate::{log, start};
+use futures_util::StreamExt;
+use hiqlite::{params, Client, Error, Param};
+
+pub async fn test_query_stream(client_1: &Client) -> Result<(), Error> {
+    log("Inserting rows for query streams");
+    for id in 41..=50 {
+        client_1
+            .execute(
+                "INSERT INTO test VALUES ($1, $2, $3)",
+                params!(id, 0, format!("stream row {}", id)),
+            )
+            .await?;
+    }
+
+    log("Stream rows from a local client");
+    check_stream(client_1).await?;
+
+    log("Stream rows from a remote client");
+    let nodes = start::nodes()
+        .into_iter()
+        .map(|n| n.addr_api)
+        .collect::<Vec<_>>();
+    let client_remote = Client::remote(nodes, false, false, SECRET_API.to_string(), false).await?;
+    check_stream(&client_remote).await?;
+
+    client_1
+        .execute("DELETE FROM test WHERE id > $1", params!(40))
+        .await?;
+
+    Ok(())
+}
+
+async fn check_stream(client: &Client) -> Result<(), Error> {
+    let sql = "SELECT * FROM test WHERE id > 40 ORDER BY id";
+
+    let mut stream = client.query_stream(sql, params!(), 3);
+    let mut chunk_sizes = Vec::new();
+    let mut ids = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        chunk_sizes.push(chunk.len());
+        for mut row in chunk {
+            ids.push(row.get::<i64>("id"));
+        }
+    }
+    assert_eq!(chunk_sizes, vec![3, 3, 3, 1]);
+    assert_eq!(ids, (41..=50).collect::<Vec<i64>>());
+
+    // dropping a stream early must cancel it without any issues for the next one
+    let mut stream = client.query_stream(sql, params!(), 2);
+    let first = stream.next().await.unwrap()?;
+    assert_eq!(first.len(), 2);
+    drop(stream);
+
+    let mut stream = client.query_stream("SELECT * FROM does_not_exist", params!(), 2);
+    assert!(stream.next().await.unwrap().is_err());
+    assert!(stream.next().await.is_none());
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::start::SECRET_API;
+use crate::{log, start};
+use hiqlite::{params, Client, Error, Param, Row};
+use std::time::Duration;
+use tokio::time;
+
+/// Never finishes without being interrupted
+const RUNAWAY: &str = r#"
+WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+SELECT count(*) AS count FROM c"#;
+
+static TIMEOUT: Duration = Duration::from_millis(100);
+
+pub async fn test_query_timeout(client_1: &Client, client_2: &Client) -> Result<(), Error> {
+    log("Runaway queries time out on local clients");
+    let res = client_1
+        .query_raw_timeout(TIMEOUT, RUNAWAY, params!())
+        .await;
+    assert!(matches!(res, Err(Error::Timeout(_))), "{:?}", res);
+    let res: Result<Vec<Count>, Error> = client_2
+        .query_map_timeout(TIMEOUT, RUNAWAY, params!())
+        .await;
+    assert!(matches!(res, Err(Error::Timeout(_))), "{:?}", res);
+
+    log("Runaway consistent queries time out on the leader");
+    let res = client_2
+        .query_consistent_timeout(TIMEOUT, RUNAWAY, params!())
+        .await;
+    assert!(matches!(res, Err(Error::Timeout(_))), "{:?}", res);
+
+    log("Runaway queries time out on remote clients");
+    let nodes = start::nodes()
+        .into_iter()
+        .map(|n| n.addr_api)
+        .collect::<Vec<_>>();
+    let client_remote = Client::remote(nodes, false, false, SECRET_API.to_string(), false).await?;
+    let res = client_remote
+        .query_raw_timeout(TIMEOUT, RUNAWAY, params!())
+        .await;
+    assert!(matches!(res, Err(Error::Timeout(_))), "{:?}", res);
+
+    log("A query within the timeout returns normally");
+    let count: Count = client_remote
+        .query_map_one_timeout(
+            Duration::from_secs(10),
+            "SELECT count(*) AS count FROM test",
+            params!(),
+        )
+        .await?;
+    assert!(count.0 >= 0);
+
+    log("Cancelled queries give their read connection back to the pool");
+    // more cancelled queries than the read pool has connections
+    for _ in 0..8 {
+        let res = time::timeout(TIMEOUT, client_1.query_raw(RUNAWAY, params!())).await;
+        assert!(res.is_err());
+    }
+    let mut row = time::timeout(
+        Duration::from_secs(5),
+        client_1.query_raw_one("SELECT 1 AS one", params!()),
+    )
+    .await
+    .expect("the read pool to not be exhausted by cancelled queries")?;
+    assert_eq!(row.get::<i64>("one"), 1);
+
+    log("Cancelled remote queries are interrupted on the serving node");
+    for _ in 0..8 {
+        let res = time::timeout(TIMEOUT, client_remote.query_raw(RUNAWAY, params!())).await;
+        assert!(res.is_err());
+    }
+    let mut row = time::timeout(
+        Duration::from_secs(5),
+        client_remote.query_raw_one("SELECT 1 AS one", params!()),
+    )
+    .await
+    .expect("the remote read pool to not be exhausted by cancelled queries")?;
+    assert_eq!(row.get::<i64>("one"), 1);
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Count(i64);
+
+impl<'r> From<Row<'r>> for Count {
+    fn from(mut row: Row<'r>) -> Self {
+        Self(row.get("count"))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::start::SECRET_API;
+use crate::{log, start};
+use futures_util::future::join_all;
+use futures_util::StreamExt;
+use hiqlite::{params, Client, Error, Param, Row};
+
+/// Takes a moment, so concurrent queries queue up
+const SLOW: &str = r#"
+WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 300000)
+SELECT count(*) AS count FROM c"#;
+
+/// Returns more rows than fit into the buffer of a query stream
+const ROWS: &str = r#"
+WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 100)
+SELECT x FROM c"#;
+
+pub async fn test_read_pool(client_1: &Client) -> Result<(), Error> {
+    let before = client_1.metrics_read_pool()?;
+    assert_eq!(before.min, 1);
+    assert_eq!(before.max, 4);
+
+    log("Queued up queries start additional readers");
+    let queries = (0..16)
+        .map(|_| client_1.query_raw_one(SLOW, params!()))
+        .collect::<Vec<_>>();
+    for res in join_all(queries).await {
+        assert_eq!(res?.get::<i64>("count"), 300000);
+    }
+
+    // Whether the pool has grown depends on how fast the readers are, so only the bounds
+    // can be checked here.
+    let metrics = client_1.metrics_read_pool()?;
+    assert!(metrics.readers >= metrics.min, "{:?}", metrics);
+    assert!(metrics.readers <= metrics.max, "{:?}", metrics);
+    assert!(metrics.queries >= before.queries + 16, "{:?}", metrics);
+    assert!(metrics.wait_max_micros > 0, "{:?}", metrics);
+    assert_eq!(metrics.queue_len, 0);
+
+    log("Open query streams grow the read pool up to its max");
+    // Each unfinished stream keeps its reader busy, so all of them can only receive their
+    // first chunk once the pool has been grown to the max.
+    let mut streams = (0..metrics.max)
+        .map(|_| client_1.query_stream(ROWS, params!(), 1))
+        .collect::<Vec<_>>();
+    for stream in streams.iter_mut() {
+        let rows = stream.next().await.expect("a first chunk")?;
+        assert_eq!(rows.len(), 1);
+    }
+    let metrics = client_1.metrics_read_pool()?;
+    assert_eq!(metrics.readers, metrics.max, "{:?}", metrics);
+    assert_eq!(metrics.idle, 0, "{:?}", metrics);
+    drop(streams);
+
+    log("A panic while mapping rows does not kill the reader");
+    for _ in 0..metrics.max * 2 {
+        let res = client_1
+            .query_map_one::<Panics, _>("SELECT 1 AS one", params!())
+            .await;
+        assert!(res.is_err());
+    }
+    let mut row = client_1.query_raw_one("SELECT 1 AS one", params!()).await?;
+    assert_eq!(row.get::<i64>("one"), 1);
+    let after = client_1.metrics_read_pool()?;
+    assert!(after.readers >= after.min, "{:?}", after);
+
+    log("Read pool metrics only exist on local clients");
+    let nodes = start::nodes()
+        .into_iter()
+        .map(|n| n.addr_api)
+        .collect::<Vec<_>>();
+    let client_remote = Client::remote(nodes, false, false, SECRET_API.to_string(), false).await?;
+    let res = client_remote.metrics_read_pool();
+    assert!(matches!(res, Err(Error::Config(_))), "{:?}", res);
+
+    Ok(())
+}
+
+struct Panics;
+
+impl<'r> From<Row<'r>> for Panics {
+    fn from(_: Row<'r>) -> Self {
+        panic!("mapping a row panicked on purpose");
+    }
+}
@@ -3,6 +3,7 @@ use crate::{cache, check, log, Cache, TEST_DATA_DIR};
 use futures_util::future::join_all;
 use hiqlite::{start_node_with_cache, Client, Error};
 use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::{fs, time};
 
 pub async fn test_self_healing(
@@ -31,9 +32,21 @@ pub async fn test_self_healing(
     log("Test recovery in case of state machine crash on non-leader");
     time::sleep(Duration::from_secs(2)).await;
     if !is_leader(&client_1, 1).await? {
-        client_1 = shutdown_lock_sm_db_restart(client_1, 1).await?;
+        client_1 = shutdown_lock_sm_db_restart(client_1, 1, false).await?;
     } else {
-        client_2 = shutdown_lock_sm_db_restart(client_2, 2).await?;
+        client_2 = shutdown_lock_sm_db_restart(client_2, 2, false).await?;
+    };
+    check::is_client_db_healthy(&client_1, Some(1)).await?;
+    check::is_client_db_healthy(&client_2, Some(2)).await?;
+    check::is_client_db_healthy(&client_3, Some(3)).await?;
+    log("Client has self-healed successfully");
+
+    log("Test rebuild after state machine crash with a corrupted database on non-leader");
+    time::sleep(Duration::from_secs(2)).await;
+    if !is_leader(&client_1, 1).await? {
+        client_1 = shutdown_lock_sm_db_restart(client_1, 1, true).await?;
+    } else {
+        client_2 = shutdown_lock_sm_db_restart(client_2, 2, true).await?;
     };
     check::is_client_db_healthy(&client_1, Some(1)).await?;
     check::is_client_db_healthy(&client_2, Some(2)).await?;
@@ -143,7 +156,11 @@ async fn modify_cache_restart_after_purge(client: Client, node_id: u64) -> Resul
     Ok(client)
 }
 
-async fn shutdown_lock_sm_db_restart(client: Client, node_id: u64) -> Result<Client, Error> {
+async fn shutdown_lock_sm_db_restart(
+    client: Client,
+    node_id: u64,
+    corrupt_db: bool,
+) -> Result<Client, Error> {
     log(format!("Shutting down client {}", node_id));
     client.shutdown().await?;
 
@@ -154,6 +171,14 @@ async fn shutdown_lock_sm_db_restart(client: Client, node_id: u64) -> Result<Cli
     ));
     fs::File::create_new(path_lock_file).await?;
 
+    if corrupt_db {
+        let path_db = format!("{}/db/hiqlite.db", folder_state_machine(node_id));
+        log(format!("Overwriting the database header in {}", path_db));
+        let mut file = fs::OpenOptions::new().write(true).open(path_db).await?;
+        file.write_all(b"not a database!!").await?;
+        file.sync_all().await?;
+    }
+
     log(format!("Re-starting client {}", node_id));
     let client = start_node_with_cache::<Cache>(build_config(node_id).await).await?;
     time::sleep(Duration::from_millis(150)).await;
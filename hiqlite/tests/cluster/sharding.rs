@@ -0,0 +1,114 @@
+use crate::log;
+use hiqlite::{params, Client, Error, Param, Row};
+use std::time::Duration;
+use tokio::time;
+
+#[derive(Debug)]
+struct Event {
+    id: i64,
+    tenant: String,
+}
+
+impl<'r> From<Row<'r>> for Event {
+    fn from(mut row: Row<'r>) -> Self {
+        Self {
+            id: row.get("id"),
+            tenant: row.get("tenant"),
+        }
+    }
+}
+
+pub async fn test_sharding(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Make sure all shards have a leader");
+    for group in [1, 2] {
+        for client in [client_1, client_2, client_3] {
+            let metrics = client.metrics_shard(group).await?;
+            assert!(metrics.current_leader.is_some());
+        }
+    }
+    assert!(client_1.metrics_shard(0).await.is_err());
+    assert!(client_1.metrics_shard(3).await.is_err());
+
+    log("Make sure keys map to stable shards");
+    let tenants = ["tenant_a", "tenant_b", "tenant_c", "tenant_d"];
+    for tenant in tenants {
+        let group = client_1.shard(tenant).group();
+        assert!(group == 1 || group == 2);
+        assert_eq!(client_2.shard(tenant).group(), group);
+        assert_eq!(client_3.shard(tenant).group(), group);
+        assert!(client_1.shard(tenant).name().is_none());
+    }
+    // make sure we actually cover both shards
+    let groups = tenants
+        .iter()
+        .map(|t| client_1.shard(t).group())
+        .collect::<Vec<_>>();
+    assert!(groups.contains(&1));
+    assert!(groups.contains(&2));
+
+    log("Create a table inside each shard");
+    for tenant in tenants {
+        // the table may exist already if 2 tenants map to the same shard
+        client_1
+            .shard(tenant)
+            .execute(
+                "CREATE TABLE IF NOT EXISTS event (id INTEGER PRIMARY KEY, tenant TEXT NOT NULL)",
+                params!(),
+            )
+            .await?;
+    }
+
+    log("Execute on the shards from all nodes");
+    let sql = "INSERT INTO event (id, tenant) VALUES ($1, $2)";
+    let mut id: i64 = 0;
+    for tenant in tenants {
+        for client in [client_1, client_2, client_3] {
+            let rows_affected = client
+                .shard(tenant)
+                .execute(sql, params!(id, tenant))
+                .await?;
+            assert_eq!(rows_affected, 1);
+            id += 1;
+        }
+    }
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    log("Make sure each shard only contains its own tenants");
+    for tenant in tenants {
+        let group = client_1.shard(tenant).group();
+        let expected = tenants
+            .iter()
+            .filter(|t| client_1.shard(t).group() == group)
+            .count()
+            * 3;
+
+        for client in [client_1, client_2, client_3] {
+            let shard = client.shard(tenant);
+            let rows: Vec<Event> = shard
+                .query_map("SELECT * FROM event ORDER BY id", params!())
+                .await?;
+            assert_eq!(rows.len(), expected);
+            assert!(rows
+                .iter()
+                .all(|ev| client_1.shard(&ev.tenant).group() == group));
+
+            let rows: Vec<Event> = shard
+                .query_consistent_map("SELECT * FROM event WHERE tenant = $1", params!(tenant))
+                .await?;
+            assert_eq!(rows.len(), 3);
+            assert!(rows.iter().all(|ev| ev.id >= 0));
+        }
+    }
+
+    log("Make sure the main database is not affected");
+    let res = client_1.query_raw("SELECT * FROM event", params!()).await;
+    assert!(res.is_err());
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use crate::log;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_snapshot_schedule(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    client_1
+        .execute(
+            "CREATE TABLE IF NOT EXISTS snapshot_schedule (id INTEGER PRIMARY KEY)",
+            params!(),
+        )
+        .await?;
+    client_1
+        .execute("INSERT INTO snapshot_schedule (id) VALUES ($1)", params!(1))
+        .await?;
+    let index = client_1.metrics_db().await?.last_applied.unwrap().index;
+
+    log("A snapshot is built after the max interval with far less logs than the policy");
+    for client in [client_1, client_2, client_3] {
+        let mut snapshot = None;
+        for _ in 0..60 {
+            snapshot = client.metrics_db().await?.snapshot;
+            if snapshot.is_some_and(|id| id.index >= index) {
+                break;
+            }
+            time::sleep(Duration::from_millis(500)).await;
+        }
+        assert!(
+            snapshot.is_some_and(|id| id.index >= index),
+            "no snapshot for index {}: {:?}",
+            index,
+            snapshot
+        );
+    }
+
+    Ok(())
+}
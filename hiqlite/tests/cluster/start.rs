@@ -1,9 +1,16 @@
-use crate::{log, Cache, TEST_DATA_DIR};
-use hiqlite::{start_node_with_cache, Client, Error, Node, NodeConfig};
+use crate::{divergence, log, Cache, TEST_DATA_DIR};
+use futures_util::future::join_all;
+use hiqlite::{
+    params, start_node_with_cache, BackupConfig, Client, Error, Node, NodeConfig, Param,
+    SqlFunctions,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::{fs, task, time};
 
 pub const SECRET_API: &str = "qweqweqweqweqweqwe";
+pub const WRITE_COST_LIMIT: u64 = 10_000_000;
 
 pub async fn start_test_cluster() -> Result<(Client, Client, Client), Error> {
     let handle_client_1 = task::spawn(start_node_with_cache::<Cache>(build_config(1).await));
@@ -72,7 +79,19 @@ pub async fn build_config(node_id: u64) -> NodeConfig {
         node_id,
         nodes: nodes(),
         data_dir,
+        // run one of the nodes with an in-memory DB to cover both modes
+        in_memory: node_id == 3,
+        databases: vec!["billing".to_string()],
+        shards: 2,
         log_statements: true,
+        lease_reads: true,
+        write_coalesce_window: 1000,
+        write_cost_limit: WRITE_COST_LIMIT,
+        divergence_check_interval: 3,
+        divergence_resync: true,
+        snapshot_max_interval: 10,
+        snapshot_min_interval: 5,
+        sql_functions: sql_functions(),
         raft_config: NodeConfig::default_raft_config(1000),
         // TODO currently we can't test with TLS, because this depends on `axum_server`.
         // This does not support graceful shutdown, which we need for testing from
@@ -81,7 +100,7 @@ pub async fn build_config(node_id: u64) -> NodeConfig {
         tls_api: None,
         secret_raft: "asdasdasdasdasdasd".to_string(),
         secret_api: SECRET_API.to_string(),
-        backup_config: Default::default(),
+        backup_config: BackupConfig::default().with_archive_interval(1),
         enc_keys_from: hiqlite::s3::EncKeysFrom::Env,
         s3_config: hiqlite::s3::S3Config::try_from_env(),
         #[cfg(feature = "dashboard")]
@@ -90,6 +109,15 @@ pub async fn build_config(node_id: u64) -> NodeConfig {
     }
 }
 
+fn sql_functions() -> SqlFunctions {
+    SqlFunctions::new()
+        .scalar("slugify", 1, |ctx| {
+            let s = ctx.get::<String>(0)?;
+            Ok(s.trim().to_lowercase().replace(' ', "-"))
+        })
+        .collation("reverse", |a, b| b.cmp(a))
+}
+
 pub async fn wait_for_healthy_cluster(
     client_1: &Client,
     client_2: &Client,
@@ -126,3 +154,73 @@ pub async fn wait_for_healthy_cluster(
 
     Ok(())
 }
+
+/// Node 3 runs with an in-memory DB, where the writer must wait for active readers instead of
+/// failing, which would make it skip log entries and diverge from the others.
+pub async fn test_in_memory_reads_during_writes(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    client_1
+        .execute(
+            "CREATE TABLE in_memory (id INTEGER NOT NULL PRIMARY KEY, value TEXT NOT NULL)",
+            params!(),
+        )
+        .await?;
+    let resyncs = client_3.metrics_divergence()?.resyncs;
+
+    log("Read continuously on the in-memory node 3 while writing");
+    let done = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let client = client_3.clone();
+        let done = done.clone();
+        readers.push(task::spawn(async move {
+            let mut reads = 0;
+            while !done.load(Ordering::Relaxed) {
+                client
+                    .query_raw("SELECT id, value FROM in_memory", params!())
+                    .await?;
+                reads += 1;
+            }
+            Ok::<usize, Error>(reads)
+        }));
+    }
+
+    let mut log_index = 0;
+    for id in 0..200 {
+        log_index = client_1
+            .execute_indexed(
+                "INSERT INTO in_memory (id, value) VALUES ($1, $2)",
+                params!(id, format!("value {}", id)),
+            )
+            .await?
+            .log_index;
+    }
+    done.store(true, Ordering::Relaxed);
+    for res in join_all(readers).await {
+        let reads = res.expect("task to not panic")?;
+        assert!(reads > 0);
+    }
+
+    log("The in-memory node has the same data as the others");
+    for client in [client_1, client_2, client_3] {
+        client.wait_applied(log_index).await?;
+        let count: i64 = client
+            .query_raw_one("SELECT COUNT(*) AS count FROM in_memory", params!())
+            .await?
+            .get("count");
+        assert_eq!(count, 200);
+    }
+
+    let metrics =
+        divergence::wait_for_metrics(client_3, |m| m.last_log_index >= Some(log_index)).await?;
+    assert!(metrics.divergent_nodes.is_empty(), "{:?}", metrics);
+    assert!(metrics.missing_nodes.is_empty(), "{:?}", metrics);
+    assert_eq!(metrics.resyncs, resyncs);
+
+    client_1.execute("DROP TABLE in_memory", params!()).await?;
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use crate::execute_query::TestData;
 use crate::log;
 use chrono::Utc;
-use hiqlite::{params, Client, Error, Param};
+use hiqlite::{params, Client, Error, Guard, Param};
 use std::time::Duration;
 use tokio::time;
 
@@ -101,5 +101,58 @@ pub async fn test_transactions(
     assert_eq!(data[2].ts, now);
     assert_eq!(data[2].description, None);
 
+    log("Making sure a guarded transaction is rolled back if a guard does not match");
+    let update = "UPDATE test SET description = $1 WHERE id = $2";
+    let select_desc = "SELECT description FROM test WHERE id = $1";
+    let res = client_2
+        .txn_guarded([(
+            update,
+            params!("Should never be written", 11),
+            vec![
+                Guard::exists(select_desc, params!(11)),
+                Guard::scalar(select_desc, params!(11), "Some other description")?,
+            ],
+        )])
+        .await;
+    match res {
+        Err(Error::PreconditionFailed { index, .. }) => assert_eq!(index, 1),
+        res => panic!("expected Error::PreconditionFailed, got {:?}", res),
+    }
+
+    let data: TestData = client_1
+        .query_map_one("SELECT * FROM test WHERE id = $1", params!(11))
+        .await?;
+    assert_eq!(data.description.as_deref(), Some("Transaction Data id 11"));
+
+    log("Making sure guards see the changes of previous queries in the same transaction");
+    let results = client_3
+        .txn_guarded([
+            (
+                update,
+                params!("Transaction Data id 12 updated", 12),
+                vec![Guard::scalar(
+                    select_desc,
+                    params!(12),
+                    "Transaction Data id 12",
+                )?],
+            ),
+            (
+                update,
+                params!("Transaction Data id 12", 12),
+                vec![
+                    Guard::row_count("SELECT * FROM test WHERE id >= $1", params!(11), 3),
+                    Guard::scalar(select_desc, params!(12), "Transaction Data id 12 updated")?,
+                ],
+            ),
+        ])
+        .await?;
+    assert_eq!(results.len(), 2);
+    for res in results {
+        assert_eq!(res?, 1);
+    }
+
+    let res = Guard::scalar(select_desc, params!(12), Param::named("description", "x"));
+    assert!(matches!(res, Err(Error::QueryParams(_))));
+
     Ok(())
 }
@@ -42,6 +42,31 @@ impl<'r> From<hiqlite::Row<'r>> for Data {
     }
 }
 
+#[derive(Debug, PartialEq, hiqlite::FromRow)]
+struct DataDerived {
+    #[row(rename = "id")]
+    key: i64,
+    id_none: Option<i64>,
+    #[row(flatten)]
+    names: Names,
+    #[row(with = "is_true")]
+    is_bool: bool,
+    // this column does not exist
+    #[row(default)]
+    missing: Option<String>,
+}
+
+#[derive(Debug, PartialEq, hiqlite::FromRow)]
+struct Names {
+    name: String,
+    name_none: Option<String>,
+    name_opt: Option<String>,
+}
+
+fn is_true(value: i64) -> bool {
+    value == 1
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Json {
     id: i64,
@@ -123,6 +148,27 @@ pub async fn test_type_conversions(client: &Client) -> Result<(), Error> {
         .await?;
     assert_eq!(slf, data);
 
+    let derived: DataDerived = client
+        .query_map_one(
+            "SELECT * FROM type_conversion WHERE id = $1",
+            params!(data.id),
+        )
+        .await?;
+    assert_eq!(
+        derived,
+        DataDerived {
+            key: data.id,
+            id_none: None,
+            names: Names {
+                name: data.name.clone(),
+                name_none: None,
+                name_opt: data.name_opt.clone(),
+            },
+            is_bool: true,
+            missing: None,
+        }
+    );
+
     // TODO we can't use the automatic conversion with `query_as`, because of the default serialization
     // / deserialization for chrono types is not the one implemented in rusqlite. Can we make this work?
     // let slf: Data = client
@@ -0,0 +1,71 @@
+use crate::log;
+use crate::start::WRITE_COST_LIMIT;
+use hiqlite::{params, Client, Error, Param};
+use std::time::Duration;
+use tokio::time;
+
+/// Never finishes without being aborted
+const RUNAWAY: &str = r#"
+INSERT INTO test
+WITH RECURSIVE c(x) AS (SELECT 1000000 UNION ALL SELECT x + 1 FROM c)
+SELECT x, 0, 'runaway' FROM c"#;
+
+pub async fn test_write_cost(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("A runaway write is aborted with an error");
+    let res = client_1.execute(RUNAWAY, params!()).await;
+    assert!(matches!(res, Err(Error::CostLimit(_))), "{:?}", res);
+
+    log("Writes applied together with an aborted one are kept");
+    let sql = "INSERT INTO test VALUES ($1, $2, $3)";
+    let (res_1, res_2, res_3, res_runaway) = tokio::join!(
+        client_2.execute(sql, params!(901, 0, "cost guard")),
+        client_2.execute(sql, params!(902, 0, "cost guard")),
+        client_3.execute(sql, params!(903, 0, "cost guard")),
+        client_2.execute(RUNAWAY, params!()),
+    );
+    assert_eq!(res_1?, 1);
+    assert_eq!(res_2?, 1);
+    assert_eq!(res_3?, 1);
+    assert!(
+        matches!(res_runaway, Err(Error::CostLimit(_))),
+        "{:?}",
+        res_runaway
+    );
+
+    // race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+
+    log("Make sure all nodes aborted the same writes");
+    for client in [client_1, client_2, client_3] {
+        let mut row = client
+            .query_raw_one(
+                "SELECT count(*) AS count FROM test WHERE id BETWEEN 901 AND 903",
+                params!(),
+            )
+            .await?;
+        assert_eq!(row.get::<i64>("count"), 3);
+
+        let mut row = client
+            .query_raw_one(
+                "SELECT count(*) AS count FROM test WHERE id >= 1000000",
+                params!(),
+            )
+            .await?;
+        assert_eq!(row.get::<i64>("count"), 0);
+
+        let metrics = client.metrics_write_cost()?;
+        assert_eq!(metrics.limit, WRITE_COST_LIMIT);
+        assert!(metrics.aborted >= 2, "{:?}", metrics);
+        assert!(metrics.max_steps > WRITE_COST_LIMIT, "{:?}", metrics);
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id BETWEEN 901 AND 903", params!())
+        .await?;
+
+    Ok(())
+}